@@ -1,27 +1,118 @@
 use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine as _;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use encoding_rs::Encoding;
+use futures::{StreamExt, TryStreamExt, stream};
 use libsql::Builder;
 use oauth2::{
     AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointNotSet,
     EndpointSet, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, Scope,
-    TokenResponse, TokenUrl,
-    basic::BasicClient,
+    TokenResponse, TokenUrl, basic::BasicClient,
 };
 use reqwest::Client;
-use serde::{Deserialize, de::DeserializeOwned};
-use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tokio::time::{Duration, timeout};
-use tracing::info;
+use tracing::{info, warn};
 use url::Url;
 
 const DEFAULT_REDIRECT_URL: &str = "http://127.0.0.1:53682/callback";
-const DEFAULT_DB_PATH: &str = ".mail/mail.db";
-const CALLBACK_TIMEOUT_SECS: u64 = 180;
+const GRAPH_API_URL: &str = "https://graph.microsoft.com/v1.0";
+const LEGACY_DB_PATH: &str = ".mail/mail.db";
+const DB_FILE_NAME: &str = "mail.db";
+const DEFAULT_PROVIDER_KEY: &str = "default_provider";
+const LAST_PROVIDER_KEY: &str = "last_provider";
+const LOAD_REMOTE_IMAGES_KEY: &str = "load_remote_images";
+const AUTO_MARK_READ_KEY: &str = "auto_mark_read_on_open";
+const MARK_READ_DELAY_KEY: &str = "mark_read_delay_secs";
+const PREFER_HTML_KEY: &str = "prefer_html_body";
+const PREVIEW_LENGTH_KEY: &str = "preview_length";
+const LIST_DATE_DISPLAY_KEY: &str = "list_date_display";
+const DETAIL_DATE_DISPLAY_KEY: &str = "detail_date_display";
+const MESSAGE_SORT_KEY: &str = "message_sort";
+const SORT_ASCENDING_KEY: &str = "message_sort_ascending";
+const PLAINTEXT_MODE_KEY: &str = "plaintext_mode";
+const FONT_SCALE_KEY: &str = "font_scale";
+const PORTABLE_MARKER: &str = "mail.portable";
+const IMPORTED_LABEL: &str = "IMPORTED";
+/// `local_flags.provider` for imported messages, which have no provider.
+const IMPORTED_FLAG_PROVIDER: &str = "imported";
+/// Gmail has no follow-up flag; flagged messages get this user label.
+const FOLLOW_UP_LABEL: &str = "Opvolgen";
+const CALLBACK_TIMEOUT_SECS: u64 = 120;
 const MESSAGE_LIMIT: usize = 20;
+const SECS_PER_DAY: i64 = 86_400;
+/// How long a remembered account email is used before userinfo is asked again.
+const ACCOUNT_EMAIL_REFRESH_SECS: i64 = SECS_PER_DAY;
+const UNKNOWN_ACCOUNT: &str = "(onbekend account)";
+const GMAIL_API_LIBRARY_URL: &str =
+    "https://console.cloud.google.com/apis/library/gmail.googleapis.com";
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_LABELS: [&str; 7] = ["ma", "di", "wo", "do", "vr", "za", "zo"];
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+/// Consecutive 5xx/timeout fetch failures before the error hints at an outage.
+const OUTAGE_FAILURE_THRESHOLD: u32 = 3;
+const SEARCH_RESULT_LIMIT: i64 = 100;
+const OFFLINE_BODIES_KEY: &str = "offline_bodies";
+const CACHE_LIMIT_KEY: &str = "cache_message_limit";
+/// Messages listed per page of [`Engine::export_account_mbox`]; progress is
+/// checkpointed after each page.
+const EXPORT_PAGE_SIZE: usize = 100;
+pub const DEFAULT_CACHE_MESSAGE_LIMIT: usize = 5_000;
+const DB_BUSY_TIMEOUT_SECS: u64 = 5;
+const RATE_LIMIT_RETRIES: u32 = 3;
+const RETRY_BASE_MS: u64 = 1000;
+const CALLBACK_READ_TIMEOUT_SECS: u64 = 20;
+const FETCH_CONCURRENCY: usize = 4;
+/// How long `GET /status` answers from its last snapshot before asking the
+/// providers for fresh unread counts again.
+const STATUS_CACHE_SECS: u64 = 60;
+const EAGER_BODY_COUNT: usize = 3;
+const DEFAULT_USER_AGENT: &str = concat!("mail/", env!("CARGO_PKG_VERSION"));
+const GMAIL_ATTACHMENT_LIMIT_BYTES: usize = 25 * 1024 * 1024;
+const OUTLOOK_INLINE_ATTACHMENT_LIMIT_BYTES: usize = 3 * 1024 * 1024;
+const GRAPH_MESSAGE_SIZE_PROPERTY: &str = "Long 0xe08";
+const GRAPH_MESSAGE_SIZE_EXPAND: &str =
+    "singleValueExtendedProperties($filter=id eq 'Long 0x0E08')";
+const SPAM_SCORE_THRESHOLD: u8 = 5;
+const GRAPH_CATEGORY_SCAN_LIMIT: usize = 100;
+/// Hex colors for Outlook's `preset0`..`preset24` category colors.
+const GRAPH_CATEGORY_COLORS: [&str; 25] = [
+    "#e74856", "#ff8c00", "#ab620d", "#fff100", "#47d041", "#30c6cc", "#73aa24", "#4cc2ff",
+    "#a700ae", "#c3207a", "#b4bdcc", "#738091", "#9fa3a6", "#62676b", "#2b2b2b", "#a4262c",
+    "#c85100", "#8a4a0b", "#b39700", "#0b6a0b", "#038387", "#586e21", "#0063b1", "#5c126b",
+    "#8b0c3c",
+];
+const GMAIL_BATCH_MODIFY_LIMIT: usize = 1000;
+const GRAPH_BATCH_LIMIT: usize = 20;
+const GMAIL_UNMOVABLE_LABELS: &[&str] = &["DRAFT", "SENT", "UNREAD", "CHAT"];
+const REDACTED: &str = "[verborgen]";
+const SECRET_KEYS: &[(&str, &[char])] = &[
+    ("access_token", &['=', ':']),
+    ("refresh_token", &['=', ':']),
+    ("id_token", &['=', ':']),
+    ("client_secret", &['=', ':']),
+    ("code_verifier", &['=', ':']),
+    ("code", &['=']),
+    ("bearer", &[' ']),
+];
+const TOKEN_PREFIXES: &[&str] = &["ya29.", "1//", "eyJ"];
 pub const DEFAULT_GOOGLE_CLIENT_ID: &str = "";
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub const ISSUE_TRACKER_URL: &str = "https://github.com/NostraDavid/mail/issues/new";
+pub const DEFAULT_PREVIEW_LENGTH: usize = 100;
+/// Prefix of the labels added by [`RuleAction::Label`].
+pub const LOCAL_LABEL_PREFIX: &str = "local:";
+/// Prefix of the label that files a message under a rule pseudo-folder, see
+/// [`RuleAction::MoveTo`].
+pub const RULE_FOLDER_PREFIX: &str = "folder:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Provider {
     Google,
     Outlook,
@@ -35,6 +126,13 @@ impl Provider {
         }
     }
 
+    pub fn inbox_folder_id(self) -> &'static str {
+        match self {
+            Provider::Google => "INBOX",
+            Provider::Outlook => "inbox",
+        }
+    }
+
     fn as_key(self) -> &'static str {
         match self {
             Provider::Google => "google",
@@ -51,950 +149,10660 @@ impl Provider {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    InteractionRequired {
+        provider: Provider,
+        detail: String,
+    },
+    NotFound {
+        provider: Provider,
+        id: String,
+    },
+    TokenExpired {
+        provider: Provider,
+    },
+    /// The database directory or file can't be written.
+    StorageUnwritable {
+        path: PathBuf,
+        problem: StorageProblem,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageProblem {
+    ReadOnly,
+    DiskFull,
+    PermissionDenied,
+}
+
+impl StorageProblem {
+    pub fn label(self) -> &'static str {
+        match self {
+            StorageProblem::ReadOnly => "alleen-lezen bestandssysteem",
+            StorageProblem::DiskFull => "schijf vol",
+            StorageProblem::PermissionDenied => "geen schrijfrechten",
+        }
+    }
+
+    /// Recognizes the underlying `io` error kinds and the matching SQLite
+    /// messages anywhere in the error chain.
+    fn detect(error: &anyhow::Error) -> Option<Self> {
+        error.chain().find_map(|cause| {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::ReadOnlyFilesystem => return Some(Self::ReadOnly),
+                    std::io::ErrorKind::StorageFull => return Some(Self::DiskFull),
+                    std::io::ErrorKind::PermissionDenied => return Some(Self::PermissionDenied),
+                    _ => {}
+                }
+            }
+            let message = cause.to_string().to_ascii_lowercase();
+            if message.contains("readonly database") || message.contains("read-only file system") {
+                Some(Self::ReadOnly)
+            } else if message.contains("disk is full") || message.contains("no space left") {
+                Some(Self::DiskFull)
+            } else if message.contains("permission denied") {
+                Some(Self::PermissionDenied)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::InteractionRequired { provider, detail } => write!(
+                f,
+                "{} vraagt om opnieuw interactief inloggen (MFA/conditional access): {detail}",
+                provider.label()
+            ),
+            EngineError::NotFound { provider, id } => {
+                write!(f, "bericht {id} niet gevonden bij {}", provider.label())
+            }
+            EngineError::TokenExpired { provider } => write!(
+                f,
+                "je sessie bij {} is verlopen of ingetrokken, log opnieuw in",
+                provider.label()
+            ),
+            EngineError::StorageUnwritable { path, problem } => write!(
+                f,
+                "kan niet schrijven naar {}: {}; maak ruimte vrij of kies een andere map via MAIL_DB_PATH",
+                path.display(),
+                problem.label()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbLocationKind {
+    EnvOverride,
+    Portable,
+    UserData,
+}
+
+#[derive(Debug, Clone)]
+pub struct DbLocation {
+    pub path: PathBuf,
+    pub kind: DbLocationKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoogleSetupFix {
+    ConsentScreen,
+    /// The Gmail API is not enabled in the client's Cloud project.
+    EnableGmailApi,
+}
+
+impl GoogleSetupFix {
+    pub fn detect(error: &str) -> Option<Self> {
+        let lowered = error.to_ascii_lowercase();
+        if is_gmail_api_disabled(&lowered) {
+            return Some(GoogleSetupFix::EnableGmailApi);
+        }
+        if is_google_access_not_configured(&lowered) {
+            return Some(GoogleSetupFix::ConsentScreen);
+        }
+        None
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GoogleSetupFix::ConsentScreen => "OAuth consent screen openen",
+            GoogleSetupFix::EnableGmailApi => "Gmail API inschakelen",
+        }
+    }
+
+    pub fn url(self, client_id: &str) -> String {
+        let base = match self {
+            GoogleSetupFix::ConsentScreen => {
+                "https://console.cloud.google.com/apis/credentials/consent"
+            }
+            GoogleSetupFix::EnableGmailApi => GMAIL_API_LIBRARY_URL,
+        };
+
+        match google_project_number(client_id) {
+            Some(project) => format!("{base}?project={project}"),
+            None => base.to_owned(),
+        }
+    }
+}
+
+pub fn google_project_number(client_id: &str) -> Option<&str> {
+    let (project, _) = client_id.trim().split_once('-')?;
+    if !project.is_empty() && project.bytes().all(|byte| byte.is_ascii_digit()) {
+        Some(project)
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProviderCredentials {
     pub client_id: String,
     pub client_secret: Option<String>,
+    /// Microsoft Entra tenant (GUID, `organizations` or `consumers`) for
+    /// single-tenant app registrations; `common` when unset. Ignored for Google.
+    pub tenant: Option<String>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct SavedOAuthSettings {
     pub google: Option<ProviderCredentials>,
     pub outlook: Option<ProviderCredentials>,
+    pub default_provider: Option<Provider>,
+    pub last_provider: Option<Provider>,
+    pub load_remote_images: bool,
+    pub auto_mark_read_on_open: bool,
+    /// How long a message has to stay selected before it is marked read.
+    pub mark_read_delay: Duration,
+    /// Use the HTML part as the text body when a Gmail message has both.
+    pub prefer_html: bool,
+    /// Maximum number of characters shown as preview in list rows; 0 hides it.
+    pub preview_length: usize,
+    pub list_date_display: DateDisplay,
+    pub detail_date_display: DateDisplay,
+    pub message_sort: SortKey,
+    /// Oldest, A-Z or smallest first.
+    pub sort_ascending: bool,
+    /// Accessibility mode: no HTML rendering, high contrast.
+    pub plaintext_mode: bool,
+    /// UI scale factor; `1.0` unless changed in the settings.
+    pub font_scale: f32,
+    /// Fetch full bodies during sync so the offline search covers them.
+    pub offline_bodies: bool,
+    /// Cached messages kept before the oldest are evicted.
+    pub cache_message_limit: usize,
+    pub trusted_image_senders: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+impl SavedOAuthSettings {
+    pub fn startup_provider(&self) -> Provider {
+        self.default_provider
+            .or(self.last_provider)
+            .unwrap_or(Provider::Google)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct MailMessage {
+    pub id: String,
+    pub labels: Vec<String>,
     pub subject: String,
     pub from: String,
     pub date: String,
     pub body: String,
+    pub body_html: Option<String>,
+    pub size_estimate: Option<u64>,
+    pub has_attachments: bool,
+    pub unsubscribe: Option<Unsubscribe>,
+    pub authentication: Option<AuthenticationResults>,
+    pub spam_score: Option<u8>,
+    pub is_suspicious: bool,
+    pub importance: Importance,
+    /// Gmail `threadId` / Graph `conversationId`; see [`MailMessage::conversation_key`].
+    pub conversation_id: Option<String>,
+    /// The RFC 5322 `Message-ID`, as `<id@host>`. Unlike `id` it is the same
+    /// in every mailbox holding a copy of the message.
+    pub internet_message_id: Option<String>,
+    /// Values of the extra headers/fields requested via [`EngineBuilder::extra_fields`].
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Clone)]
-pub struct LoginResult {
-    pub provider: Provider,
-    pub account: String,
-    pub messages: Vec<MailMessage>,
-}
-
-pub struct Engine {
-    app_name: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum Importance {
+    Low,
+    #[default]
+    Normal,
+    High,
 }
 
-impl Engine {
-    pub fn new(app_name: impl Into<String>) -> Self {
-        Self {
-            app_name: app_name.into(),
+impl Importance {
+    /// Parses `X-Priority` (`1`-`5`, optionally followed by a label) and the
+    /// `Importance` header / Graph `importance` values (`high`, `normal`, `low`).
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        match value.split_whitespace().next()? {
+            "1" | "2" | "high" | "urgent" => Some(Importance::High),
+            "3" | "normal" => Some(Importance::Normal),
+            "4" | "5" | "low" | "non-urgent" => Some(Importance::Low),
+            _ => None,
         }
     }
 
-    pub async fn start(&self) -> Result<()> {
-        info!("engine start: {}", self.app_name);
-        Ok(())
+    pub fn label(self) -> &'static str {
+        match self {
+            Importance::Low => "laag",
+            Importance::Normal => "normaal",
+            Importance::High => "hoog",
+        }
     }
+}
 
-    pub async fn load_oauth_settings(&self) -> Result<SavedOAuthSettings> {
-        let conn = open_conn().await?;
-        let mut rows = conn
-            .query(
-                "SELECT provider, client_id, COALESCE(client_secret, '') FROM oauth_settings",
-                (),
-            )
-            .await?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AuthVerdict {
+    Pass,
+    Fail,
+    Neutral,
+}
 
-        let mut settings = SavedOAuthSettings::default();
+impl AuthVerdict {
+    fn parse(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "pass" => AuthVerdict::Pass,
+            "fail" | "softfail" | "permerror" => AuthVerdict::Fail,
+            _ => AuthVerdict::Neutral,
+        }
+    }
 
-        while let Some(row) = rows.next().await? {
-            let provider_raw: String = row.get(0)?;
-            let client_id: String = row.get(1)?;
-            let client_secret_raw: String = row.get(2)?;
+    pub fn label(self) -> &'static str {
+        match self {
+            AuthVerdict::Pass => "geslaagd",
+            AuthVerdict::Fail => "mislukt",
+            AuthVerdict::Neutral => "onbekend",
+        }
+    }
+}
 
-            let credentials = ProviderCredentials {
-                client_id,
-                client_secret: empty_to_none(client_secret_raw),
-            };
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuthenticationResults {
+    pub spf: Option<AuthVerdict>,
+    pub dkim: Option<AuthVerdict>,
+    pub dmarc: Option<AuthVerdict>,
+}
 
-            match Provider::from_key(&provider_raw) {
-                Some(Provider::Google) => settings.google = Some(credentials),
-                Some(Provider::Outlook) => settings.outlook = Some(credentials),
-                None => {}
+impl AuthenticationResults {
+    pub fn parse(header: &str) -> Self {
+        let mut results = Self::default();
+        for clause in header.split(';') {
+            let Some((method, rest)) = clause.trim().split_once('=') else {
+                continue;
+            };
+            let verdict = AuthVerdict::parse(rest.split_whitespace().next().unwrap_or_default());
+            match method.trim().to_ascii_lowercase().as_str() {
+                "spf" => {
+                    results.spf.get_or_insert(verdict);
+                }
+                "dkim" => {
+                    results.dkim.get_or_insert(verdict);
+                }
+                "dmarc" => {
+                    results.dmarc.get_or_insert(verdict);
+                }
+                _ => {}
             }
         }
+        results
+    }
 
-        Ok(settings)
+    pub fn any_failed(&self) -> bool {
+        [self.spf, self.dkim, self.dmarc].contains(&Some(AuthVerdict::Fail))
     }
 
-    pub async fn save_provider_credentials(
-        &self,
-        provider: Provider,
-        credentials: ProviderCredentials,
-    ) -> Result<()> {
-        let client_id = credentials.client_id.trim();
-        if client_id.is_empty() {
-            bail!("client id mag niet leeg zijn");
+    /// `Verified` needs a DMARC pass, or SPF and DKIM both passing without
+    /// any failure; any failure, or no pass at all, is `Unverified`. `None`
+    /// when the results are too incomplete to tell.
+    pub fn sender_trust(&self) -> Option<SenderTrust> {
+        let passed = |verdict: Option<AuthVerdict>| verdict == Some(AuthVerdict::Pass);
+        if self.any_failed() || ![self.spf, self.dkim, self.dmarc].iter().any(|v| passed(*v)) {
+            Some(SenderTrust::Unverified)
+        } else if passed(self.dmarc) || (passed(self.spf) && passed(self.dkim)) {
+            Some(SenderTrust::Verified)
+        } else {
+            None
         }
-
-        let conn = open_conn().await?;
-
-        conn.execute(
-            "INSERT INTO oauth_settings (provider, client_id, client_secret)
-             VALUES (?1, ?2, ?3)
-             ON CONFLICT(provider) DO UPDATE SET
-                client_id = excluded.client_id,
-                client_secret = excluded.client_secret",
-            libsql::params![
-                provider.as_key(),
-                client_id.to_owned(),
-                normalized_secret(credentials.client_secret)
-            ],
-        )
-        .await?;
-
-        Ok(())
     }
 
-    pub async fn login_and_fetch(&self, provider: Provider) -> Result<LoginResult> {
-        info!("starting OAuth for provider={}", provider.label());
-        let credentials = self
-            .load_provider_credentials(provider)
-            .await?
-            .ok_or_else(|| {
-                anyhow!(
-                    "Geen OAuth-client ingesteld voor {}. Vul eerst Client ID in de app in.",
-                    provider.label()
-                )
-            })?;
-
-        validate_credentials(provider, &credentials)?;
+    /// E.g. `SPF geslaagd, DKIM mislukt`; methods missing from the header are left out.
+    pub fn summary(&self) -> String {
+        [
+            ("SPF", self.spf),
+            ("DKIM", self.dkim),
+            ("DMARC", self.dmarc),
+        ]
+        .into_iter()
+        .filter_map(|(name, verdict)| Some(format!("{name} {}", verdict?.label())))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
 
-        let config = ProviderConfig::from_credentials(provider, credentials);
-        let redirect_url = redirect_url()?;
-        let redirect_target = RedirectTarget::from_url(&redirect_url)?;
-        let oauth = build_oauth_client(&config, redirect_url)?;
-        let stored_refresh = self.load_refresh_token(provider).await?;
+/// Sender badge derived from SPF/DKIM/DMARC, see
+/// [`AuthenticationResults::sender_trust`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SenderTrust {
+    Verified,
+    Unverified,
+}
 
-        if let Some(refresh_token) = stored_refresh.as_deref() {
-            match exchange_refresh_token(&oauth, refresh_token.to_owned()).await {
-                Ok(token_set) => {
-                    if let Some(new_refresh_token) = token_set.refresh_token {
-                        self.save_refresh_token(provider, &new_refresh_token).await?;
-                    }
-                    return fetch_inbox(&config, &token_set.access_token).await;
-                }
-                Err(error) => {
-                    info!(
-                        "stored refresh token rejected for provider={}: {error:#}",
-                        provider.label()
-                    );
-                    self.clear_refresh_token(provider).await?;
-                }
-            }
+impl SenderTrust {
+    pub fn label(self) -> &'static str {
+        match self {
+            SenderTrust::Verified => "geverifieerde afzender",
+            SenderTrust::Unverified => "niet geverifieerd",
         }
+    }
+}
 
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-        let mut request = oauth
-            .authorize_url(CsrfToken::new_random)
-            .set_pkce_challenge(pkce_challenge);
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unsubscribe {
+    pub mailto: Option<String>,
+    pub url: Option<String>,
+    pub one_click: bool,
+}
 
-        for scope in config.scopes {
-            request = request.add_scope(Scope::new((*scope).to_owned()));
+impl Unsubscribe {
+    pub fn parse(list_unsubscribe: &str, list_unsubscribe_post: Option<&str>) -> Option<Self> {
+        let mut mailto = None;
+        let mut url = None;
+
+        for target in list_unsubscribe.split(',') {
+            let target = target
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .trim();
+            let lowered = target.to_ascii_lowercase();
+            if lowered.starts_with("mailto:") {
+                mailto.get_or_insert_with(|| target.to_owned());
+            } else if lowered.starts_with("https://") || lowered.starts_with("http://") {
+                url.get_or_insert_with(|| target.to_owned());
+            }
         }
 
-        if provider == Provider::Google && stored_refresh.is_none() {
-            request = request
-                .add_extra_param("access_type", "offline")
-                .add_extra_param("prompt", "consent");
+        if mailto.is_none() && url.is_none() {
+            return None;
         }
 
-        let (auth_url, csrf_state) = request.url();
-        webbrowser::open(auth_url.as_str())
-            .map_err(|error| anyhow!("browser kon niet worden geopend: {error}"))?;
+        let one_click = url.is_some()
+            && list_unsubscribe_post.is_some_and(|value| {
+                value
+                    .trim()
+                    .eq_ignore_ascii_case("List-Unsubscribe=One-Click")
+            });
+
+        Some(Self {
+            mailto,
+            url,
+            one_click,
+        })
+    }
+}
 
-        let code = wait_for_oauth_code(&redirect_target, csrf_state.secret()).await?;
-        let token_set = exchange_token(&oauth, code, pkce_verifier)
-            .await
-            .map_err(|error| with_token_exchange_hint(provider, error))?;
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MessageKey(String);
 
-        if let Some(refresh_token) = token_set.refresh_token {
-            self.save_refresh_token(provider, &refresh_token).await?;
-        }
+impl MailMessage {
+    pub fn key(&self) -> MessageKey {
+        MessageKey(self.id.clone())
+    }
 
-        fetch_inbox(&config, &token_set.access_token).await
+    /// Thread identifier; a message without one forms its own conversation.
+    pub fn conversation_key(&self) -> &str {
+        self.conversation_id.as_deref().unwrap_or(&self.id)
     }
 
-    pub async fn try_restore_session(&self, provider: Provider) -> Result<Option<LoginResult>> {
-        let refresh_token = match self.load_refresh_token(provider).await? {
-            Some(token) => token,
-            None => return Ok(None),
-        };
+    /// The pseudo-folder a [`RuleAction::MoveTo`] rule filed this message under.
+    pub fn rule_folder(&self) -> Option<&str> {
+        self.labels
+            .iter()
+            .find_map(|label| label.strip_prefix(RULE_FOLDER_PREFIX))
+    }
 
-        let credentials = self
-            .load_provider_credentials(provider)
-            .await?
-            .ok_or_else(|| {
-                anyhow!(
-                    "Geen OAuth-client ingesteld voor {}. Vul eerst Client ID in de app in.",
-                    provider.label()
-                )
-            })?;
+    /// Names of the labels added by [`RuleAction::Label`] rules.
+    pub fn local_labels(&self) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter_map(|label| label.strip_prefix(LOCAL_LABEL_PREFIX))
+    }
 
-        validate_credentials(provider, &credentials)?;
+    pub fn to_pretty_json(&self) -> Result<String> {
+        let json = serde_json::to_string_pretty(self)?;
+        Ok(redact_secrets(&json))
+    }
 
-        let config = ProviderConfig::from_credentials(provider, credentials);
-        let oauth = build_oauth_client(&config, redirect_url()?)?;
-        let token_set = match exchange_refresh_token(&oauth, refresh_token).await {
-            Ok(token_set) => token_set,
-            Err(error) => {
-                info!(
-                    "session restore failed for provider={}: {error:#}",
-                    provider.label()
-                );
-                self.clear_refresh_token(provider).await?;
-                return Ok(None);
-            }
-        };
+    /// Unix timestamp of [`MailMessage::date`], if it could be parsed.
+    pub fn timestamp(&self) -> Option<i64> {
+        parse_mail_date(&self.date)
+    }
 
-        if let Some(new_refresh_token) = token_set.refresh_token {
-            self.save_refresh_token(provider, &new_refresh_token).await?;
+    /// Single-line preview of the body, cut at `max_chars` characters with an
+    /// ellipsis so Gmail snippets and Graph previews line up in lists.
+    pub fn preview(&self, max_chars: usize) -> String {
+        let collapsed = self.body.split_whitespace().collect::<Vec<_>>().join(" ");
+        match collapsed.char_indices().nth(max_chars) {
+            Some((cut, _)) => format!("{}...", collapsed[..cut].trim_end()),
+            None => collapsed,
         }
+    }
+}
 
-        let result = fetch_inbox(&config, &token_set.access_token).await?;
-        Ok(Some(result))
+/// A list row without a body; see [`Engine::list_messages_metadata_only`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSummary {
+    pub id: String,
+    pub subject: String,
+    pub from: String,
+    pub date: String,
+    /// Gmail snippet / Graph `bodyPreview`.
+    pub preview: String,
+    pub unread: bool,
+    pub conversation_id: Option<String>,
+}
+
+impl From<MailMessage> for MessageSummary {
+    fn from(message: MailMessage) -> Self {
+        Self {
+            unread: message.labels.iter().any(|label| label == "UNREAD"),
+            id: message.id,
+            subject: message.subject,
+            from: message.from,
+            date: message.date,
+            preview: message.body,
+            conversation_id: message.conversation_id,
+        }
     }
+}
 
-    async fn load_provider_credentials(
-        &self,
-        provider: Provider,
-    ) -> Result<Option<ProviderCredentials>> {
-        let settings = self.load_oauth_settings().await?;
-        let creds = match provider {
-            Provider::Google => settings.google.or_else(load_google_credentials_from_env),
-            Provider::Outlook => settings.outlook,
-        };
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+}
 
-        Ok(creds)
+/// A user label (Gmail) or category (Outlook) that can be attached to
+/// messages. `id` is what appears in [`MailMessage::labels`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailLabel {
+    pub id: String,
+    pub name: String,
+    /// Background color as `#rrggbb`, when the provider defines one.
+    pub color: Option<String>,
+}
+
+impl std::fmt::Display for MailLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
     }
+}
 
-    async fn load_refresh_token(&self, provider: Provider) -> Result<Option<String>> {
-        let conn = open_conn().await?;
-        let mut rows = conn
-            .query(
-                "SELECT refresh_token FROM oauth_tokens WHERE provider = ?1",
-                libsql::params![provider.as_key()],
-            )
-            .await?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FolderCounts {
+    pub total: u64,
+    pub unread: u64,
+}
 
-        let token = if let Some(row) = rows.next().await? {
-            let refresh_token: String = row.get(0)?;
-            if refresh_token.trim().is_empty() {
-                None
-            } else {
-                Some(refresh_token)
-            }
-        } else {
-            None
-        };
+/// Read-only app state served by [`Engine::serve_status`].
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusSnapshot {
+    pub accounts: Vec<AccountStatus>,
+}
 
-        Ok(token)
-    }
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountStatus {
+    pub provider: &'static str,
+    pub email: String,
+    /// Whether a session for this provider is stored.
+    pub logged_in: bool,
+    /// Unread inbox messages of the provider's current session; `None` when
+    /// not logged in or the provider couldn't be reached.
+    pub unread: Option<u64>,
+    /// Unix seconds of the last inbox sync.
+    pub last_synced: Option<i64>,
+}
 
-    async fn save_refresh_token(&self, provider: Provider, refresh_token: &str) -> Result<()> {
-        let conn = open_conn().await?;
-        conn.execute(
-            "INSERT INTO oauth_tokens (provider, refresh_token)
-             VALUES (?1, ?2)
-             ON CONFLICT(provider) DO UPDATE SET
-                refresh_token = excluded.refresh_token",
-            libsql::params![provider.as_key(), refresh_token],
-        )
-        .await?;
-        Ok(())
-    }
+/// Mailbox storage in bytes. `total` is only known when the provider exposes
+/// a quota (Google); for Outlook `used` is the sum of the top-level folders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub used: u64,
+    pub total: Option<u64>,
+}
 
-    async fn clear_refresh_token(&self, provider: Provider) -> Result<()> {
-        let conn = open_conn().await?;
-        conn.execute(
-            "DELETE FROM oauth_tokens WHERE provider = ?1",
-            libsql::params![provider.as_key()],
-        )
-        .await?;
-        Ok(())
+impl std::fmt::Display for Folder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
     }
 }
 
-fn empty_to_none(value: String) -> Option<String> {
-    let trimmed = value.trim();
-    if trimmed.is_empty() {
-        None
-    } else {
-        Some(trimmed.to_owned())
-    }
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+    pub attachments: Vec<(String, Vec<u8>, String)>,
 }
 
-fn normalized_secret(secret: Option<String>) -> Option<String> {
-    secret.and_then(|value| {
-        let trimmed = value.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(trimmed.to_owned())
-        }
-    })
+#[derive(Debug, Clone, Default)]
+pub struct Draft {
+    pub id: Option<String>,
+    pub message: OutgoingMessage,
 }
 
-fn load_google_credentials_from_env() -> Option<ProviderCredentials> {
-    let client_id = std::env::var("MAIL_GOOGLE_CLIENT_ID").ok()?;
-    let client_id = client_id.trim().to_owned();
-    if client_id.is_empty() {
-        return None;
-    }
+/// The autosaved compose state. Attachment contents are not kept; their
+/// names are, so the user can be asked to add them again.
+#[derive(Debug, Clone, Default)]
+pub struct LocalDraft {
+    pub draft: Draft,
+    pub lost_attachments: Vec<String>,
+}
 
-    let client_secret = std::env::var("MAIL_GOOGLE_CLIENT_SECRET")
-        .ok()
-        .and_then(|value| {
-            let trimmed = value.trim();
-            if trimmed.is_empty() {
-                None
-            } else {
-                Some(trimmed.to_owned())
-            }
-        });
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelfTestOutcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
 
-    Some(ProviderCredentials {
-        client_id,
-        client_secret,
-    })
+#[derive(Debug, Clone)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub outcome: SelfTestOutcome,
 }
 
-fn validate_credentials(provider: Provider, credentials: &ProviderCredentials) -> Result<()> {
-    let client_id = credentials.client_id.trim();
-    if client_id.is_empty() {
-        bail!("Client ID is leeg.");
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub provider: Provider,
+    pub steps: Vec<SelfTestStep>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|step| step.outcome == SelfTestOutcome::Passed)
     }
 
-    if provider == Provider::Google && !client_id.ends_with(".apps.googleusercontent.com") {
-        bail!(
-            "Google Client ID lijkt ongeldig. Gebruik de volledige OAuth Client ID uit Google Cloud (eindigt op .apps.googleusercontent.com)."
-        );
+    fn record<T>(&mut self, name: &'static str, result: Result<T>) -> Option<T> {
+        let (outcome, value) = match result {
+            Ok(value) => (SelfTestOutcome::Passed, Some(value)),
+            Err(error) => (SelfTestOutcome::Failed(format!("{error:#}")), None),
+        };
+        self.steps.push(SelfTestStep { name, outcome });
+        value
     }
 
-    Ok(())
+    fn skip(&mut self, names: &[&'static str]) {
+        for name in names {
+            self.steps.push(SelfTestStep {
+                name,
+                outcome: SelfTestOutcome::Skipped,
+            });
+        }
+    }
 }
 
-fn with_token_exchange_hint(provider: Provider, error: anyhow::Error) -> anyhow::Error {
-    let rendered = format!("{error:#}");
-    let lowered = rendered.to_ascii_lowercase();
+/// Inclusive date bounds for a fetch. Dates are whole days in UTC, so the same
+/// range selects the same messages on Gmail and Graph. The provider can also
+/// be asked for unread messages only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateRange {
+    after: Option<i64>,
+    before: Option<i64>,
+    unread_only: bool,
+}
 
-    if lowered.contains("client_secret is missing")
-        || lowered.contains("client secret is missing")
-        || lowered.contains("aadsts7000218")
-    {
-        let hint = match provider {
-            Provider::Google => {
-                "Deze Google OAuth client verwacht een Client Secret. Vul de juiste secret in, of \
-                 gebruik een Desktop app OAuth client in Google Cloud."
-            }
-            Provider::Outlook => {
-                "Deze Microsoft App Registration verwacht een Client Secret. Voeg een secret toe \
-                 (Certificates & secrets), of zet de app als public client (Allow public client \
-                 flows) en log in zonder secret."
-            }
+impl DateRange {
+    /// Parses `YYYY-MM-DD` bounds; an empty string leaves that side open.
+    pub fn parse(after: &str, before: &str) -> Result<Self> {
+        let range = Self {
+            after: parse_day(after)?,
+            before: parse_day(before)?,
+            unread_only: false,
         };
+        if let (Some(after), Some(before)) = (range.after, range.before)
+            && after > before
+        {
+            bail!("begindatum ligt na de einddatum");
+        }
+        Ok(range)
+    }
 
-        return anyhow!("{rendered}\nTip: {hint}");
+    pub fn with_unread_only(mut self, unread_only: bool) -> Self {
+        self.unread_only = unread_only;
+        self
     }
 
-    if lowered.contains("invalid_client") {
-        let hint = match provider {
-            Provider::Google => {
-                "Controleer Google OAuth: juiste Client ID/Secret uit hetzelfde project en \
-                 app-type Desktop app. Gebruik exact dezelfde credentials als in Google Cloud \
-                 Console."
-            }
-            Provider::Outlook => {
-                "Controleer Microsoft OAuth: Client ID/Secret moeten uit dezelfde App \
-                 Registration komen. Als je geen secret gebruikt, zet de app als public client \
-                 (mobile/desktop) en gebruik de loopback redirect URI."
+    /// No date bounds; [`DateRange::with_unread_only`] does not count.
+    pub fn is_empty(&self) -> bool {
+        self.after.is_none() && self.before.is_none()
+    }
+
+    fn start_secs(&self) -> Option<i64> {
+        self.after.map(|day| day * SECS_PER_DAY)
+    }
+
+    fn end_secs(&self) -> Option<i64> {
+        self.before.map(|day| (day + 1) * SECS_PER_DAY)
+    }
+
+    fn gmail_query(&self) -> Option<String> {
+        let terms: Vec<String> = self
+            .start_secs()
+            .map(|secs| format!("after:{secs}"))
+            .into_iter()
+            .chain(self.end_secs().map(|secs| format!("before:{secs}")))
+            .chain(self.unread_only.then(|| "is:unread".to_owned()))
+            .collect();
+        (!terms.is_empty()).then(|| terms.join(" "))
+    }
+
+    fn graph_filter(&self) -> Option<String> {
+        let mut terms: Vec<String> = self
+            .start_secs()
+            .map(|secs| format!("receivedDateTime ge {}", utc_timestamp(secs)))
+            .into_iter()
+            .chain(
+                self.end_secs()
+                    .map(|secs| format!("receivedDateTime lt {}", utc_timestamp(secs))),
+            )
+            .collect();
+        if self.unread_only {
+            // Graph rejects a filter that does not start with the `$orderby`
+            // property, so an open range still gets a receivedDateTime term.
+            if terms.is_empty() {
+                terms.push("receivedDateTime ge 1900-01-01T00:00:00Z".to_owned());
             }
+            terms.push("isRead eq false".to_owned());
+        }
+        (!terms.is_empty()).then(|| terms.join(" and "))
+    }
+}
+
+/// How message dates are rendered: relative to now ("3 u geleden", "ma") or as
+/// an absolute UTC timestamp ("2024-06-03 14:22").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateDisplay {
+    Relative,
+    #[default]
+    Absolute,
+}
+
+impl DateDisplay {
+    /// Formats a raw `Date` header or Graph timestamp; unparsable values are
+    /// returned unchanged.
+    pub fn format(self, date: &str, now: i64) -> String {
+        let Some(secs) = parse_mail_date(date) else {
+            return date.to_owned();
         };
+        match self {
+            DateDisplay::Relative => relative_date(secs, now),
+            DateDisplay::Absolute => absolute_date(secs),
+        }
+    }
 
-        return anyhow!("{rendered}\nTip: {hint}");
+    fn as_key(self) -> &'static str {
+        match self {
+            DateDisplay::Relative => "relative",
+            DateDisplay::Absolute => "absolute",
+        }
     }
 
-    error
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "relative" => Some(DateDisplay::Relative),
+            "absolute" => Some(DateDisplay::Absolute),
+            _ => None,
+        }
+    }
 }
 
-fn local_db_path() -> String {
-    std::env::var("MAIL_DB_PATH").unwrap_or_else(|_| DEFAULT_DB_PATH.to_owned())
+/// What the message list is ordered by. Newest first by date is the
+/// provider's own order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Date,
+    Sender,
+    Subject,
+    Size,
 }
 
-async fn open_conn() -> Result<libsql::Connection> {
-    let path = local_db_path();
-    let path_ref = Path::new(&path);
+impl SortKey {
+    pub const ALL: [SortKey; 4] = [
+        SortKey::Date,
+        SortKey::Sender,
+        SortKey::Subject,
+        SortKey::Size,
+    ];
 
-    if let Some(parent) = path_ref.parent()
-        && !parent.as_os_str().is_empty()
-    {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("kan DB map niet maken: {}", parent.display()))?;
+    pub fn label(self) -> &'static str {
+        match self {
+            SortKey::Date => "Datum",
+            SortKey::Sender => "Afzender",
+            SortKey::Subject => "Onderwerp",
+            SortKey::Size => "Grootte",
+        }
     }
 
-    let db = Builder::new_local(path).build().await?;
-    let conn = db.connect()?;
-    ensure_schema(&conn).await?;
-    Ok(conn)
-}
+    /// The direction picked when switching to this key: A-Z for text,
+    /// newest or largest first otherwise.
+    pub fn default_ascending(self) -> bool {
+        matches!(self, SortKey::Sender | SortKey::Subject)
+    }
 
-async fn ensure_schema(conn: &libsql::Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS oauth_settings (
-            provider TEXT PRIMARY KEY NOT NULL,
-            client_id TEXT NOT NULL,
-            client_secret TEXT
-        )",
-        (),
-    )
-    .await?;
+    pub fn direction_label(self, ascending: bool) -> &'static str {
+        match (self, ascending) {
+            (SortKey::Date, false) => "Nieuwste eerst",
+            (SortKey::Date, true) => "Oudste eerst",
+            (SortKey::Sender | SortKey::Subject, true) => "A-Z",
+            (SortKey::Sender | SortKey::Subject, false) => "Z-A",
+            (SortKey::Size, false) => "Grootste eerst",
+            (SortKey::Size, true) => "Kleinste eerst",
+        }
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS oauth_tokens (
-            provider TEXT PRIMARY KEY NOT NULL,
-            refresh_token TEXT NOT NULL
-        )",
-        (),
-    )
-    .await?;
+    /// Ascending order: oldest, A-Z, smallest. Unparsable dates and unknown
+    /// sizes sort as the lowest value.
+    pub fn compare(self, a: &MailMessage, b: &MailMessage) -> std::cmp::Ordering {
+        match self {
+            SortKey::Date => a.timestamp().cmp(&b.timestamp()),
+            SortKey::Sender => a.from.to_lowercase().cmp(&b.from.to_lowercase()),
+            SortKey::Subject => a.subject.to_lowercase().cmp(&b.subject.to_lowercase()),
+            SortKey::Size => a.size_estimate.cmp(&b.size_estimate),
+        }
+    }
 
-    Ok(())
+    fn as_key(self) -> &'static str {
+        match self {
+            SortKey::Date => "date",
+            SortKey::Sender => "sender",
+            SortKey::Subject => "subject",
+            SortKey::Size => "size",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sort| sort.as_key() == key)
+    }
 }
 
-#[derive(Debug)]
-struct ProviderConfig {
-    provider: Provider,
-    credentials: ProviderCredentials,
-    auth_url: &'static str,
-    token_url: &'static str,
-    scopes: &'static [&'static str],
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
 }
 
-impl ProviderConfig {
-    fn from_credentials(provider: Provider, credentials: ProviderCredentials) -> Self {
-        match provider {
-            Provider::Google => Self {
-                provider,
-                credentials,
-                auth_url: "https://accounts.google.com/o/oauth2/v2/auth",
-                token_url: "https://oauth2.googleapis.com/token",
-                scopes: &[
-                    "openid",
-                    "email",
-                    "profile",
-                    "https://www.googleapis.com/auth/gmail.readonly",
-                ],
-            },
-            Provider::Outlook => Self {
-                provider,
-                credentials,
-                auth_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
-                token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
-                scopes: &["openid", "email", "profile", "offline_access", "Mail.Read"],
-            },
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailAction {
+    Archive,
+    Trash,
+}
+
+impl MailAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            MailAction::Archive => "gearchiveerd",
+            MailAction::Trash => "naar prullenbak verplaatst",
         }
     }
 }
 
-type OAuthClient =
-    BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>;
+/// Progress of [`Engine::download_for_offline`].
+#[derive(Debug, Clone, Copy)]
+pub struct OfflineProgress {
+    pub downloaded: usize,
+    pub estimated_total: Option<u64>,
+}
 
+/// Progress of [`Engine::export_account_mbox`].
+#[derive(Debug, Clone, Copy)]
+pub struct ExportProgress {
+    pub exported: usize,
+    pub estimated_total: Option<u64>,
+}
+
+/// Where an interrupted [`Engine::export_account_mbox`] picks up again.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportCheckpoint {
+    path: PathBuf,
+    /// Listing cursor of the first page not yet written.
+    cursor: Option<String>,
+    exported: usize,
+    /// Length of the file after the last complete page.
+    bytes: u64,
+}
+
+/// Raw sources of one listing page, in listing order.
+struct ExportPage {
+    raws: Vec<Vec<u8>>,
+    next_cursor: Option<String>,
+    estimated_total: Option<u64>,
+}
+
+/// A cached message found by [`Engine::search_cache`].
 #[derive(Debug, Clone)]
-struct TokenSet {
-    access_token: String,
-    refresh_token: Option<String>,
+pub struct SearchHit {
+    pub account: Account,
+    /// Gmail label id or Graph folder id the message was fetched from.
+    pub folder: String,
+    pub message: MailMessage,
 }
 
-fn build_oauth_client(config: &ProviderConfig, redirect_url: Url) -> Result<OAuthClient> {
-    let mut client = BasicClient::new(ClientId::new(config.credentials.client_id.clone()))
-        .set_auth_uri(AuthUrl::new(config.auth_url.to_owned())?)
-        .set_token_uri(TokenUrl::new(config.token_url.to_owned())?)
-        .set_redirect_uri(RedirectUrl::new(redirect_url.to_string())?)
-        .set_auth_type(AuthType::RequestBody);
+/// A message flagged for follow-up, see [`Engine::set_followup`].
+#[derive(Debug, Clone)]
+pub struct FollowUp {
+    pub message_id: String,
+    /// Unix seconds.
+    pub due_at: i64,
+}
 
-    if let Some(secret) = &config.credentials.client_secret {
-        client = client.set_client_secret(ClientSecret::new(secret.clone()));
+impl FollowUp {
+    pub fn is_overdue(&self, now: i64) -> bool {
+        self.due_at <= now
     }
 
-    Ok(client)
+    /// Due date as `YYYY-MM-DD` (UTC).
+    pub fn due_day(&self) -> String {
+        let (year, month, day) = civil_from_days(self.due_at.div_euclid(SECS_PER_DAY));
+        format!("{year:04}-{month:02}-{day:02}")
+    }
 }
 
-fn redirect_url() -> Result<Url> {
-    let raw = std::env::var("MAIL_OAUTH_REDIRECT_URI")
-        .unwrap_or_else(|_| DEFAULT_REDIRECT_URL.to_owned());
-    Url::parse(&raw).with_context(|| format!("ongeldige MAIL_OAUTH_REDIRECT_URI: {raw}"))
+/// What a [`MailRule`] looks at; text comparisons ignore case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleCondition {
+    FromContains(String),
+    SubjectContains(String),
+    HasAttachment,
 }
 
-#[derive(Debug)]
-struct RedirectTarget {
-    host: String,
-    port: u16,
-    path: String,
-}
+impl RuleCondition {
+    pub fn matches(&self, message: &MailMessage) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        match self {
+            RuleCondition::FromContains(needle) => contains(&message.from, needle),
+            RuleCondition::SubjectContains(needle) => contains(&message.subject, needle),
+            RuleCondition::HasAttachment => message.has_attachments,
+        }
+    }
 
-impl RedirectTarget {
-    fn from_url(url: &Url) -> Result<Self> {
-        if url.scheme() != "http" {
-            bail!("redirect URI moet http zijn (localhost callback)");
+    fn to_parts(&self) -> (&'static str, &str) {
+        match self {
+            RuleCondition::FromContains(value) => ("from_contains", value),
+            RuleCondition::SubjectContains(value) => ("subject_contains", value),
+            RuleCondition::HasAttachment => ("has_attachment", ""),
         }
+    }
 
-        let host = url
-            .host_str()
-            .ok_or_else(|| anyhow!("redirect URI mist host"))?
-            .to_owned();
+    fn from_parts(kind: &str, value: String) -> Option<Self> {
+        match kind {
+            "from_contains" => Some(RuleCondition::FromContains(value)),
+            "subject_contains" => Some(RuleCondition::SubjectContains(value)),
+            "has_attachment" => Some(RuleCondition::HasAttachment),
+            _ => None,
+        }
+    }
+}
 
-        if host != "127.0.0.1" && host != "localhost" {
-            bail!("redirect URI host moet localhost of 127.0.0.1 zijn");
+impl std::fmt::Display for RuleCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleCondition::FromContains(value) => write!(f, "afzender bevat \"{value}\""),
+            RuleCondition::SubjectContains(value) => write!(f, "onderwerp bevat \"{value}\""),
+            RuleCondition::HasAttachment => f.write_str("heeft bijlage"),
         }
+    }
+}
 
-        let port = url
-            .port_or_known_default()
-            .ok_or_else(|| anyhow!("redirect URI mist poort"))?;
+/// What a matching [`MailRule`] does. Everything stays local: the provider
+/// never sees the label, read state or folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Adds `LOCAL_LABEL_PREFIX` + name to the labels.
+    Label(String),
+    MarkRead,
+    /// Files the message under a pseudo-folder (`RULE_FOLDER_PREFIX` +
+    /// name); the first matching move wins.
+    MoveTo(String),
+    /// Drops the message from the listing; later rules are skipped.
+    Hide,
+}
 
-        let path = url.path().to_owned();
-        if path.is_empty() || path == "/" {
-            bail!("redirect URI pad moet specifiek zijn (bijv. /callback)");
+impl RuleAction {
+    fn to_parts(&self) -> (&'static str, &str) {
+        match self {
+            RuleAction::Label(value) => ("label", value),
+            RuleAction::MarkRead => ("mark_read", ""),
+            RuleAction::MoveTo(value) => ("move_to", value),
+            RuleAction::Hide => ("hide", ""),
         }
+    }
 
-        Ok(Self { host, port, path })
+    fn from_parts(kind: &str, value: String) -> Option<Self> {
+        match kind {
+            "label" => Some(RuleAction::Label(value)),
+            "mark_read" => Some(RuleAction::MarkRead),
+            "move_to" => Some(RuleAction::MoveTo(value)),
+            "hide" => Some(RuleAction::Hide),
+            _ => None,
+        }
     }
 }
 
-async fn wait_for_oauth_code(target: &RedirectTarget, expected_state: &str) -> Result<String> {
-    let listener = TcpListener::bind((target.host.as_str(), target.port))
-        .await
-        .with_context(|| {
-            format!(
-                "kan callback server niet starten op {}:{}",
-                target.host, target.port
-            )
-        })?;
-
-    let (mut stream, _) = timeout(
-        Duration::from_secs(CALLBACK_TIMEOUT_SECS),
-        listener.accept(),
-    )
-    .await
-    .context("timeout wachtend op OAuth callback")??;
-
-    let mut buf = [0_u8; 8192];
-    let n = timeout(Duration::from_secs(20), stream.read(&mut buf))
-        .await
-        .context("timeout bij lezen callback request")??;
-    if n == 0 {
-        bail!("lege callback request ontvangen");
+impl std::fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleAction::Label(value) => write!(f, "label \"{value}\" toevoegen"),
+            RuleAction::MarkRead => f.write_str("als gelezen markeren"),
+            RuleAction::MoveTo(value) => write!(f, "verplaatsen naar \"{value}\""),
+            RuleAction::Hide => f.write_str("verbergen"),
+        }
     }
+}
 
-    let request = String::from_utf8_lossy(&buf[..n]);
-    let first_line = request
-        .lines()
-        .next()
-        .ok_or_else(|| anyhow!("onleesbare callback request"))?;
-    let path_with_query = first_line
-        .split_whitespace()
-        .nth(1)
-        .ok_or_else(|| anyhow!("onleesbare callback request line"))?;
-
-    let callback_url = Url::parse(&format!("http://localhost{path_with_query}"))
-        .context("ongeldige callback URL")?;
-    let (status, body, code) = parse_callback_params(&callback_url, target, expected_state)?;
-
-    let response = format!(
-        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
-        body.len()
-    );
-    stream.write_all(response.as_bytes()).await?;
-    stream.flush().await?;
-
-    code
+/// A local filter rule, see [`Engine::apply_rules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailRule {
+    /// `None` until saved; rules run in id order.
+    pub id: Option<i64>,
+    pub condition: RuleCondition,
+    pub action: RuleAction,
 }
 
-fn parse_callback_params(
-    callback_url: &Url,
-    target: &RedirectTarget,
-    expected_state: &str,
-) -> Result<(&'static str, String, Result<String>)> {
-    if callback_url.path() != target.path {
-        let msg = format!("Ongeldige callback path: {}", callback_url.path());
-        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
-    }
+/// Flags kept only in the local database, independent of provider support.
+#[derive(Debug, Clone, Default)]
+pub struct LocalFlags {
+    pub pinned: HashSet<String>,
+}
 
-    let mut code: Option<String> = None;
-    let mut state: Option<String> = None;
-    let mut error: Option<String> = None;
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub provider: Provider,
+    pub email: String,
+}
 
-    for (key, value) in callback_url.query_pairs() {
-        match key.as_ref() {
-            "code" => code = Some(value.into_owned()),
-            "state" => state = Some(value.into_owned()),
-            "error" => error = Some(value.into_owned()),
-            _ => {}
+impl Account {
+    /// Web client link to a message. The account is passed along
+    /// (`authuser`/`login_hint`) so the right mailbox opens when several are
+    /// signed in.
+    pub fn message_permalink(&self, message_id: &str) -> String {
+        let encode = |value: &str| {
+            url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>()
+        };
+        match self.provider {
+            Provider::Google => format!(
+                "https://mail.google.com/mail/?authuser={}#all/{}",
+                encode(&self.email),
+                encode(message_id)
+            ),
+            Provider::Outlook => {
+                let domain = self.email.rsplit('@').next().unwrap_or_default();
+                let host = if ["outlook.", "hotmail.", "live.", "msn."]
+                    .iter()
+                    .any(|prefix| domain.to_ascii_lowercase().starts_with(prefix))
+                {
+                    "outlook.live.com"
+                } else {
+                    "outlook.office.com"
+                };
+                format!(
+                    "https://{host}/mail/deeplink/read/{}?login_hint={}",
+                    encode(message_id),
+                    encode(&self.email)
+                )
+            }
         }
     }
 
-    if let Some(error) = error {
-        let msg = format!("OAuth login mislukt: {error}");
-        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
+    /// Same provider and an email address equal up to ASCII case.
+    pub fn same_mailbox(&self, other: &Account) -> bool {
+        self.provider == other.provider && self.email.eq_ignore_ascii_case(&other.email)
     }
+}
 
-    if state.as_deref() != Some(expected_state) {
-        let msg = "OAuth state mismatch".to_owned();
-        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
+/// Scopes needed for sending, moving and labelling that are absent from
+/// `granted`. Nothing is reported while `granted` is empty (unknown).
+pub fn missing_write_scopes(provider: Provider, granted: &[String]) -> Vec<&'static str> {
+    if granted.is_empty() {
+        return Vec::new();
     }
+    let required: &[&'static str] = match provider {
+        Provider::Google => &[
+            "https://www.googleapis.com/auth/gmail.modify",
+            "https://www.googleapis.com/auth/gmail.send",
+        ],
+        Provider::Outlook => &["Mail.ReadWrite", "Mail.Send"],
+    };
+    required
+        .iter()
+        .copied()
+        .filter(|scope| {
+            // Graph may report scopes with their resource prefix.
+            let suffix = format!("/{}", scope.to_ascii_lowercase());
+            !granted.iter().any(|granted| {
+                granted.eq_ignore_ascii_case(scope)
+                    || granted.to_ascii_lowercase().ends_with(&suffix)
+            })
+        })
+        .collect()
+}
 
-    match code {
-        Some(code) => Ok((
-            "200 OK",
-            "Login geslaagd. Je kunt dit tabblad sluiten.".to_owned(),
-            Ok(code),
-        )),
-        None => {
-            let msg = "OAuth callback bevat geen code".to_owned();
-            Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))))
+/// Email addresses connected through more than one provider, lowercased.
+pub fn duplicate_account_emails(accounts: &[Account]) -> Vec<String> {
+    let mut duplicates: Vec<String> = Vec::new();
+    for (index, account) in accounts.iter().enumerate() {
+        let email = account.email.to_ascii_lowercase();
+        if !duplicates.contains(&email)
+            && accounts[index + 1..].iter().any(|other| {
+                other.provider != account.provider && other.email.eq_ignore_ascii_case(&email)
+            })
+        {
+            duplicates.push(email);
         }
     }
+    duplicates
 }
 
-async fn exchange_token(
-    client: &OAuthClient,
-    code: String,
-    pkce_verifier: PkceCodeVerifier,
-) -> Result<TokenSet> {
-    let http_client = reqwest::ClientBuilder::new()
-        .redirect(reqwest::redirect::Policy::none())
-        .build()?;
-
-    let response = client
-        .exchange_code(AuthorizationCode::new(code))
-        .set_pkce_verifier(pkce_verifier)
-        .request_async(&http_client)
-        .await
-        .context("token exchange mislukt")?;
+impl std::fmt::Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.provider.label(), self.email)
+    }
+}
 
-    Ok(TokenSet {
-        access_token: response.access_token().secret().to_owned(),
-        refresh_token: response.refresh_token().map(|token| token.secret().to_owned()),
-    })
+#[derive(Debug, Clone)]
+pub struct LoginResult {
+    pub provider: Provider,
+    pub account: String,
+    pub messages: Vec<MailMessage>,
+    pub next_cursor: Option<String>,
+    pub estimated_total: Option<u64>,
+    /// The provider answered successfully without any messages, so the
+    /// folder (within the requested range) is empty rather than unloaded.
+    pub mailbox_empty: bool,
+    /// Set when some listed messages could not be loaded: deleted ones are
+    /// skipped, the others are kept and listed in `load_errors`.
+    pub warning: Option<String>,
+    /// Messages in `messages` that came back without their body, with the
+    /// error; either the list summary or a placeholder. Retry them with
+    /// [`Engine::fetch_message`].
+    pub load_errors: HashMap<String, String>,
 }
 
-async fn exchange_refresh_token(client: &OAuthClient, refresh_token: String) -> Result<TokenSet> {
-    let http_client = reqwest::ClientBuilder::new()
-        .redirect(reqwest::redirect::Policy::none())
-        .build()?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoginMode {
+    /// Reuse the stored refresh token when it is still valid.
+    Resume,
+    SelectAccount,
+    Consent,
+}
 
-    let response = client
-        .exchange_refresh_token(&RefreshToken::new(refresh_token))
-        .request_async(&http_client)
-        .await
-        .context("refresh token exchange mislukt")?;
+/// A login waiting for the user to paste the redirect back, see
+/// [`Engine::start_manual_login`].
+#[derive(Clone)]
+pub struct ManualLogin {
+    pub provider: Provider,
+    pub authorize_url: String,
+    state: String,
+    pkce_verifier: String,
+}
 
-    Ok(TokenSet {
-        access_token: response.access_token().secret().to_owned(),
-        refresh_token: response.refresh_token().map(|token| token.secret().to_owned()),
-    })
+impl std::fmt::Debug for ManualLogin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ManualLogin")
+            .field("provider", &self.provider)
+            .finish_non_exhaustive()
+    }
 }
 
-async fn fetch_inbox(config: &ProviderConfig, access_token: &str) -> Result<LoginResult> {
-    let http = Client::new();
+/// Called with the OAuth authorize URL when the engine does not (or cannot)
+/// open the browser itself, so the caller can show it to the user.
+#[derive(Clone)]
+pub struct AuthorizeUrlHook(std::sync::Arc<dyn Fn(&str) + Send + Sync>);
 
-    match config.provider {
-        Provider::Google => fetch_google_inbox(http, access_token).await,
-        Provider::Outlook => fetch_outlook_inbox(http, access_token).await,
+impl std::fmt::Debug for AuthorizeUrlHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthorizeUrlHook")
     }
 }
 
-async fn fetch_google_inbox(http: Client, access_token: &str) -> Result<LoginResult> {
-    let me: GoogleUserInfo = send_google_json(
-        http.get("https://www.googleapis.com/oauth2/v3/userinfo")
-            .bearer_auth(access_token),
-        "Google userinfo",
-    )
-    .await?;
+/// Called with each listed message as soon as its details are fetched, in
+/// listing order, so a caller can show rows before the whole page is done.
+/// The final [`LoginResult`] still carries the complete, grouped list.
+#[derive(Clone)]
+pub struct FetchedMessageHook(std::sync::Arc<dyn Fn(&MailMessage) + Send + Sync>);
 
-    let list: GoogleListResponse = send_google_json(
-        http.get("https://gmail.googleapis.com/gmail/v1/users/me/messages")
-            .query(&[("maxResults", MESSAGE_LIMIT)])
-            .bearer_auth(access_token),
-        "Gmail messages list",
-    )
-    .await?;
+impl std::fmt::Debug for FetchedMessageHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FetchedMessageHook")
+    }
+}
 
-    let mut messages = Vec::new();
-    for message in list.messages.unwrap_or_default() {
-        let detail: GoogleMessageResponse = send_google_json(
-            http.get(format!(
-                "https://gmail.googleapis.com/gmail/v1/users/me/messages/{}",
-                message.id
-            ))
-            .query(&[
-                ("format", "metadata"),
-                ("metadataHeaders", "Subject"),
-                ("metadataHeaders", "From"),
-                ("metadataHeaders", "Date"),
-            ])
-            .bearer_auth(access_token),
-            "Gmail message detail",
-        )
-        .await?;
+/// Runs over every fetched message before anything else sees it: before
+/// [`FetchedMessageHook`], the local cache and the returned [`LoginResult`].
+/// Meant for cheap, local rules such as adding a label or redacting text;
+/// it runs on the fetch path, so it should not block.
+#[derive(Clone)]
+pub struct MessageTransformHook(std::sync::Arc<dyn Fn(&mut MailMessage) + Send + Sync>);
 
-        let GoogleMessageResponse { payload, snippet } = detail;
-        let (subject, from, date) = extract_google_headers(payload);
-        messages.push(MailMessage {
-            subject,
-            from,
-            date,
-            body: snippet
-                .filter(|value| !value.trim().is_empty())
-                .unwrap_or_else(|| "(geen inhoud)".to_owned()),
-        });
+impl std::fmt::Debug for MessageTransformHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MessageTransformHook")
     }
+}
 
-    Ok(LoginResult {
-        provider: Provider::Google,
-        account: me.email.unwrap_or_else(|| "(onbekend account)".to_owned()),
-        messages,
-    })
+/// Engine configuration. Values set here take precedence over the matching
+/// environment variables (`MAIL_DB_PATH`, `MAIL_OAUTH_REDIRECT_URI`,
+/// `MAIL_NO_BROWSER`), which in turn take precedence over the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub app_name: String,
+    pub db_path: Option<PathBuf>,
+    /// How long a DB operation waits for another connection's write lock.
+    /// Every engine call opens its own connection and the database runs in
+    /// WAL mode, so reads never wait on a background write; only concurrent
+    /// writes are serialized by this timeout.
+    pub db_busy_timeout: Duration,
+    pub message_limit: usize,
+    pub request_timeout: Duration,
+    /// How long login waits for the browser to hit the redirect URI.
+    pub callback_timeout: Duration,
+    /// How long login waits for the callback request once connected.
+    pub callback_read_timeout: Duration,
+    /// Retries of a rate-limited request; the delay doubles after each.
+    pub rate_limit_retries: u32,
+    pub retry_base_delay: Duration,
+    pub concurrency: usize,
+    /// Number of messages at the top of a listing whose full body is fetched
+    /// right away; the rest is loaded on demand via [`Engine::fetch_message`].
+    pub eager_body_count: usize,
+    pub proxy: Option<String>,
+    pub user_agent: String,
+    pub redirect_url: Option<String>,
+    /// Open the authorize URL in the default browser during login.
+    pub open_browser: Option<bool>,
+    pub authorize_url_hook: Option<AuthorizeUrlHook>,
+    pub fetched_message_hook: Option<FetchedMessageHook>,
+    pub message_transform_hook: Option<MessageTransformHook>,
+    pub google_scopes: Option<Vec<String>>,
+    pub outlook_scopes: Option<Vec<String>>,
+    pub google_extra_headers: Vec<String>,
+    pub outlook_extra_fields: Vec<String>,
 }
 
-async fn send_google_json<T>(request: reqwest::RequestBuilder, endpoint: &str) -> Result<T>
-where
-    T: DeserializeOwned,
-{
-    let response = request
-        .send()
-        .await
-        .with_context(|| format!("{endpoint} request mislukt"))?;
-    let status = response.status();
-    let body = response
-        .text()
-        .await
-        .with_context(|| format!("{endpoint} response kon niet gelezen worden"))?;
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            app_name: "mail".to_owned(),
+            db_path: None,
+            db_busy_timeout: Duration::from_secs(DB_BUSY_TIMEOUT_SECS),
+            message_limit: MESSAGE_LIMIT,
+            request_timeout: Duration::from_secs(REQUEST_TIMEOUT_SECS),
+            callback_timeout: Duration::from_secs(CALLBACK_TIMEOUT_SECS),
+            callback_read_timeout: Duration::from_secs(CALLBACK_READ_TIMEOUT_SECS),
+            rate_limit_retries: RATE_LIMIT_RETRIES,
+            retry_base_delay: Duration::from_millis(RETRY_BASE_MS),
+            concurrency: FETCH_CONCURRENCY,
+            eager_body_count: EAGER_BODY_COUNT,
+            proxy: None,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            redirect_url: None,
+            open_browser: None,
+            authorize_url_hook: None,
+            fetched_message_hook: None,
+            message_transform_hook: None,
+            google_scopes: None,
+            outlook_scopes: None,
+            google_extra_headers: Vec::new(),
+            outlook_extra_fields: Vec::new(),
+        }
+    }
+}
 
-    if !status.is_success() {
-        let detail = google_error_detail(&body);
-        let mut msg = format!("{endpoint} gaf HTTP {status}");
-        if let Some(detail) = detail {
-            msg.push_str(&format!(": {detail}"));
-            if let Some(hint) = google_error_hint(&detail) {
-                msg.push_str(&format!("\nTip: {hint}"));
-            }
-        } else {
-            let raw = body.lines().next().unwrap_or("").trim();
-            if !raw.is_empty() {
-                msg.push_str(&format!(": {raw}"));
+impl EngineConfig {
+    /// The defaults with the `MAIL_*` tuning variables from the environment
+    /// applied; see [`EngineConfig::with_overrides`].
+    pub fn from_env() -> Self {
+        Self::default().with_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Applies the tuning overrides that `lookup` returns for
+    /// `MAIL_MESSAGE_LIMIT`, `MAIL_REQUEST_TIMEOUT_SECS`,
+    /// `MAIL_CALLBACK_TIMEOUT_SECS`, `MAIL_CALLBACK_READ_TIMEOUT_SECS`,
+    /// `MAIL_RETRY_COUNT` and `MAIL_RETRY_BASE_MS`. Unparsable values are
+    /// logged and ignored.
+    pub fn with_overrides(mut self, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let number = |name: &str| -> Option<u64> {
+            let value = lookup(name)?;
+            match value.trim().parse() {
+                Ok(number) => Some(number),
+                Err(_) => {
+                    warn!(name, value, "ongeldige waarde genegeerd");
+                    None
+                }
             }
+        };
+        if let Some(limit) = number("MAIL_MESSAGE_LIMIT") {
+            self.message_limit = (limit as usize).max(1);
+        }
+        if let Some(secs) = number("MAIL_REQUEST_TIMEOUT_SECS") {
+            self.request_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = number("MAIL_CALLBACK_TIMEOUT_SECS") {
+            self.callback_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = number("MAIL_CALLBACK_READ_TIMEOUT_SECS") {
+            self.callback_read_timeout = Duration::from_secs(secs);
         }
-        bail!(msg);
+        if let Some(retries) = number("MAIL_RETRY_COUNT") {
+            self.rate_limit_retries = retries.min(u64::from(u32::MAX)) as u32;
+        }
+        if let Some(millis) = number("MAIL_RETRY_BASE_MS") {
+            self.retry_base_delay = Duration::from_millis(millis);
+        }
+        self
     }
 
-    serde_json::from_str(&body).with_context(|| format!("{endpoint} response heeft ongeldige JSON"))
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.rate_limit_retries,
+            base_delay: self.retry_base_delay,
+        }
+    }
 }
 
-fn google_error_detail(body: &str) -> Option<String> {
-    let parsed: GoogleErrorEnvelope = serde_json::from_str(body).ok()?;
-    let error = parsed.error?;
-    let mut detail = error.message?;
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+}
 
-    if let Some(first_reason) = error
-        .errors
-        .and_then(|items| items.into_iter().find_map(|item| item.reason))
-    {
-        detail.push_str(&format!(" (reason: {first_reason})"));
-    }
+/// Starts from [`EngineConfig::from_env`], so builder calls take precedence
+/// over env variables, which take precedence over the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+}
 
-    Some(detail)
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            config: EngineConfig::from_env(),
+        }
+    }
 }
 
-fn google_error_hint(detail: &str) -> Option<&'static str> {
-    let lowered = detail.to_ascii_lowercase();
+impl EngineBuilder {
+    pub fn app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.config.app_name = app_name.into();
+        self
+    }
 
-    if lowered.contains("has not been used in project") || lowered.contains("is disabled") {
-        return Some(
-            "Enable de Gmail API in hetzelfde Google Cloud project als deze OAuth client.",
-        );
+    pub fn db_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.db_path = Some(path.into());
+        self
     }
 
-    if lowered.contains("insufficient authentication scopes")
-        || lowered.contains("insufficientpermissions")
-        || lowered.contains("insufficient permissions")
-    {
-        return Some(
-            "Verwijder app-toegang in je Google account en log opnieuw in zodat gmail.readonly opnieuw wordt toegekend.",
-        );
+    pub fn db_busy_timeout(mut self, timeout: Duration) -> Self {
+        self.config.db_busy_timeout = timeout;
+        self
     }
 
-    if lowered.contains("access blocked") || lowered.contains("access_not_configured") {
-        return Some(
-            "Controleer OAuth consent screen + test users en bevestig dat deze account toegang heeft tot de app.",
-        );
+    pub fn message_limit(mut self, limit: usize) -> Self {
+        self.config.message_limit = limit.max(1);
+        self
     }
 
-    None
-}
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
 
-async fn fetch_outlook_inbox(http: Client, access_token: &str) -> Result<LoginResult> {
-    let me: GraphMeResponse = http
-        .get("https://graph.microsoft.com/v1.0/me?$select=mail,userPrincipalName")
-        .bearer_auth(access_token)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    pub fn callback_timeout(mut self, timeout: Duration) -> Self {
+        self.config.callback_timeout = timeout;
+        self
+    }
 
-    let inbox: GraphInboxResponse = http
-        .get("https://graph.microsoft.com/v1.0/me/messages")
-        .query(&[
-            ("$top", MESSAGE_LIMIT.to_string()),
-            (
-                "$select",
-                "subject,from,receivedDateTime,bodyPreview".to_owned(),
-            ),
-            ("$orderby", "receivedDateTime desc".to_owned()),
-        ])
-        .bearer_auth(access_token)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    pub fn callback_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.callback_read_timeout = timeout;
+        self
+    }
 
-    let messages = inbox
-        .value
-        .into_iter()
-        .map(|entry| MailMessage {
-            subject: entry
-                .subject
-                .unwrap_or_else(|| "(geen onderwerp)".to_owned()),
-            from: entry
-                .from
-                .and_then(|f| f.email_address)
-                .and_then(|a| a.address)
-                .unwrap_or_else(|| "(onbekend)".to_owned()),
-            date: entry
-                .received_date_time
-                .unwrap_or_else(|| "(onbekend)".to_owned()),
-            body: entry
-                .body_preview
-                .filter(|value| !value.trim().is_empty())
-                .unwrap_or_else(|| "(geen inhoud)".to_owned()),
-        })
-        .collect();
+    pub fn rate_limit_retries(mut self, retries: u32) -> Self {
+        self.config.rate_limit_retries = retries;
+        self
+    }
 
-    Ok(LoginResult {
-        provider: Provider::Outlook,
-        account: me
-            .mail
-            .or(me.user_principal_name)
-            .unwrap_or_else(|| "(onbekend account)".to_owned()),
-        messages,
-    })
-}
+    pub fn retry_base_delay(mut self, delay: Duration) -> Self {
+        self.config.retry_base_delay = delay;
+        self
+    }
 
-fn extract_google_headers(payload: Option<GooglePayload>) -> (String, String, String) {
-    let mut subject = "(geen onderwerp)".to_owned();
-    let mut from = "(onbekend)".to_owned();
-    let mut date = "(onbekend)".to_owned();
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = concurrency.max(1);
+        self
+    }
 
-    if let Some(payload) = payload {
-        for header in payload.headers.unwrap_or_default() {
-            match header.name.as_str() {
-                "Subject" => subject = header.value,
-                "From" => from = header.value,
-                "Date" => date = header.value,
-                _ => {}
-            }
-        }
+    pub fn eager_body_count(mut self, count: usize) -> Self {
+        self.config.eager_body_count = count;
+        self
     }
 
-    (subject, from, date)
-}
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.config.proxy = Some(proxy.into());
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleUserInfo {
-    email: Option<String>,
-}
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.config.user_agent = user_agent.into();
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleListResponse {
-    messages: Option<Vec<GoogleMessageRef>>,
-}
+    pub fn redirect_url(mut self, redirect_url: impl Into<String>) -> Self {
+        self.config.redirect_url = Some(redirect_url.into());
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleMessageRef {
-    id: String,
-}
+    pub fn open_browser(mut self, open_browser: bool) -> Self {
+        self.config.open_browser = Some(open_browser);
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleMessageResponse {
-    payload: Option<GooglePayload>,
-    snippet: Option<String>,
-}
+    /// See [`AuthorizeUrlHook`].
+    pub fn on_authorize_url(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.config.authorize_url_hook = Some(AuthorizeUrlHook(std::sync::Arc::new(hook)));
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GooglePayload {
-    headers: Option<Vec<GoogleHeader>>,
-}
+    /// See [`FetchedMessageHook`].
+    pub fn on_fetched_message(
+        mut self,
+        hook: impl Fn(&MailMessage) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.fetched_message_hook = Some(FetchedMessageHook(std::sync::Arc::new(hook)));
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleHeader {
-    name: String,
-    value: String,
-}
+    /// See [`MessageTransformHook`].
+    pub fn on_message(mut self, hook: impl Fn(&mut MailMessage) + Send + Sync + 'static) -> Self {
+        self.config.message_transform_hook = Some(MessageTransformHook(std::sync::Arc::new(hook)));
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleErrorEnvelope {
-    error: Option<GoogleErrorResponse>,
-}
+    pub fn scopes(mut self, provider: Provider, scopes: Vec<String>) -> Self {
+        match provider {
+            Provider::Google => self.config.google_scopes = Some(scopes),
+            Provider::Outlook => self.config.outlook_scopes = Some(scopes),
+        }
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleErrorResponse {
-    message: Option<String>,
-    errors: Option<Vec<GoogleErrorItem>>,
-}
+    /// Extra Gmail metadata headers or Graph `$select` fields to request on
+    /// every fetch; their values end up in [`MailMessage::extra`].
+    pub fn extra_fields(mut self, provider: Provider, fields: Vec<String>) -> Self {
+        match provider {
+            Provider::Google => self.config.google_extra_headers = fields,
+            Provider::Outlook => self.config.outlook_extra_fields = fields,
+        }
+        self
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoogleErrorItem {
-    reason: Option<String>,
+    pub fn build(self) -> Engine {
+        Engine {
+            config: self.config,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct GraphMeResponse {
-    mail: Option<String>,
-    #[serde(rename = "userPrincipalName")]
-    user_principal_name: Option<String>,
+pub struct Engine {
+    config: EngineConfig,
 }
 
-#[derive(Debug, Deserialize)]
-struct GraphInboxResponse {
-    value: Vec<GraphMessage>,
-}
+impl Engine {
+    pub fn new(app_name: impl Into<String>) -> Self {
+        Self::builder().app_name(app_name).build()
+    }
 
-#[derive(Debug, Deserialize)]
-struct GraphMessage {
-    subject: Option<String>,
-    from: Option<GraphFrom>,
-    #[serde(rename = "receivedDateTime")]
-    received_date_time: Option<String>,
-    #[serde(rename = "bodyPreview")]
-    body_preview: Option<String>,
-}
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
 
-#[derive(Debug, Deserialize)]
-struct GraphFrom {
-    #[serde(rename = "emailAddress")]
-    email_address: Option<GraphEmailAddress>,
-}
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
 
-#[derive(Debug, Deserialize)]
-struct GraphEmailAddress {
-    address: Option<String>,
+    pub async fn start(&self) -> Result<()> {
+        info!("engine start: {}", self.config.app_name);
+        Ok(())
+    }
+
+    /// Combines a fresh listing with the messages shown before, newest first.
+    /// Cached messages at least as new as the oldest fresh one but missing
+    /// from the listing were archived or deleted on the server and are dropped.
+    pub fn merge_messages(cached: Vec<MailMessage>, fresh: Vec<MailMessage>) -> Vec<MailMessage> {
+        let mut seen: HashSet<MessageKey> = HashSet::with_capacity(fresh.len() + cached.len());
+        let mut merged = Vec::with_capacity(fresh.len() + cached.len());
+        let window_start = fresh.iter().filter_map(MailMessage::timestamp).min();
+
+        for message in fresh {
+            seen.insert(message.key());
+            merged.push(message);
+        }
+        for message in cached {
+            let in_window = window_start
+                .zip(message.timestamp())
+                .is_some_and(|(start, timestamp)| timestamp >= start);
+            if !in_window && seen.insert(message.key()) {
+                merged.push(message);
+            }
+        }
+
+        merged.sort_by_key(|message| std::cmp::Reverse(message.timestamp()));
+        merged
+    }
+
+    pub async fn load_oauth_settings(&self) -> Result<SavedOAuthSettings> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT provider, client_id, COALESCE(client_secret, ''), COALESCE(tenant, '')
+                 FROM oauth_settings",
+                (),
+            )
+            .await?;
+
+        let mut settings = SavedOAuthSettings::default();
+
+        while let Some(row) = rows.next().await? {
+            let provider_raw: String = row.get(0)?;
+            let client_id: String = row.get(1)?;
+            let client_secret_raw: String = row.get(2)?;
+            let tenant_raw: String = row.get(3)?;
+
+            let credentials = ProviderCredentials {
+                client_id,
+                client_secret: empty_to_none(client_secret_raw),
+                tenant: empty_to_none(tenant_raw),
+            };
+
+            match Provider::from_key(&provider_raw) {
+                Some(Provider::Google) => settings.google = Some(credentials),
+                Some(Provider::Outlook) => settings.outlook = Some(credentials),
+                None => {}
+            }
+        }
+
+        settings.default_provider = load_app_setting(&conn, DEFAULT_PROVIDER_KEY)
+            .await?
+            .as_deref()
+            .and_then(Provider::from_key);
+        settings.last_provider = load_app_setting(&conn, LAST_PROVIDER_KEY)
+            .await?
+            .as_deref()
+            .and_then(Provider::from_key);
+        settings.load_remote_images = load_app_setting(&conn, LOAD_REMOTE_IMAGES_KEY)
+            .await?
+            .as_deref()
+            == Some("1");
+        settings.auto_mark_read_on_open = load_app_setting(&conn, AUTO_MARK_READ_KEY)
+            .await?
+            .as_deref()
+            != Some("0");
+        settings.mark_read_delay = Duration::from_secs(
+            load_app_setting(&conn, MARK_READ_DELAY_KEY)
+                .await?
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0),
+        );
+        settings.prefer_html = load_prefer_html(&conn).await?;
+        settings.preview_length = load_app_setting(&conn, PREVIEW_LENGTH_KEY)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_PREVIEW_LENGTH);
+        settings.list_date_display = load_app_setting(&conn, LIST_DATE_DISPLAY_KEY)
+            .await?
+            .as_deref()
+            .and_then(DateDisplay::from_key)
+            .unwrap_or(DateDisplay::Relative);
+        settings.detail_date_display = load_app_setting(&conn, DETAIL_DATE_DISPLAY_KEY)
+            .await?
+            .as_deref()
+            .and_then(DateDisplay::from_key)
+            .unwrap_or(DateDisplay::Absolute);
+        settings.message_sort = load_app_setting(&conn, MESSAGE_SORT_KEY)
+            .await?
+            .as_deref()
+            .and_then(SortKey::from_key)
+            .unwrap_or_default();
+        settings.sort_ascending = load_app_setting(&conn, SORT_ASCENDING_KEY)
+            .await?
+            .as_deref()
+            == Some("1");
+        settings.plaintext_mode = load_app_setting(&conn, PLAINTEXT_MODE_KEY)
+            .await?
+            .as_deref()
+            == Some("1");
+        settings.font_scale = load_app_setting(&conn, FONT_SCALE_KEY)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .filter(|scale: &f32| scale.is_finite() && *scale > 0.0)
+            .unwrap_or(1.0);
+        settings.offline_bodies = load_app_setting(&conn, OFFLINE_BODIES_KEY)
+            .await?
+            .as_deref()
+            == Some("1");
+        settings.cache_message_limit = load_cache_limit(&conn).await?;
+
+        let mut rows = conn
+            .query(
+                "SELECT sender FROM trusted_image_senders ORDER BY sender",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            settings.trusted_image_senders.push(row.get(0)?);
+        }
+
+        Ok(settings)
+    }
+
+    pub async fn set_load_remote_images(&self, enabled: bool) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(
+            &conn,
+            LOAD_REMOTE_IMAGES_KEY,
+            if enabled { "1" } else { "0" },
+        )
+        .await
+    }
+
+    pub async fn set_auto_mark_read_on_open(&self, enabled: bool) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, AUTO_MARK_READ_KEY, if enabled { "1" } else { "0" }).await
+    }
+
+    pub async fn set_mark_read_delay(&self, delay: Duration) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, MARK_READ_DELAY_KEY, &delay.as_secs().to_string()).await
+    }
+
+    pub async fn set_prefer_html(&self, enabled: bool) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, PREFER_HTML_KEY, if enabled { "1" } else { "0" }).await
+    }
+
+    pub async fn set_preview_length(&self, max_chars: usize) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, PREVIEW_LENGTH_KEY, &max_chars.to_string()).await
+    }
+
+    pub async fn set_date_display(&self, list: DateDisplay, detail: DateDisplay) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, LIST_DATE_DISPLAY_KEY, list.as_key()).await?;
+        save_app_setting(&conn, DETAIL_DATE_DISPLAY_KEY, detail.as_key()).await
+    }
+
+    pub async fn set_message_sort(&self, key: SortKey, ascending: bool) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, MESSAGE_SORT_KEY, key.as_key()).await?;
+        save_app_setting(&conn, SORT_ASCENDING_KEY, if ascending { "1" } else { "0" }).await
+    }
+
+    pub async fn set_accessibility(&self, plaintext_mode: bool, font_scale: f32) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(
+            &conn,
+            PLAINTEXT_MODE_KEY,
+            if plaintext_mode { "1" } else { "0" },
+        )
+        .await?;
+        save_app_setting(&conn, FONT_SCALE_KEY, &font_scale.to_string()).await
+    }
+
+    pub async fn set_offline_sync(&self, offline_bodies: bool, cache_limit: usize) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(
+            &conn,
+            OFFLINE_BODIES_KEY,
+            if offline_bodies { "1" } else { "0" },
+        )
+        .await?;
+        save_app_setting(&conn, CACHE_LIMIT_KEY, &cache_limit.max(1).to_string()).await
+    }
+
+    pub async fn trust_image_sender(&self, sender: &str) -> Result<String> {
+        let sender = sender_address(sender);
+        if sender.is_empty() {
+            bail!("afzender heeft geen e-mailadres");
+        }
+
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO trusted_image_senders (sender) VALUES (?1)
+             ON CONFLICT(sender) DO NOTHING",
+            libsql::params![sender.as_str()],
+        )
+        .await?;
+        Ok(sender)
+    }
+
+    /// Hides the sender's messages locally, across providers; returns the
+    /// stored address.
+    pub async fn block_sender(&self, sender: &str) -> Result<String> {
+        let sender = sender_address(sender);
+        if sender.is_empty() {
+            bail!("afzender heeft geen e-mailadres");
+        }
+
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO blocked_senders (sender) VALUES (?1)
+             ON CONFLICT(sender) DO NOTHING",
+            libsql::params![sender.as_str()],
+        )
+        .await?;
+        Ok(sender)
+    }
+
+    pub async fn unblock_sender(&self, sender: &str) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "DELETE FROM blocked_senders WHERE sender = ?1",
+            libsql::params![sender_address(sender)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_blocked(&self) -> Result<Vec<String>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query("SELECT sender FROM blocked_senders ORDER BY sender", ())
+            .await?;
+        let mut senders = Vec::new();
+        while let Some(row) = rows.next().await? {
+            senders.push(row.get(0)?);
+        }
+        Ok(senders)
+    }
+
+    pub async fn set_default_provider(&self, provider: Option<Provider>) -> Result<()> {
+        let conn = self.open_conn().await?;
+        match provider {
+            Some(provider) => {
+                save_app_setting(&conn, DEFAULT_PROVIDER_KEY, provider.as_key()).await
+            }
+            None => clear_app_setting(&conn, DEFAULT_PROVIDER_KEY).await,
+        }
+    }
+
+    /// Stores the OAuth client after a format check of the client id. An id
+    /// that only looks unusual is saved anyway and the warning returned.
+    pub async fn save_provider_credentials(
+        &self,
+        provider: Provider,
+        credentials: ProviderCredentials,
+    ) -> Result<Option<String>> {
+        let client_id = credentials.client_id.trim();
+        let warning = check_client_id(provider, client_id)?;
+        let tenant = normalized_secret(credentials.tenant);
+        if let Some(tenant) = &tenant {
+            validate_tenant(tenant)?;
+        }
+
+        let conn = self.open_conn().await?;
+
+        conn.execute(
+            "INSERT INTO oauth_settings (provider, client_id, client_secret, tenant)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(provider) DO UPDATE SET
+                client_id = excluded.client_id,
+                client_secret = excluded.client_secret,
+                tenant = excluded.tenant",
+            libsql::params![
+                provider.as_key(),
+                client_id.to_owned(),
+                normalized_secret(credentials.client_secret),
+                tenant
+            ],
+        )
+        .await?;
+
+        Ok(warning)
+    }
+
+    pub async fn login_and_fetch(&self, provider: Provider) -> Result<LoginResult> {
+        self.login(provider, LoginMode::Resume).await
+    }
+
+    pub async fn login_with_other_account(&self, provider: Provider) -> Result<LoginResult> {
+        self.login(provider, LoginMode::SelectAccount).await
+    }
+
+    /// Runs the interactive flow with a fresh consent prompt even when a
+    /// refresh token is stored, e.g. after a scope change or revoked access.
+    pub async fn reauthorize(&self, provider: Provider) -> Result<LoginResult> {
+        self.login(provider, LoginMode::Consent).await
+    }
+
+    async fn login(&self, provider: Provider, mode: LoginMode) -> Result<LoginResult> {
+        info!("starting OAuth for provider={}", provider.label());
+        let credentials = self.require_provider_credentials(provider).await?;
+
+        let config = self.provider_config(provider, credentials);
+        let redirect_url = self.redirect_url()?;
+        let redirect_target = RedirectTarget::from_url(&redirect_url)?;
+        let oauth = build_oauth_client(&config, redirect_url)?;
+        let stored_refresh = match mode {
+            LoginMode::Resume => self.load_refresh_token(provider).await?,
+            LoginMode::SelectAccount | LoginMode::Consent => None,
+        };
+
+        if let Some(refresh_token) = stored_refresh.as_deref() {
+            match exchange_refresh_token(
+                &self.oauth_http_client()?,
+                &oauth,
+                refresh_token.to_owned(),
+            )
+            .await
+            {
+                Ok(token_set) => {
+                    if let Some(new_refresh_token) = token_set.refresh_token {
+                        self.save_refresh_token(provider, &new_refresh_token)
+                            .await?;
+                    }
+                    if let Some(scopes) = &token_set.scopes {
+                        self.save_granted_scopes(provider, scopes).await?;
+                    }
+                    let result = self
+                        .fetch_inbox(
+                            &config,
+                            &token_set.access_token,
+                            &DateRange::default(),
+                            None,
+                        )
+                        .await?;
+                    self.remember_session(&result).await?;
+                    return Ok(result);
+                }
+                Err(error) => {
+                    info!(
+                        "stored refresh token rejected for provider={}: {error:#}",
+                        provider.label()
+                    );
+                    self.clear_refresh_token(provider).await?;
+                }
+            }
+        }
+
+        let (auth_url, csrf_state, pkce_verifier) =
+            authorize_request(&oauth, &config, mode, stored_refresh.is_none());
+        self.present_authorize_url(&auth_url)?;
+
+        let code = wait_for_oauth_code(
+            &redirect_target,
+            &csrf_state,
+            self.config.callback_timeout,
+            self.config.callback_read_timeout,
+        )
+        .await?;
+        self.complete_login(&config, &oauth, code, pkce_verifier)
+            .await
+    }
+
+    /// Starts a login where the user pastes the redirect back into the app
+    /// instead of the engine listening on the loopback port, e.g. when the
+    /// port is blocked or the browser runs on another machine.
+    pub async fn start_manual_login(&self, provider: Provider) -> Result<ManualLogin> {
+        info!("starting manual OAuth for provider={}", provider.label());
+        let credentials = self.require_provider_credentials(provider).await?;
+        let config = self.provider_config(provider, credentials);
+        let oauth = build_oauth_client(&config, self.redirect_url()?)?;
+        let (authorize_url, state, pkce_verifier) =
+            authorize_request(&oauth, &config, LoginMode::Consent, true);
+        Ok(ManualLogin {
+            provider,
+            authorize_url,
+            state,
+            pkce_verifier: pkce_verifier.secret().clone(),
+        })
+    }
+
+    /// Finishes a [`Engine::start_manual_login`] flow with what the user
+    /// pasted: the full redirect URL from the address bar, its query string
+    /// or just the code.
+    pub async fn finish_manual_login(
+        &self,
+        login: &ManualLogin,
+        pasted: &str,
+    ) -> Result<LoginResult> {
+        let code = parse_pasted_code(pasted, &login.state)?;
+        let credentials = self.require_provider_credentials(login.provider).await?;
+        let config = self.provider_config(login.provider, credentials);
+        let oauth = build_oauth_client(&config, self.redirect_url()?)?;
+        self.complete_login(
+            &config,
+            &oauth,
+            code,
+            PkceCodeVerifier::new(login.pkce_verifier.clone()),
+        )
+        .await
+    }
+
+    async fn complete_login(
+        &self,
+        config: &ProviderConfig,
+        oauth: &OAuthClient,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<LoginResult> {
+        let provider = config.provider;
+        let token_set = exchange_token(&self.oauth_http_client()?, oauth, code, pkce_verifier)
+            .await
+            .map_err(|error| with_token_exchange_hint(provider, error))?;
+
+        if let Some(refresh_token) = token_set.refresh_token {
+            self.save_refresh_token(provider, &refresh_token).await?;
+        }
+        // The browser login may have picked a different account.
+        self.forget_account_email(provider).await?;
+        if let Some(scopes) = &token_set.scopes {
+            self.save_granted_scopes(provider, scopes).await?;
+        }
+
+        let result = self
+            .fetch_inbox(config, &token_set.access_token, &DateRange::default(), None)
+            .await?;
+        self.remember_session(&result).await?;
+        Ok(result)
+    }
+
+    pub async fn try_restore_session(&self, provider: Provider) -> Result<Option<LoginResult>> {
+        let refresh_token = match self.load_refresh_token(provider).await? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let credentials = self.require_provider_credentials(provider).await?;
+        let config = self.provider_config(provider, credentials);
+        let oauth = build_oauth_client(&config, self.redirect_url()?)?;
+        let token_set =
+            match exchange_refresh_token(&self.oauth_http_client()?, &oauth, refresh_token).await {
+                Ok(token_set) => token_set,
+                Err(error) if requires_interaction(&error) => {
+                    return Err(interaction_required(provider, error));
+                }
+                Err(error) if is_token_expired(&error) => {
+                    return Err(self.token_expired(provider, error).await);
+                }
+                Err(error) => {
+                    info!(
+                        "session restore failed for provider={}: {error:#}",
+                        provider.label()
+                    );
+                    return Err(error.context(format!(
+                        "sessie bij {} kon niet worden hersteld, probeer het opnieuw",
+                        provider.label()
+                    )));
+                }
+            };
+
+        if let Some(new_refresh_token) = token_set.refresh_token {
+            self.save_refresh_token(provider, &new_refresh_token)
+                .await?;
+        }
+        if let Some(scopes) = &token_set.scopes {
+            self.save_granted_scopes(provider, scopes).await?;
+        }
+
+        let result = self
+            .fetch_inbox(
+                &config,
+                &token_set.access_token,
+                &DateRange::default(),
+                None,
+            )
+            .await?;
+        self.remember_session(&result).await?;
+        Ok(Some(result))
+    }
+
+    pub async fn list_accounts(&self) -> Result<Vec<Account>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT provider, email FROM accounts ORDER BY provider, email",
+                (),
+            )
+            .await?;
+
+        let mut accounts: Vec<Account> = Vec::new();
+        let mut merged = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let provider_raw: String = row.get(0)?;
+            if let Some(provider) = Provider::from_key(&provider_raw) {
+                let account = Account {
+                    provider,
+                    email: row.get(1)?,
+                };
+                if accounts.iter().any(|known| known.same_mailbox(&account)) {
+                    merged.push(account);
+                } else {
+                    accounts.push(account);
+                }
+            }
+        }
+        // Addresses differing only in case were stored before emails were
+        // compared case-insensitively; keep one entry per mailbox.
+        for duplicate in merged {
+            info!(account = %duplicate, "merging duplicate account entry");
+            conn.execute(
+                "DELETE FROM accounts WHERE provider = ?1 AND email = ?2",
+                libsql::params![duplicate.provider.as_key(), duplicate.email.as_str()],
+            )
+            .await?;
+        }
+
+        Ok(accounts)
+    }
+
+    pub async fn switch_account(&self, account: &Account) -> Result<LoginResult> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT refresh_token FROM accounts WHERE provider = ?1 AND email = ?2",
+                libsql::params![account.provider.as_key(), account.email.as_str()],
+            )
+            .await?;
+        let Some(row) = rows.next().await? else {
+            bail!("account {account} is niet bekend, log opnieuw in");
+        };
+        let refresh_token: String = row.get(0)?;
+        self.save_refresh_token(account.provider, &refresh_token)
+            .await?;
+        self.forget_account_email(account.provider).await?;
+
+        match self.try_restore_session(account.provider).await {
+            Ok(Some(result)) => Ok(result),
+            Ok(None) => bail!("account {account} is niet bekend, log opnieuw in"),
+            Err(error) => {
+                if let Some(EngineError::TokenExpired { .. }) = error.downcast_ref::<EngineError>()
+                {
+                    conn.execute(
+                        "DELETE FROM accounts WHERE provider = ?1 AND email = ?2",
+                        libsql::params![account.provider.as_key(), account.email.as_str()],
+                    )
+                    .await?;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    pub async fn send_message(&self, provider: Provider, message: OutgoingMessage) -> Result<()> {
+        validate_outgoing(provider, &message)?;
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                send_google_message(http, &access_token, self.config.retry_policy(), &message).await
+            }
+            Provider::Outlook => send_outlook_message(http, &access_token, &message).await,
+        }
+    }
+
+    pub async fn save_draft(&self, provider: Provider, draft: Draft) -> Result<String> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                save_google_draft(http, &access_token, self.config.retry_policy(), &draft).await
+            }
+            Provider::Outlook => save_outlook_draft(http, &access_token, &draft).await,
+        }
+    }
+
+    pub async fn list_drafts(&self, provider: Provider) -> Result<Vec<Draft>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                list_google_drafts(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    self.config.message_limit,
+                )
+                .await
+            }
+            Provider::Outlook => {
+                list_outlook_drafts(http, &access_token, self.config.message_limit).await
+            }
+        }
+    }
+
+    pub async fn delete_draft(&self, provider: Provider, draft_id: &str) -> Result<()> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                send_google_empty(
+                    self.config.retry_policy(),
+                    http.delete(format!(
+                        "https://gmail.googleapis.com/gmail/v1/users/me/drafts/{draft_id}"
+                    ))
+                    .bearer_auth(&access_token),
+                    "Gmail drafts delete",
+                )
+                .await
+            }
+            Provider::Outlook => delete_outlook_message(http, &access_token, draft_id).await,
+        }
+    }
+
+    pub async fn list_folders(&self, provider: Provider) -> Result<Vec<Folder>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                list_google_folders(http, &access_token, self.config.retry_policy()).await
+            }
+            Provider::Outlook => list_outlook_folders(http, &access_token).await,
+        }
+    }
+
+    pub async fn list_labels(&self, provider: Provider) -> Result<Vec<MailLabel>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                list_google_labels(http, &access_token, self.config.retry_policy()).await
+            }
+            Provider::Outlook => list_outlook_categories(http, &access_token).await,
+        }
+    }
+
+    /// Adds and removes labels (Gmail) or categories (Outlook) on one message.
+    pub async fn modify_labels(
+        &self,
+        provider: Provider,
+        message_id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<()> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    add.iter().map(String::as_str).collect(),
+                    remove.iter().map(String::as_str).collect(),
+                )
+                .await?;
+                Ok(())
+            }
+            Provider::Outlook => {
+                modify_outlook_categories(http, &access_token, message_id, add, remove).await
+            }
+        }
+    }
+
+    pub async fn count_messages(&self, provider: Provider, folder_id: &str) -> Result<u64> {
+        Ok(self.folder_counts(provider, folder_id).await?.total)
+    }
+
+    /// Total and unread counts in one request (Gmail label / Graph mailFolder).
+    pub async fn folder_counts(&self, provider: Provider, folder_id: &str) -> Result<FolderCounts> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                let label: GoogleLabelCounts = send_google_json(
+                    self.config.retry_policy(),
+                    http.get(format!(
+                        "https://gmail.googleapis.com/gmail/v1/users/me/labels/{folder_id}"
+                    ))
+                    .bearer_auth(&access_token),
+                    "Gmail label",
+                )
+                .await?;
+                Ok(FolderCounts {
+                    total: label.messages_total.unwrap_or_default(),
+                    unread: label.messages_unread.unwrap_or_default(),
+                })
+            }
+            Provider::Outlook => {
+                let folder: GraphFolderCounts = http
+                    .get(format!(
+                        "https://graph.microsoft.com/v1.0/me/mailFolders/{folder_id}"
+                    ))
+                    .query(&[("$select", "totalItemCount,unreadItemCount")])
+                    .bearer_auth(&access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(FolderCounts {
+                    total: folder.total_item_count.unwrap_or_default(),
+                    unread: folder.unread_item_count.unwrap_or_default(),
+                })
+            }
+        }
+    }
+
+    /// Storage used by the account, or `None` when the granted scopes don't
+    /// give access to it.
+    pub async fn storage_usage(&self, provider: Provider) -> Result<Option<StorageUsage>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let request = match provider {
+            Provider::Google => http
+                .get("https://www.googleapis.com/drive/v3/about")
+                .query(&[("fields", "storageQuota")]),
+            Provider::Outlook => http
+                .get("https://graph.microsoft.com/v1.0/me/mailFolders")
+                .query(&[
+                    ("$top", "100"),
+                    ("$select", "id"),
+                    ("includeHiddenFolders", "true"),
+                    ("$expand", GRAPH_MESSAGE_SIZE_EXPAND),
+                ]),
+        };
+        let response = request.bearer_auth(&access_token).send().await?;
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+        ) {
+            info!(
+                "storage usage not available for provider={}: {}",
+                provider.as_key(),
+                response.status()
+            );
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+
+        let usage = match provider {
+            Provider::Google => {
+                let about: GoogleDriveAbout = response.json().await?;
+                let quota = about.storage_quota.unwrap_or_default();
+                quota
+                    .usage
+                    .and_then(|used| used.parse().ok())
+                    .map(|used| StorageUsage {
+                        used,
+                        total: quota.limit.and_then(|limit| limit.parse().ok()),
+                    })
+            }
+            Provider::Outlook => {
+                let folders: GraphFolderSizes = response.json().await?;
+                let used = folders
+                    .value
+                    .into_iter()
+                    .flat_map(|folder| folder.single_value_extended_properties.unwrap_or_default())
+                    .filter(|property| {
+                        property
+                            .id
+                            .eq_ignore_ascii_case(GRAPH_MESSAGE_SIZE_PROPERTY)
+                    })
+                    .filter_map(|property| property.value.parse::<u64>().ok())
+                    .sum();
+                Some(StorageUsage { used, total: None })
+            }
+        };
+        Ok(usage)
+    }
+
+    pub async fn fetch_inbox_in_range(
+        &self,
+        provider: Provider,
+        range: &DateRange,
+    ) -> Result<LoginResult> {
+        let (config, access_token) = self.authorized_session(provider).await?;
+        self.fetch_inbox(&config, &access_token, range, None).await
+    }
+
+    /// Fetches the newest messages in one folder: a Gmail label id or a Graph
+    /// mailFolder id / well-known name.
+    pub async fn fetch_folder(
+        &self,
+        provider: Provider,
+        folder_id: &str,
+        unread_only: bool,
+    ) -> Result<LoginResult> {
+        let (config, access_token) = self.authorized_session(provider).await?;
+        self.fetch_inbox(
+            &config,
+            &access_token,
+            &DateRange::default().with_unread_only(unread_only),
+            Some(folder_id),
+        )
+        .await
+    }
+
+    /// The newest messages of the inbox or `folder` without fetching any body
+    /// and without touching the cache. Outlook returns everything in the list
+    /// call; Gmail only lists ids, so this still costs one `format=metadata`
+    /// request per message (run with the configured concurrency).
+    pub async fn list_messages_metadata_only(
+        &self,
+        provider: Provider,
+        folder: Option<&str>,
+    ) -> Result<Vec<MessageSummary>> {
+        let (config, access_token) = self.authorized_session(provider).await?;
+        let result = self
+            .fetch_page(
+                &config,
+                &access_token,
+                &DateRange::default(),
+                folder,
+                None,
+                0,
+            )
+            .await?;
+        Ok(result
+            .messages
+            .into_iter()
+            .map(MessageSummary::from)
+            .collect())
+    }
+
+    pub async fn fetch_message(&self, provider: Provider, message_id: &str) -> Result<MailMessage> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let mut message = match provider {
+            Provider::Google => {
+                GmailApi {
+                    http,
+                    retry: self.config.retry_policy(),
+                    access_token: &access_token,
+                    extra_headers: &self.config.google_extra_headers,
+                    prefer_html: load_prefer_html(&self.open_conn().await?).await?,
+                }
+                .get_message(message_id)
+                .await
+            }
+            Provider::Outlook => {
+                GraphApi {
+                    http,
+                    access_token: &access_token,
+                    extra_fields: &self.config.outlook_extra_fields,
+                }
+                .get_message(message_id)
+                .await
+            }
+        }?;
+        if let Some(MessageTransformHook(hook)) = &self.config.message_transform_hook {
+            hook(&mut message);
+        }
+        if let Err(error) = self.cache_message_body(provider, &message).await {
+            warn!(error = %error, "message cache update failed");
+        }
+        Ok(message)
+    }
+
+    /// Every message in `conversation_id`, oldest first, without bodies;
+    /// load those per message with [`Engine::fetch_message`].
+    pub async fn fetch_thread(
+        &self,
+        provider: Provider,
+        conversation_id: &str,
+    ) -> Result<Vec<MailMessage>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let mut messages = match provider {
+            Provider::Google => {
+                GmailApi {
+                    http,
+                    retry: self.config.retry_policy(),
+                    access_token: &access_token,
+                    extra_headers: &self.config.google_extra_headers,
+                    prefer_html: false,
+                }
+                .list_thread(conversation_id)
+                .await
+            }
+            Provider::Outlook => {
+                GraphApi {
+                    http,
+                    access_token: &access_token,
+                    extra_fields: &self.config.outlook_extra_fields,
+                }
+                .list_thread(conversation_id)
+                .await
+            }
+        }?;
+        messages.sort_by_key(|message| message.timestamp().unwrap_or(i64::MIN));
+        Ok(messages)
+    }
+
+    /// Raw RFC 822 source of a message, for "view source" style debugging.
+    pub async fn fetch_raw_message(&self, provider: Provider, message_id: &str) -> Result<Vec<u8>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                GmailApi {
+                    http,
+                    retry: self.config.retry_policy(),
+                    access_token: &access_token,
+                    extra_headers: &[],
+                    prefer_html: false,
+                }
+                .get_raw(message_id)
+                .await
+            }
+            Provider::Outlook => {
+                GraphApi {
+                    http,
+                    access_token: &access_token,
+                    extra_fields: &[],
+                }
+                .get_raw(message_id)
+                .await
+            }
+        }
+    }
+
+    pub async fn selftest(&self, provider: Provider) -> Result<SelfTestReport> {
+        const CREDENTIALS: &str = "OAuth-client ingesteld";
+        const TOKEN_ENDPOINT: &str = "Token-endpoint bereikbaar";
+        const REFRESH_TOKEN: &str = "Opgeslagen sessie geldig";
+        const USERINFO: &str = "Accountgegevens opvraagbaar";
+        const INBOX: &str = "Inbox opvraagbaar";
+
+        let mut report = SelfTestReport {
+            provider,
+            steps: Vec::new(),
+        };
+        let http = self.http_client()?;
+
+        let Some(credentials) = report.record(
+            CREDENTIALS,
+            self.require_provider_credentials(provider).await,
+        ) else {
+            report.skip(&[TOKEN_ENDPOINT, REFRESH_TOKEN, USERINFO, INBOX]);
+            return Ok(report);
+        };
+        let config = self.provider_config(provider, credentials);
+
+        let reachable = http
+            .post(&config.token_url)
+            .send()
+            .await
+            .map(|_| ())
+            .with_context(|| format!("{} niet bereikbaar", config.token_url));
+        if report.record(TOKEN_ENDPOINT, reachable).is_none() {
+            report.skip(&[REFRESH_TOKEN, USERINFO, INBOX]);
+            return Ok(report);
+        }
+
+        let session = self.authorized_session(provider).await;
+        let Some((_config, access_token)) = report.record(REFRESH_TOKEN, session) else {
+            report.skip(&[USERINFO, INBOX]);
+            return Ok(report);
+        };
+
+        let userinfo = match provider {
+            Provider::Google => {
+                GmailApi {
+                    http: http.clone(),
+                    retry: self.config.retry_policy(),
+                    access_token: &access_token,
+                    extra_headers: &[],
+                    prefer_html: false,
+                }
+                .userinfo()
+                .await
+            }
+            Provider::Outlook => {
+                GraphApi {
+                    http: http.clone(),
+                    access_token: &access_token,
+                    extra_fields: &[],
+                }
+                .userinfo()
+                .await
+            }
+        };
+        report.record(USERINFO, userinfo.map(|_| ()));
+
+        let inbox = match provider {
+            Provider::Google => send_google_json::<GoogleListResponse>(
+                self.config.retry_policy(),
+                http.get("https://gmail.googleapis.com/gmail/v1/users/me/messages")
+                    .query(&[("maxResults", 1)])
+                    .bearer_auth(&access_token),
+                "Gmail messages list",
+            )
+            .await
+            .map(|_| ()),
+            Provider::Outlook => http
+                .get("https://graph.microsoft.com/v1.0/me/messages")
+                .query(&[("$top", "1"), ("$select", "id")])
+                .bearer_auth(&access_token)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+        };
+        report.record(INBOX, inbox);
+
+        Ok(report)
+    }
+
+    pub async fn unsubscribe_one_click(&self, url: &str) -> Result<()> {
+        self.http_client()?
+            .post(url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .context("afmeldverzoek mislukt")?
+            .error_for_status()
+            .context("afmeldverzoek geweigerd")?;
+        Ok(())
+    }
+
+    pub async fn apply_action(
+        &self,
+        provider: Provider,
+        action: MailAction,
+        message_id: &str,
+    ) -> Result<String> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let new_id = match (provider, action) {
+            (Provider::Google, MailAction::Archive) => {
+                modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    vec![],
+                    vec!["INBOX"],
+                )
+                .await?;
+                message_id.to_owned()
+            }
+            (Provider::Google, MailAction::Trash) => {
+                post_google_message_action(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    "trash",
+                )
+                .await?;
+                message_id.to_owned()
+            }
+            (Provider::Outlook, MailAction::Archive) => {
+                relocate_outlook_message(http, &access_token, message_id, "archive").await?
+            }
+            (Provider::Outlook, MailAction::Trash) => {
+                relocate_outlook_message(http, &access_token, message_id, "deleteditems").await?
+            }
+        };
+        self.forget_cached_messages(provider, &[message_id.to_owned()])
+            .await;
+        self.relocate_followups(provider, &[(message_id.to_owned(), new_id.clone())])
+            .await;
+        Ok(new_id)
+    }
+
+    pub async fn apply_action_bulk(
+        &self,
+        provider: Provider,
+        action: MailAction,
+        message_ids: &[String],
+    ) -> Result<Vec<Result<String>>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let results = match (provider, action) {
+            (Provider::Google, MailAction::Archive) => {
+                batch_modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_ids,
+                    &[],
+                    &["INBOX"],
+                )
+                .await?;
+                message_ids.iter().cloned().map(Ok).collect()
+            }
+            (Provider::Google, MailAction::Trash) => {
+                let mut results = Vec::with_capacity(message_ids.len());
+                for message_id in message_ids {
+                    let trashed = post_google_message_action(
+                        http.clone(),
+                        &access_token,
+                        self.config.retry_policy(),
+                        message_id,
+                        "trash",
+                    )
+                    .await;
+                    results.push(trashed.map(|()| message_id.clone()));
+                }
+                results
+            }
+            (Provider::Outlook, action) => {
+                let target = match action {
+                    MailAction::Archive => "archive",
+                    MailAction::Trash => "deleteditems",
+                };
+                relocate_outlook_messages(
+                    &http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_ids,
+                    target,
+                )
+                .await?
+            }
+        };
+        let moves = successful_moves(message_ids, &results);
+        let done: Vec<String> = moves.iter().map(|(old_id, _)| old_id.clone()).collect();
+        self.forget_cached_messages(provider, &done).await;
+        self.relocate_followups(provider, &moves).await;
+        Ok(results)
+    }
+
+    /// Bulk form of [`Engine::move_message`], with a result per message.
+    pub async fn move_messages(
+        &self,
+        provider: Provider,
+        message_ids: &[String],
+        source_folder_id: &str,
+        target_folder_id: &str,
+    ) -> Result<Vec<Result<String>>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        let results = match provider {
+            Provider::Google => {
+                batch_modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_ids,
+                    &[target_folder_id],
+                    &google_move_removals(source_folder_id, target_folder_id),
+                )
+                .await?;
+                message_ids.iter().cloned().map(Ok).collect()
+            }
+            Provider::Outlook => {
+                relocate_outlook_messages(
+                    &http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_ids,
+                    target_folder_id,
+                )
+                .await?
+            }
+        };
+        let moves = successful_moves(message_ids, &results);
+        self.relocate_cached_messages(provider, &moves, target_folder_id)
+            .await;
+        self.relocate_followups(provider, &moves).await;
+        Ok(results)
+    }
+
+    pub async fn set_read_state(
+        &self,
+        provider: Provider,
+        message_ids: &[String],
+        read: bool,
+    ) -> Result<()> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                let (add, remove): (&[&str], &[&str]) = if read {
+                    (&[], &["UNREAD"])
+                } else {
+                    (&["UNREAD"], &[])
+                };
+                batch_modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_ids,
+                    add,
+                    remove,
+                )
+                .await
+            }
+            Provider::Outlook => {
+                let body = serde_json::to_value(GraphReadStateRequest { is_read: read })?;
+                let requests = message_ids
+                    .iter()
+                    .map(|message_id| GraphBatchRequest {
+                        method: "PATCH",
+                        url: format!("/me/messages/{message_id}"),
+                        body: Some(body.clone()),
+                    })
+                    .collect();
+                let retry = self.config.retry_policy();
+                for result in send_graph_batch(&http, &access_token, retry, requests).await? {
+                    result?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn undo_action(
+        &self,
+        provider: Provider,
+        action: MailAction,
+        message_id: &str,
+    ) -> Result<String> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match (provider, action) {
+            (Provider::Google, MailAction::Archive) => {
+                modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    vec!["INBOX"],
+                    vec![],
+                )
+                .await?;
+                Ok(message_id.to_owned())
+            }
+            (Provider::Google, MailAction::Trash) => {
+                post_google_message_action(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    "untrash",
+                )
+                .await?;
+                Ok(message_id.to_owned())
+            }
+            (Provider::Outlook, _) => {
+                relocate_outlook_message(http, &access_token, message_id, "inbox").await
+            }
+        }
+    }
+
+    /// Reverses [`Engine::move_message`] of an inbox message that went to
+    /// `moved_to` and returns its id in the inbox.
+    pub async fn undo_move(
+        &self,
+        provider: Provider,
+        message_id: &str,
+        moved_to: &str,
+    ) -> Result<String> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                modify_google_labels(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    vec!["INBOX"],
+                    vec![moved_to],
+                )
+                .await?;
+                Ok(message_id.to_owned())
+            }
+            Provider::Outlook => {
+                relocate_outlook_message(http, &access_token, message_id, "inbox").await
+            }
+        }
+    }
+
+    /// Archives a message and remembers to bring it back to the inbox at
+    /// `until` (unix seconds); see [`Engine::restore_due_snoozes`].
+    pub async fn snooze(&self, provider: Provider, message_id: &str, until: i64) -> Result<()> {
+        let archived_id = self
+            .apply_action(provider, MailAction::Archive, message_id)
+            .await?;
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO snoozes (provider, message_id, due_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(provider, message_id) DO UPDATE SET due_at = excluded.due_at",
+            libsql::params![provider.as_key(), archived_id, until],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Moves snoozed messages that are due back to the inbox and returns them.
+    pub async fn restore_due_snoozes(
+        &self,
+        provider: Provider,
+        now: i64,
+    ) -> Result<Vec<MailMessage>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT message_id FROM snoozes WHERE provider = ?1 AND due_at <= ?2
+                 ORDER BY due_at",
+                libsql::params![provider.as_key(), now],
+            )
+            .await?;
+        let mut due = Vec::new();
+        while let Some(row) = rows.next().await? {
+            due.push(row.get::<String>(0)?);
+        }
+
+        let mut restored = Vec::with_capacity(due.len());
+        for message_id in due {
+            // A message deleted while snoozed only needs its record dropped.
+            let inbox_id = match self.fetch_message(provider, &message_id).await {
+                Ok(_) => Some(
+                    self.undo_action(provider, MailAction::Archive, &message_id)
+                        .await?,
+                ),
+                Err(error)
+                    if matches!(error.downcast_ref(), Some(EngineError::NotFound { .. })) =>
+                {
+                    None
+                }
+                Err(error) => return Err(error),
+            };
+            conn.execute(
+                "DELETE FROM snoozes WHERE provider = ?1 AND message_id = ?2",
+                libsql::params![provider.as_key(), message_id],
+            )
+            .await?;
+            if let Some(inbox_id) = inbox_id {
+                restored.push(self.fetch_message(provider, &inbox_id).await?);
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Pins or unpins a message locally; `None` addresses imported messages.
+    pub async fn set_local_pin(
+        &self,
+        provider: Option<Provider>,
+        message_id: &str,
+        pinned: bool,
+    ) -> Result<()> {
+        let conn = self.open_conn().await?;
+        let provider_key = provider.map_or(IMPORTED_FLAG_PROVIDER, Provider::as_key);
+        if pinned {
+            conn.execute(
+                "INSERT INTO local_flags (provider, message_id, pinned) VALUES (?1, ?2, 1)
+                 ON CONFLICT(provider, message_id) DO UPDATE SET pinned = 1",
+                libsql::params![provider_key, message_id],
+            )
+            .await?;
+        } else {
+            conn.execute(
+                "DELETE FROM local_flags WHERE provider = ?1 AND message_id = ?2",
+                libsql::params![provider_key, message_id],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn load_local_flags(&self, provider: Option<Provider>) -> Result<LocalFlags> {
+        let conn = self.open_conn().await?;
+        let provider_key = provider.map_or(IMPORTED_FLAG_PROVIDER, Provider::as_key);
+        let mut rows = conn
+            .query(
+                "SELECT message_id FROM local_flags WHERE provider = ?1 AND pinned = 1",
+                libsql::params![provider_key],
+            )
+            .await?;
+        let mut flags = LocalFlags::default();
+        while let Some(row) = rows.next().await? {
+            flags.pinned.insert(row.get(0)?);
+        }
+        Ok(flags)
+    }
+
+    /// Flags a message for follow-up by `due` (unix seconds): a Graph
+    /// follow-up flag with due date for Outlook, an "Opvolgen" label for
+    /// Gmail. The due date is also kept locally for
+    /// [`Engine::followups`].
+    pub async fn set_followup(&self, provider: Provider, message_id: &str, due: i64) -> Result<()> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+        match provider {
+            Provider::Google => {
+                let retry = self.config.retry_policy();
+                let label_id =
+                    google_label_id(http.clone(), &access_token, retry, FOLLOW_UP_LABEL, true)
+                        .await?
+                        .context("label voor opvolgen ontbreekt")?;
+                modify_google_labels(
+                    http,
+                    &access_token,
+                    retry,
+                    message_id,
+                    vec![&label_id],
+                    Vec::new(),
+                )
+                .await?;
+            }
+            Provider::Outlook => {
+                let start = due.min(unix_now());
+                let flag = serde_json::json!({
+                    "flagStatus": "flagged",
+                    "startDateTime": { "dateTime": graph_datetime(start), "timeZone": "UTC" },
+                    "dueDateTime": { "dateTime": graph_datetime(due), "timeZone": "UTC" },
+                });
+                flag_outlook_message(http, &access_token, message_id, flag).await?;
+            }
+        }
+
+        let conn = self.open_conn().await?;
+        let account = cache_account(&conn, provider).await?;
+        conn.execute(
+            "INSERT INTO followups (provider, account, message_id, due_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(provider, account, message_id) DO UPDATE SET due_at = excluded.due_at",
+            libsql::params![provider.as_key(), account, message_id, due],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Removes the follow-up flag (or label) set by [`Engine::set_followup`].
+    pub async fn clear_followup(&self, provider: Provider, message_id: &str) -> Result<()> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+        match provider {
+            Provider::Google => {
+                let retry = self.config.retry_policy();
+                if let Some(label_id) =
+                    google_label_id(http.clone(), &access_token, retry, FOLLOW_UP_LABEL, false)
+                        .await?
+                {
+                    modify_google_labels(
+                        http,
+                        &access_token,
+                        retry,
+                        message_id,
+                        Vec::new(),
+                        vec![&label_id],
+                    )
+                    .await?;
+                }
+            }
+            Provider::Outlook => {
+                let flag = serde_json::json!({ "flagStatus": "notFlagged" });
+                flag_outlook_message(http, &access_token, message_id, flag).await?;
+            }
+        }
+
+        self.forget_followup(provider, message_id).await
+    }
+
+    async fn forget_followup(&self, provider: Provider, message_id: &str) -> Result<()> {
+        let conn = self.open_conn().await?;
+        let account = cache_account(&conn, provider).await?;
+        conn.execute(
+            "DELETE FROM followups WHERE provider = ?1 AND account = ?2 AND message_id = ?3",
+            libsql::params![provider.as_key(), account, message_id],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Messages the signed-in account flagged for follow-up, earliest due
+    /// date first.
+    pub async fn followups(&self, provider: Provider) -> Result<Vec<FollowUp>> {
+        let conn = self.open_conn().await?;
+        let account = cache_account(&conn, provider).await?;
+        let mut rows = conn
+            .query(
+                "SELECT message_id, due_at FROM followups WHERE provider = ?1 AND account = ?2
+                 ORDER BY due_at",
+                libsql::params![provider.as_key(), account],
+            )
+            .await?;
+        let mut followups = Vec::new();
+        while let Some(row) = rows.next().await? {
+            followups.push(FollowUp {
+                message_id: row.get(0)?,
+                due_at: row.get(1)?,
+            });
+        }
+        Ok(followups)
+    }
+
+    /// The messages behind [`Engine::followups`], in due order. Messages
+    /// deleted since they were flagged are left out and lose their follow-up.
+    pub async fn followup_messages(&self, provider: Provider) -> Result<Vec<MailMessage>> {
+        let followups = self.followups(provider).await?;
+        let fetched: Vec<_> = stream::iter(followups.clone())
+            .map(|followup| async move { self.fetch_message(provider, &followup.message_id).await })
+            .buffered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut messages = Vec::with_capacity(followups.len());
+        for (followup, fetched) in followups.iter().zip(fetched) {
+            match fetched {
+                Ok(message) => messages.push(message),
+                Err(error)
+                    if matches!(error.downcast_ref(), Some(EngineError::NotFound { .. })) =>
+                {
+                    self.forget_followup(provider, &followup.message_id).await?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Points follow-ups at the new ids of relocated messages, given as
+    /// `(old id, new id)` pairs.
+    async fn relocate_followups(&self, provider: Provider, moves: &[(String, String)]) {
+        let relocated = async {
+            let conn = self.open_conn().await?;
+            let account = cache_account(&conn, provider).await?;
+            for (old_id, new_id) in moves.iter().filter(|(old_id, new_id)| old_id != new_id) {
+                conn.execute(
+                    "UPDATE OR REPLACE followups SET message_id = ?1
+                     WHERE provider = ?2 AND account = ?3 AND message_id = ?4",
+                    libsql::params![
+                        new_id.as_str(),
+                        provider.as_key(),
+                        account.as_str(),
+                        old_id.as_str()
+                    ],
+                )
+                .await?;
+            }
+            anyhow::Ok(())
+        };
+        if let Err(error) = relocated.await {
+            warn!(error = %error, "follow-up update failed");
+        }
+    }
+
+    /// Local filter rules in the order [`Engine::apply_rules`] runs them.
+    pub async fn list_rules(&self) -> Result<Vec<MailRule>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, condition_kind, condition_value, action_kind, action_value
+                 FROM rules ORDER BY id",
+                (),
+            )
+            .await?;
+        let mut rules = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let id: i64 = row.get(0)?;
+            let condition = RuleCondition::from_parts(&row.get::<String>(1)?, row.get(2)?);
+            let action = RuleAction::from_parts(&row.get::<String>(3)?, row.get(4)?);
+            match (condition, action) {
+                (Some(condition), Some(action)) => rules.push(MailRule {
+                    id: Some(id),
+                    condition,
+                    action,
+                }),
+                _ => warn!(id, "unknown rule skipped"),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Stores a new rule at the end of the list, or updates it in place when
+    /// it has an id. Returns the id.
+    pub async fn save_rule(&self, rule: &MailRule) -> Result<i64> {
+        let conn = self.open_conn().await?;
+        let (condition_kind, condition_value) = rule.condition.to_parts();
+        let (action_kind, action_value) = rule.action.to_parts();
+        match rule.id {
+            Some(id) => {
+                conn.execute(
+                    "UPDATE rules SET condition_kind = ?2, condition_value = ?3,
+                     action_kind = ?4, action_value = ?5 WHERE id = ?1",
+                    libsql::params![
+                        id,
+                        condition_kind,
+                        condition_value,
+                        action_kind,
+                        action_value
+                    ],
+                )
+                .await?;
+                Ok(id)
+            }
+            None => {
+                conn.execute(
+                    "INSERT INTO rules (condition_kind, condition_value, action_kind, action_value)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    libsql::params![condition_kind, condition_value, action_kind, action_value],
+                )
+                .await?;
+                Ok(conn.last_insert_rowid())
+            }
+        }
+    }
+
+    pub async fn delete_rule(&self, id: i64) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute("DELETE FROM rules WHERE id = ?1", libsql::params![id])
+            .await?;
+        Ok(())
+    }
+
+    /// Runs the saved rules over `messages`, each rule in order against
+    /// each message. Called on every inbox fetch before caching.
+    pub async fn apply_rules(&self, messages: &mut Vec<MailMessage>) -> Result<()> {
+        let rules = self.list_rules().await?;
+        apply_rules_to(&rules, messages);
+        Ok(())
+    }
+
+    /// Moves a message out of `source_folder_id`, the folder (Gmail label)
+    /// it is listed in, into `target_folder_id`.
+    pub async fn move_message(
+        &self,
+        provider: Provider,
+        message_id: &str,
+        source_folder_id: &str,
+        target_folder_id: &str,
+    ) -> Result<Vec<String>> {
+        let (_config, access_token) = self.authorized_session(provider).await?;
+        let http = self.http_client()?;
+
+        match provider {
+            Provider::Google => {
+                let labels = move_google_message(
+                    http,
+                    &access_token,
+                    self.config.retry_policy(),
+                    message_id,
+                    source_folder_id,
+                    target_folder_id,
+                )
+                .await?;
+                let moved = [(message_id.to_owned(), message_id.to_owned())];
+                self.relocate_cached_messages(provider, &moved, target_folder_id)
+                    .await;
+                Ok(labels)
+            }
+            Provider::Outlook => {
+                let moved =
+                    post_outlook_move(http, &access_token, message_id, target_folder_id).await?;
+                let moves = [(message_id.to_owned(), moved.id)];
+                self.relocate_cached_messages(provider, &moves, target_folder_id)
+                    .await;
+                self.relocate_followups(provider, &moves).await;
+                Ok(moved.parent_folder_id.into_iter().collect())
+            }
+        }
+    }
+
+    pub async fn save_local_draft(&self, draft: &Draft) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO local_draft (slot, draft_id, recipients, subject, body, attachment_names)
+             VALUES (1, ?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(slot) DO UPDATE SET
+                draft_id = excluded.draft_id,
+                recipients = excluded.recipients,
+                subject = excluded.subject,
+                body = excluded.body,
+                attachment_names = excluded.attachment_names",
+            libsql::params![
+                draft.id.clone(),
+                draft.message.to.join(", "),
+                draft.message.subject.clone(),
+                draft.message.body.clone(),
+                draft
+                    .message
+                    .attachments
+                    .iter()
+                    .map(|(name, _, _)| name.replace('\n', " "))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn load_local_draft(&self) -> Result<Option<LocalDraft>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT COALESCE(draft_id, ''), recipients, subject, body, attachment_names
+                 FROM local_draft WHERE slot = 1",
+                (),
+            )
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+
+        let draft_id: String = row.get(0)?;
+        let recipients: String = row.get(1)?;
+        let subject: String = row.get(2)?;
+        let body: String = row.get(3)?;
+        let attachment_names: String = row.get(4)?;
+
+        Ok(Some(LocalDraft {
+            draft: Draft {
+                id: empty_to_none(draft_id),
+                message: OutgoingMessage {
+                    to: split_recipients(&recipients),
+                    subject,
+                    body,
+                    attachments: Vec::new(),
+                },
+            },
+            lost_attachments: attachment_names
+                .lines()
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        }))
+    }
+
+    pub async fn clear_local_draft(&self) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute("DELETE FROM local_draft", ()).await?;
+        Ok(())
+    }
+
+    /// Factory reset: removes credentials, tokens, accounts, settings, the
+    /// local draft and imported mail.
+    pub async fn clear_all_data(&self) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute_batch(
+            "BEGIN;
+             DELETE FROM oauth_tokens;
+             DELETE FROM oauth_settings;
+             DELETE FROM accounts;
+             DELETE FROM trusted_image_senders;
+             DELETE FROM blocked_senders;
+             DELETE FROM app_settings;
+             DELETE FROM local_draft;
+             DELETE FROM imported_messages;
+             DELETE FROM snoozes;
+             DELETE FROM followups;
+             DELETE FROM rules;
+             DELETE FROM local_flags;
+             DELETE FROM cached_messages;
+             DELETE FROM folder_syncs;
+             COMMIT;",
+        )
+        .await
+        .context("app-data wissen mislukt")?;
+        info!("all local app data cleared");
+        Ok(())
+    }
+
+    pub async fn import_mbox(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("kan {} niet openen", path.display()))?;
+        let mut reader = tokio::io::BufReader::new(file);
+        let conn = self.open_conn().await?;
+
+        let mut imported = 0;
+        let mut current = String::new();
+        let mut previous_blank = true;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                break;
+            }
+            let decoded = String::from_utf8_lossy(&line);
+            let text = decoded.trim_end_matches(['\r', '\n']);
+
+            if previous_blank && text.starts_with("From ") {
+                imported += import_mbox_message(&conn, &current).await?;
+                current.clear();
+            } else {
+                current.push_str(unescape_mbox_line(text));
+                current.push('\n');
+            }
+            previous_blank = text.is_empty();
+        }
+        imported += import_mbox_message(&conn, &current).await?;
+
+        info!(path = %path.display(), imported, "mbox imported");
+        Ok(imported)
+    }
+
+    pub async fn list_imported_messages(&self) -> Result<Vec<MailMessage>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, subject, sender, date, body, body_html, size FROM imported_messages
+                 ORDER BY rowid DESC",
+                (),
+            )
+            .await?;
+
+        let mut messages = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let size: i64 = row.get(6)?;
+            let id: String = row.get(0)?;
+            let internet_message_id = imported_message_id(&id);
+            messages.push(MailMessage {
+                id,
+                labels: vec![IMPORTED_LABEL.to_owned()],
+                subject: row.get(1)?,
+                from: row.get(2)?,
+                date: row.get(3)?,
+                body: row.get(4)?,
+                body_html: row.get(5)?,
+                size_estimate: u64::try_from(size).ok(),
+                unsubscribe: None,
+                authentication: None,
+                spam_score: None,
+                is_suspicious: false,
+                importance: Importance::Normal,
+                has_attachments: false,
+                conversation_id: None,
+                internet_message_id,
+                extra: HashMap::new(),
+            });
+        }
+        Ok(messages)
+    }
+
+    /// Full-text search over every cached account and folder, best match first.
+    pub async fn search_cache(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let Some(expression) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT m.provider, m.account, m.folder, m.id, m.subject, m.sender, m.date,
+                        m.body, m.body_html, m.size
+                 FROM message_search
+                 JOIN cached_messages m ON m.rowid = message_search.rowid
+                 WHERE message_search MATCH ?1
+                 ORDER BY rank
+                 LIMIT ?2",
+                libsql::params![expression, SEARCH_RESULT_LIMIT],
+            )
+            .await?;
+
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let Some(provider) = Provider::from_key(&row.get::<String>(0)?) else {
+                continue;
+            };
+            let folder: String = row.get(2)?;
+            let size: Option<i64> = row.get(9)?;
+            hits.push(SearchHit {
+                account: Account {
+                    provider,
+                    email: row.get(1)?,
+                },
+                message: MailMessage {
+                    id: row.get(3)?,
+                    labels: vec![folder.clone()],
+                    subject: row.get(4)?,
+                    from: row.get(5)?,
+                    date: row.get(6)?,
+                    body: row.get(7)?,
+                    body_html: row.get(8)?,
+                    size_estimate: size.and_then(|size| u64::try_from(size).ok()),
+                    unsubscribe: None,
+                    authentication: None,
+                    spam_score: None,
+                    is_suspicious: false,
+                    importance: Importance::Normal,
+                    has_attachments: false,
+                    conversation_id: None,
+                    internet_message_id: None,
+                    extra: HashMap::new(),
+                },
+                folder,
+            });
+        }
+        Ok(hits)
+    }
+
+    /// Records in `folder_syncs` when `folder` of this account was last fetched.
+    async fn record_sync(&self, result: &LoginResult, folder: Option<&str>) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO folder_syncs (provider, account, folder, synced_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(provider, account, folder) DO UPDATE SET synced_at = excluded.synced_at",
+            libsql::params![
+                result.provider.as_key(),
+                result.account.as_str(),
+                folder.unwrap_or_default(),
+                unix_now()
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Unix time of the last successful fetch of `folder` (`None` for the
+    /// inbox listing) for this account.
+    pub async fn last_synced(
+        &self,
+        provider: Provider,
+        account: &str,
+        folder: Option<&str>,
+    ) -> Result<Option<i64>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT synced_at FROM folder_syncs
+                 WHERE provider = ?1 AND account = ?2 AND folder = ?3",
+                libsql::params![provider.as_key(), account, folder.unwrap_or_default()],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Accounts with session state, unread inbox count and last sync time.
+    /// Unread counts cost one provider request per logged-in provider.
+    pub async fn status_snapshot(&self) -> Result<StatusSnapshot> {
+        let mut unread: HashMap<Provider, Option<u64>> = HashMap::new();
+        let mut accounts = Vec::new();
+        for account in self.list_accounts().await? {
+            let provider = account.provider;
+            let logged_in = self.load_refresh_token(provider).await?.is_some();
+            if logged_in && !unread.contains_key(&provider) {
+                let counts = self
+                    .folder_counts(provider, provider.inbox_folder_id())
+                    .await;
+                if let Err(error) = &counts {
+                    warn!(provider = provider.label(), error = %error, "status unread count failed");
+                }
+                unread.insert(provider, counts.ok().map(|counts| counts.unread));
+            }
+            accounts.push(AccountStatus {
+                provider: provider.as_key(),
+                last_synced: self.last_synced(provider, &account.email, None).await?,
+                unread: unread.get(&provider).copied().flatten(),
+                logged_in,
+                email: account.email,
+            });
+        }
+        Ok(StatusSnapshot { accounts })
+    }
+
+    /// Serves [`Engine::status_snapshot`] as JSON on `GET /status` at
+    /// `127.0.0.1:port` until the future is dropped. Only loopback is bound,
+    /// requests for another `Host` (DNS rebinding) are refused, and a
+    /// snapshot is reused for [`STATUS_CACHE_SECS`] so polling can't drain
+    /// the provider quota. Nothing can be changed through it.
+    pub async fn serve_status(&self, port: u16) -> Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .with_context(|| format!("kan statusserver niet starten op 127.0.0.1:{port}"))?;
+        info!(port, "status endpoint listening");
+        let mut cached: Option<(std::time::Instant, String)> = None;
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    warn!(error = %error, "status connection not accepted");
+                    continue;
+                }
+            };
+            let mut buf = [0_u8; 1024];
+            let n = match timeout(self.config.callback_read_timeout, stream.read(&mut buf)).await {
+                Ok(Ok(n)) => n,
+                _ => continue,
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let mut parts = request
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            let fresh = cached
+                .as_ref()
+                .filter(|(at, _)| at.elapsed() < Duration::from_secs(STATUS_CACHE_SECS));
+            let (status, body) = match (parts.next(), parts.next()) {
+                _ if !status_host_allowed(&request, port) => (
+                    "403 Forbidden",
+                    serde_json::json!({ "error": "ongeldige Host" }).to_string(),
+                ),
+                (Some("GET"), Some("/status")) if fresh.is_some() => (
+                    "200 OK",
+                    fresh.map(|(_, body)| body.clone()).unwrap_or_default(),
+                ),
+                (Some("GET"), Some("/status")) => {
+                    match self
+                        .status_snapshot()
+                        .await
+                        .and_then(|snapshot| Ok(serde_json::to_string(&snapshot)?))
+                    {
+                        Ok(body) => {
+                            cached = Some((std::time::Instant::now(), body.clone()));
+                            ("200 OK", body)
+                        }
+                        Err(error) => (
+                            "500 Internal Server Error",
+                            serde_json::json!({ "error": format!("{error:#}") }).to_string(),
+                        ),
+                    }
+                }
+                _ => (
+                    "404 Not Found",
+                    serde_json::json!({ "error": "alleen GET /status" }).to_string(),
+                ),
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(error) = stream.write_all(response.as_bytes()).await {
+                warn!(error = %error, "status response could not be written");
+            }
+        }
+    }
+
+    /// Mirrors a fetched listing into `cached_messages` (and so the search
+    /// index), then evicts the oldest messages past the cache limit. A shorter
+    /// body never replaces a longer one, so a list snippet doesn't overwrite a
+    /// body fetched earlier.
+    async fn cache_messages(&self, result: &LoginResult, folder: Option<&str>) -> Result<()> {
+        let folder = folder.unwrap_or(result.provider.inbox_folder_id());
+        let conn = self.open_conn().await?;
+        let cache_limit = load_cache_limit(&conn).await?;
+        let tx = conn.transaction().await?;
+        for message in result
+            .messages
+            .iter()
+            .filter(|message| !result.load_errors.contains_key(&message.id))
+        {
+            tx.execute(
+                "INSERT INTO cached_messages
+                    (provider, account, folder, id, subject, sender, date, received_at, body,
+                     body_html, size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                 ON CONFLICT(provider, account, id) DO UPDATE SET
+                    folder = excluded.folder,
+                    subject = excluded.subject,
+                    sender = excluded.sender,
+                    date = excluded.date,
+                    received_at = excluded.received_at,
+                    body = CASE WHEN length(excluded.body) >= length(cached_messages.body)
+                        THEN excluded.body ELSE cached_messages.body END,
+                    body_html = COALESCE(excluded.body_html, cached_messages.body_html),
+                    size = COALESCE(excluded.size, cached_messages.size)",
+                libsql::params![
+                    result.provider.as_key(),
+                    result.account.as_str(),
+                    folder,
+                    message.id.as_str(),
+                    message.subject.as_str(),
+                    message.from.as_str(),
+                    message.date.as_str(),
+                    message.timestamp(),
+                    message.body.as_str(),
+                    message.body_html.clone(),
+                    message
+                        .size_estimate
+                        .and_then(|size| i64::try_from(size).ok())
+                ],
+            )
+            .await?;
+        }
+        tx.execute(
+            "DELETE FROM cached_messages WHERE rowid IN (
+                SELECT rowid FROM cached_messages
+                ORDER BY COALESCE(received_at, 0)
+                LIMIT max((SELECT COUNT(*) FROM cached_messages) - ?1, 0)
+            )",
+            libsql::params![i64::try_from(cache_limit).unwrap_or(i64::MAX)],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Stores a fully fetched body for a message that is already cached.
+    async fn cache_message_body(&self, provider: Provider, message: &MailMessage) -> Result<()> {
+        let conn = self.open_conn().await?;
+        let account = cache_account(&conn, provider).await?;
+        conn.execute(
+            "UPDATE cached_messages SET body = ?1, body_html = COALESCE(?2, body_html)
+             WHERE provider = ?3 AND account = ?4 AND id = ?5",
+            libsql::params![
+                message.body.as_str(),
+                message.body_html.clone(),
+                provider.as_key(),
+                account,
+                message.id.as_str()
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Drops cached messages that left the folder they were cached for.
+    async fn forget_cached_messages(&self, provider: Provider, message_ids: &[String]) {
+        let forgotten = async {
+            let conn = self.open_conn().await?;
+            let account = cache_account(&conn, provider).await?;
+            for message_id in message_ids {
+                conn.execute(
+                    "DELETE FROM cached_messages WHERE provider = ?1 AND account = ?2 AND id = ?3",
+                    libsql::params![provider.as_key(), account.as_str(), message_id.as_str()],
+                )
+                .await?;
+            }
+            anyhow::Ok(())
+        };
+        if let Err(error) = forgotten.await {
+            warn!(error = %error, "message cache update failed");
+        }
+    }
+
+    /// Points cached messages at `folder` and their id there, given as
+    /// `(old id, new id)` pairs.
+    async fn relocate_cached_messages(
+        &self,
+        provider: Provider,
+        moves: &[(String, String)],
+        folder: &str,
+    ) {
+        let relocated = async {
+            let conn = self.open_conn().await?;
+            let account = cache_account(&conn, provider).await?;
+            for (old_id, new_id) in moves {
+                conn.execute(
+                    "UPDATE OR REPLACE cached_messages SET id = ?1, folder = ?2
+                     WHERE provider = ?3 AND account = ?4 AND id = ?5",
+                    libsql::params![
+                        new_id.as_str(),
+                        folder,
+                        provider.as_key(),
+                        account.as_str(),
+                        old_id.as_str()
+                    ],
+                )
+                .await?;
+            }
+            anyhow::Ok(())
+        };
+        if let Err(error) = relocated.await {
+            warn!(error = %error, "message cache update failed");
+        }
+    }
+
+    async fn authorized_session(&self, provider: Provider) -> Result<(ProviderConfig, String)> {
+        let refresh_token = self
+            .load_refresh_token(provider)
+            .await?
+            .ok_or_else(|| anyhow!("Niet ingelogd bij {}. Log eerst in.", provider.label()))?;
+
+        let credentials = self.require_provider_credentials(provider).await?;
+        let config = self.provider_config(provider, credentials);
+        let oauth = build_oauth_client(&config, self.redirect_url()?)?;
+        let token_set =
+            match exchange_refresh_token(&self.oauth_http_client()?, &oauth, refresh_token).await {
+                Ok(token_set) => token_set,
+                Err(error) if requires_interaction(&error) => {
+                    return Err(interaction_required(provider, error));
+                }
+                Err(error) if is_token_expired(&error) => {
+                    return Err(self.token_expired(provider, error).await);
+                }
+                Err(error) => return Err(with_token_exchange_hint(provider, error)),
+            };
+
+        if let Some(new_refresh_token) = token_set.refresh_token {
+            self.save_refresh_token(provider, &new_refresh_token)
+                .await?;
+        }
+        if let Some(scopes) = &token_set.scopes {
+            self.save_granted_scopes(provider, scopes).await?;
+        }
+
+        Ok((config, token_set.access_token))
+    }
+
+    async fn token_expired(&self, provider: Provider, error: anyhow::Error) -> anyhow::Error {
+        info!(
+            "refresh token expired or revoked for provider={}: {error:#}",
+            provider.label()
+        );
+        if let Err(clear_error) = self.clear_refresh_token(provider).await {
+            return clear_error;
+        }
+        EngineError::TokenExpired { provider }.into()
+    }
+
+    async fn open_conn(&self) -> Result<libsql::Connection> {
+        match &self.config.db_path {
+            Some(path) => connect_db(path, self.config.db_busy_timeout).await,
+            None => {
+                let location = db_location();
+                if location.kind == DbLocationKind::UserData {
+                    // Once per process: the check stats a path relative to
+                    // the working directory at first use. A failure sticks, so
+                    // no empty database is created next to the legacy one.
+                    static MIGRATION: OnceLock<Result<(), String>> = OnceLock::new();
+                    MIGRATION
+                        .get_or_init(|| {
+                            migrate_legacy_db(&location.path).map_err(|error| format!("{error:#}"))
+                        })
+                        .clone()
+                        .map_err(|error| anyhow!(error))?;
+                }
+                connect_db(&location.path, self.config.db_busy_timeout).await
+            }
+        }
+    }
+
+    fn http_client(&self) -> Result<Client> {
+        self.client_builder()?
+            .build()
+            .context("HTTP client kon niet worden gemaakt")
+    }
+
+    fn oauth_http_client(&self) -> Result<Client> {
+        self.client_builder()?
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .context("HTTP client kon niet worden gemaakt")
+    }
+
+    fn client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        // Message lists and bodies compress well; reqwest sends
+        // `Accept-Encoding` and decodes transparently.
+        let mut builder = Client::builder()
+            .timeout(self.config.request_timeout)
+            .user_agent(self.config.user_agent.as_str())
+            .gzip(true)
+            .brotli(true)
+            .deflate(true);
+        if let Some(proxy) = &self.config.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).with_context(|| format!("ongeldige proxy: {proxy}"))?,
+            );
+        }
+        Ok(builder)
+    }
+
+    fn open_browser(&self) -> bool {
+        self.config.open_browser.unwrap_or_else(|| {
+            !std::env::var("MAIL_NO_BROWSER")
+                .map(|value| matches!(value.trim(), "1" | "true" | "on" | "yes"))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Opens the authorize URL in the browser, or hands it to the
+    /// [`AuthorizeUrlHook`] when auto-open is disabled or fails. Without a hook
+    /// the URL is logged so it can be copied from the terminal.
+    fn present_authorize_url(&self, auth_url: &str) -> Result<()> {
+        if self.open_browser() {
+            match webbrowser::open(auth_url) {
+                Ok(()) => return Ok(()),
+                Err(error) if self.config.authorize_url_hook.is_none() => {
+                    bail!("browser kon niet worden geopend: {error}");
+                }
+                Err(error) => info!("browser kon niet worden geopend: {error}"),
+            }
+        }
+        match &self.config.authorize_url_hook {
+            Some(AuthorizeUrlHook(hook)) => hook(auth_url),
+            None => info!("open deze URL in een browser om in te loggen: {auth_url}"),
+        }
+        Ok(())
+    }
+
+    fn redirect_url(&self) -> Result<Url> {
+        let raw = match &self.config.redirect_url {
+            Some(raw) => raw.clone(),
+            None => std::env::var("MAIL_OAUTH_REDIRECT_URI")
+                .unwrap_or_else(|_| DEFAULT_REDIRECT_URL.to_owned()),
+        };
+        Url::parse(&raw).with_context(|| format!("ongeldige redirect URI: {raw}"))
+    }
+
+    fn provider_config(
+        &self,
+        provider: Provider,
+        credentials: ProviderCredentials,
+    ) -> ProviderConfig {
+        let mut config = ProviderConfig::from_credentials(provider, credentials);
+        let scopes = match provider {
+            Provider::Google => &self.config.google_scopes,
+            Provider::Outlook => &self.config.outlook_scopes,
+        };
+        if let Some(scopes) = scopes {
+            config.scopes = scopes.clone();
+        }
+        config
+    }
+
+    async fn fetch_inbox(
+        &self,
+        config: &ProviderConfig,
+        access_token: &str,
+        range: &DateRange,
+        folder: Option<&str>,
+    ) -> Result<LoginResult> {
+        let conn = self.open_conn().await?;
+        let offline_bodies = load_app_setting(&conn, OFFLINE_BODIES_KEY)
+            .await?
+            .as_deref()
+            == Some("1");
+        let eager_body_count = if offline_bodies {
+            self.config.message_limit
+        } else {
+            self.config.eager_body_count
+        };
+        let mut result = self
+            .fetch_page(config, access_token, range, folder, None, eager_body_count)
+            .await?;
+        if let Err(error) = self.apply_rules(&mut result.messages).await {
+            warn!(error = %error, "local rules not applied");
+        }
+        if let Err(error) = self.cache_messages(&result, folder).await {
+            warn!(error = %error, "message cache update failed");
+        }
+        if let Err(error) = self.record_sync(&result, folder).await {
+            warn!(error = %error, "folder sync time update failed");
+        }
+        Ok(result)
+    }
+
+    async fn fetch_page(
+        &self,
+        config: &ProviderConfig,
+        access_token: &str,
+        range: &DateRange,
+        folder: Option<&str>,
+        cursor: Option<&str>,
+        eager_body_count: usize,
+    ) -> Result<LoginResult> {
+        let http = self.http_client()?;
+        let cached_account = self
+            .cached_account_email(config.provider)
+            .await
+            .unwrap_or_else(|error| {
+                warn!(error = %error, "cached account email unavailable");
+                None
+            });
+        let options = ListingOptions {
+            limit: self.config.message_limit,
+            concurrency: self.config.concurrency,
+            eager_body_count,
+            on_message: self.config.fetched_message_hook.as_ref(),
+            transform: self.config.message_transform_hook.as_ref(),
+            account: cached_account.as_deref(),
+        };
+
+        let result = match config.provider {
+            Provider::Google => {
+                let api = GmailApi {
+                    http,
+                    retry: self.config.retry_policy(),
+                    access_token,
+                    extra_headers: &self.config.google_extra_headers,
+                    prefer_html: load_prefer_html(&self.open_conn().await?).await?,
+                };
+                fetch_listing(&api, &options, range, folder, cursor).await
+            }
+            Provider::Outlook => {
+                let api = GraphApi {
+                    http,
+                    access_token,
+                    extra_fields: &self.config.outlook_extra_fields,
+                };
+                fetch_listing(&api, &options, range, folder, cursor).await
+            }
+        };
+        if let Ok(result) = &result
+            && cached_account.is_none()
+            && result.account != UNKNOWN_ACCOUNT
+            && let Err(error) = self
+                .save_account_email(config.provider, &result.account)
+                .await
+        {
+            warn!(error = %error, "account email not cached");
+        }
+        self.track_provider_health(config.provider, result).await
+    }
+
+    /// The account email from an earlier fetch, unless it is older than
+    /// [`ACCOUNT_EMAIL_REFRESH_SECS`].
+    async fn cached_account_email(&self, provider: Provider) -> Result<Option<String>> {
+        let conn = self.open_conn().await?;
+        let checked_at = load_app_setting(&conn, &account_email_checked_key(provider))
+            .await?
+            .and_then(|value| value.parse::<i64>().ok());
+        if !checked_at.is_some_and(|at| unix_now() - at < ACCOUNT_EMAIL_REFRESH_SECS) {
+            return Ok(None);
+        }
+        load_app_setting(&conn, &account_email_key(provider)).await
+    }
+
+    async fn save_account_email(&self, provider: Provider, email: &str) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, &account_email_key(provider), email).await?;
+        save_app_setting(
+            &conn,
+            &account_email_checked_key(provider),
+            &unix_now().to_string(),
+        )
+        .await
+    }
+
+    /// Called whenever the stored token may belong to another account.
+    async fn forget_account_email(&self, provider: Provider) -> Result<()> {
+        let conn = self.open_conn().await?;
+        clear_app_setting(&conn, &account_email_key(provider)).await?;
+        clear_app_setting(&conn, &account_email_checked_key(provider)).await
+    }
+
+    /// Pages through the mailbox with full bodies and stores everything in
+    /// the cache, up to the configured cache limit. `on_progress` receives the
+    /// running count after each page.
+    pub async fn download_for_offline(
+        &self,
+        provider: Provider,
+        on_progress: impl Fn(OfflineProgress),
+    ) -> Result<usize> {
+        let (config, access_token) = self.authorized_session(provider).await?;
+        let cache_limit = load_cache_limit(&self.open_conn().await?).await?;
+        let mut cursor = None;
+        let mut downloaded = 0;
+        loop {
+            let page = self
+                .fetch_page(
+                    &config,
+                    &access_token,
+                    &DateRange::default(),
+                    None,
+                    cursor.as_deref(),
+                    usize::MAX,
+                )
+                .await?;
+            downloaded += page.messages.len();
+            self.cache_messages(&page, None).await?;
+            on_progress(OfflineProgress {
+                downloaded,
+                estimated_total: page.estimated_total,
+            });
+            cursor = page.next_cursor;
+            if cursor.is_none() || downloaded >= cache_limit {
+                break;
+            }
+        }
+        info!(
+            provider = provider.label(),
+            downloaded, "offline download finished"
+        );
+        Ok(downloaded)
+    }
+
+    /// Writes every message of `account` (for Gmail everything but spam and
+    /// trash) to `out` as mboxrd, newest first. `account` must be the
+    /// signed-in account of its provider. Progress is checkpointed per page:
+    /// running it again with the same `out` after an error or crash resumes
+    /// after the last complete page. Without a pending export for `out` an
+    /// existing file is overwritten. Returns the number of messages written.
+    pub async fn export_account_mbox(
+        &self,
+        account: &Account,
+        out: &Path,
+        on_progress: impl Fn(ExportProgress),
+    ) -> Result<usize> {
+        let provider = account.provider;
+        let mut expected_account = Some(account.email.as_str());
+        let next_page = |cursor: Option<String>| {
+            let expected = expected_account.take();
+            async move {
+                // A fresh token per page; a large export outlives one access token.
+                let (_config, access_token) = self.authorized_session(provider).await?;
+                let http = self.http_client()?;
+                let retry = self.config.retry_policy();
+                let cursor = cursor.as_deref();
+                match provider {
+                    Provider::Google => {
+                        let api = GmailApi {
+                            http,
+                            retry,
+                            access_token: &access_token,
+                            extra_headers: &[],
+                            prefer_html: false,
+                        };
+                        export_page(&api, retry, self.config.concurrency, expected, cursor).await
+                    }
+                    Provider::Outlook => {
+                        let api = GraphApi {
+                            http,
+                            access_token: &access_token,
+                            extra_fields: &[],
+                        };
+                        export_page(&api, retry, self.config.concurrency, expected, cursor).await
+                    }
+                }
+            }
+        };
+        let exported = self
+            .write_mbox_export(&mbox_export_key(account), out, next_page, on_progress)
+            .await?;
+        info!(
+            provider = provider.label(),
+            exported, "mbox export finished"
+        );
+        Ok(exported)
+    }
+
+    /// The checkpointed page loop of [`Engine::export_account_mbox`].
+    async fn write_mbox_export<F>(
+        &self,
+        key: &str,
+        out: &Path,
+        mut next_page: impl FnMut(Option<String>) -> F,
+        on_progress: impl Fn(ExportProgress),
+    ) -> Result<usize>
+    where
+        F: Future<Output = Result<ExportPage>>,
+    {
+        let conn = self.open_conn().await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(out)
+            .await
+            .with_context(|| format!("kan {} niet openen", out.display()))?;
+        let length = file.metadata().await?.len();
+        let mut checkpoint = match self.pending_export(&conn, key).await? {
+            Some(checkpoint) if checkpoint.path == out && checkpoint.bytes <= length => {
+                info!(exported = checkpoint.exported, "resuming mbox export");
+                checkpoint
+            }
+            _ => ExportCheckpoint {
+                path: out.to_owned(),
+                cursor: None,
+                exported: 0,
+                bytes: 0,
+            },
+        };
+        file.set_len(checkpoint.bytes).await?;
+
+        loop {
+            let page = next_page(checkpoint.cursor.clone()).await?;
+            let exported_at = unix_now();
+            for raw in &page.raws {
+                let entry = mbox_entry(raw, exported_at);
+                file.write_all(&entry).await?;
+                checkpoint.bytes += entry.len() as u64;
+            }
+            file.sync_data().await?;
+            checkpoint.exported += page.raws.len();
+            checkpoint.cursor = page.next_cursor;
+            on_progress(ExportProgress {
+                exported: checkpoint.exported,
+                estimated_total: page.estimated_total,
+            });
+            if checkpoint.cursor.is_none() {
+                break;
+            }
+            save_app_setting(&conn, key, &serde_json::to_string(&checkpoint)?).await?;
+        }
+
+        clear_app_setting(&conn, key).await?;
+        Ok(checkpoint.exported)
+    }
+
+    /// The file of an unfinished [`Engine::export_account_mbox`] for
+    /// `account`, if any.
+    pub async fn pending_mbox_export(&self, account: &Account) -> Result<Option<PathBuf>> {
+        let conn = self.open_conn().await?;
+        Ok(self
+            .pending_export(&conn, &mbox_export_key(account))
+            .await?
+            .map(|checkpoint| checkpoint.path))
+    }
+
+    async fn pending_export(
+        &self,
+        conn: &libsql::Connection,
+        key: &str,
+    ) -> Result<Option<ExportCheckpoint>> {
+        Ok(load_app_setting(conn, key)
+            .await?
+            .and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    /// Counts consecutive server-side fetch failures and, past
+    /// [`OUTAGE_FAILURE_THRESHOLD`], points at the provider's status page.
+    async fn track_provider_health<T>(&self, provider: Provider, result: Result<T>) -> Result<T> {
+        let key = format!("fetch_failures_{}", provider.as_key());
+        let conn = match self.open_conn().await {
+            Ok(conn) => conn,
+            Err(error) => {
+                warn!(error = %error, "provider health bookkeeping skipped");
+                return result;
+            }
+        };
+        let error = match result {
+            Ok(value) => {
+                if let Err(error) = clear_app_setting(&conn, &key).await {
+                    warn!(error = %error, "provider health bookkeeping failed");
+                }
+                return Ok(value);
+            }
+            Err(error) if is_server_side_failure(&error) => error,
+            Err(error) => return Err(error),
+        };
+
+        let failures = load_app_setting(&conn, &key)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(0)
+            + 1;
+        if let Err(save_error) = save_app_setting(&conn, &key, &failures.to_string()).await {
+            warn!(error = %save_error, "provider health bookkeeping failed");
+        }
+        if failures < OUTAGE_FAILURE_THRESHOLD {
+            return Err(error);
+        }
+        let (service, status_page) = match provider {
+            Provider::Google => ("Gmail", "https://www.google.com/appsstatus/dashboard/"),
+            Provider::Outlook => ("Microsoft 365", "https://status.cloud.microsoft/"),
+        };
+        Err(anyhow!(
+            "{error:#}\nTip: {failures} keer achter elkaar een serverfout of time-out. Dit kan \
+             een storing bij {service} zijn; kijk op {status_page} voordat je je instellingen \
+             aanpast."
+        ))
+    }
+
+    async fn require_provider_credentials(
+        &self,
+        provider: Provider,
+    ) -> Result<ProviderCredentials> {
+        let credentials = self
+            .load_provider_credentials(provider)
+            .await?
+            .ok_or_else(|| {
+                anyhow!(
+                    "Geen OAuth-client ingesteld voor {}. Vul eerst Client ID in de app in.",
+                    provider.label()
+                )
+            })?;
+
+        validate_credentials(provider, &credentials)?;
+        Ok(credentials)
+    }
+
+    async fn load_provider_credentials(
+        &self,
+        provider: Provider,
+    ) -> Result<Option<ProviderCredentials>> {
+        self.resolve_provider_credentials(provider, |name| std::env::var(name).ok())
+            .await
+    }
+
+    /// Saved settings win; `lookup` (the environment outside tests) is only
+    /// consulted when nothing is stored for `provider`.
+    async fn resolve_provider_credentials(
+        &self,
+        provider: Provider,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<ProviderCredentials>> {
+        let settings = self.load_oauth_settings().await?;
+        let creds = match provider {
+            Provider::Google => settings
+                .google
+                .or_else(|| google_credentials_from_env(&lookup)),
+            Provider::Outlook => settings
+                .outlook
+                .or_else(|| outlook_credentials_from_env(&lookup)),
+        };
+
+        Ok(creds)
+    }
+
+    async fn load_refresh_token(&self, provider: Provider) -> Result<Option<String>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT refresh_token FROM oauth_tokens WHERE provider = ?1",
+                libsql::params![provider.as_key()],
+            )
+            .await?;
+
+        let token = if let Some(row) = rows.next().await? {
+            let refresh_token: String = row.get(0)?;
+            if refresh_token.trim().is_empty() {
+                None
+            } else {
+                Some(refresh_token)
+            }
+        } else {
+            None
+        };
+
+        Ok(token)
+    }
+
+    async fn save_refresh_token(&self, provider: Provider, refresh_token: &str) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "INSERT INTO oauth_tokens (provider, refresh_token)
+             VALUES (?1, ?2)
+             ON CONFLICT(provider) DO UPDATE SET
+                refresh_token = excluded.refresh_token",
+            libsql::params![provider.as_key(), refresh_token],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn save_granted_scopes(&self, provider: Provider, scopes: &str) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "UPDATE oauth_tokens SET scopes = ?2 WHERE provider = ?1",
+            libsql::params![provider.as_key(), scopes],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The scopes the provider reported at the last login or token refresh;
+    /// empty when it never reported any. See [`missing_write_scopes`].
+    pub async fn granted_scopes(&self, provider: Provider) -> Result<Vec<String>> {
+        let conn = self.open_conn().await?;
+        let mut rows = conn
+            .query(
+                "SELECT scopes FROM oauth_tokens WHERE provider = ?1",
+                libsql::params![provider.as_key()],
+            )
+            .await?;
+        let scopes: Option<String> = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => None,
+        };
+        Ok(scopes
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect())
+    }
+
+    async fn clear_refresh_token(&self, provider: Provider) -> Result<()> {
+        let conn = self.open_conn().await?;
+        conn.execute(
+            "DELETE FROM oauth_tokens WHERE provider = ?1",
+            libsql::params![provider.as_key()],
+        )
+        .await?;
+        self.forget_account_email(provider).await
+    }
+
+    async fn remember_session(&self, result: &LoginResult) -> Result<()> {
+        let conn = self.open_conn().await?;
+        save_app_setting(&conn, LAST_PROVIDER_KEY, result.provider.as_key()).await?;
+
+        if let Some(refresh_token) = self.load_refresh_token(result.provider).await? {
+            conn.execute(
+                "DELETE FROM accounts
+                 WHERE provider = ?1 AND lower(email) = lower(?2) AND email <> ?2",
+                libsql::params![result.provider.as_key(), result.account.as_str()],
+            )
+            .await?;
+            conn.execute(
+                "INSERT INTO accounts (provider, email, refresh_token)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT(provider, email) DO UPDATE SET
+                    refresh_token = excluded.refresh_token",
+                libsql::params![
+                    result.provider.as_key(),
+                    result.account.as_str(),
+                    refresh_token
+                ],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn load_prefer_html(conn: &libsql::Connection) -> Result<bool> {
+    Ok(load_app_setting(conn, PREFER_HTML_KEY).await?.as_deref() == Some("1"))
+}
+
+async fn load_cache_limit(conn: &libsql::Connection) -> Result<usize> {
+    Ok(load_app_setting(conn, CACHE_LIMIT_KEY)
+        .await?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_MESSAGE_LIMIT))
+}
+
+/// The account cached listings of `provider` are stored under; see
+/// [`Engine::cache_messages`].
+async fn cache_account(conn: &libsql::Connection, provider: Provider) -> Result<String> {
+    Ok(load_app_setting(conn, &account_email_key(provider))
+        .await?
+        .unwrap_or_else(|| UNKNOWN_ACCOUNT.to_owned()))
+}
+
+fn account_email_key(provider: Provider) -> String {
+    format!("account_email_{}", provider.as_key())
+}
+
+fn account_email_checked_key(provider: Provider) -> String {
+    format!("account_email_checked_{}", provider.as_key())
+}
+
+async fn load_app_setting(conn: &libsql::Connection, key: &str) -> Result<Option<String>> {
+    let mut rows = conn
+        .query(
+            "SELECT value FROM app_settings WHERE key = ?1",
+            libsql::params![key],
+        )
+        .await?;
+
+    match rows.next().await? {
+        Some(row) => Ok(empty_to_none(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+async fn save_app_setting(conn: &libsql::Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO app_settings (key, value)
+         VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET
+            value = excluded.value",
+        libsql::params![key, value],
+    )
+    .await?;
+    Ok(())
+}
+
+async fn clear_app_setting(conn: &libsql::Connection, key: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM app_settings WHERE key = ?1",
+        libsql::params![key],
+    )
+    .await?;
+    Ok(())
+}
+
+fn empty_to_none(value: String) -> Option<String> {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_owned())
+    }
+}
+
+fn normalized_secret(secret: Option<String>) -> Option<String> {
+    secret.and_then(|value| {
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            None
+        } else {
+            Some(trimmed.to_owned())
+        }
+    })
+}
+
+fn google_credentials_from_env(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Option<ProviderCredentials> {
+    let client_id = lookup("MAIL_GOOGLE_CLIENT_ID")?;
+    let client_id = client_id.trim().to_owned();
+    if client_id.is_empty() {
+        return None;
+    }
+
+    Some(ProviderCredentials {
+        client_id,
+        client_secret: normalized_secret(lookup("MAIL_GOOGLE_CLIENT_SECRET")),
+        tenant: None,
+    })
+}
+
+fn outlook_credentials_from_env(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Option<ProviderCredentials> {
+    let client_id = lookup("MAIL_OUTLOOK_CLIENT_ID")?;
+    let client_id = client_id.trim().to_owned();
+    if client_id.is_empty() {
+        return None;
+    }
+
+    let client_secret = normalized_secret(lookup("MAIL_OUTLOOK_CLIENT_SECRET"));
+    let tenant = lookup("MAIL_OUTLOOK_TENANT")
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty());
+
+    Some(ProviderCredentials {
+        client_id,
+        client_secret,
+        tenant,
+    })
+}
+
+fn validate_credentials(provider: Provider, credentials: &ProviderCredentials) -> Result<()> {
+    if let Some(warning) = check_client_id(provider, credentials.client_id.trim())? {
+        warn!(provider = provider.label(), "{warning}");
+    }
+    if provider == Provider::Outlook
+        && let Some(tenant) = &credentials.tenant
+    {
+        validate_tenant(tenant)?;
+    }
+
+    Ok(())
+}
+
+/// Format check of a client id: an error for ids that can't be right (empty,
+/// spaces, the other provider's format), a warning for ids that merely look
+/// unusual.
+fn check_client_id(provider: Provider, client_id: &str) -> Result<Option<String>> {
+    const GOOGLE_SUFFIX: &str = ".apps.googleusercontent.com";
+    if client_id.is_empty() {
+        bail!("Client ID is leeg.");
+    }
+    if client_id.chars().any(char::is_whitespace) {
+        bail!("Client ID bevat spaties. Controleer of je alleen de ID hebt geplakt.");
+    }
+    let warning = match provider {
+        Provider::Google if is_guid(client_id) => bail!(
+            "Dit lijkt een Microsoft Application (client) ID. Gebruik de OAuth Client ID uit Google Cloud."
+        ),
+        Provider::Google if !client_id.ends_with(GOOGLE_SUFFIX) => Some(format!(
+            "Google Client ID eindigt normaal op {GOOGLE_SUFFIX}; controleer of de ID volledig is."
+        )),
+        Provider::Outlook if client_id.ends_with(GOOGLE_SUFFIX) => bail!(
+            "Dit is een Google Client ID. Gebruik de Application (client) ID uit Microsoft Entra."
+        ),
+        Provider::Outlook if !is_guid(client_id) => Some(
+            "Outlook Client ID is normaal een GUID (xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx); controleer de Application (client) ID."
+                .to_owned(),
+        ),
+        _ => None,
+    };
+    Ok(warning)
+}
+
+fn is_guid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(index, c)| match index {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Accepts a tenant GUID or one of the multi-tenant authorities.
+fn validate_tenant(tenant: &str) -> Result<()> {
+    let is_guid = is_guid(tenant);
+    let is_authority = ["common", "organizations", "consumers"]
+        .iter()
+        .any(|authority| tenant.eq_ignore_ascii_case(authority));
+    if !is_guid && !is_authority {
+        bail!(
+            "Tenant '{tenant}' is ongeldig. Gebruik de tenant-ID (GUID) uit Microsoft Entra, 'organizations' of 'consumers'."
+        );
+    }
+    Ok(())
+}
+
+fn with_token_exchange_hint(provider: Provider, error: anyhow::Error) -> anyhow::Error {
+    let rendered = format!("{error:#}");
+    let lowered = rendered.to_ascii_lowercase();
+
+    if lowered.contains("client_secret is missing")
+        || lowered.contains("client secret is missing")
+        || lowered.contains("aadsts7000218")
+    {
+        let hint = match provider {
+            Provider::Google => {
+                "Deze Google OAuth client verwacht een Client Secret. Vul de juiste secret in, of \
+                 gebruik een Desktop app OAuth client in Google Cloud."
+            }
+            Provider::Outlook => {
+                "Deze Microsoft App Registration verwacht een Client Secret. Voeg een secret toe \
+                 (Certificates & secrets), of zet de app als public client (Allow public client \
+                 flows) en log in zonder secret."
+            }
+        };
+
+        return anyhow!("{rendered}\nTip: {hint}");
+    }
+
+    if lowered.contains("invalid_client") {
+        let hint = match provider {
+            Provider::Google => {
+                "Controleer Google OAuth: juiste Client ID/Secret uit hetzelfde project en \
+                 app-type Desktop app. Gebruik exact dezelfde credentials als in Google Cloud \
+                 Console."
+            }
+            Provider::Outlook => {
+                "Controleer Microsoft OAuth: Client ID/Secret moeten uit dezelfde App \
+                 Registration komen. Als je geen secret gebruikt, zet de app als public client \
+                 (mobile/desktop) en gebruik de loopback redirect URI."
+            }
+        };
+
+        return anyhow!("{rendered}\nTip: {hint}");
+    }
+
+    if provider == Provider::Outlook && is_interaction_required(&lowered) {
+        return anyhow!(
+            "{rendered}\nTip: Je organisatie vereist MFA of conditional access. Log opnieuw in \
+             met Outlook en rond de extra verificatie in de browser af. Blijft dit terugkomen, \
+             vraag je beheerder of deze app is toegestaan."
+        );
+    }
+
+    error
+}
+
+fn requires_interaction(error: &anyhow::Error) -> bool {
+    is_interaction_required(&format!("{error:#}").to_ascii_lowercase())
+}
+
+fn is_interaction_required(lowered: &str) -> bool {
+    lowered.contains("interaction_required")
+        || lowered.contains("aadsts50076")
+        || lowered.contains("aadsts50079")
+}
+
+fn is_token_expired(error: &anyhow::Error) -> bool {
+    format!("{error:#}")
+        .to_ascii_lowercase()
+        .contains("invalid_grant")
+}
+
+fn interaction_required(provider: Provider, error: anyhow::Error) -> anyhow::Error {
+    info!(
+        "refresh token needs interactive re-auth for provider={}: {error:#}",
+        provider.label()
+    );
+    EngineError::InteractionRequired {
+        provider,
+        detail: format!("{error:#}"),
+    }
+    .into()
+}
+
+pub fn db_location() -> DbLocation {
+    if let Ok(path) = std::env::var("MAIL_DB_PATH") {
+        return DbLocation {
+            path: PathBuf::from(path),
+            kind: DbLocationKind::EnvOverride,
+        };
+    }
+
+    if let Some(exe_dir) = executable_dir()
+        && portable_mode_enabled(&exe_dir)
+    {
+        return DbLocation {
+            path: exe_dir.join(LEGACY_DB_PATH),
+            kind: DbLocationKind::Portable,
+        };
+    }
+
+    let path = directories::ProjectDirs::from("", "", "mail")
+        .map(|dirs| dirs.data_dir().join(DB_FILE_NAME))
+        .unwrap_or_else(|| PathBuf::from(LEGACY_DB_PATH));
+
+    DbLocation {
+        path,
+        kind: DbLocationKind::UserData,
+    }
+}
+
+pub fn is_first_run() -> bool {
+    !db_location().path.exists() && !Path::new(LEGACY_DB_PATH).exists()
+}
+
+pub fn set_portable_mode(enabled: bool) -> Result<DbLocation> {
+    let current = db_location();
+    if current.kind == DbLocationKind::EnvOverride {
+        bail!("MAIL_DB_PATH is gezet; portable modus heeft dan geen effect.");
+    }
+    if !enabled && portable_mode_forced() {
+        bail!("MAIL_PORTABLE is gezet; haal die weg om portable modus uit te zetten.");
+    }
+
+    let exe_dir = executable_dir().ok_or_else(|| anyhow!("map van het programma is onbekend"))?;
+    let marker = exe_dir.join(PORTABLE_MARKER);
+    if enabled {
+        std::fs::write(&marker, b"")
+            .with_context(|| format!("kan {} niet aanmaken", marker.display()))?;
+    } else if marker.exists() {
+        std::fs::remove_file(&marker)
+            .with_context(|| format!("kan {} niet verwijderen", marker.display()))?;
+    }
+
+    let target = db_location();
+    if current.path != target.path && current.path.exists() && !target.path.exists() {
+        move_db_files(&current.path, &target.path)?;
+    }
+
+    Ok(target)
+}
+
+fn executable_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    exe.parent().map(Path::to_path_buf)
+}
+
+/// Whether `MAIL_PORTABLE` turns portable mode on, whatever the marker says.
+pub fn portable_mode_forced() -> bool {
+    std::env::var("MAIL_PORTABLE")
+        .map(|value| matches!(value.trim(), "1" | "true" | "on" | "yes"))
+        .unwrap_or(false)
+}
+
+fn portable_mode_enabled(exe_dir: &Path) -> bool {
+    portable_mode_forced() || exe_dir.join(PORTABLE_MARKER).exists()
+}
+
+fn migrate_legacy_db(target: &Path) -> Result<()> {
+    let legacy = Path::new(LEGACY_DB_PATH);
+    if target.exists() || !legacy.exists() {
+        return Ok(());
+    }
+
+    info!(
+        "migrating database from {} to {}",
+        legacy.display(),
+        target.display()
+    );
+    move_db_files(legacy, target)
+}
+
+fn move_db_files(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("kan DB map niet maken: {}", parent.display()))?;
+    }
+
+    for suffix in ["", "-wal", "-shm"] {
+        let source = PathBuf::from(format!("{}{suffix}", from.display()));
+        if !source.exists() {
+            continue;
+        }
+        let dest = PathBuf::from(format!("{}{suffix}", to.display()));
+        if std::fs::rename(&source, &dest).is_err() {
+            std::fs::copy(&source, &dest).with_context(|| {
+                format!(
+                    "kan {} niet kopiëren naar {}",
+                    source.display(),
+                    dest.display()
+                )
+            })?;
+            std::fs::remove_file(&source)
+                .with_context(|| format!("kan {} niet verwijderen", source.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn data_dir() -> PathBuf {
+    let path = db_location().path;
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Error text plus a short environment summary for bug reports. Secrets
+/// are redacted and the home directory is shortened to `~`.
+pub fn error_report(error: &str, provider: Option<Provider>) -> String {
+    let mut error = redact_secrets(error);
+    if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))
+        && let Some(home) = home.to_str().filter(|home| home.len() > 1)
+    {
+        error = error.replace(home, "~");
+    }
+    let storage = match db_location().kind {
+        DbLocationKind::EnvOverride => "MAIL_DB_PATH",
+        DbLocationKind::Portable => "portable",
+        DbLocationKind::UserData => "gebruikersmap",
+    };
+    format!(
+        "Fout: {error}\n\nmail {}\nOS: {} ({})\nProvider: {}\nDatabase: {storage}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        provider.map_or("geen", Provider::label),
+    )
+}
+
+/// New-issue URL with the first line of `report` as title and the whole
+/// report as body.
+pub fn issue_url(report: &str) -> String {
+    let title: String = report
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(80)
+        .collect();
+    let encode =
+        |value: &str| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+    format!(
+        "{ISSUE_TRACKER_URL}?title={}&body={}",
+        encode(&title),
+        encode(report)
+    )
+}
+
+pub fn redact_secrets(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(ch) = rest.chars().next() {
+        let at_word_start = output
+            .chars()
+            .next_back()
+            .is_none_or(|prev| !prev.is_ascii_alphanumeric() && prev != '_');
+
+        if at_word_start && let Some(consumed) = redact_secret_at(rest, &mut output) {
+            rest = &rest[consumed..];
+            continue;
+        }
+
+        output.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+
+    output
+}
+
+fn redact_secret_at(rest: &str, output: &mut String) -> Option<usize> {
+    for (key, separators) in SECRET_KEYS {
+        let Some(candidate) = rest.get(..key.len()) else {
+            continue;
+        };
+        if !candidate.eq_ignore_ascii_case(key) {
+            continue;
+        }
+
+        let after_key = &rest[key.len()..];
+        let separator_len = after_key
+            .find(|ch: char| !matches!(ch, ' ' | '"' | '\'' | '=' | ':'))
+            .unwrap_or(after_key.len())
+            .min(4);
+        let separator = &after_key[..separator_len];
+        if !separator.contains(*separators) {
+            continue;
+        }
+
+        let token_len = secret_token_len(&after_key[separator_len..]);
+        if token_len == 0 {
+            continue;
+        }
+
+        output.push_str(candidate);
+        output.push_str(separator);
+        output.push_str(REDACTED);
+        return Some(key.len() + separator_len + token_len);
+    }
+
+    for prefix in TOKEN_PREFIXES {
+        if rest.starts_with(prefix) {
+            let token_len = secret_token_len(rest);
+            if token_len >= 16 {
+                output.push_str(REDACTED);
+                return Some(token_len);
+            }
+        }
+    }
+
+    None
+}
+
+fn secret_token_len(value: &str) -> usize {
+    value
+        .find(|ch: char| !(ch.is_ascii_alphanumeric() || "-._~+/%=".contains(ch)))
+        .unwrap_or(value.len())
+}
+
+/// Whether the request's `Host` header names the status server itself, so a
+/// rebound DNS name from a web page is refused.
+fn status_host_allowed(request: &str, port: u16) -> bool {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .is_some_and(|(_, host)| {
+            let host = host.trim().to_ascii_lowercase();
+            host == format!("127.0.0.1:{port}") || host == format!("localhost:{port}")
+        })
+}
+
+async fn connect_db(path: &Path, busy_timeout: Duration) -> Result<libsql::Connection> {
+    open_db(path, busy_timeout)
+        .await
+        .map_err(|error| match StorageProblem::detect(&error) {
+            Some(problem) => error.context(EngineError::StorageUnwritable {
+                path: path.parent().unwrap_or(path).to_owned(),
+                problem,
+            }),
+            None => error,
+        })
+}
+
+async fn open_db(path: &Path, busy_timeout: Duration) -> Result<libsql::Connection> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("kan DB map niet maken: {}", parent.display()))?;
+        // Before the first open, a probe file gives a plain io error instead
+        // of whatever SQLite makes of an unwritable directory.
+        if !path.exists() {
+            let probe = parent.join(".mail-write-test");
+            std::fs::write(&probe, b"")
+                .with_context(|| format!("kan niet schrijven in {}", parent.display()))?;
+            let _ = std::fs::remove_file(&probe);
+        }
+    }
+
+    let db = Builder::new_local(path).build().await?;
+    let conn = db.connect()?;
+    conn.busy_timeout(busy_timeout)?;
+    // WAL lets readers proceed while another connection holds the write lock.
+    conn.query("PRAGMA journal_mode = WAL", ()).await?;
+    ensure_schema(&conn).await?;
+    Ok(conn)
+}
+
+async fn ensure_schema(conn: &libsql::Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_settings (
+            provider TEXT PRIMARY KEY NOT NULL,
+            client_id TEXT NOT NULL,
+            client_secret TEXT,
+            tenant TEXT
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_tokens (
+            provider TEXT PRIMARY KEY NOT NULL,
+            refresh_token TEXT NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS accounts (
+            provider TEXT NOT NULL,
+            email TEXT NOT NULL,
+            refresh_token TEXT NOT NULL,
+            PRIMARY KEY (provider, email)
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS trusted_image_senders (
+            sender TEXT PRIMARY KEY NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY NOT NULL,
+            value TEXT NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS imported_messages (
+            id TEXT PRIMARY KEY NOT NULL,
+            subject TEXT NOT NULL,
+            sender TEXT NOT NULL,
+            date TEXT NOT NULL,
+            body TEXT NOT NULL,
+            body_html TEXT,
+            size INTEGER NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS snoozes (
+            provider TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            due_at INTEGER NOT NULL,
+            PRIMARY KEY (provider, message_id)
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS followups (
+            provider TEXT NOT NULL,
+            account TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            due_at INTEGER NOT NULL,
+            PRIMARY KEY (provider, account, message_id)
+        )",
+        (),
+    )
+    .await?;
+
+    // Follow-ups were first kept per provider only; the account joined the
+    // key later, so the table is rebuilt with the account last signed in.
+    let mut columns = conn
+        .query(
+            "SELECT 1 FROM pragma_table_info('followups') WHERE name = 'account'",
+            (),
+        )
+        .await?;
+    if columns.next().await?.is_none() {
+        conn.execute_batch(&format!(
+            "BEGIN;
+             ALTER TABLE followups RENAME TO followups_old;
+             CREATE TABLE followups (
+                provider TEXT NOT NULL,
+                account TEXT NOT NULL,
+                message_id TEXT NOT NULL,
+                due_at INTEGER NOT NULL,
+                PRIMARY KEY (provider, account, message_id)
+             );
+             INSERT INTO followups (provider, account, message_id, due_at)
+                SELECT provider,
+                    COALESCE(
+                        (SELECT NULLIF(value, '') FROM app_settings
+                         WHERE key = 'account_email_' || followups_old.provider),
+                        '{UNKNOWN_ACCOUNT}'
+                    ),
+                    message_id, due_at
+                FROM followups_old;
+             DROP TABLE followups_old;
+             COMMIT;"
+        ))
+        .await?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            condition_kind TEXT NOT NULL,
+            condition_value TEXT NOT NULL,
+            action_kind TEXT NOT NULL,
+            action_value TEXT NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blocked_senders (
+            sender TEXT PRIMARY KEY NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS folder_syncs (
+            provider TEXT NOT NULL,
+            account TEXT NOT NULL,
+            folder TEXT NOT NULL,
+            synced_at INTEGER NOT NULL,
+            PRIMARY KEY (provider, account, folder)
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS cached_messages (
+            provider TEXT NOT NULL,
+            account TEXT NOT NULL,
+            folder TEXT NOT NULL,
+            id TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            sender TEXT NOT NULL,
+            date TEXT NOT NULL,
+            received_at INTEGER,
+            body TEXT NOT NULL,
+            body_html TEXT,
+            size INTEGER,
+            PRIMARY KEY (provider, account, id)
+        )",
+        (),
+    )
+    .await?;
+
+    // `tenant` came after the first settings schema.
+    let mut columns = conn
+        .query(
+            "SELECT 1 FROM pragma_table_info('oauth_settings') WHERE name = 'tenant'",
+            (),
+        )
+        .await?;
+    if columns.next().await?.is_none() {
+        conn.execute("ALTER TABLE oauth_settings ADD COLUMN tenant TEXT", ())
+            .await?;
+    }
+
+    let mut columns = conn
+        .query(
+            "SELECT 1 FROM pragma_table_info('oauth_tokens') WHERE name = 'scopes'",
+            (),
+        )
+        .await?;
+    if columns.next().await?.is_none() {
+        conn.execute("ALTER TABLE oauth_tokens ADD COLUMN scopes TEXT", ())
+            .await?;
+    }
+
+    // `received_at` came after the first cache schema.
+    let mut columns = conn
+        .query(
+            "SELECT 1 FROM pragma_table_info('cached_messages') WHERE name = 'received_at'",
+            (),
+        )
+        .await?;
+    if columns.next().await?.is_none() {
+        conn.execute(
+            "ALTER TABLE cached_messages ADD COLUMN received_at INTEGER",
+            (),
+        )
+        .await?;
+    }
+
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(
+            subject, sender, body,
+            content = 'cached_messages', content_rowid = 'rowid'
+        )",
+        (),
+    )
+    .await?;
+
+    // Keep the external-content FTS index in sync with cached_messages.
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS cached_messages_ai AFTER INSERT ON cached_messages BEGIN
+            INSERT INTO message_search (rowid, subject, sender, body)
+            VALUES (new.rowid, new.subject, new.sender, new.body);
+        END",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS cached_messages_ad AFTER DELETE ON cached_messages BEGIN
+            INSERT INTO message_search (message_search, rowid, subject, sender, body)
+            VALUES ('delete', old.rowid, old.subject, old.sender, old.body);
+        END",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS cached_messages_au AFTER UPDATE ON cached_messages BEGIN
+            INSERT INTO message_search (message_search, rowid, subject, sender, body)
+            VALUES ('delete', old.rowid, old.subject, old.sender, old.body);
+            INSERT INTO message_search (rowid, subject, sender, body)
+            VALUES (new.rowid, new.subject, new.sender, new.body);
+        END",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS local_flags (
+            provider TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            pinned INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (provider, message_id)
+        )",
+        (),
+    )
+    .await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS local_draft (
+            slot INTEGER PRIMARY KEY NOT NULL,
+            draft_id TEXT,
+            recipients TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            body TEXT NOT NULL,
+            attachment_names TEXT NOT NULL DEFAULT ''
+        )",
+        (),
+    )
+    .await?;
+
+    let mut columns = conn
+        .query(
+            "SELECT 1 FROM pragma_table_info('local_draft') WHERE name = 'attachment_names'",
+            (),
+        )
+        .await?;
+    if columns.next().await?.is_none() {
+        conn.execute(
+            "ALTER TABLE local_draft ADD COLUMN attachment_names TEXT NOT NULL DEFAULT ''",
+            (),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ProviderConfig {
+    provider: Provider,
+    credentials: ProviderCredentials,
+    auth_url: String,
+    token_url: String,
+    scopes: Vec<String>,
+}
+
+impl ProviderConfig {
+    fn from_credentials(provider: Provider, credentials: ProviderCredentials) -> Self {
+        match provider {
+            Provider::Google => Self {
+                provider,
+                credentials,
+                auth_url: "https://accounts.google.com/o/oauth2/v2/auth".to_owned(),
+                token_url: "https://oauth2.googleapis.com/token".to_owned(),
+                scopes: owned_scopes(GOOGLE_SCOPES),
+            },
+            Provider::Outlook => {
+                let tenant = credentials.tenant.as_deref().unwrap_or("common");
+                let authority = format!("https://login.microsoftonline.com/{tenant}/oauth2/v2.0");
+                Self {
+                    provider,
+                    auth_url: format!("{authority}/authorize"),
+                    token_url: format!("{authority}/token"),
+                    credentials,
+                    scopes: owned_scopes(OUTLOOK_SCOPES),
+                }
+            }
+        }
+    }
+}
+
+const GOOGLE_SCOPES: &[&str] = &[
+    "openid",
+    "email",
+    "profile",
+    "https://www.googleapis.com/auth/gmail.readonly",
+    "https://www.googleapis.com/auth/gmail.send",
+    "https://www.googleapis.com/auth/gmail.compose",
+    "https://www.googleapis.com/auth/gmail.modify",
+];
+
+const OUTLOOK_SCOPES: &[&str] = &[
+    "openid",
+    "email",
+    "profile",
+    "offline_access",
+    "Mail.Read",
+    "Mail.Send",
+    "Mail.ReadWrite",
+];
+
+fn owned_scopes(scopes: &[&str]) -> Vec<String> {
+    scopes.iter().map(|scope| (*scope).to_owned()).collect()
+}
+
+type OAuthClient =
+    BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>;
+
+#[derive(Debug, Clone)]
+struct TokenSet {
+    access_token: String,
+    refresh_token: Option<String>,
+    /// The `scope` field of the token response, space separated; providers
+    /// may omit it when everything requested was granted.
+    scopes: Option<String>,
+}
+
+fn build_oauth_client(config: &ProviderConfig, redirect_url: Url) -> Result<OAuthClient> {
+    let mut client = BasicClient::new(ClientId::new(config.credentials.client_id.clone()))
+        .set_auth_uri(AuthUrl::new(config.auth_url.clone())?)
+        .set_token_uri(TokenUrl::new(config.token_url.clone())?)
+        .set_redirect_uri(RedirectUrl::new(redirect_url.to_string())?)
+        .set_auth_type(AuthType::RequestBody);
+
+    if let Some(secret) = &config.credentials.client_secret {
+        client = client.set_client_secret(ClientSecret::new(secret.clone()));
+    }
+
+    Ok(client)
+}
+
+#[derive(Debug)]
+struct RedirectTarget {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl RedirectTarget {
+    fn from_url(url: &Url) -> Result<Self> {
+        if url.scheme() != "http" {
+            bail!("redirect URI moet http zijn (localhost callback)");
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("redirect URI mist host"))?
+            .to_owned();
+
+        if host != "127.0.0.1" && host != "localhost" {
+            bail!("redirect URI host moet localhost of 127.0.0.1 zijn");
+        }
+
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("redirect URI mist poort"))?;
+
+        let path = url.path().to_owned();
+        if path.is_empty() || path == "/" {
+            bail!("redirect URI pad moet specifiek zijn (bijv. /callback)");
+        }
+
+        Ok(Self { host, port, path })
+    }
+}
+
+async fn wait_for_oauth_code(
+    target: &RedirectTarget,
+    expected_state: &str,
+    callback_timeout: Duration,
+    read_timeout: Duration,
+) -> Result<String> {
+    // A login that was just abandoned for a retry may still be releasing the
+    // port, so an occupied address gets a few short retries.
+    let mut attempts = 0;
+    let listener = loop {
+        match TcpListener::bind((target.host.as_str(), target.port)).await {
+            Ok(listener) => break listener,
+            Err(error) if error.kind() == std::io::ErrorKind::AddrInUse && attempts < 10 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(error) => {
+                return Err(error).with_context(|| {
+                    format!(
+                        "kan callback server niet starten op {}:{}",
+                        target.host, target.port
+                    )
+                });
+            }
+        }
+    };
+
+    let (mut stream, _) = timeout(callback_timeout, listener.accept())
+        .await
+        .context("timeout wachtend op OAuth callback, probeer opnieuw in te loggen")??;
+
+    let mut buf = [0_u8; 8192];
+    let n = timeout(read_timeout, stream.read(&mut buf))
+        .await
+        .context("timeout bij lezen callback request")??;
+    if n == 0 {
+        bail!("lege callback request ontvangen");
+    }
+
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let first_line = request
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("onleesbare callback request"))?;
+    let path_with_query = first_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("onleesbare callback request line"))?;
+
+    let callback_url = Url::parse(&format!("http://localhost{path_with_query}"))
+        .context("ongeldige callback URL")?;
+    let (status, body, code) = parse_callback_params(&callback_url, target, expected_state)?;
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+
+    code
+}
+
+fn authorize_request(
+    oauth: &OAuthClient,
+    config: &ProviderConfig,
+    mode: LoginMode,
+    needs_refresh_token: bool,
+) -> (String, String, PkceCodeVerifier) {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let mut request = oauth
+        .authorize_url(CsrfToken::new_random)
+        .set_pkce_challenge(pkce_challenge);
+
+    for scope in &config.scopes {
+        request = request.add_scope(Scope::new(scope.clone()));
+    }
+
+    match (config.provider, mode) {
+        (Provider::Google, LoginMode::SelectAccount) => {
+            request = request
+                .add_extra_param("access_type", "offline")
+                .add_extra_param("prompt", "select_account consent");
+        }
+        (Provider::Google, _) if needs_refresh_token => {
+            request = request
+                .add_extra_param("access_type", "offline")
+                .add_extra_param("prompt", "consent");
+        }
+        (Provider::Outlook, LoginMode::SelectAccount) => {
+            request = request.add_extra_param("prompt", "select_account");
+        }
+        (Provider::Outlook, LoginMode::Consent) => {
+            request = request.add_extra_param("prompt", "consent");
+        }
+        _ => {}
+    }
+
+    let (auth_url, csrf_state) = request.url();
+    (
+        auth_url.to_string(),
+        csrf_state.secret().clone(),
+        pkce_verifier,
+    )
+}
+
+/// Pulls the authorization code out of a pasted redirect URL, a bare query
+/// string or the code itself.
+fn parse_pasted_code(pasted: &str, expected_state: &str) -> Result<String> {
+    let pasted = pasted.trim();
+    if pasted.is_empty() {
+        bail!("Plak de adresregel of code uit de browser");
+    }
+    let query = match Url::parse(pasted) {
+        Ok(url) => url.query().unwrap_or_default().to_owned(),
+        Err(_) if pasted.contains("code=") || pasted.contains("error=") => {
+            pasted.trim_start_matches('?').to_owned()
+        }
+        Err(_) => return Ok(pasted.to_owned()),
+    };
+
+    let mut code = None;
+    let mut state = None;
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            "error" => bail!("OAuth login mislukt: {value}"),
+            _ => {}
+        }
+    }
+    if state.is_some_and(|state| state != expected_state) {
+        bail!("OAuth state mismatch");
+    }
+    code.ok_or_else(|| anyhow!("Geplakte tekst bevat geen code"))
+}
+
+fn parse_callback_params(
+    callback_url: &Url,
+    target: &RedirectTarget,
+    expected_state: &str,
+) -> Result<(&'static str, String, Result<String>)> {
+    if callback_url.path() != target.path {
+        let msg = format!("Ongeldige callback path: {}", callback_url.path());
+        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
+    }
+
+    let mut code: Option<String> = None;
+    let mut state: Option<String> = None;
+    let mut error: Option<String> = None;
+
+    for (key, value) in callback_url.query_pairs() {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            "error" => error = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    if let Some(error) = error {
+        let msg = format!("OAuth login mislukt: {error}");
+        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
+    }
+
+    if state.as_deref() != Some(expected_state) {
+        let msg = "OAuth state mismatch".to_owned();
+        return Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))));
+    }
+
+    match code {
+        Some(code) => Ok((
+            "200 OK",
+            "Login geslaagd. Je kunt dit tabblad sluiten.".to_owned(),
+            Ok(code),
+        )),
+        None => {
+            let msg = "OAuth callback bevat geen code".to_owned();
+            Ok(("400 Bad Request", msg.clone(), Err(anyhow!(msg))))
+        }
+    }
+}
+
+async fn exchange_token(
+    http_client: &Client,
+    client: &OAuthClient,
+    code: String,
+    pkce_verifier: PkceCodeVerifier,
+) -> Result<TokenSet> {
+    let response = client
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(pkce_verifier)
+        .request_async(http_client)
+        .await
+        .context("token exchange mislukt")?;
+
+    Ok(TokenSet {
+        access_token: response.access_token().secret().to_owned(),
+        refresh_token: response
+            .refresh_token()
+            .map(|token| token.secret().to_owned()),
+        scopes: token_scopes(&response),
+    })
+}
+
+fn token_scopes(response: &impl TokenResponse) -> Option<String> {
+    let scopes = response.scopes()?;
+    Some(
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+async fn exchange_refresh_token(
+    http_client: &Client,
+    client: &OAuthClient,
+    refresh_token: String,
+) -> Result<TokenSet> {
+    let response = client
+        .exchange_refresh_token(&RefreshToken::new(refresh_token))
+        .request_async(http_client)
+        .await
+        .context("refresh token exchange mislukt")?;
+
+    Ok(TokenSet {
+        access_token: response.access_token().secret().to_owned(),
+        refresh_token: response
+            .refresh_token()
+            .map(|token| token.secret().to_owned()),
+        scopes: token_scopes(&response),
+    })
+}
+
+/// Provider-specific half of a mailbox fetch. [`fetch_listing`] drives the
+/// shared pipeline (account lookup, listing, concurrent detail fetches), so a
+/// new backend only has to implement these calls.
+trait MailProvider {
+    fn provider(&self) -> Provider;
+
+    async fn userinfo(&self) -> Result<Option<String>>;
+
+    /// One page of the listing; `cursor` is a previous
+    /// [`MessageListing::next_cursor`].
+    async fn list_messages(
+        &self,
+        limit: usize,
+        range: &DateRange,
+        folder: Option<&str>,
+        cursor: Option<&str>,
+    ) -> Result<MessageListing>;
+
+    /// List-level details for a message the listing only returned an id for.
+    async fn message_summary(&self, id: String) -> Result<MailMessage>;
+
+    async fn get_message(&self, message_id: &str) -> Result<MailMessage>;
+
+    /// The unparsed RFC 822 source of a message.
+    async fn get_raw(&self, message_id: &str) -> Result<Vec<u8>>;
+
+    /// List-level details of every message in a conversation; bodies are
+    /// loaded per message with [`MailProvider::get_message`].
+    async fn list_thread(&self, conversation_id: &str) -> Result<Vec<MailMessage>>;
+}
+
+struct MessageListing {
+    entries: Vec<ListedMessage>,
+    next_cursor: Option<String>,
+    estimated_total: Option<u64>,
+    mailbox_empty: bool,
+}
+
+enum ListedMessage {
+    Id(String),
+    Complete(Box<MailMessage>),
+}
+
+struct ListingOptions<'a> {
+    limit: usize,
+    concurrency: usize,
+    eager_body_count: usize,
+    on_message: Option<&'a FetchedMessageHook>,
+    transform: Option<&'a MessageTransformHook>,
+    /// Remembered account email; skips the userinfo request when set.
+    account: Option<&'a str>,
+}
+
+/// Dropping the future cancels the listing together with every detail
+/// request still in flight, which is how the UI aborts a stalled fetch.
+async fn fetch_listing<P: MailProvider>(
+    provider: &P,
+    options: &ListingOptions<'_>,
+    range: &DateRange,
+    folder: Option<&str>,
+    cursor: Option<&str>,
+) -> Result<LoginResult> {
+    let &ListingOptions {
+        limit,
+        concurrency,
+        eager_body_count,
+        on_message,
+        transform,
+        ..
+    } = options;
+    let account = match options.account {
+        Some(account) => Some(account.to_owned()),
+        None => provider.userinfo().await?,
+    };
+    let MessageListing {
+        entries,
+        next_cursor,
+        estimated_total,
+        mailbox_empty,
+    } = provider.list_messages(limit, range, folder, cursor).await?;
+
+    let results: Vec<LoadedEntry> = stream::iter(entries.into_iter().enumerate())
+        .map(|(index, entry)| async move {
+            let (id, summary) = match entry {
+                ListedMessage::Id(id) => (id, None),
+                ListedMessage::Complete(message) => (message.id.clone(), Some(*message)),
+            };
+            if index >= eager_body_count
+                && let Some(summary) = summary
+            {
+                return LoadedEntry::Loaded(summary);
+            }
+            let loaded = if index < eager_body_count {
+                provider.get_message(&id).await
+            } else {
+                provider.message_summary(id.clone()).await
+            };
+            match loaded {
+                Ok(message) => LoadedEntry::Loaded(message),
+                Err(error)
+                    if matches!(error.downcast_ref(), Some(EngineError::NotFound { .. })) =>
+                {
+                    LoadedEntry::Gone(error)
+                }
+                Err(error) => {
+                    LoadedEntry::Failed(summary.unwrap_or_else(|| unloaded_message(id)), error)
+                }
+            }
+        })
+        .buffered(concurrency)
+        .map(|mut entry| {
+            if let LoadedEntry::Loaded(message) = &mut entry {
+                if let Some(MessageTransformHook(hook)) = transform {
+                    hook(message);
+                }
+                if let Some(FetchedMessageHook(hook)) = on_message {
+                    hook(message);
+                }
+            }
+            entry
+        })
+        .collect()
+        .await;
+
+    // One failing message (deleted meanwhile, a single 404) should not cost
+    // the whole page: deleted ones are skipped, others are kept for a retry.
+    // Only when nothing loaded is the first error returned.
+    let listed = results.len();
+    let mut messages = Vec::with_capacity(listed);
+    let mut load_errors = HashMap::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            LoadedEntry::Loaded(message) => messages.push(message),
+            LoadedEntry::Failed(message, error) => {
+                warn!(error = %format!("{error:#}"), "message body not loaded");
+                load_errors.insert(message.id.clone(), format!("{error:#}"));
+                messages.push(message);
+                first_error.get_or_insert(error);
+            }
+            LoadedEntry::Gone(error) => {
+                warn!(error = %format!("{error:#}"), "message skipped");
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    let failed = listed - messages.len() + load_errors.len();
+    if failed == listed
+        && let Some(error) = first_error
+    {
+        return Err(error);
+    }
+    let warning = (failed > 0).then(|| match failed {
+        1 => "1 bericht kon niet geladen worden".to_owned(),
+        count => format!("{count} berichten konden niet geladen worden"),
+    });
+
+    Ok(LoginResult {
+        provider: provider.provider(),
+        account: account.unwrap_or_else(|| UNKNOWN_ACCOUNT.to_owned()),
+        messages: group_by_conversation(messages),
+        next_cursor,
+        estimated_total,
+        mailbox_empty,
+        warning,
+        load_errors,
+    })
+}
+
+enum LoadedEntry {
+    Loaded(MailMessage),
+    /// Loading failed; holds the list summary or a placeholder.
+    Failed(MailMessage, anyhow::Error),
+    /// Deleted since it was listed.
+    Gone(anyhow::Error),
+}
+
+/// Stands in for a listed message whose details could not be loaded.
+fn unloaded_message(id: String) -> MailMessage {
+    MailMessage {
+        id,
+        labels: Vec::new(),
+        subject: "(bericht niet geladen)".to_owned(),
+        from: "(onbekend)".to_owned(),
+        date: "(onbekend)".to_owned(),
+        body: "(geen inhoud)".to_owned(),
+        body_html: None,
+        size_estimate: None,
+        has_attachments: false,
+        unsubscribe: None,
+        authentication: None,
+        spam_score: None,
+        is_suspicious: false,
+        importance: Importance::Normal,
+        conversation_id: None,
+        internet_message_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// Moves messages next to the newest message of their conversation, keeping
+/// the order within each conversation and of the conversations themselves.
+pub fn group_by_conversation(messages: Vec<MailMessage>) -> Vec<MailMessage> {
+    let mut groups: Vec<Vec<MailMessage>> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for message in messages {
+        match positions.get(message.conversation_key()) {
+            Some(&position) => groups[position].push(message),
+            None => {
+                positions.insert(message.conversation_key().to_owned(), groups.len());
+                groups.push(vec![message]);
+            }
+        }
+    }
+    groups.into_iter().flatten().collect()
+}
+
+struct GmailApi<'a> {
+    http: Client,
+    access_token: &'a str,
+    extra_headers: &'a [String],
+    /// Passed to [`google_mail_message`] for full messages.
+    prefer_html: bool,
+    retry: RetryPolicy,
+}
+
+impl GmailApi<'_> {
+    fn metadata_request(&self, url: String) -> reqwest::RequestBuilder {
+        self.http
+            .get(url)
+            .query(&[
+                ("format", "metadata"),
+                ("metadataHeaders", "Subject"),
+                ("metadataHeaders", "From"),
+                ("metadataHeaders", "Date"),
+                ("metadataHeaders", "Message-ID"),
+                ("metadataHeaders", "List-Unsubscribe"),
+                ("metadataHeaders", "List-Unsubscribe-Post"),
+                ("metadataHeaders", "Authentication-Results"),
+                ("metadataHeaders", "ARC-Authentication-Results"),
+                ("metadataHeaders", "X-Priority"),
+                ("metadataHeaders", "Importance"),
+            ])
+            .query(
+                &self
+                    .extra_headers
+                    .iter()
+                    .map(|name| ("metadataHeaders", name.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+            .bearer_auth(self.access_token)
+    }
+}
+
+impl MailProvider for GmailApi<'_> {
+    fn provider(&self) -> Provider {
+        Provider::Google
+    }
+
+    async fn userinfo(&self) -> Result<Option<String>> {
+        let me: GoogleUserInfo = send_google_json(
+            self.retry,
+            self.http
+                .get("https://www.googleapis.com/oauth2/v3/userinfo")
+                .bearer_auth(self.access_token),
+            "Google userinfo",
+        )
+        .await?;
+        Ok(me.email)
+    }
+
+    async fn list_messages(
+        &self,
+        limit: usize,
+        range: &DateRange,
+        folder: Option<&str>,
+        cursor: Option<&str>,
+    ) -> Result<MessageListing> {
+        let mut list_request = self
+            .http
+            .get("https://gmail.googleapis.com/gmail/v1/users/me/messages")
+            .query(&[("maxResults", limit)]);
+        if let Some(page_token) = cursor {
+            list_request = list_request.query(&[("pageToken", page_token)]);
+        }
+        if let Some(query) = range.gmail_query() {
+            list_request = list_request.query(&[("q", query)]);
+        }
+        if let Some(label) = folder {
+            list_request = list_request.query(&[("labelIds", label)]);
+            if matches!(label, "SPAM" | "TRASH") {
+                list_request = list_request.query(&[("includeSpamTrash", "true")]);
+            }
+        }
+        let list: GoogleListResponse = send_google_json(
+            self.retry,
+            list_request.bearer_auth(self.access_token),
+            "Gmail messages list",
+        )
+        .await?;
+
+        let GoogleListResponse {
+            messages: refs,
+            next_page_token,
+            result_size_estimate,
+        } = list;
+        let mailbox_empty = refs.is_none() && next_page_token.is_none();
+        if mailbox_empty && result_size_estimate.is_none() {
+            warn!("Gmail messages list returned neither messages nor resultSizeEstimate");
+        }
+
+        Ok(MessageListing {
+            entries: refs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|message| ListedMessage::Id(message.id))
+                .collect(),
+            next_cursor: next_page_token,
+            estimated_total: result_size_estimate,
+            mailbox_empty,
+        })
+    }
+
+    async fn message_summary(&self, id: String) -> Result<MailMessage> {
+        let detail: GoogleMessageResponse = send_google_json(
+            self.retry,
+            self.metadata_request(format!(
+                "https://gmail.googleapis.com/gmail/v1/users/me/messages/{id}"
+            )),
+            "Gmail message detail",
+        )
+        .await?;
+        Ok(google_mail_message(id, detail, self.extra_headers, false))
+    }
+
+    async fn list_thread(&self, conversation_id: &str) -> Result<Vec<MailMessage>> {
+        let thread: GoogleThreadResponse = send_google_json(
+            self.retry,
+            self.metadata_request(format!(
+                "https://gmail.googleapis.com/gmail/v1/users/me/threads/{conversation_id}"
+            )),
+            "Gmail thread",
+        )
+        .await?;
+        Ok(thread
+            .messages
+            .unwrap_or_default()
+            .into_iter()
+            .map(|message| {
+                google_mail_message(message.id, message.detail, self.extra_headers, false)
+            })
+            .collect())
+    }
+
+    async fn get_message(&self, message_id: &str) -> Result<MailMessage> {
+        let endpoint = "Gmail message detail";
+        let response = self
+            .http
+            .get(format!(
+                "https://gmail.googleapis.com/gmail/v1/users/me/messages/{message_id}"
+            ))
+            .query(&[("format", "full")])
+            .bearer_auth(self.access_token)
+            .send()
+            .await
+            .with_context(|| format!("{endpoint} request mislukt"))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(EngineError::NotFound {
+                provider: Provider::Google,
+                id: message_id.to_owned(),
+            }
+            .into());
+        }
+
+        let detail: GoogleMessageResponse = read_google_json(response, endpoint).await?;
+        let inline_parts = detail
+            .payload
+            .as_ref()
+            .map(google_inline_parts)
+            .unwrap_or_default();
+        let mut message = google_mail_message(
+            message_id.to_owned(),
+            detail,
+            self.extra_headers,
+            self.prefer_html,
+        );
+
+        if let Some(html) = message.body_html.as_deref()
+            && html.contains("cid:")
+        {
+            let mut images = Vec::new();
+            for (content_id, mime_type, body) in inline_parts {
+                if !html.contains(&format!("cid:{content_id}")) {
+                    continue;
+                }
+                let data = match (body.data, body.attachment_id) {
+                    (Some(data), _) => data,
+                    (None, Some(attachment_id)) => {
+                        let url = format!(
+                            "https://gmail.googleapis.com/gmail/v1/users/me/messages/{message_id}/attachments/{attachment_id}"
+                        );
+                        let attachment: GoogleBody = send_google_json(
+                            self.retry,
+                            self.http.get(url).bearer_auth(self.access_token),
+                            "Gmail attachment",
+                        )
+                        .await?;
+                        attachment.data.unwrap_or_default()
+                    }
+                    (None, None) => continue,
+                };
+                if let Ok(bytes) = BASE64_URL.decode(data.trim_end_matches('=')) {
+                    images.push((content_id, inline_image_uri(&mime_type, &bytes)));
+                }
+            }
+            message.body_html = Some(resolve_inline_images(html, &images));
+        }
+
+        Ok(message)
+    }
+
+    async fn get_raw(&self, message_id: &str) -> Result<Vec<u8>> {
+        let endpoint = "Gmail raw message";
+        let response = self
+            .http
+            .get(format!(
+                "https://gmail.googleapis.com/gmail/v1/users/me/messages/{message_id}"
+            ))
+            .query(&[("format", "raw")])
+            .bearer_auth(self.access_token)
+            .send()
+            .await
+            .with_context(|| format!("{endpoint} request mislukt"))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(EngineError::NotFound {
+                provider: Provider::Google,
+                id: message_id.to_owned(),
+            }
+            .into());
+        }
+
+        let detail: GoogleRawResponse = read_google_json(response, endpoint).await?;
+        let raw = detail.raw.unwrap_or_default();
+        BASE64_URL
+            .decode(raw.trim_end_matches('='))
+            .context("Gmail raw bericht is geen geldige base64url")
+    }
+}
+
+fn google_inline_parts(payload: &GooglePayload) -> Vec<(String, String, GoogleBody)> {
+    let mut parts = Vec::new();
+    let content_id = payload
+        .headers
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("Content-ID"))
+        .map(|header| header.value.trim().trim_matches(['<', '>']).to_owned());
+    if let (Some(content_id), Some(mime_type), Some(body)) = (
+        content_id,
+        payload.mime_type.as_deref(),
+        payload.body.as_ref(),
+    ) && mime_type.to_ascii_lowercase().starts_with("image/")
+    {
+        parts.push((content_id, mime_type.to_owned(), body.clone()));
+    }
+    for part in payload.parts.as_deref().unwrap_or_default() {
+        parts.extend(google_inline_parts(part));
+    }
+    parts
+}
+
+/// `prefer_html` picks the HTML part over `text/plain` for `body` when a
+/// message carries both.
+fn google_mail_message(
+    id: String,
+    detail: GoogleMessageResponse,
+    extra_headers: &[String],
+    prefer_html: bool,
+) -> MailMessage {
+    let GoogleMessageResponse {
+        payload,
+        snippet,
+        label_ids,
+        size_estimate,
+        thread_id,
+    } = detail;
+    let raw_html = payload
+        .as_ref()
+        .and_then(|payload| google_body_of_type(payload, "text/html"));
+    let html_text = || raw_html.as_deref().map(html_to_text);
+    let plain_text = || {
+        payload
+            .as_ref()
+            .and_then(google_plain_text_body)
+            .filter(|value| !value.trim().is_empty())
+    };
+    let body = if prefer_html {
+        html_text()
+            .filter(|value| !value.trim().is_empty())
+            .or_else(plain_text)
+    } else {
+        plain_text().or_else(html_text)
+    };
+    let body = body
+        .or(snippet)
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "(geen inhoud)".to_owned());
+    let body_html = raw_html.map(|html| sanitize_html(&html));
+    let has_attachments = payload.as_ref().is_some_and(google_has_attachments);
+    let signals = header_signals(
+        payload
+            .as_ref()
+            .and_then(|payload| payload.headers.as_deref())
+            .unwrap_or_default()
+            .iter()
+            .map(|header| (header.name.as_str(), header.value.as_str())),
+    );
+    let extra = payload
+        .as_ref()
+        .and_then(|payload| payload.headers.as_deref())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|header| {
+            let name = extra_headers
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(&header.name))?;
+            Some((name.clone(), serde_json::Value::from(header.value.clone())))
+        })
+        .collect();
+    let internet_message_id = payload
+        .as_ref()
+        .and_then(|payload| payload.headers.as_deref())
+        .unwrap_or_default()
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("Message-ID"))
+        .and_then(|header| normalize_message_id(&header.value));
+    let (subject, from, date) = extract_google_headers(payload);
+    let labels = label_ids.unwrap_or_default();
+    let is_suspicious = labels.iter().any(|label| label == "SPAM") || signals.is_suspicious();
+    let importance = if labels.iter().any(|label| label == "IMPORTANT") {
+        Importance::High
+    } else {
+        signals.importance.unwrap_or_default()
+    };
+
+    MailMessage {
+        id,
+        labels,
+        subject,
+        from,
+        date,
+        body,
+        body_html,
+        size_estimate,
+        unsubscribe: signals.unsubscribe,
+        authentication: signals.authentication,
+        spam_score: signals.spam_score,
+        is_suspicious,
+        importance,
+        has_attachments,
+        conversation_id: thread_id,
+        internet_message_id,
+        extra,
+    }
+}
+
+struct HeaderSignals {
+    unsubscribe: Option<Unsubscribe>,
+    authentication: Option<AuthenticationResults>,
+    spam_score: Option<u8>,
+    importance: Option<Importance>,
+}
+
+impl HeaderSignals {
+    fn is_suspicious(&self) -> bool {
+        self.spam_score
+            .is_some_and(|score| score >= SPAM_SCORE_THRESHOLD)
+            || self
+                .authentication
+                .as_ref()
+                .is_some_and(AuthenticationResults::any_failed)
+    }
+}
+
+fn header_signals<'a>(headers: impl Iterator<Item = (&'a str, &'a str)>) -> HeaderSignals {
+    let headers: Vec<(&str, &str)> = headers.collect();
+    let find = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    };
+
+    let spam_score = find("X-MS-Exchange-Organization-SCL")
+        .and_then(|value| value.trim().parse().ok())
+        .or_else(|| {
+            find("X-Forefront-Antispam-Report")?
+                .split(';')
+                .find_map(|part| part.trim().strip_prefix("SCL:"))
+                .and_then(|value| value.trim().parse().ok())
+        });
+
+    HeaderSignals {
+        unsubscribe: find("List-Unsubscribe")
+            .and_then(|value| Unsubscribe::parse(value, find("List-Unsubscribe-Post"))),
+        // Exchange sometimes only keeps the ARC copy of the results.
+        authentication: find("Authentication-Results")
+            .or_else(|| find("ARC-Authentication-Results"))
+            .map(AuthenticationResults::parse),
+        spam_score,
+        importance: find("X-Priority")
+            .or_else(|| find("Importance"))
+            .and_then(Importance::parse),
+    }
+}
+
+async fn send_google_json<T>(
+    retry: RetryPolicy,
+    request: reqwest::RequestBuilder,
+    endpoint: &str,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let body = send_google(retry, request, endpoint).await?;
+    serde_json::from_str(&body).with_context(|| format!("{endpoint} response heeft ongeldige JSON"))
+}
+
+async fn send_google_empty(
+    retry: RetryPolicy,
+    request: reqwest::RequestBuilder,
+    endpoint: &str,
+) -> Result<()> {
+    send_google(retry, request, endpoint).await.map(|_| ())
+}
+
+/// Sends a Gmail request, retrying transient rate limits with exponential
+/// backoff. An exhausted daily quota fails immediately.
+async fn send_google(
+    retry: RetryPolicy,
+    mut request: reqwest::RequestBuilder,
+    endpoint: &str,
+) -> Result<String> {
+    let mut delay = retry.base_delay;
+    let mut retries_left = retry.retries;
+    loop {
+        let retry = request.try_clone();
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("{endpoint} request mislukt"))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .with_context(|| format!("{endpoint} response kon niet gelezen worden"))?;
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        let detail = google_error_detail(&body);
+        if let Some(next) = retry
+            && retries_left > 0
+            && is_google_rate_limited(status, detail.as_deref())
+        {
+            info!(endpoint, ?delay, "Gmail rate limit, retrying");
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            retries_left -= 1;
+            request = next;
+            continue;
+        }
+        return Err(google_http_error(endpoint, status, detail, &body));
+    }
+}
+
+async fn read_google_json<T>(response: reqwest::Response, endpoint: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .with_context(|| format!("{endpoint} response kon niet gelezen worden"))?;
+
+    if !status.is_success() {
+        let detail = google_error_detail(&body);
+        return Err(google_http_error(endpoint, status, detail, &body));
+    }
+
+    serde_json::from_str(&body).with_context(|| format!("{endpoint} response heeft ongeldige JSON"))
+}
+
+fn google_http_error(
+    endpoint: &str,
+    status: reqwest::StatusCode,
+    detail: Option<String>,
+    body: &str,
+) -> anyhow::Error {
+    let mut msg = format!("{endpoint} gaf HTTP {status}");
+    if let Some(detail) = detail {
+        msg.push_str(&format!(": {detail}"));
+        if let Some(hint) = google_error_hint(&detail) {
+            msg.push_str(&format!("\nTip: {hint}"));
+        }
+    } else {
+        let raw = body.lines().next().unwrap_or("").trim();
+        if !raw.is_empty() {
+            msg.push_str(&format!(": {raw}"));
+        }
+    }
+    GoogleHttpError {
+        status,
+        message: msg,
+    }
+    .into()
+}
+
+/// 5xx responses and timeouts: the provider's side rather than the user's setup.
+fn is_server_side_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            error.is_timeout()
+                || error
+                    .status()
+                    .is_some_and(|status| status.is_server_error())
+        } else if let Some(error) = cause.downcast_ref::<GoogleHttpError>() {
+            error.status.is_server_error()
+        } else {
+            false
+        }
+    })
+}
+
+#[derive(Debug)]
+struct GoogleHttpError {
+    status: reqwest::StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for GoogleHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for GoogleHttpError {}
+
+fn is_google_rate_limited(status: reqwest::StatusCode, detail: Option<&str>) -> bool {
+    let lowered = detail.unwrap_or_default().to_ascii_lowercase();
+    if is_google_quota_exhausted(&lowered) {
+        return false;
+    }
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || (status == reqwest::StatusCode::FORBIDDEN && lowered.contains("ratelimitexceeded"))
+}
+
+fn is_google_quota_exhausted(lowered: &str) -> bool {
+    lowered.contains("dailylimitexceeded")
+        || lowered.contains("quotaexceeded")
+        || lowered.contains("per day")
+}
+
+fn google_error_detail(body: &str) -> Option<String> {
+    let parsed: GoogleErrorEnvelope = serde_json::from_str(body).ok()?;
+    let error = parsed.error?;
+    let mut detail = error.message?;
+
+    if let Some(first_reason) = error
+        .errors
+        .and_then(|items| items.into_iter().find_map(|item| item.reason))
+    {
+        detail.push_str(&format!(" (reason: {first_reason})"));
+    }
+
+    Some(detail)
+}
+
+fn google_error_hint(detail: &str) -> Option<String> {
+    let lowered = detail.to_ascii_lowercase();
+
+    if is_google_quota_exhausted(&lowered) {
+        return Some(
+            "Het dagelijkse Gmail API-quotum van dit Google Cloud project is op. Het quotum wordt om middernacht Pacific Time (rond 09:00 Nederlandse tijd) gereset; verhoog het anders in de Cloud Console."
+                .to_owned(),
+        );
+    }
+
+    if lowered.contains("ratelimitexceeded") || lowered.contains("rate limit exceeded") {
+        return Some(
+            "Gmail beperkt tijdelijk het aantal verzoeken. Wacht een minuut en probeer het opnieuw."
+                .to_owned(),
+        );
+    }
+
+    if is_gmail_api_disabled(&lowered) {
+        let url = match project_in_error(&lowered) {
+            Some(project) => format!("{GMAIL_API_LIBRARY_URL}?project={project}"),
+            None => GMAIL_API_LIBRARY_URL.to_owned(),
+        };
+        return Some(format!(
+            "Enable de Gmail API in hetzelfde Google Cloud project als deze OAuth client: {url}"
+        ));
+    }
+
+    if lowered.contains("insufficient authentication scopes")
+        || lowered.contains("insufficientpermissions")
+        || lowered.contains("insufficient permissions")
+    {
+        return Some(
+            "Verwijder app-toegang in je Google account en log opnieuw in zodat gmail.readonly opnieuw wordt toegekend."
+                .to_owned(),
+        );
+    }
+
+    if is_google_access_not_configured(&lowered) {
+        return Some(
+            "Controleer OAuth consent screen + test users en bevestig dat deze account toegang heeft tot de app."
+                .to_owned(),
+        );
+    }
+
+    None
+}
+
+fn is_gmail_api_disabled(lowered: &str) -> bool {
+    lowered.contains("has not been used in project") || lowered.contains("is disabled")
+}
+
+/// The project number Google names in "... has not been used in project 123".
+fn project_in_error(lowered: &str) -> Option<&str> {
+    let (_, rest) = lowered.split_once("in project ")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    (end > 0).then(|| &rest[..end])
+}
+
+fn is_google_access_not_configured(lowered: &str) -> bool {
+    lowered.contains("access blocked") || lowered.contains("access_not_configured")
+}
+
+struct GraphApi<'a> {
+    http: Client,
+    access_token: &'a str,
+    extra_fields: &'a [String],
+}
+
+impl GraphApi<'_> {
+    fn first_list_request(
+        &self,
+        limit: usize,
+        range: &DateRange,
+        folder: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        let url = match folder {
+            Some(folder) => {
+                format!("https://graph.microsoft.com/v1.0/me/mailFolders/{folder}/messages")
+            }
+            None => "https://graph.microsoft.com/v1.0/me/messages".to_owned(),
+        };
+        let mut inbox_request = self
+            .http
+            .get(url)
+            .query(&[
+                ("$top", limit.to_string()),
+                (
+                    "$select",
+                    graph_select(
+                        "subject,from,receivedDateTime,bodyPreview,parentFolderId,isRead,importance,categories,conversationId,internetMessageId,hasAttachments,internetMessageHeaders",
+                        self.extra_fields,
+                    ),
+                ),
+                ("$orderby", "receivedDateTime desc".to_owned()),
+                ("$count", "true".to_owned()),
+                ("$expand", GRAPH_MESSAGE_SIZE_EXPAND.to_owned()),
+            ]);
+        if let Some(filter) = range.graph_filter() {
+            inbox_request = inbox_request.query(&[("$filter", filter)]);
+        }
+        inbox_request
+    }
+}
+
+impl MailProvider for GraphApi<'_> {
+    fn provider(&self) -> Provider {
+        Provider::Outlook
+    }
+
+    async fn userinfo(&self) -> Result<Option<String>> {
+        let me: GraphMeResponse = self
+            .http
+            .get("https://graph.microsoft.com/v1.0/me?$select=mail,userPrincipalName")
+            .bearer_auth(self.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(me.mail.or(me.user_principal_name))
+    }
+
+    async fn list_messages(
+        &self,
+        limit: usize,
+        range: &DateRange,
+        folder: Option<&str>,
+        cursor: Option<&str>,
+    ) -> Result<MessageListing> {
+        let inbox_request = match cursor {
+            // `@odata.nextLink` already carries every query parameter.
+            Some(next_link) => self.http.get(next_link),
+            None => self.first_list_request(limit, range, folder),
+        };
+        let inbox: GraphInboxResponse = inbox_request
+            .bearer_auth(self.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let GraphInboxResponse {
+            value,
+            next_link,
+            count,
+        } = inbox;
+        let mailbox_empty = value.is_empty() && next_link.is_none();
+
+        Ok(MessageListing {
+            entries: value
+                .into_iter()
+                .map(|entry| {
+                    ListedMessage::Complete(Box::new(graph_mail_message(entry, self.extra_fields)))
+                })
+                .collect(),
+            next_cursor: next_link,
+            estimated_total: count,
+            mailbox_empty,
+        })
+    }
+
+    async fn message_summary(&self, id: String) -> Result<MailMessage> {
+        self.get_message(&id).await
+    }
+
+    async fn list_thread(&self, conversation_id: &str) -> Result<Vec<MailMessage>> {
+        // Graph rejects `$orderby` combined with a conversationId filter, so
+        // the caller sorts.
+        let mut request = self.http.get("https://graph.microsoft.com/v1.0/me/messages").query(&[
+            (
+                "$filter",
+                format!(
+                    "conversationId eq '{}'",
+                    conversation_id.replace('\'', "''")
+                ),
+            ),
+            (
+                "$select",
+                graph_select(
+                    "subject,from,receivedDateTime,bodyPreview,parentFolderId,isRead,importance,categories,conversationId,internetMessageId,hasAttachments,internetMessageHeaders",
+                    self.extra_fields,
+                ),
+            ),
+            ("$expand", GRAPH_MESSAGE_SIZE_EXPAND.to_owned()),
+        ]);
+        let mut messages = Vec::new();
+        loop {
+            let page: GraphInboxResponse = request
+                .bearer_auth(self.access_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            messages.extend(
+                page.value
+                    .into_iter()
+                    .map(|entry| graph_mail_message(entry, self.extra_fields)),
+            );
+            match page.next_link {
+                Some(next_link) => request = self.http.get(next_link),
+                None => break,
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn get_message(&self, message_id: &str) -> Result<MailMessage> {
+        let select = graph_select(
+            "subject,from,receivedDateTime,bodyPreview,parentFolderId,isRead,importance,categories,conversationId,internetMessageId,hasAttachments,body,internetMessageHeaders",
+            self.extra_fields,
+        );
+        let response = self
+            .http
+            .get(format!(
+                "https://graph.microsoft.com/v1.0/me/messages/{message_id}"
+            ))
+            .query(&[
+                ("$select", select.as_str()),
+                ("$expand", GRAPH_MESSAGE_SIZE_EXPAND),
+            ])
+            .bearer_auth(self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(EngineError::NotFound {
+                provider: Provider::Outlook,
+                id: message_id.to_owned(),
+            }
+            .into());
+        }
+
+        let entry: GraphMessage = response.error_for_status()?.json().await?;
+        let mut message = graph_mail_message(entry, self.extra_fields);
+
+        if let Some(html) = message.body_html.as_deref()
+            && html.contains("cid:")
+        {
+            let attachments: GraphAttachmentList = self
+                .http
+                .get(format!(
+                    "https://graph.microsoft.com/v1.0/me/messages/{message_id}/attachments"
+                ))
+                .query(&[("$filter", "isInline eq true")])
+                .bearer_auth(self.access_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let images: Vec<(String, String)> = attachments
+                .value
+                .into_iter()
+                .filter_map(|attachment| {
+                    let content_id = attachment.content_id?;
+                    let bytes = BASE64.decode(attachment.content_bytes?).ok()?;
+                    let mime_type = attachment.content_type.unwrap_or_default();
+                    Some((
+                        content_id.trim().trim_matches(['<', '>']).to_owned(),
+                        inline_image_uri(&mime_type, &bytes),
+                    ))
+                })
+                .collect();
+            message.body_html = Some(resolve_inline_images(html, &images));
+        }
+
+        Ok(message)
+    }
+
+    async fn get_raw(&self, message_id: &str) -> Result<Vec<u8>> {
+        let response = self
+            .http
+            .get(format!(
+                "https://graph.microsoft.com/v1.0/me/messages/{message_id}/$value"
+            ))
+            .bearer_auth(self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(EngineError::NotFound {
+                provider: Provider::Outlook,
+                id: message_id.to_owned(),
+            }
+            .into());
+        }
+
+        Ok(response.error_for_status()?.bytes().await?.to_vec())
+    }
+}
+
+fn graph_select(base: &str, extra_fields: &[String]) -> String {
+    extra_fields
+        .iter()
+        .fold(base.to_owned(), |mut select, field| {
+            select.push(',');
+            select.push_str(field);
+            select
+        })
+}
+
+fn graph_mail_message(mut entry: GraphMessage, extra_fields: &[String]) -> MailMessage {
+    let extra = extra_fields
+        .iter()
+        .filter_map(|field| Some((field.clone(), entry.extra.remove(field)?)))
+        .collect();
+    let signals = header_signals(
+        entry
+            .internet_message_headers
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .map(|header| (header.name.as_str(), header.value.as_str())),
+    );
+    let is_suspicious = signals.is_suspicious();
+    let importance = entry
+        .importance
+        .as_deref()
+        .and_then(Importance::parse)
+        .or(signals.importance)
+        .unwrap_or_default();
+
+    let (body, body_html) = match entry.body {
+        Some(GraphBody {
+            content: Some(content),
+            content_type: Some(content_type),
+        }) if content_type.eq_ignore_ascii_case("html") => {
+            (entry.body_preview, Some(sanitize_html(&content)))
+        }
+        Some(body) => (body.content.or(entry.body_preview), None),
+        None => (entry.body_preview, None),
+    };
+
+    MailMessage {
+        id: entry.id,
+        labels: entry
+            .parent_folder_id
+            .into_iter()
+            .chain((entry.is_read == Some(false)).then(|| "UNREAD".to_owned()))
+            .chain(entry.categories.unwrap_or_default())
+            .collect(),
+        subject: entry
+            .subject
+            .unwrap_or_else(|| "(geen onderwerp)".to_owned()),
+        from: entry
+            .from
+            .and_then(|f| f.email_address)
+            .and_then(|a| a.address)
+            .unwrap_or_else(|| "(onbekend)".to_owned()),
+        date: entry
+            .received_date_time
+            .unwrap_or_else(|| "(onbekend)".to_owned()),
+        body: body
+            .filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| "(geen inhoud)".to_owned()),
+        body_html,
+        size_estimate: entry
+            .single_value_extended_properties
+            .unwrap_or_default()
+            .into_iter()
+            .find(|property| {
+                property
+                    .id
+                    .eq_ignore_ascii_case(GRAPH_MESSAGE_SIZE_PROPERTY)
+            })
+            .and_then(|property| property.value.parse().ok()),
+        unsubscribe: signals.unsubscribe,
+        authentication: signals.authentication,
+        spam_score: signals.spam_score,
+        is_suspicious,
+        importance,
+        has_attachments: entry.has_attachments.unwrap_or(false),
+        conversation_id: entry.conversation_id,
+        internet_message_id: entry
+            .internet_message_id
+            .as_deref()
+            .and_then(normalize_message_id),
+        extra,
+    }
+}
+
+pub fn sanitize_html(html: &str) -> String {
+    clean_html(html, false)
+}
+
+pub fn block_remote_images(html: &str) -> String {
+    clean_html(html, true)
+}
+
+fn clean_html(html: &str, block_remote: bool) -> String {
+    ammonia::Builder::default()
+        .link_rel(Some("noopener noreferrer"))
+        .add_url_schemes(&["cid", "data"])
+        .attribute_filter(move |element, attribute, value| {
+            let is_image_src = element == "img" && attribute == "src";
+            let blocked_data = is_data_url(value) && !(is_image_src && is_inline_image_url(value));
+            let blocked_remote = block_remote && is_image_src && is_remote_url(value);
+            if blocked_data || blocked_remote {
+                None
+            } else {
+                Some(value.into())
+            }
+        })
+        .clean(html)
+        .to_string()
+}
+
+/// Plain-text rendering of an HTML body for messages without a text/plain part.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_collapsed(&mut text, &rest[..start]);
+        let tag_rest = &rest[start..];
+        let Some(end) = tag_rest.find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = tag_rest[1..end].trim().to_ascii_lowercase();
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|ch: char| ch.is_whitespace() || ch == '/')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        rest = &tag_rest[end + 1..];
+
+        if !closing && matches!(name.as_str(), "script" | "style" | "head" | "title") {
+            let close = format!("</{name}");
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(index) => rest[index..]
+                    .find('>')
+                    .map_or("", |end| &rest[index + end + 1..]),
+                None => "",
+            };
+            continue;
+        }
+        match name.as_str() {
+            "br" | "p" | "div" | "tr" | "table" | "hr" | "blockquote" | "h1" | "h2" | "h3"
+            | "h4" | "h5" | "h6" => text.push('\n'),
+            "li" if !closing => text.push_str("\n- "),
+            "td" | "th" if !closing => text.push(' '),
+            _ => {}
+        }
+    }
+    push_collapsed(&mut text, rest);
+
+    let decoded = decode_html_entities(&text);
+    let mut lines: Vec<&str> = Vec::new();
+    for line in decoded.lines().map(str::trim) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn push_collapsed(text: &mut String, segment: &str) {
+    for ch in segment.chars() {
+        if ch.is_whitespace() {
+            if !text.ends_with([' ', '\n']) && !text.is_empty() {
+                text.push(' ');
+            }
+        } else {
+            text.push(ch);
+        }
+    }
+}
+
+fn decode_html_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let candidate = &rest[start + 1..];
+        let entity = candidate
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_html_entity(&candidate[..end])?, end)));
+        match entity {
+            Some((ch, end)) => {
+                decoded.push(ch);
+                rest = &candidate[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = candidate;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_html_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let number = entity.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+fn is_data_url(value: &str) -> bool {
+    value.trim().to_ascii_lowercase().starts_with("data:")
+}
+
+fn is_inline_image_url(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+    value.starts_with("data:image/") && !value.starts_with("data:image/svg")
+}
+
+fn inline_image_uri(mime_type: &str, bytes: &[u8]) -> String {
+    let mime_type = if mime_type.to_ascii_lowercase().starts_with("image/") {
+        mime_type
+    } else {
+        "image/png"
+    };
+    format!("data:{mime_type};base64,{}", BASE64.encode(bytes))
+}
+
+fn resolve_inline_images(html: &str, images: &[(String, String)]) -> String {
+    images
+        .iter()
+        .fold(html.to_owned(), |html, (content_id, uri)| {
+            html.replace(&format!("cid:{content_id}"), uri)
+        })
+}
+
+pub fn has_remote_images(html: &str) -> bool {
+    block_remote_images(html) != sanitize_html(html)
+}
+
+pub fn sender_address(from: &str) -> String {
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from,
+    };
+    let address = address.trim();
+    if address.contains('@') {
+        address.to_ascii_lowercase()
+    } else {
+        String::new()
+    }
+}
+
+/// Byte offset in a plain-text body where the quoted earlier message starts:
+/// an "On ... wrote:" / "Op ... schreef:" attribution, an Outlook separator
+/// or header block, or the trailing run of `>` lines. `None` when the body
+/// has no recognizable quote.
+pub fn quoted_reply_start(body: &str) -> Option<usize> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        lines.push((offset, line.trim()));
+        offset += line.len();
+    }
+
+    let is_attribution = |line: &str| {
+        (line.starts_with("On ") && line.ends_with("wrote:"))
+            || (line.starts_with("Op ") && line.ends_with("schreef:"))
+    };
+    let is_separator = |index: usize| {
+        let line: &str = lines[index].1;
+        let next = lines[index + 1..]
+            .iter()
+            .map(|(_, line)| *line)
+            .find(|line| !line.is_empty())
+            .unwrap_or("");
+        let header_follows = next.starts_with("From:") || next.starts_with("Van:");
+        line.starts_with("-----Original Message")
+            || line.starts_with("-----Oorspronkelijk bericht")
+            || (line.len() >= 10 && line.chars().all(|c| c == '_') && header_follows)
+            || ((line.starts_with("From:") || line.starts_with("Van:"))
+                && lines.get(index + 1).is_some_and(|(_, next)| {
+                    next.starts_with("Sent:") || next.starts_with("Verzonden:")
+                }))
+    };
+
+    for index in 0..lines.len() {
+        let line = lines[index].1;
+        if is_attribution(line) || is_separator(index) {
+            return Some(lines[index].0);
+        }
+        // Gmail wraps long attributions: "On ..., Name <address>" + "wrote:".
+        if line == "wrote:" && index > 0 && lines[index - 1].1.starts_with("On ") {
+            return Some(lines[index - 1].0);
+        }
+    }
+
+    let trailing = lines
+        .iter()
+        .rev()
+        .take_while(|(_, line)| line.is_empty() || line.starts_with('>'))
+        .collect::<Vec<_>>();
+    trailing
+        .iter()
+        .rev()
+        .find(|(_, line)| line.starts_with('>'))
+        .map(|(offset, _)| *offset)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextSegment<'a> {
+    Text(&'a str),
+    Link(&'a str),
+}
+
+/// Splits plain text into text and `http`/`https`/`mailto` links, so a body
+/// can be shown with clickable URLs.
+pub fn linkify(text: &str) -> Vec<TextSegment<'_>> {
+    const SCHEMES: [&str; 3] = ["https://", "http://", "mailto:"];
+    // ASCII lowercasing keeps byte offsets, so positions carry over to `text`.
+    let lowered = text.to_ascii_lowercase();
+    let mut segments = Vec::new();
+    let mut plain_start = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        let at_boundary = text[..pos]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        if at_boundary
+            && let Some(scheme) = SCHEMES
+                .iter()
+                .find(|scheme| lowered[pos..].starts_with(*scheme))
+        {
+            let rest = &text[pos..];
+            let end = rest
+                .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+                .unwrap_or(rest.len());
+            let url = trim_url_end(&rest[..end]);
+            if url.len() > scheme.len() {
+                if plain_start < pos {
+                    segments.push(TextSegment::Text(&text[plain_start..pos]));
+                }
+                segments.push(TextSegment::Link(url));
+                pos += url.len();
+                plain_start = pos;
+                continue;
+            }
+        }
+        pos += text[pos..].chars().next().map_or(1, char::len_utf8);
+    }
+    if plain_start < text.len() {
+        segments.push(TextSegment::Text(&text[plain_start..]));
+    }
+    segments
+}
+
+/// Drops sentence punctuation and unbalanced closing brackets after a URL.
+fn trim_url_end(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let Some(last) = url.chars().next_back() else {
+            return url;
+        };
+        let unbalanced = match last {
+            ')' => url.matches('(').count() < url.matches(')').count(),
+            ']' => url.matches('[').count() < url.matches(']').count(),
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' => true,
+            _ => false,
+        };
+        if !unbalanced {
+            return url;
+        }
+        url = &url[..url.len() - last.len_utf8()];
+    }
+}
+
+fn is_remote_url(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+    value.starts_with("http://") || value.starts_with("https://") || value.starts_with("//")
+}
+
+pub fn attachment_mime_type(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "json" => "application/json",
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "ics" => "text/calendar",
+        "eml" => "message/rfc822",
+        _ => "application/octet-stream",
+    }
+}
+
+fn validate_outgoing(provider: Provider, message: &OutgoingMessage) -> Result<()> {
+    if message.to.iter().all(|address| address.trim().is_empty()) {
+        bail!("Vul minimaal een ontvanger in.");
+    }
+
+    let total: usize = message
+        .attachments
+        .iter()
+        .map(|(_, data, _)| data.len())
+        .sum();
+    let limit = match provider {
+        Provider::Google => GMAIL_ATTACHMENT_LIMIT_BYTES,
+        Provider::Outlook => OUTLOOK_INLINE_ATTACHMENT_LIMIT_BYTES,
+    };
+
+    if total > limit {
+        bail!(
+            "Bijlagen zijn samen {:.1} MB; {} accepteert via deze weg maximaal {} MB.",
+            total as f64 / (1024.0 * 1024.0),
+            provider.label(),
+            limit / (1024 * 1024)
+        );
+    }
+
+    Ok(())
+}
+
+async fn send_google_message(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message: &OutgoingMessage,
+) -> Result<()> {
+    let raw = build_mime_message(message);
+    let _sent: GoogleMessageRef = send_google_json(
+        retry,
+        http.post("https://gmail.googleapis.com/gmail/v1/users/me/messages/send")
+            .json(&GoogleSendRequest {
+                raw: BASE64_URL.encode(raw),
+            })
+            .bearer_auth(access_token),
+        "Gmail messages send",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn send_outlook_message(
+    http: Client,
+    access_token: &str,
+    message: &OutgoingMessage,
+) -> Result<()> {
+    let request = GraphSendMailRequest {
+        message: graph_outgoing_message(message),
+        save_to_sent_items: true,
+    };
+
+    http.post("https://graph.microsoft.com/v1.0/me/sendMail")
+        .json(&request)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn graph_outgoing_message(message: &OutgoingMessage) -> GraphOutgoingMessage {
+    GraphOutgoingMessage {
+        subject: message.subject.clone(),
+        body: GraphItemBody {
+            content_type: "Text",
+            content: message.body.clone(),
+        },
+        to_recipients: recipients(&message.to)
+            .map(|address| GraphRecipient {
+                email_address: GraphOutgoingAddress {
+                    address: address.to_owned(),
+                },
+            })
+            .collect(),
+        attachments: message
+            .attachments
+            .iter()
+            .map(|(name, data, mime)| GraphFileAttachment {
+                odata_type: "#microsoft.graph.fileAttachment",
+                name: name.clone(),
+                content_type: mime.clone(),
+                content_bytes: BASE64.encode(data),
+            })
+            .collect(),
+    }
+}
+
+async fn save_google_draft(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    draft: &Draft,
+) -> Result<String> {
+    let body = GoogleDraftRequest {
+        message: GoogleSendRequest {
+            raw: BASE64_URL.encode(build_mime_message(&draft.message)),
+        },
+    };
+
+    let request = match &draft.id {
+        Some(id) => http.put(format!(
+            "https://gmail.googleapis.com/gmail/v1/users/me/drafts/{id}"
+        )),
+        None => http.post("https://gmail.googleapis.com/gmail/v1/users/me/drafts"),
+    };
+
+    let saved: GoogleMessageRef = send_google_json(
+        retry,
+        request.json(&body).bearer_auth(access_token),
+        "Gmail drafts save",
+    )
+    .await?;
+    Ok(saved.id)
+}
+
+async fn list_google_drafts(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    limit: usize,
+) -> Result<Vec<Draft>> {
+    let list: GoogleDraftListResponse = send_google_json(
+        retry,
+        http.get("https://gmail.googleapis.com/gmail/v1/users/me/drafts")
+            .query(&[("maxResults", limit)])
+            .bearer_auth(access_token),
+        "Gmail drafts list",
+    )
+    .await?;
+
+    let mut drafts = Vec::new();
+    for entry in list.drafts.unwrap_or_default() {
+        let detail: GoogleDraftResponse = send_google_json(
+            retry,
+            http.get(format!(
+                "https://gmail.googleapis.com/gmail/v1/users/me/drafts/{}",
+                entry.id
+            ))
+            .query(&[("format", "full")])
+            .bearer_auth(access_token),
+            "Gmail drafts detail",
+        )
+        .await?;
+
+        let payload = detail.message.and_then(|message| message.payload);
+        let body = payload
+            .as_ref()
+            .and_then(google_plain_text_body)
+            .unwrap_or_default();
+        let mut to = String::new();
+        let mut subject = String::new();
+        for header in payload.and_then(|p| p.headers).unwrap_or_default() {
+            match header.name.as_str() {
+                "To" => to = decode_encoded_words(&header.value),
+                "Subject" => subject = decode_encoded_words(&header.value),
+                _ => {}
+            }
+        }
+
+        drafts.push(Draft {
+            id: Some(detail.id),
+            message: OutgoingMessage {
+                to: split_recipients(&to),
+                subject,
+                body,
+                attachments: Vec::new(),
+            },
+        });
+    }
+
+    Ok(drafts)
+}
+
+async fn save_outlook_draft(http: Client, access_token: &str, draft: &Draft) -> Result<String> {
+    save_outlook_draft_at(http, GRAPH_API_URL, access_token, draft).await
+}
+
+async fn save_outlook_draft_at(
+    http: Client,
+    api_url: &str,
+    access_token: &str,
+    draft: &Draft,
+) -> Result<String> {
+    // Graph staat geen inline bijlagen toe bij PATCH, dus een bestaand concept wordt vervangen.
+    // Het oude concept verdwijnt pas als het nieuwe is aangemaakt.
+    let created: GraphCreatedMessage = http
+        .post(format!("{api_url}/me/messages"))
+        .json(&graph_outgoing_message(&draft.message))
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(id) = &draft.id
+        && let Err(error) = delete_outlook_message_at(http, api_url, access_token, id).await
+    {
+        warn!(error = %error, "replaced draft could not be deleted");
+    }
+    Ok(created.id)
+}
+
+async fn list_outlook_drafts(http: Client, access_token: &str, limit: usize) -> Result<Vec<Draft>> {
+    let list: GraphDraftListResponse = http
+        .get("https://graph.microsoft.com/v1.0/me/mailFolders/drafts/messages")
+        .query(&[
+            ("$top", limit.to_string()),
+            ("$select", "subject,toRecipients,body".to_owned()),
+            ("$orderby", "lastModifiedDateTime desc".to_owned()),
+        ])
+        .header("Prefer", "outlook.body-content-type=\"text\"")
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(list
+        .value
+        .into_iter()
+        .map(|entry| Draft {
+            id: Some(entry.id),
+            message: OutgoingMessage {
+                to: entry
+                    .to_recipients
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|recipient| recipient.email_address.and_then(|a| a.address))
+                    .collect(),
+                subject: entry.subject.unwrap_or_default(),
+                body: entry.body.and_then(|body| body.content).unwrap_or_default(),
+                attachments: Vec::new(),
+            },
+        })
+        .collect())
+}
+
+async fn delete_outlook_message(http: Client, access_token: &str, id: &str) -> Result<()> {
+    delete_outlook_message_at(http, GRAPH_API_URL, access_token, id).await
+}
+
+async fn delete_outlook_message_at(
+    http: Client,
+    api_url: &str,
+    access_token: &str,
+    id: &str,
+) -> Result<()> {
+    http.delete(format!("{api_url}/me/messages/{id}"))
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+async fn list_google_folders(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+) -> Result<Vec<Folder>> {
+    let list: GoogleLabelListResponse = send_google_json(
+        retry,
+        http.get("https://gmail.googleapis.com/gmail/v1/users/me/labels")
+            .bearer_auth(access_token),
+        "Gmail labels list",
+    )
+    .await?;
+
+    let mut folders: Vec<(bool, Folder)> = list
+        .labels
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|label| !GMAIL_UNMOVABLE_LABELS.contains(&label.id.as_str()))
+        .filter(|label| !label.id.starts_with("CATEGORY_"))
+        .map(|label| {
+            let is_user = label.label_type.as_deref() == Some("user");
+            (
+                is_user,
+                Folder {
+                    id: label.id,
+                    name: label.name,
+                },
+            )
+        })
+        .collect();
+    folders.sort_by(|(a_user, a), (b_user, b)| a_user.cmp(b_user).then(a.name.cmp(&b.name)));
+
+    Ok(folders.into_iter().map(|(_, folder)| folder).collect())
+}
+
+async fn list_google_labels(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+) -> Result<Vec<MailLabel>> {
+    let list: GoogleLabelListResponse = send_google_json(
+        retry,
+        http.get("https://gmail.googleapis.com/gmail/v1/users/me/labels")
+            .bearer_auth(access_token),
+        "Gmail labels list",
+    )
+    .await?;
+
+    let mut labels: Vec<MailLabel> = list
+        .labels
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|label| label.label_type.as_deref() == Some("user"))
+        .map(|label| MailLabel {
+            id: label.id,
+            name: label.name,
+            color: label.color.and_then(|color| color.background_color),
+        })
+        .collect();
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(labels)
+}
+
+/// Id of the user label called `name`, created first when `create` is set.
+async fn google_label_id(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    name: &str,
+    create: bool,
+) -> Result<Option<String>> {
+    let labels = list_google_labels(http.clone(), access_token, retry).await?;
+    if let Some(label) = labels.into_iter().find(|label| label.name == name) {
+        return Ok(Some(label.id));
+    }
+    if !create {
+        return Ok(None);
+    }
+    let created: GoogleLabel = send_google_json(
+        retry,
+        http.post("https://gmail.googleapis.com/gmail/v1/users/me/labels")
+            .json(&serde_json::json!({
+                "name": name,
+                "labelListVisibility": "labelShow",
+                "messageListVisibility": "show",
+            }))
+            .bearer_auth(access_token),
+        "Gmail labels create",
+    )
+    .await?;
+    Ok(Some(created.id))
+}
+
+async fn move_google_message(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message_id: &str,
+    source_label_id: &str,
+    target_label_id: &str,
+) -> Result<Vec<String>> {
+    modify_google_labels(
+        http,
+        access_token,
+        retry,
+        message_id,
+        vec![target_label_id],
+        google_move_removals(source_label_id, target_label_id),
+    )
+    .await
+}
+
+/// Gmail kent geen mappen: verplaatsen betekent het doellabel toevoegen en
+/// het label van de bronmap verwijderen.
+fn google_move_removals<'a>(source_label_id: &'a str, target_label_id: &str) -> Vec<&'a str> {
+    if source_label_id == target_label_id {
+        Vec::new()
+    } else {
+        vec![source_label_id]
+    }
+}
+
+async fn modify_google_labels(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message_id: &str,
+    add_label_ids: Vec<&str>,
+    remove_label_ids: Vec<&str>,
+) -> Result<Vec<String>> {
+    let modified: GoogleModifiedMessage = send_google_json(
+        retry,
+        http.post(format!(
+            "https://gmail.googleapis.com/gmail/v1/users/me/messages/{message_id}/modify"
+        ))
+        .json(&GoogleModifyRequest {
+            add_label_ids,
+            remove_label_ids,
+        })
+        .bearer_auth(access_token),
+        "Gmail messages modify",
+    )
+    .await?;
+
+    Ok(modified.label_ids.unwrap_or_default())
+}
+
+async fn batch_modify_google_labels(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message_ids: &[String],
+    add_label_ids: &[&str],
+    remove_label_ids: &[&str],
+) -> Result<()> {
+    for chunk in message_ids.chunks(GMAIL_BATCH_MODIFY_LIMIT) {
+        send_google_empty(
+            retry,
+            http.post("https://gmail.googleapis.com/gmail/v1/users/me/messages/batchModify")
+                .json(&GoogleBatchModifyRequest {
+                    ids: chunk,
+                    add_label_ids,
+                    remove_label_ids,
+                })
+                .bearer_auth(access_token),
+            "Gmail messages batchModify",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn post_google_message_action(
+    http: Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message_id: &str,
+    action: &str,
+) -> Result<()> {
+    let _: GoogleModifiedMessage = send_google_json(
+        retry,
+        http.post(format!(
+            "https://gmail.googleapis.com/gmail/v1/users/me/messages/{message_id}/{action}"
+        ))
+        .bearer_auth(access_token),
+        &format!("Gmail messages {action}"),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn list_outlook_folders(http: Client, access_token: &str) -> Result<Vec<Folder>> {
+    let list: GraphFolderListResponse = http
+        .get("https://graph.microsoft.com/v1.0/me/mailFolders")
+        .query(&[("$top", "100"), ("$select", "id,displayName")])
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(list
+        .value
+        .into_iter()
+        .map(|folder| Folder {
+            id: folder.id,
+            name: folder.display_name,
+        })
+        .collect())
+}
+
+async fn list_outlook_categories(http: Client, access_token: &str) -> Result<Vec<MailLabel>> {
+    let response = http
+        .get("https://graph.microsoft.com/v1.0/me/outlook/masterCategories")
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+
+    // The master list needs MailboxSettings.Read; without it, fall back to the
+    // categories in use on recent messages.
+    if !matches!(
+        response.status(),
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+    ) {
+        let list: GraphCategoryListResponse = response.error_for_status()?.json().await?;
+        let mut labels: Vec<MailLabel> = list
+            .value
+            .into_iter()
+            .map(|category| MailLabel {
+                color: category
+                    .color
+                    .as_deref()
+                    .and_then(|preset| preset.strip_prefix("preset"))
+                    .and_then(|index| GRAPH_CATEGORY_COLORS.get(index.parse::<usize>().ok()?))
+                    .map(|color| (*color).to_owned()),
+                id: category.display_name.clone(),
+                name: category.display_name,
+            })
+            .collect();
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        return Ok(labels);
+    }
+
+    let recent: GraphCategorizedMessages = http
+        .get("https://graph.microsoft.com/v1.0/me/messages")
+        .query(&[
+            ("$top", GRAPH_CATEGORY_SCAN_LIMIT.to_string()),
+            ("$select", "categories".to_owned()),
+            ("$filter", "categories/any()".to_owned()),
+        ])
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let names: std::collections::BTreeSet<String> = recent
+        .value
+        .into_iter()
+        .flat_map(|message| message.categories.unwrap_or_default())
+        .collect();
+    Ok(names
+        .into_iter()
+        .map(|name| MailLabel {
+            id: name.clone(),
+            name,
+            color: None,
+        })
+        .collect())
+}
+
+async fn modify_outlook_categories(
+    http: Client,
+    access_token: &str,
+    message_id: &str,
+    add: &[String],
+    remove: &[String],
+) -> Result<()> {
+    let url = format!("https://graph.microsoft.com/v1.0/me/messages/{message_id}");
+    let current: GraphCategorizedMessage = http
+        .get(&url)
+        .query(&[("$select", "categories")])
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let mut categories = current.categories.unwrap_or_default();
+    categories.retain(|category| !remove.contains(category));
+    for category in add {
+        if !categories.contains(category) {
+            categories.push(category.clone());
+        }
+    }
+
+    http.patch(&url)
+        .json(&serde_json::json!({ "categories": categories }))
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+async fn flag_outlook_message(
+    http: Client,
+    access_token: &str,
+    message_id: &str,
+    flag: serde_json::Value,
+) -> Result<()> {
+    http.patch(format!(
+        "https://graph.microsoft.com/v1.0/me/messages/{message_id}"
+    ))
+    .json(&serde_json::json!({ "flag": flag }))
+    .bearer_auth(access_token)
+    .send()
+    .await?
+    .error_for_status()?;
+    Ok(())
+}
+
+async fn relocate_outlook_message(
+    http: Client,
+    access_token: &str,
+    message_id: &str,
+    target_folder_id: &str,
+) -> Result<String> {
+    let moved = post_outlook_move(http, access_token, message_id, target_folder_id).await?;
+    Ok(moved.id)
+}
+
+/// `(old id, new id)` of every message a bulk action succeeded for.
+fn successful_moves(message_ids: &[String], results: &[Result<String>]) -> Vec<(String, String)> {
+    message_ids
+        .iter()
+        .zip(results)
+        .filter_map(|(old_id, result)| Some((old_id.clone(), result.as_ref().ok()?.clone())))
+        .collect()
+}
+
+/// Moves every message with `$batch` and returns, in the same order, the new
+/// id of each moved message or why it could not be moved.
+async fn relocate_outlook_messages(
+    http: &Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    message_ids: &[String],
+    target_folder_id: &str,
+) -> Result<Vec<Result<String>>> {
+    let body = serde_json::to_value(GraphMoveRequest {
+        destination_id: target_folder_id,
+    })?;
+    let requests = message_ids
+        .iter()
+        .map(|message_id| GraphBatchRequest {
+            method: "POST",
+            url: format!("/me/messages/{message_id}/move"),
+            body: Some(body.clone()),
+        })
+        .collect();
+    Ok(send_graph_batch(http, access_token, retry, requests)
+        .await?
+        .into_iter()
+        .map(|result| {
+            let moved: GraphMovedMessage = serde_json::from_value(result?)
+                .context("Graph move response heeft ongeldige JSON")?;
+            Ok(moved.id)
+        })
+        .collect())
+}
+
+/// A sub-request of a Graph `$batch` call; `url` is relative to `/v1.0`.
+struct GraphBatchRequest {
+    method: &'static str,
+    url: String,
+    body: Option<serde_json::Value>,
+}
+
+/// Sends `requests` through the Graph `$batch` endpoint in chunks of
+/// [`GRAPH_BATCH_LIMIT`] and returns each response body (or its error) in
+/// the order of `requests`. Graph runs sub-requests in parallel, so some hit
+/// the mailbox concurrency limit; those are resent after their `Retry-After`.
+async fn send_graph_batch(
+    http: &Client,
+    access_token: &str,
+    retry: RetryPolicy,
+    requests: Vec<GraphBatchRequest>,
+) -> Result<Vec<Result<serde_json::Value>>> {
+    let mut results = Vec::with_capacity(requests.len());
+    for chunk in requests.chunks(GRAPH_BATCH_LIMIT) {
+        let mut outcomes: Vec<Option<Result<serde_json::Value>>> =
+            chunk.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..chunk.len()).collect();
+        let mut delay = retry.base_delay;
+        for attempt in 0..=retry.retries {
+            let sent: Vec<&GraphBatchRequest> =
+                pending.iter().map(|&index| &chunk[index]).collect();
+            let response = post_graph_batch(http, access_token, &sent).await?;
+            let mut throttled = Vec::new();
+            let mut wait = delay;
+            for (&index, outcome) in pending.iter().zip(graph_batch_results(&sent, response)) {
+                match outcome {
+                    GraphBatchOutcome::Throttled(retry_after) if attempt < retry.retries => {
+                        throttled.push(index);
+                        wait = wait.max(retry_after.unwrap_or_default());
+                    }
+                    GraphBatchOutcome::Throttled(_) => {
+                        outcomes[index] = Some(Err(anyhow!(
+                            "{} {} gaf HTTP 429: te veel gelijktijdige verzoeken",
+                            chunk[index].method,
+                            chunk[index].url
+                        )));
+                    }
+                    GraphBatchOutcome::Done(result) => outcomes[index] = Some(result),
+                }
+            }
+            if throttled.is_empty() {
+                break;
+            }
+            warn!(
+                throttled = throttled.len(),
+                wait_ms = wait.as_millis() as u64,
+                "Graph batch throttled, retrying"
+            );
+            tokio::time::sleep(wait).await;
+            delay *= 2;
+            pending = throttled;
+        }
+        results.extend(
+            outcomes.into_iter().map(|outcome| {
+                outcome.unwrap_or_else(|| Err(anyhow!("Graph $batch niet voltooid")))
+            }),
+        );
+    }
+    Ok(results)
+}
+
+async fn post_graph_batch(
+    http: &Client,
+    access_token: &str,
+    chunk: &[&GraphBatchRequest],
+) -> Result<GraphBatchResponse> {
+    let body = GraphBatchBody {
+        requests: chunk
+            .iter()
+            .enumerate()
+            .map(|(index, request)| GraphBatchEntry {
+                id: index.to_string(),
+                method: request.method,
+                url: &request.url,
+                headers: request
+                    .body
+                    .as_ref()
+                    .map(|_| HashMap::from([("Content-Type", "application/json")])),
+                body: request.body.as_ref(),
+            })
+            .collect(),
+    };
+    http.post("https://graph.microsoft.com/v1.0/$batch")
+        .json(&body)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Graph $batch response heeft ongeldige JSON")
+}
+
+/// One sub-request's answer; a throttled one carries its `Retry-After`.
+enum GraphBatchOutcome {
+    Done(Result<serde_json::Value>),
+    Throttled(Option<Duration>),
+}
+
+/// Maps batch responses (which may arrive in any order) back onto `chunk`.
+fn graph_batch_results(
+    chunk: &[&GraphBatchRequest],
+    response: GraphBatchResponse,
+) -> Vec<GraphBatchOutcome> {
+    let mut by_id: HashMap<String, GraphBatchResult> = response
+        .responses
+        .into_iter()
+        .map(|result| (result.id.clone(), result))
+        .collect();
+    chunk
+        .iter()
+        .enumerate()
+        .map(|(index, request)| {
+            let Some(result) = by_id.remove(&index.to_string()) else {
+                return GraphBatchOutcome::Done(Err(anyhow!(
+                    "Graph $batch gaf geen antwoord voor {}",
+                    request.url
+                )));
+            };
+            if result.status == 429 {
+                let retry_after = result
+                    .headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))
+                    .and_then(|(_, value)| match value {
+                        serde_json::Value::String(secs) => secs.trim().parse().ok(),
+                        other => other.as_u64(),
+                    })
+                    .map(Duration::from_secs);
+                return GraphBatchOutcome::Throttled(retry_after);
+            }
+            let body = result.body.unwrap_or(serde_json::Value::Null);
+            if (200..300).contains(&result.status) {
+                return GraphBatchOutcome::Done(Ok(body));
+            }
+            let detail = body
+                .pointer("/error/message")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or("onbekende fout");
+            GraphBatchOutcome::Done(Err(anyhow!(
+                "{} {} gaf HTTP {}: {detail}",
+                request.method,
+                request.url,
+                result.status
+            )))
+        })
+        .collect()
+}
+
+async fn post_outlook_move(
+    http: Client,
+    access_token: &str,
+    message_id: &str,
+    target_folder_id: &str,
+) -> Result<GraphMovedMessage> {
+    let moved: GraphMovedMessage = http
+        .post(format!(
+            "https://graph.microsoft.com/v1.0/me/messages/{message_id}/move"
+        ))
+        .json(&GraphMoveRequest {
+            destination_id: target_folder_id,
+        })
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(moved)
+}
+
+pub fn split_recipients(raw: &str) -> Vec<String> {
+    raw.split([',', ';'])
+        .map(|address| address.trim().to_owned())
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+
+fn recipients(to: &[String]) -> impl Iterator<Item = &str> {
+    to.iter()
+        .map(|address| address.trim())
+        .filter(|address| !address.is_empty())
+}
+
+fn build_mime_message(message: &OutgoingMessage) -> String {
+    let mut mime = String::new();
+    let to = single_line(&recipients(&message.to).collect::<Vec<_>>().join(", "));
+    if !to.is_empty() {
+        mime.push_str(&format!("To: {to}\r\n"));
+    }
+    mime.push_str(&format!(
+        "Subject: {}\r\n",
+        encode_header_value(&message.subject)
+    ));
+    mime.push_str("MIME-Version: 1.0\r\n");
+
+    if message.attachments.is_empty() {
+        push_text_part(&mut mime, &message.body);
+        return mime;
+    }
+
+    let boundary = mime_boundary();
+    mime.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n"
+    ));
+    mime.push_str(&format!("--{boundary}\r\n"));
+    push_text_part(&mut mime, &message.body);
+
+    for (filename, data, mime_type) in &message.attachments {
+        let filename = mime_quoted_param(filename);
+        mime.push_str(&format!("--{boundary}\r\n"));
+        mime.push_str(&format!(
+            "Content-Type: {mime_type}; name=\"{filename}\"\r\n"
+        ));
+        mime.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{filename}\"\r\n"
+        ));
+        mime.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+        mime.push_str(&base64_lines(data));
+    }
+
+    mime.push_str(&format!("--{boundary}--\r\n"));
+    mime
+}
+
+fn push_text_part(mime: &mut String, body: &str) {
+    mime.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    mime.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+    mime.push_str(&base64_lines(body.as_bytes()));
+}
+
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = BASE64.encode(data);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / 38 + 2);
+    for chunk in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn encode_header_value(value: &str) -> String {
+    let value = single_line(value);
+    if value.is_ascii() {
+        value
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64.encode(value))
+    }
+}
+
+/// Replaces control characters with spaces, so a pasted CR/LF cannot end
+/// the header or start a new one.
+fn single_line(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect()
+}
+
+fn mime_quoted_param(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|ch| {
+            if ch == '"' || ch == '\\' || ch.is_control() {
+                '_'
+            } else {
+                ch
+            }
+        })
+        .collect();
+    encode_header_value(&cleaned)
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn mime_boundary() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("mail-boundary-{nanos:x}")
+}
+
+fn apply_rules_to(rules: &[MailRule], messages: &mut Vec<MailMessage>) {
+    messages.retain_mut(|message| {
+        for rule in rules {
+            if !rule.condition.matches(message) {
+                continue;
+            }
+            match &rule.action {
+                RuleAction::Label(name) => {
+                    let label = format!("{LOCAL_LABEL_PREFIX}{name}");
+                    if !message.labels.contains(&label) {
+                        message.labels.push(label);
+                    }
+                }
+                RuleAction::MarkRead => message.labels.retain(|label| label != "UNREAD"),
+                RuleAction::MoveTo(folder) => {
+                    if message.rule_folder().is_none() {
+                        message.labels.push(format!("{RULE_FOLDER_PREFIX}{folder}"));
+                    }
+                }
+                RuleAction::Hide => return false,
+            }
+        }
+        true
+    });
+}
+
+fn google_has_attachments(payload: &GooglePayload) -> bool {
+    payload
+        .filename
+        .as_deref()
+        .is_some_and(|name| !name.is_empty())
+        || payload.mime_type.as_deref() == Some("multipart/mixed")
+        || payload.parts.iter().flatten().any(google_has_attachments)
+}
+
+fn google_plain_text_body(payload: &GooglePayload) -> Option<String> {
+    google_body_of_type(payload, "text/plain")
+}
+
+fn google_body_of_type(payload: &GooglePayload, mime_type: &str) -> Option<String> {
+    let matches = payload
+        .mime_type
+        .as_deref()
+        .is_some_and(|mime| mime.eq_ignore_ascii_case(mime_type));
+
+    if matches && let Some(data) = payload.body.as_ref().and_then(|body| body.data.as_deref()) {
+        let charset = payload
+            .headers
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|header| mime_param(&header.value, "charset"));
+        return decode_base64url(data, charset);
+    }
+
+    payload
+        .parts
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .find_map(|part| google_body_of_type(part, mime_type))
+}
+
+fn decode_base64url(data: &str, charset: Option<&str>) -> Option<String> {
+    let bytes = BASE64_URL.decode(data.trim_end_matches('=')).ok()?;
+    Some(decode_charset(&bytes, charset))
+}
+
+fn extract_google_headers(payload: Option<GooglePayload>) -> (String, String, String) {
+    let mut subject = "(geen onderwerp)".to_owned();
+    let mut from = "(onbekend)".to_owned();
+    let mut date = "(onbekend)".to_owned();
+
+    if let Some(payload) = payload {
+        for header in payload.headers.unwrap_or_default() {
+            match header.name.as_str() {
+                "Subject" => subject = header.value,
+                "From" => from = header.value,
+                "Date" => date = header.value,
+                _ => {}
+            }
+        }
+    }
+
+    (subject, from, date)
+}
+
+/// Turns free text into an FTS5 expression: every word must match as a
+/// prefix. Quoting each word keeps FTS operators in the input literal.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn parse_day(value: &str) -> Result<Option<i64>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || anyhow!("ongeldige datum: {value} (verwacht JJJJ-MM-DD)");
+    let mut parts = value.splitn(3, '-');
+    let mut next = || -> Result<i64> {
+        parts
+            .next()
+            .and_then(|part| part.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    Ok(Some(days_from_civil(year, month, day)))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn utc_timestamp(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+    format!("{year:04}-{month:02}-{day:02}T00:00:00Z")
+}
+
+/// Graph `dateTimeTimeZone.dateTime`, without offset (the zone is separate).
+fn graph_datetime(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+    let clock = secs.rem_euclid(SECS_PER_DAY);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        clock / 3600,
+        clock % 3600 / 60,
+        clock % 60
+    )
+}
+
+/// Parses an RFC 2822 `Date` header or an RFC 3339 timestamp (Graph) into Unix
+/// seconds.
+fn parse_mail_date(value: &str) -> Option<i64> {
+    let value = value.trim();
+    parse_rfc3339(value).or_else(|| parse_rfc2822(value))
+}
+
+fn parse_rfc3339(value: &str) -> Option<i64> {
+    let (date, rest) = value.split_once(['T', 't', ' '])?;
+    let day = parse_day(date).ok()??;
+    let clock = parse_clock(rest.get(..8)?)?;
+    let zone = rest[8..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        zone => parse_zone_offset(zone)?,
+    };
+    Some(day * SECS_PER_DAY + clock - offset)
+}
+
+fn parse_rfc2822(value: &str) -> Option<i64> {
+    let value = value.split('(').next().unwrap_or(value);
+    let mut tokens = value.split_whitespace().peekable();
+    if tokens
+        .peek()
+        .is_some_and(|token| token.starts_with(|c: char| c.is_ascii_alphabetic()))
+    {
+        tokens.next();
+    }
+    let day: i64 = tokens.next()?.parse().ok()?;
+    let month_token = tokens.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTH_NAMES.iter().position(|name| *name == month_token)? as i64 + 1;
+    let year = match tokens.next()?.parse::<i64>().ok()? {
+        year @ 0..50 => year + 2000,
+        year @ 50..100 => year + 1900,
+        year => year,
+    };
+    if day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let clock = parse_clock(tokens.next()?)?;
+    let offset = tokens.next().and_then(parse_zone_offset).unwrap_or(0);
+    Some(days_from_civil(year, month, day) * SECS_PER_DAY + clock - offset)
+}
+
+fn parse_clock(value: &str) -> Option<i64> {
+    let mut parts = value.split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds: i64 = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+    ((0..24).contains(&hours) && (0..60).contains(&minutes) && (0..=60).contains(&seconds))
+        .then_some(hours * 3600 + minutes * 60 + seconds)
+}
+
+fn parse_zone_offset(zone: &str) -> Option<i64> {
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn absolute_date(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+    let clock = secs.rem_euclid(SECS_PER_DAY);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        clock / 3600,
+        clock % 3600 / 60
+    )
+}
+
+fn relative_date(secs: i64, now: i64) -> String {
+    let age = now - secs;
+    match age {
+        -300..60 => "zojuist".to_owned(),
+        60..3600 => format!("{} min geleden", age / 60),
+        3600..SECS_PER_DAY => format!("{} u geleden", age / 3600),
+        SECS_PER_DAY..604_800 => {
+            let weekday = (secs.div_euclid(SECS_PER_DAY) + 3).rem_euclid(7);
+            WEEKDAY_LABELS[weekday as usize].to_owned()
+        }
+        _ => absolute_date(secs)[..10].to_owned(),
+    }
+}
+
+fn mbox_export_key(account: &Account) -> String {
+    format!(
+        "mbox_export_{}_{}",
+        account.provider.as_key(),
+        account.email.to_lowercase()
+    )
+}
+
+async fn export_page(
+    api: &impl MailProvider,
+    retry: RetryPolicy,
+    concurrency: usize,
+    expected_account: Option<&str>,
+    cursor: Option<&str>,
+) -> Result<ExportPage> {
+    if let Some(expected) = expected_account
+        && let Some(email) = api.userinfo().await?
+        && !email.eq_ignore_ascii_case(expected)
+    {
+        bail!("ingelogd als {email}, niet als {expected}; wissel eerst van account");
+    }
+    let listing = api
+        .list_messages(EXPORT_PAGE_SIZE, &DateRange::default(), None, cursor)
+        .await?;
+    let ids = listing.entries.into_iter().map(|entry| match entry {
+        ListedMessage::Id(id) => id,
+        ListedMessage::Complete(message) => message.id,
+    });
+    let raws: Vec<Option<Vec<u8>>> = stream::iter(ids)
+        .map(|id| async move { raw_for_export(api, retry, &id).await })
+        .buffered(concurrency)
+        .try_collect()
+        .await?;
+    Ok(ExportPage {
+        raws: raws.into_iter().flatten().collect(),
+        next_cursor: listing.next_cursor,
+        estimated_total: listing.estimated_total,
+    })
+}
+
+/// `None` when the message was deleted since it was listed. Rate limits are
+/// retried with the engine's backoff for both providers.
+async fn raw_for_export(
+    api: &impl MailProvider,
+    retry: RetryPolicy,
+    message_id: &str,
+) -> Result<Option<Vec<u8>>> {
+    let mut delay = retry.base_delay;
+    let mut retries_left = retry.retries;
+    loop {
+        match api.get_raw(message_id).await {
+            Ok(raw) => return Ok(Some(raw)),
+            Err(error) if matches!(error.downcast_ref(), Some(EngineError::NotFound { .. })) => {
+                warn!(message_id, "message gone during export, skipped");
+                return Ok(None);
+            }
+            Err(error) if retries_left > 0 && is_rate_limited(&error) => {
+                info!(?delay, "rate limited during export, retrying");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                retries_left -= 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn is_rate_limited(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let status = cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            .or_else(|| {
+                cause
+                    .downcast_ref::<GoogleHttpError>()
+                    .map(|error| error.status)
+            });
+        status == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+    })
+}
+
+/// One mboxrd entry: a `From ` separator line, the message with CRLF line
+/// ends turned into LF and `From ` lines quoted with `>`, and a blank line.
+fn mbox_entry(raw: &[u8], exported_at: i64) -> Vec<u8> {
+    let mut entry = format!("From MAILER-DAEMON {}\n", asctime(exported_at)).into_bytes();
+    let raw = raw.strip_suffix(b"\n").unwrap_or(raw);
+    for line in raw.split(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let unquoted = &line[line.iter().take_while(|&&byte| byte == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            entry.push(b'>');
+        }
+        entry.extend_from_slice(line);
+        entry.push(b'\n');
+    }
+    entry.push(b'\n');
+    entry
+}
+
+/// `Thu Jan  1 00:00:00 1970`, the date format of mbox separator lines.
+fn asctime(secs: i64) -> String {
+    const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs.div_euclid(SECS_PER_DAY);
+    let time = secs.rem_euclid(SECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{} {} {day:2} {:02}:{:02}:{:02} {year}",
+        DAYS[(days + 3).rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// `<id@host>` from a `Message-ID` value with or without angle brackets.
+fn normalize_message_id(value: &str) -> Option<String> {
+    let id = value
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim();
+    (!id.is_empty()).then(|| format!("<{id}>"))
+}
+
+/// Imported messages are keyed by their `Message-ID`, or by a hash of the
+/// source when they had none.
+fn imported_message_id(id: &str) -> Option<String> {
+    id.strip_prefix("mbox:")
+        .filter(|id| id.contains('@'))
+        .and_then(normalize_message_id)
+}
+
+fn unescape_mbox_line(line: &str) -> &str {
+    match line.strip_prefix('>') {
+        Some(stripped) if stripped.trim_start_matches('>').starts_with("From ") => stripped,
+        _ => line,
+    }
+}
+
+async fn import_mbox_message(conn: &libsql::Connection, raw: &str) -> Result<usize> {
+    let Some(message) = mbox_mail_message(raw) else {
+        return Ok(0);
+    };
+    let inserted = conn
+        .execute(
+            "INSERT INTO imported_messages (id, subject, sender, date, body, body_html, size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(id) DO NOTHING",
+            libsql::params![
+                message.id,
+                message.subject,
+                message.from,
+                message.date,
+                message.body,
+                message.body_html,
+                message.size_estimate.unwrap_or_default() as i64
+            ],
+        )
+        .await?;
+    Ok(inserted as usize)
+}
+
+fn mbox_mail_message(raw: &str) -> Option<MailMessage> {
+    if raw.trim().is_empty() {
+        return None;
+    }
+    let (headers, body) = split_mime_headers(raw);
+    let header = |name: &str| {
+        mime_header(&headers, name)
+            .map(decode_encoded_words)
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    let id = header("Message-ID")
+        .map(|value| value.trim().trim_matches(['<', '>']).to_owned())
+        .unwrap_or_else(|| {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            raw.hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        });
+    let raw_html = mime_text_body(&headers, body, "text/html");
+    let body = mime_text_body(&headers, body, "text/plain")
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| raw_html.as_deref().map(html_to_text))
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "(geen inhoud)".to_owned());
+    let body_html = raw_html.map(|html| sanitize_html(&html));
+
+    Some(MailMessage {
+        id: format!("mbox:{id}"),
+        labels: vec![IMPORTED_LABEL.to_owned()],
+        subject: header("Subject").unwrap_or_else(|| "(geen onderwerp)".to_owned()),
+        from: header("From").unwrap_or_else(|| "(onbekend)".to_owned()),
+        date: header("Date").unwrap_or_else(|| "(onbekend)".to_owned()),
+        body,
+        body_html,
+        size_estimate: Some(raw.len() as u64),
+        unsubscribe: None,
+        authentication: None,
+        spam_score: None,
+        is_suspicious: false,
+        importance: header("X-Priority")
+            .or_else(|| header("Importance"))
+            .and_then(|value| Importance::parse(&value))
+            .unwrap_or_default(),
+        has_attachments: raw
+            .to_ascii_lowercase()
+            .contains("content-disposition: attachment"),
+        conversation_id: None,
+        internet_message_id: header("Message-ID").and_then(|value| normalize_message_id(&value)),
+        extra: HashMap::new(),
+    })
+}
+
+fn split_mime_headers(raw: &str) -> (Vec<(String, String)>, &str) {
+    let (head, body) = if let Some(body) = raw.strip_prefix('\n') {
+        ("", body)
+    } else {
+        raw.split_once("\n\n").unwrap_or((raw, ""))
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    (headers, body)
+}
+
+fn mime_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn mime_param<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+fn mime_text_body(headers: &[(String, String)], body: &str, mime_type: &str) -> Option<String> {
+    let content_type = mime_header(headers, "Content-Type").unwrap_or("text/plain");
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+
+    if essence.to_ascii_lowercase().starts_with("multipart/") {
+        let delimiter = format!("--{}", mime_param(content_type, "boundary")?);
+        return body
+            .split(delimiter.as_str())
+            .skip(1)
+            .take_while(|part| !part.starts_with("--"))
+            .find_map(|part| {
+                let part = part.strip_prefix('\n').unwrap_or(part);
+                let (part_headers, part_body) = split_mime_headers(part);
+                mime_text_body(&part_headers, part_body, mime_type)
+            });
+    }
+
+    if !essence.eq_ignore_ascii_case(mime_type) {
+        return None;
+    }
+    let encoding = mime_header(headers, "Content-Transfer-Encoding").unwrap_or_default();
+    let bytes = if encoding.eq_ignore_ascii_case("base64") {
+        BASE64
+            .decode(body.split_whitespace().collect::<String>())
+            .ok()?
+    } else if encoding.eq_ignore_ascii_case("quoted-printable") {
+        decode_quoted_printable(body)
+    } else {
+        body.as_bytes().to_vec()
+    };
+    Some(decode_charset(&bytes, mime_param(content_type, "charset")))
+}
+
+/// Transcodes a body to UTF-8. Covers UTF-8/ASCII and the single-byte western
+/// charsets (ISO-8859-1/-15, Windows-1252); anything else that is not valid
+/// UTF-8 is read as Windows-1252, the most common mislabel.
+/// Decodes `bytes` in the named charset. An unknown label (or none) is read
+/// as UTF-8 when valid and as Windows-1252 otherwise; ASCII labels are read
+/// as UTF-8 because mailers often mislabel 8-bit text that way.
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    let label = charset.unwrap_or("utf-8").trim();
+    let encoding = if label.eq_ignore_ascii_case("us-ascii") || label.eq_ignore_ascii_case("ascii")
+    {
+        None
+    } else {
+        Encoding::for_label(label.as_bytes())
+    };
+    let encoding = encoding.unwrap_or(if std::str::from_utf8(bytes).is_ok() {
+        encoding_rs::UTF_8
+    } else {
+        encoding_rs::WINDOWS_1252
+    });
+    encoding.decode(bytes).0.into_owned()
+}
+
+fn decode_quoted_printable(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'=' {
+            if bytes.get(index + 1) == Some(&b'\n') {
+                index += 2;
+                continue;
+            }
+            if let Some(value) = input
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(value);
+                index += 3;
+                continue;
+            }
+        }
+        out.push(bytes[index]);
+        index += 1;
+    }
+    out
+}
+
+fn decode_encoded_words(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match decode_encoded_word(candidate) {
+            Some((decoded, len)) => {
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&decoded);
+                rest = &candidate[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &candidate[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_encoded_word(input: &str) -> Option<(String, usize)> {
+    let inner = input.strip_prefix("=?")?;
+    let (charset, rest) = inner.split_once('?')?;
+    let (encoding, rest) = rest.split_once('?')?;
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    let bytes = match encoding {
+        "B" | "b" => BASE64.decode(text).ok()?,
+        "Q" | "q" => decode_quoted_printable(&text.replace('_', " ")),
+        _ => return None,
+    };
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+    Some((decode_charset(&bytes, Some(charset)), len))
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleRawResponse {
+    raw: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleUserInfo {
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleListResponse {
+    messages: Option<Vec<GoogleMessageRef>>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+    #[serde(rename = "resultSizeEstimate")]
+    result_size_estimate: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleMessageRef {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleMessageResponse {
+    payload: Option<GooglePayload>,
+    snippet: Option<String>,
+    #[serde(rename = "labelIds")]
+    label_ids: Option<Vec<String>>,
+    #[serde(rename = "sizeEstimate")]
+    size_estimate: Option<u64>,
+    #[serde(rename = "threadId")]
+    thread_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleThreadResponse {
+    messages: Option<Vec<GoogleThreadMessage>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleThreadMessage {
+    id: String,
+    #[serde(flatten)]
+    detail: GoogleMessageResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct GooglePayload {
+    #[serde(rename = "mimeType")]
+    mime_type: Option<String>,
+    filename: Option<String>,
+    headers: Option<Vec<GoogleHeader>>,
+    body: Option<GoogleBody>,
+    parts: Option<Vec<GooglePayload>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GoogleBody {
+    data: Option<String>,
+    #[serde(rename = "attachmentId")]
+    attachment_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleDraftListResponse {
+    drafts: Option<Vec<GoogleMessageRef>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleDraftResponse {
+    id: String,
+    message: Option<GoogleDraftMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleDraftMessage {
+    payload: Option<GooglePayload>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleLabelListResponse {
+    labels: Option<Vec<GoogleLabel>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleLabel {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    label_type: Option<String>,
+    color: Option<GoogleLabelColor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleLabelColor {
+    #[serde(rename = "backgroundColor")]
+    background_color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphCategoryListResponse {
+    value: Vec<GraphCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphCategory {
+    #[serde(rename = "displayName")]
+    display_name: String,
+    color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphCategorizedMessages {
+    value: Vec<GraphCategorizedMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphCategorizedMessage {
+    categories: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleLabelCounts {
+    #[serde(rename = "messagesTotal")]
+    messages_total: Option<u64>,
+    #[serde(rename = "messagesUnread")]
+    messages_unread: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleModifiedMessage {
+    #[serde(rename = "labelIds")]
+    label_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleErrorEnvelope {
+    error: Option<GoogleErrorResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleErrorResponse {
+    message: Option<String>,
+    errors: Option<Vec<GoogleErrorItem>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleErrorItem {
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphMeResponse {
+    mail: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphInboxResponse {
+    value: Vec<GraphMessage>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+    #[serde(rename = "@odata.count")]
+    count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphMessage {
+    id: String,
+    #[serde(rename = "parentFolderId")]
+    parent_folder_id: Option<String>,
+    #[serde(rename = "isRead")]
+    is_read: Option<bool>,
+    importance: Option<String>,
+    categories: Option<Vec<String>>,
+    #[serde(rename = "conversationId")]
+    conversation_id: Option<String>,
+    #[serde(rename = "internetMessageId")]
+    internet_message_id: Option<String>,
+    #[serde(rename = "hasAttachments")]
+    has_attachments: Option<bool>,
+    subject: Option<String>,
+    from: Option<GraphFrom>,
+    #[serde(rename = "receivedDateTime")]
+    received_date_time: Option<String>,
+    #[serde(rename = "bodyPreview")]
+    body_preview: Option<String>,
+    body: Option<GraphBody>,
+    #[serde(rename = "singleValueExtendedProperties")]
+    single_value_extended_properties: Option<Vec<GraphExtendedProperty>>,
+    #[serde(rename = "internetMessageHeaders")]
+    internet_message_headers: Option<Vec<GraphHeader>>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphExtendedProperty {
+    id: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphFrom {
+    #[serde(rename = "emailAddress")]
+    email_address: Option<GraphEmailAddress>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphEmailAddress {
+    address: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphFolderListResponse {
+    value: Vec<GraphMailFolder>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphMailFolder {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphFolderSizes {
+    value: Vec<GraphFolderSize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphFolderSize {
+    #[serde(rename = "singleValueExtendedProperties")]
+    single_value_extended_properties: Option<Vec<GraphExtendedProperty>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleDriveAbout {
+    #[serde(rename = "storageQuota")]
+    storage_quota: Option<GoogleStorageQuota>,
+}
+
+/// Drive reports quota values as decimal strings; `limit` is absent for
+/// unlimited accounts.
+#[derive(Debug, Default, Deserialize)]
+struct GoogleStorageQuota {
+    limit: Option<String>,
+    usage: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphFolderCounts {
+    #[serde(rename = "totalItemCount")]
+    total_item_count: Option<u64>,
+    #[serde(rename = "unreadItemCount")]
+    unread_item_count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphMovedMessage {
+    id: String,
+    #[serde(rename = "parentFolderId")]
+    parent_folder_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphCreatedMessage {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphDraftListResponse {
+    value: Vec<GraphDraft>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphDraft {
+    id: String,
+    subject: Option<String>,
+    #[serde(rename = "toRecipients")]
+    to_recipients: Option<Vec<GraphFrom>>,
+    body: Option<GraphBody>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphAttachmentList {
+    value: Vec<GraphAttachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphAttachment {
+    #[serde(rename = "contentId")]
+    content_id: Option<String>,
+    #[serde(rename = "contentType")]
+    content_type: Option<String>,
+    #[serde(rename = "contentBytes")]
+    content_bytes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphBody {
+    content: Option<String>,
+    #[serde(rename = "contentType")]
+    content_type: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct GoogleSendRequest {
+    raw: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GoogleDraftRequest {
+    message: GoogleSendRequest,
+}
+
+#[derive(Debug, Serialize)]
+struct GoogleModifyRequest<'a> {
+    #[serde(rename = "addLabelIds")]
+    add_label_ids: Vec<&'a str>,
+    #[serde(rename = "removeLabelIds")]
+    remove_label_ids: Vec<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct GoogleBatchModifyRequest<'a> {
+    ids: &'a [String],
+    #[serde(rename = "addLabelIds")]
+    add_label_ids: &'a [&'a str],
+    #[serde(rename = "removeLabelIds")]
+    remove_label_ids: &'a [&'a str],
+}
+
+#[derive(Debug, Serialize)]
+struct GraphBatchBody<'a> {
+    requests: Vec<GraphBatchEntry<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphBatchEntry<'a> {
+    id: String,
+    method: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<HashMap<&'static str, &'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<&'a serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphBatchResponse {
+    responses: Vec<GraphBatchResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphBatchResult {
+    id: String,
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphReadStateRequest {
+    #[serde(rename = "isRead")]
+    is_read: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphMoveRequest<'a> {
+    #[serde(rename = "destinationId")]
+    destination_id: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphSendMailRequest {
+    message: GraphOutgoingMessage,
+    #[serde(rename = "saveToSentItems")]
+    save_to_sent_items: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphOutgoingMessage {
+    subject: String,
+    body: GraphItemBody,
+    #[serde(rename = "toRecipients")]
+    to_recipients: Vec<GraphRecipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<GraphFileAttachment>,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphItemBody {
+    #[serde(rename = "contentType")]
+    content_type: &'static str,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphRecipient {
+    #[serde(rename = "emailAddress")]
+    email_address: GraphOutgoingAddress,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphOutgoingAddress {
+    address: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphFileAttachment {
+    #[serde(rename = "@odata.type")]
+    odata_type: &'static str,
+    name: String,
+    #[serde(rename = "contentType")]
+    content_type: String,
+    #[serde(rename = "contentBytes")]
+    content_bytes: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn encoded(value: &str) -> String {
+        BASE64_URL.encode(value)
+    }
+
+    #[test]
+    fn client_id_check_separates_errors_from_warnings() {
+        let guid = "0b1c2d3e-4f50-6172-8394-a5b6c7d8e9f0";
+        let google = "123-abc.apps.googleusercontent.com";
+        assert!(matches!(
+            check_client_id(Provider::Google, google),
+            Ok(None)
+        ));
+        assert!(matches!(check_client_id(Provider::Outlook, guid), Ok(None)));
+        assert!(matches!(
+            check_client_id(Provider::Google, "123-abc"),
+            Ok(Some(_))
+        ));
+        assert!(matches!(
+            check_client_id(Provider::Outlook, "my-app"),
+            Ok(Some(_))
+        ));
+        assert!(check_client_id(Provider::Google, guid).is_err());
+        assert!(check_client_id(Provider::Outlook, google).is_err());
+        assert!(check_client_id(Provider::Outlook, "0b1c 2d3e").is_err());
+        assert!(check_client_id(Provider::Google, "").is_err());
+    }
+
+    #[test]
+    fn quoted_reply_start_finds_common_markers() {
+        let gmail = "Prima!\n\nOn Mon, 3 Jun 2024 at 10:00, Anna <a@x.nl> wrote:\n> Zullen we?\n";
+        assert_eq!(&gmail[..quoted_reply_start(gmail).unwrap()], "Prima!\n\n");
+
+        let wrapped = "Ja\nOn Mon, 3 Jun 2024, Anna <a@x.nl>\nwrote:\n> Vraag\n";
+        assert_eq!(&wrapped[..quoted_reply_start(wrapped).unwrap()], "Ja\n");
+
+        let outlook =
+            "Akkoord.\n\n________________________________\nVan: Bob\nVerzonden: maandag\n";
+        assert_eq!(
+            &outlook[..quoted_reply_start(outlook).unwrap()],
+            "Akkoord.\n\n"
+        );
+
+        let trailing = "> eerder\nantwoord\n\n> oud 1\n>\n> oud 2\n";
+        assert_eq!(
+            &trailing[..quoted_reply_start(trailing).unwrap()],
+            "> eerder\nantwoord\n\n"
+        );
+
+        assert_eq!(quoted_reply_start("Geen citaat hier.\nGroet"), None);
+    }
+
+    #[test]
+    fn linkify_splits_links_and_trims_punctuation() {
+        use TextSegment::{Link, Text};
+        assert_eq!(
+            linkify("Zie https://example.com/a_(b). of mailto:x@y.nl, klaar"),
+            vec![
+                Text("Zie "),
+                Link("https://example.com/a_(b)"),
+                Text(". of "),
+                Link("mailto:x@y.nl"),
+                Text(", klaar"),
+            ]
+        );
+        assert_eq!(
+            linkify("(HTTP://Example.com) nohttp://x"),
+            vec![Text("("), Link("HTTP://Example.com"), Text(") nohttp://x")]
+        );
+        assert_eq!(
+            linkify("alleen https:// tekst"),
+            vec![Text("alleen https:// tekst")]
+        );
+    }
+
+    #[test]
+    fn storage_problem_detects_io_kinds_and_sqlite_messages() {
+        let io =
+            |kind| anyhow::Error::from(std::io::Error::from(kind)).context("kan DB map niet maken");
+        assert_eq!(
+            StorageProblem::detect(&io(std::io::ErrorKind::StorageFull)),
+            Some(StorageProblem::DiskFull)
+        );
+        assert_eq!(
+            StorageProblem::detect(&io(std::io::ErrorKind::ReadOnlyFilesystem)),
+            Some(StorageProblem::ReadOnly)
+        );
+        assert_eq!(
+            StorageProblem::detect(&anyhow!("attempt to write a readonly database")),
+            Some(StorageProblem::ReadOnly)
+        );
+        assert_eq!(
+            StorageProblem::detect(&io(std::io::ErrorKind::NotFound)),
+            None
+        );
+    }
+
+    #[test]
+    fn graph_batch_results_follow_request_order() {
+        let request = |id: &str| GraphBatchRequest {
+            method: "POST",
+            url: format!("/me/messages/{id}/move"),
+            body: None,
+        };
+        let response: GraphBatchResponse = serde_json::from_value(json!({
+            "responses": [
+                {"id": "1", "status": 404, "body": {"error": {"message": "Not found"}}},
+                {"id": "3", "status": 429, "headers": {"Retry-After": "7"}},
+                {"id": "0", "status": 201, "body": {"id": "new-a"}}
+            ]
+        }))
+        .unwrap();
+        let requests = [request("a"), request("b"), request("c"), request("d")];
+        let results = graph_batch_results(&requests.iter().collect::<Vec<_>>(), response);
+        let GraphBatchOutcome::Done(Ok(moved)) = &results[0] else {
+            panic!("first move failed");
+        };
+        assert_eq!(moved["id"], "new-a");
+        assert!(matches!(
+            &results[1],
+            GraphBatchOutcome::Done(Err(error)) if format!("{error:#}").contains("HTTP 404: Not found")
+        ));
+        assert!(matches!(results[2], GraphBatchOutcome::Done(Err(_))));
+        assert!(matches!(
+            results[3],
+            GraphBatchOutcome::Throttled(Some(wait)) if wait == Duration::from_secs(7)
+        ));
+    }
+
+    #[test]
+    fn missing_write_scopes_matches_prefixed_graph_scopes() {
+        let granted = |scopes: &[&str]| owned_scopes(scopes);
+        assert!(missing_write_scopes(Provider::Google, &[]).is_empty());
+        assert_eq!(
+            missing_write_scopes(
+                Provider::Google,
+                &granted(&["https://www.googleapis.com/auth/gmail.readonly"])
+            ),
+            vec![
+                "https://www.googleapis.com/auth/gmail.modify",
+                "https://www.googleapis.com/auth/gmail.send"
+            ]
+        );
+        assert_eq!(
+            missing_write_scopes(
+                Provider::Outlook,
+                &granted(&["https://graph.microsoft.com/Mail.ReadWrite", "mail.send"])
+            ),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn config_overrides_apply_below_builder_values() {
+        let env = |name: &str| match name {
+            "MAIL_MESSAGE_LIMIT" => Some("50".to_owned()),
+            "MAIL_RETRY_COUNT" => Some("1".to_owned()),
+            "MAIL_RETRY_BASE_MS" => Some("10".to_owned()),
+            "MAIL_CALLBACK_TIMEOUT_SECS" => Some("nope".to_owned()),
+            _ => None,
+        };
+        let config = EngineConfig::default().with_overrides(env);
+        assert_eq!(config.message_limit, 50);
+        assert_eq!(config.rate_limit_retries, 1);
+        assert_eq!(config.retry_base_delay, Duration::from_millis(10));
+        assert_eq!(
+            config.callback_timeout,
+            Duration::from_secs(CALLBACK_TIMEOUT_SECS)
+        );
+
+        let engine = EngineBuilder { config }.message_limit(5).build();
+        assert_eq!(engine.config().message_limit, 5);
+        assert_eq!(engine.config().rate_limit_retries, 1);
+    }
+
+    fn google_message(payload: serde_json::Value) -> MailMessage {
+        let detail = serde_json::from_value(json!({
+            "payload": payload,
+            "snippet": "snippet tekst",
+            "labelIds": ["INBOX"],
+        }))
+        .expect("valid payload");
+        google_mail_message("abc".to_owned(), detail, &[], false)
+    }
+
+    #[test]
+    fn html_only_multipart_uses_html_as_text() {
+        let message = google_message(json!({
+            "mimeType": "multipart/alternative",
+            "headers": [{ "name": "Subject", "value": "Alleen HTML" }],
+            "body": { "size": 0 },
+            "parts": [{
+                "partId": "0",
+                "mimeType": "text/html",
+                "headers": [{ "name": "Content-Type", "value": "text/html; charset=UTF-8" }],
+                "body": { "size": 52, "data": encoded("<p>Hallo&nbsp;wereld</p><p>Tweede &amp; laatste</p>") },
+            }],
+        }));
+
+        assert_eq!(message.body, "Hallo wereld\n\nTweede & laatste");
+        assert!(message.body_html.is_some());
+    }
+
+    #[test]
+    fn single_part_html_body_is_converted() {
+        let message = google_message(json!({
+            "mimeType": "text/html",
+            "headers": [{ "name": "Content-Type", "value": "text/html; charset=UTF-8" }],
+            "body": {
+                "size": 90,
+                "data": encoded("<html><head><style>p { color: red; }</style></head><body>Regel 1<br>Regel 2</body></html>"),
+            },
+        }));
+
+        assert_eq!(message.body, "Regel 1\nRegel 2");
+    }
+
+    #[test]
+    fn single_part_plain_body_is_used_directly() {
+        let message = google_message(json!({
+            "mimeType": "text/plain",
+            "body": { "size": 11, "data": encoded("Platte tekst") },
+        }));
+
+        assert_eq!(message.body, "Platte tekst");
+        assert!(message.body_html.is_none());
+    }
+
+    #[test]
+    fn alternative_body_follows_html_preference() {
+        let detail = || {
+            serde_json::from_value(json!({
+                "payload": {
+                    "mimeType": "multipart/alternative",
+                    "parts": [
+                        { "mimeType": "text/plain", "body": { "data": encoded("Platte versie") } },
+                        { "mimeType": "text/html", "body": { "data": encoded("<p>HTML versie</p>") } },
+                    ],
+                },
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            google_mail_message("abc".to_owned(), detail(), &[], false).body,
+            "Platte versie"
+        );
+        assert_eq!(
+            google_mail_message("abc".to_owned(), detail(), &[], true).body,
+            "HTML versie"
+        );
+    }
+
+    #[test]
+    fn nested_alternative_prefers_plain_text() {
+        let message = google_message(json!({
+            "mimeType": "multipart/mixed",
+            "body": { "size": 0 },
+            "parts": [
+                {
+                    "partId": "0",
+                    "mimeType": "multipart/alternative",
+                    "body": { "size": 0 },
+                    "parts": [
+                        {
+                            "partId": "0.0",
+                            "mimeType": "text/plain",
+                            "body": { "size": 5, "data": encoded("plain") },
+                        },
+                        {
+                            "partId": "0.1",
+                            "mimeType": "text/html",
+                            "body": { "size": 12, "data": encoded("<b>html</b>") },
+                        },
+                    ],
+                },
+                {
+                    "partId": "1",
+                    "mimeType": "application/pdf",
+                    "filename": "factuur.pdf",
+                    "body": { "size": 1000, "attachmentId": "att-1" },
+                },
+            ],
+        }));
+
+        assert_eq!(message.body, "plain");
+        assert_eq!(message.body_html.as_deref(), Some("<b>html</b>"));
+    }
+
+    #[test]
+    fn empty_plain_part_falls_back_to_html() {
+        let message = google_message(json!({
+            "mimeType": "multipart/alternative",
+            "parts": [
+                { "mimeType": "text/plain", "body": { "size": 0, "data": "" } },
+                { "mimeType": "text/html", "body": { "size": 20, "data": encoded("<div>Inhoud</div>") } },
+            ],
+        }));
+
+        assert_eq!(message.body, "Inhoud");
+    }
+
+    #[test]
+    fn missing_body_falls_back_to_snippet() {
+        let message = google_message(json!({
+            "mimeType": "multipart/alternative",
+            "headers": [{ "name": "Subject", "value": "Metadata" }],
+        }));
+
+        assert_eq!(message.body, "snippet tekst");
+    }
+
+    #[test]
+    fn transcodes_body_charsets() {
+        let headers = vec![
+            (
+                "Content-Type".to_owned(),
+                "text/plain; charset=\"ISO-8859-1\"".to_owned(),
+            ),
+            (
+                "Content-Transfer-Encoding".to_owned(),
+                "quoted-printable".to_owned(),
+            ),
+        ];
+        assert_eq!(
+            mime_text_body(&headers, "Caf=E9 na=EFef", "text/plain").as_deref(),
+            Some("Caf\u{e9} na\u{ef}ef")
+        );
+
+        let message = google_message(json!({
+            "mimeType": "text/plain",
+            "headers": [{ "name": "Content-Type", "value": "text/plain; charset=windows-1252" }],
+            "body": { "data": BASE64_URL.encode(b"\x93prijs\x94 \x80 5") },
+        }));
+        assert_eq!(message.body, "\u{201c}prijs\u{201d} \u{20ac} 5");
+        assert_eq!(
+            decode_encoded_words("=?iso-8859-15?Q?=A4_10?="),
+            "\u{20ac} 10"
+        );
+        assert_eq!(
+            decode_encoded_words("=?KOI8-R?B?8NLJ18XU?= uit =?iso-8859-2?Q?=A3=F3d=BC?="),
+            "Привет uit Łódź"
+        );
+        assert_eq!(
+            decode_charset(b"\x93\xfa\x96\x7b", Some("Shift_JIS")),
+            "日本"
+        );
+        assert_eq!(
+            decode_charset(b"\xe9t\xe9", Some("x-unknown")),
+            "\u{e9}t\u{e9}"
+        );
+    }
+
+    #[test]
+    fn html_to_text_handles_lists_and_entities() {
+        assert_eq!(
+            html_to_text("<ul>\n  <li>een</li>\n  <li>twee &#8364; &#x41;</li>\n</ul>"),
+            "- een\n- twee \u{20ac} A"
+        );
+        assert_eq!(html_to_text("a &unknown; b"), "a &unknown; b");
+    }
+
+    #[test]
+    fn groups_outlook_messages_by_conversation() {
+        let messages = [
+            json!({ "id": "1", "conversationId": "a" }),
+            json!({ "id": "2", "conversationId": "b" }),
+            json!({ "id": "3" }),
+            json!({ "id": "4", "conversationId": "a" }),
+        ]
+        .into_iter()
+        .map(|entry| graph_mail_message(serde_json::from_value(entry).unwrap(), &[]))
+        .collect();
+
+        let grouped = group_by_conversation(messages);
+        let ids: Vec<_> = grouped.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, ["1", "4", "2", "3"]);
+        assert_eq!(grouped[3].conversation_key(), "3");
+    }
+
+    #[test]
+    fn parses_header_and_graph_dates() {
+        let header = parse_mail_date("Mon, 3 Jun 2024 16:22:05 +0200 (CEST)");
+        let graph = parse_mail_date("2024-06-03T14:22:05Z");
+        assert_eq!(header, graph);
+        assert_eq!(
+            DateDisplay::Absolute.format("2024-06-03T14:22:05.123Z", 0),
+            "2024-06-03 14:22"
+        );
+        let now = graph.unwrap() + 3 * 3600;
+        assert_eq!(
+            DateDisplay::Relative.format("2024-06-03T14:22:05Z", now),
+            "3 u geleden"
+        );
+        assert_eq!(DateDisplay::Relative.format("onbekend", now), "onbekend");
+    }
+
+    fn google_error_body(message: &str, reason: &str) -> String {
+        json!({
+            "error": {
+                "code": 403,
+                "message": message,
+                "errors": [{ "message": message, "domain": "global", "reason": reason }],
+                "status": "PERMISSION_DENIED",
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn google_error_detail_appends_first_reason() {
+        let body = google_error_body(
+            "Gmail API has not been used in project 123 before or it is disabled.",
+            "accessNotConfigured",
+        );
+        assert_eq!(
+            google_error_detail(&body).as_deref(),
+            Some(
+                "Gmail API has not been used in project 123 before or it is disabled. \
+                 (reason: accessNotConfigured)"
+            )
+        );
+    }
+
+    #[test]
+    fn google_error_detail_without_reason_or_message() {
+        let body = json!({ "error": { "code": 401, "message": "Invalid Credentials" } });
+        assert_eq!(
+            google_error_detail(&body.to_string()).as_deref(),
+            Some("Invalid Credentials")
+        );
+        assert_eq!(google_error_detail(r#"{"error": {"code": 500}}"#), None);
+    }
+
+    #[test]
+    fn google_error_detail_ignores_malformed_json() {
+        assert_eq!(google_error_detail("<html>Bad Gateway</html>"), None);
+        assert_eq!(google_error_detail(r#"{"error": "#), None);
+        assert_eq!(google_error_detail(""), None);
+    }
+
+    #[test]
+    fn google_error_hint_selects_matching_tip() {
+        let hint = |message: &str, reason: &str| {
+            google_error_detail(&google_error_body(message, reason))
+                .and_then(|detail| google_error_hint(&detail))
+                .unwrap_or_default()
+        };
+
+        assert!(
+            hint(
+                "Gmail API has not been used in project 123 before or it is disabled.",
+                "accessNotConfigured",
+            )
+            .ends_with("gmail.googleapis.com?project=123")
+        );
+        assert_eq!(
+            GoogleSetupFix::detect("Gmail API has not been used in project 123"),
+            Some(GoogleSetupFix::EnableGmailApi)
+        );
+        assert_eq!(
+            GoogleSetupFix::EnableGmailApi.url("456-abc.apps.googleusercontent.com"),
+            format!("{GMAIL_API_LIBRARY_URL}?project=456")
+        );
+        assert!(
+            hint(
+                "Request had insufficient authentication scopes.",
+                "insufficientPermissions",
+            )
+            .contains("gmail.readonly")
+        );
+        assert!(
+            hint("Access blocked: app not verified", "forbidden")
+                .starts_with("Controleer OAuth consent screen")
+        );
+        assert!(
+            hint("Quota exceeded for quota metric", "rateLimitExceeded")
+                .starts_with("Gmail beperkt tijdelijk")
+        );
+        assert!(
+            hint("Daily Limit Exceeded", "dailyLimitExceeded")
+                .starts_with("Het dagelijkse Gmail API-quotum")
+        );
+        assert_eq!(
+            google_error_hint("Backend Error (reason: backendError)"),
+            None
+        );
+    }
+
+    fn token_hint(provider: Provider, message: &str) -> String {
+        format!(
+            "{:#}",
+            with_token_exchange_hint(provider, anyhow!(message.to_owned()))
+        )
+    }
+
+    #[test]
+    fn only_server_errors_count_towards_outage_hint() {
+        let unavailable = google_http_error(
+            "Gmail messages list",
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            None,
+            "",
+        );
+        let forbidden = google_http_error(
+            "Gmail messages list",
+            reqwest::StatusCode::FORBIDDEN,
+            None,
+            "",
+        );
+        assert!(is_server_side_failure(
+            &unavailable.context("inbox ophalen")
+        ));
+        assert!(!is_server_side_failure(&forbidden));
+        assert!(!is_server_side_failure(&anyhow!("geen verbinding")));
+    }
+
+    #[test]
+    fn token_exchange_hint_for_missing_client_secret() {
+        let google = token_hint(
+            Provider::Google,
+            "Server returned: Client_Secret Is Missing.",
+        );
+        assert!(google.starts_with("Server returned: Client_Secret Is Missing.\nTip: "));
+        assert!(google.contains("Deze Google OAuth client verwacht een Client Secret"));
+
+        let outlook = token_hint(
+            Provider::Outlook,
+            "AADSTS7000218: The request body must contain client_assertion or client_secret.",
+        );
+        assert!(outlook.contains("Deze Microsoft App Registration verwacht een Client Secret"));
+
+        let outlook = token_hint(Provider::Outlook, "client secret is MISSING");
+        assert!(outlook.contains("Deze Microsoft App Registration verwacht een Client Secret"));
+    }
+
+    #[test]
+    fn token_exchange_hint_for_invalid_client() {
+        let google = token_hint(Provider::Google, "Server returned error: INVALID_CLIENT");
+        assert!(google.contains("Tip: Controleer Google OAuth"));
+
+        let outlook = token_hint(Provider::Outlook, "Invalid_Client: unknown application");
+        assert!(outlook.contains("Tip: Controleer Microsoft OAuth"));
+    }
+
+    #[test]
+    fn token_exchange_hint_prefers_missing_secret_over_invalid_client() {
+        let hinted = token_hint(
+            Provider::Outlook,
+            "invalid_client: AADSTS7000218: client_secret is missing",
+        );
+        assert!(hinted.contains("verwacht een Client Secret"));
+        assert!(!hinted.contains("Controleer Microsoft OAuth"));
+    }
+
+    #[test]
+    fn token_exchange_hint_passes_unrelated_errors_through() {
+        for provider in [Provider::Google, Provider::Outlook] {
+            assert_eq!(
+                token_hint(provider, "connection reset by peer"),
+                "connection reset by peer"
+            );
+        }
+    }
+
+    /// A database in the temp dir, removed with its WAL and journal files
+    /// when dropped. Declare it before the engine so it outlives it.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let db = Self(
+                std::env::temp_dir().join(format!("mail-engine-{name}-{}.db", std::process::id())),
+            );
+            db.remove();
+            db
+        }
+
+        fn engine(&self) -> Engine {
+            Engine::builder().db_path(&self.0).build()
+        }
+
+        fn remove(&self) {
+            for suffix in ["", "-wal", "-shm", "-journal"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.0.display()));
+            }
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reads_do_not_wait_on_writes() {
+        let db = TempDb::new("wal");
+        let engine = Arc::new(db.engine());
+        engine.set_preview_length(80).await.unwrap();
+
+        let writer = engine.open_conn().await.unwrap();
+        writer.execute("BEGIN IMMEDIATE", ()).await.unwrap();
+        writer
+            .execute(
+                "UPDATE app_settings SET value = '120' WHERE key = ?1",
+                libsql::params![PREVIEW_LENGTH_KEY],
+            )
+            .await
+            .unwrap();
+
+        let reads = (0..8).map(|_| {
+            let engine = Arc::clone(&engine);
+            tokio::spawn(async move { engine.load_oauth_settings().await.unwrap() })
+        });
+        let settings = timeout(Duration::from_secs(2), futures::future::join_all(reads))
+            .await
+            .expect("reads blocked by an open write transaction");
+        for settings in settings {
+            assert_eq!(settings.unwrap().preview_length, 80);
+        }
+        writer.execute("COMMIT", ()).await.unwrap();
+
+        let mixed = (0..8).map(|index| {
+            let engine = Arc::clone(&engine);
+            tokio::spawn(async move {
+                if index % 2 == 0 {
+                    engine.set_preview_length(index).await.unwrap();
+                } else {
+                    engine.load_oauth_settings().await.unwrap();
+                }
+            })
+        });
+        timeout(Duration::from_secs(10), futures::future::join_all(mixed))
+            .await
+            .expect("concurrent reads and writes deadlocked");
+    }
+
+    #[tokio::test]
+    async fn search_cache_finds_cached_messages_across_accounts() {
+        let db = TempDb::new("fts");
+        let engine = db.engine();
+        let listing = |provider, account: &str, id: &str, subject: &str| LoginResult {
+            provider,
+            account: account.to_owned(),
+            messages: vec![graph_mail_message(
+                serde_json::from_value(json!({ "id": id, "subject": subject })).unwrap(),
+                &[],
+            )],
+            next_cursor: None,
+            estimated_total: None,
+            mailbox_empty: false,
+            warning: None,
+            load_errors: HashMap::new(),
+        };
+        engine
+            .cache_messages(
+                &listing(Provider::Google, "a@example.com", "1", "Factuur juni"),
+                None,
+            )
+            .await
+            .unwrap();
+        engine
+            .cache_messages(
+                &listing(Provider::Outlook, "b@example.com", "2", "Factuur juli"),
+                None,
+            )
+            .await
+            .unwrap();
+        engine
+            .cache_messages(
+                &listing(Provider::Google, "a@example.com", "1", "Offerte"),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let hits = engine.search_cache("fact").await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].account.email, "b@example.com");
+        assert!(engine.search_cache("\"OR").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn message_cache_follows_actions_of_the_signed_in_account() {
+        let db = TempDb::new("cache-actions");
+        let engine = db.engine();
+        let listing = |account: &str, ids: &[&str]| LoginResult {
+            provider: Provider::Outlook,
+            account: account.to_owned(),
+            messages: ids
+                .iter()
+                .map(|id| {
+                    graph_mail_message(serde_json::from_value(json!({ "id": id })).unwrap(), &[])
+                })
+                .collect(),
+            next_cursor: None,
+            estimated_total: None,
+            mailbox_empty: false,
+            warning: None,
+            load_errors: HashMap::new(),
+        };
+        engine
+            .cache_messages(&listing("a@example.com", &["1", "2", "3"]), None)
+            .await
+            .unwrap();
+        engine
+            .cache_messages(&listing("b@example.com", &["1"]), None)
+            .await
+            .unwrap();
+        engine
+            .save_account_email(Provider::Outlook, "a@example.com")
+            .await
+            .unwrap();
+
+        let mut fetched = listing("a@example.com", &["1"]).messages.remove(0);
+        fetched.body = "volledige tekst".to_owned();
+        engine
+            .cache_message_body(Provider::Outlook, &fetched)
+            .await
+            .unwrap();
+        engine
+            .relocate_cached_messages(
+                Provider::Outlook,
+                &[("2".to_owned(), "2b".to_owned())],
+                "f1",
+            )
+            .await;
+        engine
+            .forget_cached_messages(Provider::Outlook, &["3".to_owned()])
+            .await;
+
+        let conn = engine.open_conn().await.unwrap();
+        let mut rows = conn
+            .query(
+                "SELECT account, folder, id, body FROM cached_messages ORDER BY account, id",
+                (),
+            )
+            .await
+            .unwrap();
+        let mut cached = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            cached.push((
+                row.get::<String>(0).unwrap(),
+                row.get::<String>(1).unwrap(),
+                row.get::<String>(2).unwrap(),
+                row.get::<String>(3).unwrap(),
+            ));
+        }
+        let row = |account: &str, folder: &str, id: &str, body: &str| {
+            (
+                account.to_owned(),
+                folder.to_owned(),
+                id.to_owned(),
+                body.to_owned(),
+            )
+        };
+        assert_eq!(
+            cached,
+            [
+                row("a@example.com", "inbox", "1", "volledige tekst"),
+                row("a@example.com", "f1", "2b", "(geen inhoud)"),
+                row("b@example.com", "inbox", "1", "(geen inhoud)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_account_emails_ignores_case_and_same_provider() {
+        let account = |provider, email: &str| Account {
+            provider,
+            email: email.to_owned(),
+        };
+        let accounts = [
+            account(Provider::Google, "Jan@Example.com"),
+            account(Provider::Google, "piet@example.com"),
+            account(Provider::Outlook, "jan@example.com"),
+        ];
+        assert_eq!(duplicate_account_emails(&accounts), ["jan@example.com"]);
+        assert!(accounts[0].same_mailbox(&account(Provider::Google, "jan@EXAMPLE.com")));
+        assert!(!accounts[0].same_mailbox(&accounts[2]));
+    }
+
+    /// Runs the callback server on a free port, sends `request_target` the
+    /// way a browser would and returns the parsed code plus the raw response.
+    async fn simulate_callback(request_target: &str) -> (Result<String>, String) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let redirect = Url::parse(&format!("http://127.0.0.1:{port}/callback")).unwrap();
+        let target = RedirectTarget::from_url(&redirect).unwrap();
+        let request = format!("GET {request_target} HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n\r\n");
+
+        let browser = tokio::spawn(async move {
+            let mut stream = loop {
+                match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+
+        let code = wait_for_oauth_code(
+            &target,
+            "expected-state",
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+        )
+        .await;
+        (code, browser.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_returns_code_for_matching_state() {
+        let (code, response) =
+            simulate_callback("/callback?code=auth-code%2F1&state=expected-state").await;
+        assert_eq!(code.unwrap(), "auth-code/1");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("Login geslaagd. Je kunt dit tabblad sluiten."));
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_rejects_bad_requests() {
+        for (request_target, expected) in [
+            (
+                "/callback?code=auth-code&state=forged-state",
+                "OAuth state mismatch",
+            ),
+            (
+                "/callback?error=access_denied&state=expected-state",
+                "OAuth login mislukt: access_denied",
+            ),
+            (
+                "/elsewhere?code=auth-code&state=expected-state",
+                "Ongeldige callback path: /elsewhere",
+            ),
+            (
+                "/callback?state=expected-state",
+                "OAuth callback bevat geen code",
+            ),
+        ] {
+            let (code, response) = simulate_callback(request_target).await;
+            assert_eq!(code.unwrap_err().to_string(), expected);
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+            assert!(response.ends_with(expected), "{response}");
+        }
+    }
+
+    #[test]
+    fn unread_only_extends_provider_filters() {
+        let unread = DateRange::default().with_unread_only(true);
+        assert_eq!(unread.gmail_query().as_deref(), Some("is:unread"));
+        assert_eq!(
+            unread.graph_filter().as_deref(),
+            Some("receivedDateTime ge 1900-01-01T00:00:00Z and isRead eq false")
+        );
+
+        let bounded = DateRange::parse("2024-06-01", "")
+            .unwrap()
+            .with_unread_only(true);
+        assert_eq!(
+            bounded.graph_filter().as_deref(),
+            Some("receivedDateTime ge 2024-06-01T00:00:00Z and isRead eq false")
+        );
+        assert!(bounded.gmail_query().unwrap().ends_with(" is:unread"));
+    }
+
+    #[test]
+    fn pasted_code_accepts_url_query_or_bare_code() {
+        let url = "http://127.0.0.1:8080/callback?state=abc&code=4%2F0xyz&scope=mail";
+        assert_eq!(parse_pasted_code(url, "abc").unwrap(), "4/0xyz");
+        assert_eq!(
+            parse_pasted_code("?code=c1&state=abc", "abc").unwrap(),
+            "c1"
+        );
+        assert_eq!(parse_pasted_code("  4/0bare \n", "abc").unwrap(), "4/0bare");
+        assert!(parse_pasted_code(url, "other").is_err());
+        assert!(parse_pasted_code("http://127.0.0.1/cb?error=access_denied", "abc").is_err());
+        assert!(parse_pasted_code(" ", "abc").is_err());
+    }
+
+    #[tokio::test]
+    async fn saved_credentials_win_over_env() {
+        let db = TempDb::new("creds");
+        let engine = db.engine();
+        let env = |id: &'static str, secret: &'static str| {
+            move |name: &str| match name {
+                "MAIL_GOOGLE_CLIENT_ID" => Some(id.to_owned()),
+                "MAIL_GOOGLE_CLIENT_SECRET" => Some(secret.to_owned()),
+                _ => None,
+            }
+        };
+        let resolve = |lookup| engine.resolve_provider_credentials(Provider::Google, lookup);
+
+        let from_env = resolve(env(" env.apps.googleusercontent.com ", "  s3cret \n"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(from_env.client_id, "env.apps.googleusercontent.com");
+        assert_eq!(from_env.client_secret.as_deref(), Some("s3cret"));
+        let blank_secret = resolve(env("env.apps.googleusercontent.com", "   "))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(blank_secret.client_secret, None);
+        assert!(resolve(env("  ", "s3cret")).await.unwrap().is_none());
+
+        engine
+            .save_provider_credentials(
+                Provider::Google,
+                ProviderCredentials {
+                    client_id: "db.apps.googleusercontent.com".to_owned(),
+                    client_secret: None,
+                    tenant: None,
+                },
+            )
+            .await
+            .unwrap();
+        let saved = resolve(env("env.apps.googleusercontent.com", "s3cret"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved.client_id, "db.apps.googleusercontent.com");
+        assert_eq!(saved.client_secret, None);
+    }
+
+    #[test]
+    fn redact_secrets_hides_bearer_query_and_json_tokens() {
+        for (input, expected) in [
+            (
+                "Authorization: Bearer ya29.a0AfH6SMBx-1",
+                "Authorization: Bearer [verborgen]",
+            ),
+            (
+                "POST /token?access_token=abc.123&refresh_token=1%2F%2F0g-x&code=4%2F0A&x=1",
+                "POST /token?access_token=[verborgen]&refresh_token=[verborgen]&code=[verborgen]&x=1",
+            ),
+            (
+                r#"{"access_token":"ya29.abc","expires_in":3599,"refresh_token": "1//0g"}"#,
+                r#"{"access_token":"[verborgen]","expires_in":3599,"refresh_token": "[verborgen]"}"#,
+            ),
+            (
+                "token eyJhbGciOiJSUzI1NiJ9.eyJzdWIiOiIx verlopen",
+                "token [verborgen] verlopen",
+            ),
+            (
+                "barcode=123 encoded: ya29 bearer-token",
+                "barcode=123 encoded: ya29 bearer-token",
+            ),
+        ] {
+            assert_eq!(redact_secrets(input), expected);
+        }
+    }
+
+    #[test]
+    fn error_report_hides_tokens() {
+        let report = error_report(
+            "token refresh mislukt: refresh_token=1//abc access_token: ya29.xyz",
+            Some(Provider::Google),
+        );
+        assert!(!report.contains("1//abc"));
+        assert!(!report.contains("ya29.xyz"));
+        assert!(report.contains("Provider: Google"));
+
+        let url = issue_url(&report);
+        assert!(url.starts_with(ISSUE_TRACKER_URL));
+        assert!(!url.contains("ya29"));
+        assert!(url.contains("title=Fout%3A+token+refresh+mislukt"));
+    }
+
+    #[test]
+    fn followup_dates_render_in_utc() {
+        let followup = FollowUp {
+            message_id: "m1".to_owned(),
+            due_at: 1_717_424_520,
+        };
+        assert_eq!(followup.due_day(), "2024-06-03");
+        assert_eq!(graph_datetime(followup.due_at), "2024-06-03T14:22:00");
+        assert!(followup.is_overdue(followup.due_at));
+        assert!(!followup.is_overdue(followup.due_at - 1));
+    }
+
+    struct FlakyProvider;
+
+    impl MailProvider for FlakyProvider {
+        fn provider(&self) -> Provider {
+            Provider::Google
+        }
+
+        async fn userinfo(&self) -> Result<Option<String>> {
+            Ok(Some("me@example.com".to_owned()))
+        }
+
+        async fn list_messages(
+            &self,
+            _limit: usize,
+            _range: &DateRange,
+            _folder: Option<&str>,
+            _cursor: Option<&str>,
+        ) -> Result<MessageListing> {
+            Ok(MessageListing {
+                entries: ["a", "gone", "broken", "b"]
+                    .map(|id| ListedMessage::Id(id.to_owned()))
+                    .into(),
+                next_cursor: None,
+                estimated_total: Some(3),
+                mailbox_empty: false,
+            })
+        }
+
+        async fn message_summary(&self, id: String) -> Result<MailMessage> {
+            if id == "gone" {
+                return Err(EngineError::NotFound {
+                    provider: Provider::Google,
+                    id,
+                }
+                .into());
+            }
+            if id == "broken" {
+                bail!("Gmail message detail gaf HTTP 500 Internal Server Error");
+            }
+            Ok(graph_mail_message(
+                serde_json::from_value(json!({ "id": id })).unwrap(),
+                &[],
+            ))
+        }
+
+        async fn get_message(&self, message_id: &str) -> Result<MailMessage> {
+            self.message_summary(message_id.to_owned()).await
+        }
+
+        async fn get_raw(&self, _message_id: &str) -> Result<Vec<u8>> {
+            bail!("FlakyProvider kent geen ruwe berichten")
+        }
+
+        async fn list_thread(&self, _conversation_id: &str) -> Result<Vec<MailMessage>> {
+            bail!("FlakyProvider kent geen gesprekken")
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_skips_deleted_and_keeps_failed_messages() {
+        let options = ListingOptions {
+            limit: 3,
+            concurrency: 2,
+            eager_body_count: 0,
+            on_message: None,
+            transform: None,
+            account: None,
+        };
+        let result = fetch_listing(&FlakyProvider, &options, &DateRange::default(), None, None)
+            .await
+            .unwrap();
+        let ids: Vec<_> = result.messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["a", "broken", "b"]);
+        assert_eq!(result.messages[1].subject, "(bericht niet geladen)");
+        assert_eq!(result.load_errors.keys().collect::<Vec<_>>(), ["broken"]);
+        assert_eq!(
+            result.warning.as_deref(),
+            Some("2 berichten konden niet geladen worden")
+        );
+    }
+
+    #[test]
+    fn sort_keys_order_ascending() {
+        let message = |id: &str, from: &str, date: &str, size: Option<u64>| {
+            let mut message = graph_mail_message(
+                serde_json::from_value(json!({ "id": id, "receivedDateTime": date })).unwrap(),
+                &[],
+            );
+            message.from = from.to_owned();
+            message.size_estimate = size;
+            message
+        };
+        let old = message("1", "bob", "2024-01-01T00:00:00Z", Some(10));
+        let new = message("2", "Alice", "2024-06-01T00:00:00Z", None);
+        assert!(SortKey::Date.compare(&old, &new).is_lt());
+        assert!(SortKey::Sender.compare(&old, &new).is_gt());
+        assert!(SortKey::Size.compare(&old, &new).is_gt());
+        assert_eq!(
+            SortKey::from_key(SortKey::Size.as_key()),
+            Some(SortKey::Size)
+        );
+    }
+
+    #[tokio::test]
+    async fn account_email_cache_expires_and_is_forgotten() {
+        let db = TempDb::new("account");
+        let engine = db.engine();
+
+        engine
+            .save_account_email(Provider::Google, "me@example.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            engine.cached_account_email(Provider::Google).await.unwrap(),
+            Some("me@example.com".to_owned())
+        );
+        assert_eq!(
+            engine
+                .cached_account_email(Provider::Outlook)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let conn = engine.open_conn().await.unwrap();
+        let stale = unix_now() - ACCOUNT_EMAIL_REFRESH_SECS;
+        save_app_setting(
+            &conn,
+            &account_email_checked_key(Provider::Google),
+            &stale.to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            engine.cached_account_email(Provider::Google).await.unwrap(),
+            None
+        );
+
+        engine
+            .save_account_email(Provider::Google, "me@example.com")
+            .await
+            .unwrap();
+        engine.forget_account_email(Provider::Google).await.unwrap();
+        assert_eq!(
+            engine.cached_account_email(Provider::Google).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn transform_hook_runs_before_fetched_hook() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let transform = MessageTransformHook(Arc::new(|message: &mut MailMessage| {
+            message.subject = format!("[regel] {}", message.id);
+        }));
+        let fetched = FetchedMessageHook(Arc::new({
+            let seen = seen.clone();
+            move |message: &MailMessage| seen.lock().unwrap().push(message.subject.clone())
+        }));
+        let options = ListingOptions {
+            limit: 3,
+            concurrency: 1,
+            eager_body_count: 0,
+            on_message: Some(&fetched),
+            transform: Some(&transform),
+            account: Some("me@example.com"),
+        };
+        let result = fetch_listing(&FlakyProvider, &options, &DateRange::default(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(result.messages[0].subject, "[regel] a");
+        assert_eq!(*seen.lock().unwrap(), ["[regel] a", "[regel] b"]);
+    }
+
+    #[tokio::test]
+    async fn rules_run_in_order() {
+        let db = TempDb::new("rules");
+        let engine = db.engine();
+        let rules = [
+            (
+                RuleCondition::FromContains("NEWS@".to_owned()),
+                RuleAction::MoveTo("Nieuws".to_owned()),
+            ),
+            (
+                RuleCondition::SubjectContains("korting".to_owned()),
+                RuleAction::Hide,
+            ),
+            (
+                RuleCondition::HasAttachment,
+                RuleAction::Label("Bijlagen".to_owned()),
+            ),
+            (
+                RuleCondition::FromContains("news@".to_owned()),
+                RuleAction::MarkRead,
+            ),
+            (
+                RuleCondition::FromContains("news@".to_owned()),
+                RuleAction::MoveTo("Later".to_owned()),
+            ),
+        ];
+        for (condition, action) in rules {
+            engine
+                .save_rule(&MailRule {
+                    id: None,
+                    condition,
+                    action,
+                })
+                .await
+                .unwrap();
+        }
+        let message = |id: &str, from: &str, subject: &str, has_attachments: bool| {
+            graph_mail_message(
+                serde_json::from_value(json!({
+                    "id": id,
+                    "subject": subject,
+                    "isRead": false,
+                    "hasAttachments": has_attachments,
+                    "from": { "emailAddress": { "address": from } },
+                }))
+                .unwrap(),
+                &[],
+            )
+        };
+        let mut messages = vec![
+            message("a", "news@example.com", "Weekoverzicht", true),
+            message("b", "shop@example.com", "50% korting", false),
+            message("c", "jan@example.com", "Lunch", false),
+        ];
+        engine.apply_rules(&mut messages).await.unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].rule_folder(), Some("Nieuws"));
+        assert_eq!(messages[0].local_labels().collect::<Vec<_>>(), ["Bijlagen"]);
+        assert!(!messages[0].labels.iter().any(|label| label == "UNREAD"));
+        assert_eq!(messages[1].id, "c");
+        assert_eq!(messages[1].rule_folder(), None);
+
+        let first = engine.list_rules().await.unwrap()[0].clone();
+        engine
+            .save_rule(&MailRule {
+                action: RuleAction::Hide,
+                ..first.clone()
+            })
+            .await
+            .unwrap();
+        engine.delete_rule(first.id.unwrap() + 1).await.unwrap();
+        let saved = engine.list_rules().await.unwrap();
+        assert_eq!(saved.len(), 4);
+        assert_eq!(
+            (saved[0].id, &saved[0].action),
+            (first.id, &RuleAction::Hide)
+        );
+    }
+
+    #[test]
+    fn sender_trust_from_authentication_results() {
+        let trust = |header: &str| AuthenticationResults::parse(header).sender_trust();
+        assert_eq!(
+            trust(
+                "mx.google.com; dkim=pass header.i=@bank.nl; spf=pass smtp.mailfrom=bank.nl; dmarc=pass (p=REJECT) header.from=bank.nl"
+            ),
+            Some(SenderTrust::Verified)
+        );
+        assert_eq!(
+            trust("mx.google.com; spf=pass smtp.mailfrom=bank.nl; dmarc=fail header.from=bank.nl"),
+            Some(SenderTrust::Unverified)
+        );
+        assert_eq!(
+            trust("mx.google.com; spf=none; dkim=none"),
+            Some(SenderTrust::Unverified)
+        );
+        assert_eq!(trust("mx.google.com; spf=pass smtp.mailfrom=bank.nl"), None);
+
+        let arc_only = header_signals(
+            [(
+                "ARC-Authentication-Results",
+                "i=1; spf=pass; dkim=pass; dmarc=pass",
+            )]
+            .into_iter(),
+        );
+        let auth = arc_only.authentication.unwrap();
+        assert_eq!(auth.sender_trust(), Some(SenderTrust::Verified));
+        assert_eq!(
+            auth.summary(),
+            "SPF geslaagd, DKIM geslaagd, DMARC geslaagd"
+        );
+        assert!(header_signals(std::iter::empty()).authentication.is_none());
+    }
+
+    #[tokio::test]
+    async fn mbox_export_entries_import_back() {
+        assert_eq!(asctime(0), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(asctime(1_760_788_800), "Sat Oct 18 12:00:00 2025");
+
+        let first = b"Message-ID: <a@x>\r\nSubject: Hallo\r\n\r\nFrom here on\r\n>From quoted\r\n";
+        let second = b"Message-ID: <b@x>\nSubject: Tweede\n\nTekst";
+        let mut mbox = mbox_entry(first, 0);
+        mbox.extend(mbox_entry(second, 0));
+        let text = String::from_utf8(mbox.clone()).unwrap();
+        assert!(text.contains("\n>From here on\n>>From quoted\n\nFrom MAILER-DAEMON"));
+        assert!(!text.contains('\r'));
+
+        let db = TempDb::new("export");
+        let mbox_path = db.0.with_extension("mbox");
+        std::fs::write(&mbox_path, &mbox).unwrap();
+        let engine = db.engine();
+        assert_eq!(engine.import_mbox(&mbox_path).await.unwrap(), 2);
+        let imported = engine.list_imported_messages().await.unwrap();
+        let hallo = imported.iter().find(|m| m.subject == "Hallo").unwrap();
+        assert!(hallo.body.contains("From here on\n>From quoted"));
+
+        let _ = std::fs::remove_file(&mbox_path);
+    }
+
+    #[test]
+    fn internet_message_id_is_normalized_per_source() {
+        let graph = graph_mail_message(
+            serde_json::from_value(
+                json!({ "id": "AAMk1", "internetMessageId": "<abc@mail.example>" }),
+            )
+            .unwrap(),
+            &[],
+        );
+        assert_eq!(
+            graph.internet_message_id.as_deref(),
+            Some("<abc@mail.example>")
+        );
+
+        let gmail: GoogleMessageResponse = serde_json::from_value(json!({
+            "payload": { "headers": [{ "name": "Message-Id", "value": " abc@mail.example " }] }
+        }))
+        .unwrap();
+        let gmail = google_mail_message("18c".to_owned(), gmail, &[], false);
+        assert_eq!(gmail.internet_message_id, graph.internet_message_id);
+
+        let mbox =
+            mbox_mail_message("Message-ID: <abc@mail.example>\nSubject: x\n\nTekst").unwrap();
+        assert_eq!(mbox.internet_message_id, graph.internet_message_id);
+        assert_eq!(imported_message_id(&mbox.id), graph.internet_message_id);
+        assert_eq!(imported_message_id("mbox:0123456789abcdef"), None);
+        assert_eq!(normalize_message_id(" <> "), None);
+    }
+
+    /// Serves one canned `(status, body)` response per connection and
+    /// returns the base URL plus the request lines it received.
+    async fn mock_http(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut received = Vec::new();
+                let mut buffer = [0; 4096];
+                let header_end = loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    received.extend_from_slice(&buffer[..read]);
+                    if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&received[..header_end]).to_string();
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                while received.len() < header_end + length {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    received.extend_from_slice(&buffer[..read]);
+                }
+                requests.push(head.lines().next().unwrap_or_default().to_owned());
+                let response = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn outlook_draft_is_replaced_only_after_create_succeeds() {
+        let draft = Draft {
+            id: Some("old".to_owned()),
+            message: OutgoingMessage {
+                to: vec!["jan@example.com".to_owned()],
+                subject: "Concept".to_owned(),
+                ..OutgoingMessage::default()
+            },
+        };
+
+        let (url, server) = mock_http(vec![(500, "{}")]).await;
+        let saved = save_outlook_draft_at(Client::new(), &url, "token", &draft).await;
+        assert!(saved.is_err());
+        assert_eq!(server.await.unwrap(), ["POST /me/messages HTTP/1.1"]);
+
+        let (url, server) = mock_http(vec![(201, r#"{"id":"new"}"#), (204, "")]).await;
+        let saved = save_outlook_draft_at(Client::new(), &url, "token", &draft).await;
+        assert_eq!(saved.unwrap(), "new");
+        assert_eq!(
+            server.await.unwrap(),
+            [
+                "POST /me/messages HTTP/1.1",
+                "DELETE /me/messages/old HTTP/1.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn mime_message_builds_parts_and_keeps_headers_on_one_line() {
+        let header_block = |mime: &str| mime.split("\r\n\r\n").next().unwrap().to_owned();
+        let plain = build_mime_message(&OutgoingMessage {
+            to: vec!["jan@example.com".to_owned(), " ".to_owned()],
+            subject: "Hallo".to_owned(),
+            body: "Tekst".to_owned(),
+            attachments: Vec::new(),
+        });
+        assert_eq!(
+            header_block(&plain),
+            "To: jan@example.com\r\nSubject: Hallo\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64"
+        );
+        assert!(plain.ends_with(&format!("{}\r\n", BASE64.encode("Tekst"))));
+
+        let multipart = build_mime_message(&OutgoingMessage {
+            to: vec!["jan@example.com".to_owned()],
+            subject: "Bijlage".to_owned(),
+            body: "Zie bijlage".to_owned(),
+            attachments: vec![(
+                "rapport \"v2\".pdf".to_owned(),
+                b"%PDF".to_vec(),
+                "application/pdf".to_owned(),
+            )],
+        });
+        let boundary = multipart
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        assert_eq!(multipart.matches(&format!("--{boundary}\r\n")).count(), 2);
+        assert!(multipart.ends_with(&format!("--{boundary}--\r\n")));
+        assert!(
+            multipart
+                .contains("Content-Disposition: attachment; filename=\"rapport _v2_.pdf\"\r\n")
+        );
+        assert!(multipart.contains(&format!("{}\r\n", BASE64.encode("%PDF"))));
+
+        let unicode = build_mime_message(&OutgoingMessage {
+            subject: "Café €".to_owned(),
+            ..OutgoingMessage::default()
+        });
+        assert!(unicode.starts_with(&format!(
+            "Subject: =?UTF-8?B?{}?=\r\n",
+            BASE64.encode("Café €")
+        )));
+
+        let injected = build_mime_message(&OutgoingMessage {
+            to: vec!["jan@example.com\r\nBcc: spion@example.com".to_owned()],
+            subject: "Hoi\r\nBcc: spion@example.com\r\n\r\nnep".to_owned(),
+            ..OutgoingMessage::default()
+        });
+        let headers = header_block(&injected);
+        assert_eq!(
+            headers
+                .lines()
+                .filter(|line| line.starts_with("Bcc"))
+                .count(),
+            0
+        );
+        assert!(headers.starts_with(
+            "To: jan@example.com  Bcc: spion@example.com\r\n\
+             Subject: Hoi  Bcc: spion@example.com    nep\r\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn mbox_export_resumes_after_a_failed_page() {
+        let db = TempDb::new("resume");
+        let out = db.0.with_extension("mbox");
+        let engine = db.engine();
+        let account = Account {
+            provider: Provider::Google,
+            email: "me@example.com".to_owned(),
+        };
+        let key = mbox_export_key(&account);
+        let page = |cursor: Option<String>| {
+            let index: usize = cursor.map_or(0, |cursor| cursor.parse().unwrap());
+            ExportPage {
+                raws: (index * 2..index * 2 + 2)
+                    .map(|n| format!("Subject: Bericht {n}\r\n\r\nTekst {n}\r\n").into_bytes())
+                    .collect(),
+                next_cursor: (index < 2).then(|| (index + 1).to_string()),
+                estimated_total: Some(6),
+            }
+        };
+
+        let failed = engine
+            .write_mbox_export(
+                &key,
+                &out,
+                |cursor| {
+                    let page = if cursor.as_deref() == Some("1") {
+                        Err(anyhow!("HTTP 500"))
+                    } else {
+                        Ok(page(cursor))
+                    };
+                    async { page }
+                },
+                |_| {},
+            )
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(
+            engine.pending_mbox_export(&account).await.unwrap(),
+            Some(out.clone())
+        );
+        // A page torn by a crash after the last checkpoint.
+        let mut partial = std::fs::read(&out).unwrap();
+        partial.extend_from_slice(b"From MAILER-DAEMON\nSubject: Bericht 2\n");
+        std::fs::write(&out, partial).unwrap();
+
+        let mut cursors = Vec::new();
+        let exported = engine
+            .write_mbox_export(
+                &key,
+                &out,
+                |cursor| {
+                    cursors.push(cursor.clone());
+                    let page = page(cursor);
+                    async { Ok(page) }
+                },
+                |_| {},
+            )
+            .await
+            .unwrap();
+        assert_eq!(exported, 6);
+        assert_eq!(cursors, [Some("1".to_owned()), Some("2".to_owned())]);
+        assert_eq!(engine.pending_mbox_export(&account).await.unwrap(), None);
+
+        let mbox = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(mbox.matches("From MAILER-DAEMON").count(), 6);
+        for n in 0..6 {
+            assert_eq!(mbox.matches(&format!("Subject: Bericht {n}\n")).count(), 1);
+        }
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn status_server_accepts_only_its_own_host() {
+        let request = |host: &str| format!("GET /status HTTP/1.1\r\nHost: {host}\r\n\r\n");
+        assert!(status_host_allowed(&request("127.0.0.1:8765"), 8765));
+        assert!(status_host_allowed(&request("LocalHost:8765"), 8765));
+        assert!(!status_host_allowed(&request("evil.example:8765"), 8765));
+        assert!(!status_host_allowed(&request("127.0.0.1:80"), 8765));
+        assert!(!status_host_allowed("GET /status HTTP/1.1\r\n\r\n", 8765));
+    }
+
+    #[test]
+    fn merge_keeps_older_cache_drops_vanished_and_sorts_by_date() {
+        let message = |id: &str, date: &str| {
+            graph_mail_message(
+                serde_json::from_value(json!({ "id": id, "receivedDateTime": date })).unwrap(),
+                &[],
+            )
+        };
+        let cached = vec![
+            message("old", "2024-05-01T09:00:00Z"),
+            message("archived", "2024-06-02T09:00:00Z"),
+            message("kept", "2024-06-03T09:00:00Z"),
+            message("older", "2024-05-20T09:00:00Z"),
+        ];
+        let fresh = vec![
+            message("new", "2024-06-04T09:00:00Z"),
+            message("kept", "2024-06-03T09:00:00Z"),
+            message("first", "2024-06-01T09:00:00Z"),
+        ];
+        let ids: Vec<String> = Engine::merge_messages(cached, fresh)
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(ids, ["new", "kept", "first", "older", "old"]);
+    }
+
+    #[tokio::test]
+    async fn local_draft_remembers_which_attachments_were_dropped() {
+        let db = TempDb::new("draft");
+        let engine = db.engine();
+        let draft = Draft {
+            id: None,
+            message: OutgoingMessage {
+                to: vec!["jan@example.com".to_owned()],
+                subject: "Offerte".to_owned(),
+                body: "Zie bijlagen".to_owned(),
+                attachments: vec![
+                    (
+                        "offerte.pdf".to_owned(),
+                        b"%PDF".to_vec(),
+                        "application/pdf".to_owned(),
+                    ),
+                    ("foto.jpg".to_owned(), Vec::new(), "image/jpeg".to_owned()),
+                ],
+            },
+        };
+        engine.save_local_draft(&draft).await.unwrap();
+
+        let restored = engine.load_local_draft().await.unwrap().unwrap();
+        assert_eq!(restored.draft.message.subject, "Offerte");
+        assert!(restored.draft.message.attachments.is_empty());
+        assert_eq!(restored.lost_attachments, ["offerte.pdf", "foto.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn followups_belong_to_an_account_and_follow_moved_messages() {
+        let db = TempDb::new("followups");
+        {
+            let old = Builder::new_local(&db.0).build().await.unwrap();
+            old.connect()
+                .unwrap()
+                .execute_batch(
+                    "CREATE TABLE app_settings (key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL);
+                     INSERT INTO app_settings VALUES ('account_email_outlook', 'a@example.com');
+                     CREATE TABLE followups (
+                        provider TEXT NOT NULL,
+                        message_id TEXT NOT NULL,
+                        due_at INTEGER NOT NULL,
+                        PRIMARY KEY (provider, message_id)
+                     );
+                     INSERT INTO followups VALUES ('outlook', '1', 100), ('google', 'g', 50);",
+                )
+                .await
+                .unwrap();
+        }
+        let engine = db.engine();
+        let ids = |followups: Vec<FollowUp>| {
+            followups
+                .into_iter()
+                .map(|followup| followup.message_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(engine.followups(Provider::Outlook).await.unwrap()),
+            ["1"]
+        );
+        assert_eq!(
+            ids(engine.followups(Provider::Google).await.unwrap()),
+            ["g"]
+        );
+
+        engine
+            .relocate_followups(Provider::Outlook, &[("1".to_owned(), "1b".to_owned())])
+            .await;
+        assert_eq!(
+            ids(engine.followups(Provider::Outlook).await.unwrap()),
+            ["1b"]
+        );
+
+        engine
+            .save_account_email(Provider::Outlook, "b@example.com")
+            .await
+            .unwrap();
+        assert!(
+            engine
+                .followups(Provider::Outlook)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
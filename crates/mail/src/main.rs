@@ -1,48 +1,395 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use iced::keyboard::{Key, Modifiers, key};
 use iced::{
-    Element, Task,
-    widget::{button, column, container, row, scrollable, text, text_input},
+    Element, Subscription, Task, task,
+    widget::{
+        button, checkbox, column, container, pick_list, rich_text, row, scrollable, span, text,
+        text::Wrapping, text_editor, text_input, tooltip,
+    },
+    window,
 };
 use mail_engine::{
-    DEFAULT_GOOGLE_CLIENT_ID, Engine, LoginResult, MailMessage, Provider, ProviderCredentials,
-    SavedOAuthSettings,
+    Account, AuthVerdict, DEFAULT_CACHE_MESSAGE_LIMIT, DEFAULT_GOOGLE_CLIENT_ID,
+    DEFAULT_PREVIEW_LENGTH, DateDisplay, DateRange, DbLocation, DbLocationKind, Draft, Engine,
+    EngineError, ExportProgress, Folder, FolderCounts, FollowUp, GoogleSetupFix, Importance,
+    LocalDraft, LoginResult, MailAction, MailLabel, MailMessage, MailRule, ManualLogin,
+    OfflineProgress, OutgoingMessage, Provider, ProviderCredentials, RuleAction, RuleCondition,
+    SavedOAuthSettings, SearchHit, SelfTestOutcome, SelfTestReport, SenderTrust, SortKey,
+    StorageUsage, TextSegment, attachment_mime_type, block_remote_images, data_dir, db_location,
+    duplicate_account_emails, error_report, has_remote_images, is_first_run, issue_url, linkify,
+    missing_write_scopes, portable_mode_forced, quoted_reply_start, redact_secrets, sender_address,
+    set_portable_mode, split_recipients,
+};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    EnvFilter, fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt,
 };
-use tracing_subscriber::EnvFilter;
+
+const DRAFT_AUTOSAVE_SECS: u64 = 15;
+const UNDO_WINDOW_SECS: u64 = 8;
+const UNDO_STACK_LIMIT: usize = 10;
+const SNOOZE_CHECK_SECS: u64 = 60;
+const SNOOZE_PRESETS: [(&str, i64); 3] = [
+    ("Over 3 uur", 3 * 3600),
+    ("Morgen", 86_400),
+    ("Volgende week", 7 * 86_400),
+];
+const FOLLOW_UP_PRESETS: [(&str, i64); 3] = [
+    ("Morgen", 86_400),
+    ("Over 3 dagen", 3 * 86_400),
+    ("Volgende week", 7 * 86_400),
+];
+const LOG_FILES_KEPT: usize = 7;
+/// A folder synced longer ago than this is flagged as stale in the list header.
+const STALE_SYNC_SECS: i64 = 3600;
+/// Inbox rows built beyond each edge of the visible part of the list.
+const LIST_BUFFER_ROWS: usize = 10;
 
 fn main() -> iced::Result {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_target(false)
-        .compact()
+    let _log_guard = init_tracing();
+
+    iced::application("mail", update, view)
+        .theme(theme)
+        .scale_factor(|state| f64::from(state.font_scale))
+        .subscription(subscription)
+        .exit_on_close_request(false)
+        .run_with(|| {
+            let location = db_location();
+            let mut state = MailApp {
+                google_client_id: DEFAULT_GOOGLE_CLIENT_ID.to_owned(),
+                // Nothing to choose when the environment already decides.
+                show_db_prompt: is_first_run() && location.kind == DbLocationKind::UserData,
+                db_location: Some(location),
+                preview_length: DEFAULT_PREVIEW_LENGTH,
+                preview_length_input: DEFAULT_PREVIEW_LENGTH.to_string(),
+                cache_limit: DEFAULT_CACHE_MESSAGE_LIMIT,
+                cache_limit_input: DEFAULT_CACHE_MESSAGE_LIMIT.to_string(),
+                list_date_display: DateDisplay::Relative,
+                font_scale: 1.0,
+                ..MailApp::default()
+            };
+
+            // The database may still move once the first-run prompt is
+            // answered, so nothing opens it before then.
+            let startup = if state.show_db_prompt {
+                Task::none()
+            } else {
+                state.startup()
+            };
+            (state, startup)
+        })
+}
+
+fn init_tracing() -> Option<WorkerGuard> {
+    let (file_writer, guard) = match file_log_writer() {
+        Some((writer, guard)) => (Some(writer), Some(guard)),
+        None => (None, None),
+    };
+
+    let file_layer = file_writer.map(|writer| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_target(false)
+            .with_writer(writer)
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .compact(),
+        )
+        .with(file_layer)
         .try_init()
         .ok();
 
-    iced::application("mail", update, view).run_with(|| {
-        let mut state = MailApp::default();
-        state.google_client_id = DEFAULT_GOOGLE_CLIENT_ID.to_owned();
+    guard
+}
 
-        (
-            state,
-            Task::batch(vec![
-                Task::perform(load_saved_settings(), Message::SettingsLoaded),
-                Task::perform(restore_google_session(), Message::RestoreSessionDone),
-            ]),
-        )
-    })
+/// `MAIL_STATUS_PORT` opts in to the local status endpoint.
+fn status_port() -> Option<u16> {
+    let value = std::env::var("MAIL_STATUS_PORT").ok()?;
+    match value.trim().parse() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            tracing::warn!(value, "MAIL_STATUS_PORT is geen geldige poort");
+            None
+        }
+    }
+}
+
+fn file_log_writer() -> Option<(RedactingMakeWriter, WorkerGuard)> {
+    let enabled = std::env::var("MAIL_LOG_FILE")
+        .map(|value| matches!(value.trim(), "1" | "true" | "on" | "yes"))
+        .unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+
+    // Logs follow the database: `.mail/logs` in portable mode, otherwise the
+    // user data dir.
+    let dir = data_dir().join("logs");
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("mail")
+        .filename_suffix("log")
+        .max_log_files(LOG_FILES_KEPT)
+        .build(&dir);
+
+    match appender {
+        Ok(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            Some((RedactingMakeWriter(writer), guard))
+        }
+        Err(error) => {
+            eprintln!(
+                "logbestand in {} kon niet worden geopend: {error}",
+                dir.display()
+            );
+            None
+        }
+    }
+}
+
+struct RedactingMakeWriter(NonBlocking);
+
+impl<'a> MakeWriter<'a> for RedactingMakeWriter {
+    type Writer = RedactingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+struct RedactingWriter(NonBlocking);
+
+impl Write for RedactingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let redacted = redact_secrets(&String::from_utf8_lossy(buf));
+        self.0.write_all(redacted.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Message {
     SettingsLoaded(Result<SavedOAuthSettings, String>),
     ToggleGoogleSetup,
+    DefaultProviderSelected(DefaultProviderChoice),
+    DefaultProviderSaved(Result<(), String>),
     SelectFolder(MailFolder),
     SelectMessage(usize),
+    SelectAdjacent(isize),
+    ListScrolled(scrollable::Viewport),
+    ToggleQuoted,
+    ToggleUnreadOnly,
     GoogleClientIdChanged(String),
     GoogleClientSecretChanged(String),
     SaveGoogleSettings,
     SaveDone(Result<String, String>),
-    LoginGoogle,
+    LoginGoogle { other_account: bool },
+    OpenGoogleFix(GoogleSetupFix),
     LoginDone(Result<LoginResult, String>),
-    RestoreSessionDone(Result<Option<LoginResult>, String>),
+    MessageStreamed(MailMessage),
+    AuthorizeUrlReady(String),
+    RetryLogin,
+    StartManualLogin,
+    ManualLoginReady(Result<ManualLogin, String>),
+    ManualCodeChanged(String),
+    FinishManualLogin,
+    CancelManualLogin,
+    CancelFetch,
+    CopyAuthorizeUrl,
+    CopyErrorReport,
+    ReportIssue,
+    RestoreSessionDone(Result<Option<LoginResult>, SessionError>),
+    Reauthenticate(Provider),
+    Reauthorize(Provider),
+    RetryRestoreSession,
+    ToggleCompose,
+    ComposeToChanged(String),
+    ComposeSubjectChanged(String),
+    ComposeBodyEdited(text_editor::Action),
+    PickAttachments,
+    AttachmentsPicked(Vec<Attachment>),
+    RemoveAttachment(usize),
+    SendCompose,
+    SendDone(Result<String, String>),
+    SaveDraft,
+    DraftSaved(Result<String, String>),
+    DeleteDraft,
+    DraftDeleted(Result<String, String>),
+    DraftsLoaded(Result<Vec<Draft>, String>),
+    OpenDraft(usize),
+    AutoSaveDraft,
+    LocalDraftSaved(Result<(), String>),
+    LocalDraftLoaded(Result<Option<LocalDraft>, String>),
+    FoldersLoaded(Result<Vec<Folder>, String>),
+    LabelsLoaded(Result<Vec<MailLabel>, String>),
+    AddLabel(MailLabel),
+    RemoveLabel(String),
+    LabelsModified(Result<(String, Vec<String>, Vec<String>), String>),
+    InboxCountsLoaded(Result<FolderCounts, String>),
+    MoveSelected(Folder),
+    MoveDone(Result<(String, String, String), String>),
+    ChooseDbLocation(bool),
+    CopyMessageJson,
+    LoadFullMessage,
+    FullMessageLoaded(String, Result<MailMessage, String>),
+    RetryMessage(String),
+    ViewSource,
+    LoadStorageUsage,
+    StorageUsageLoaded(Result<Option<StorageUsage>, String>),
+    CopyPermalink,
+    OpenPermalink,
+    OpenLink(String),
+    SourceLoaded(Result<(String, String), String>),
+    CloseSource,
+    LoadThread,
+    ThreadLoaded(Result<(String, Vec<MailMessage>), String>),
+    ToggleThreadMessage(String),
+    ThreadMessageLoaded(String, Result<MailMessage, String>),
+    CloseThread,
+    OpenHtmlView,
+    AllowRemoteImages(String),
+    TrustImageSender(String),
+    ImageSenderTrusted(Result<String, String>),
+    ToggleLoadRemoteImages(bool),
+    LoadRemoteImagesSaved(Result<(), String>),
+    ToggleAutoMarkRead(bool),
+    TogglePreferHtml(bool),
+    PreferHtmlSaved(Result<(), String>),
+    AutoMarkReadSaved(Result<(), String>),
+    MarkReadDelayChanged(String),
+    PreviewLengthChanged(String),
+    PreviewLengthSaved(Result<(), String>),
+    ToggleOfflineBodies(bool),
+    CacheLimitChanged(String),
+    OfflineSyncSaved(Result<(), String>),
+    DownloadForOffline,
+    OfflineProgress(OfflineProgress),
+    BackupAccount,
+    BackupProgress(ExportProgress),
+    BackupDone(Result<Option<usize>, String>),
+    OfflineDownloadDone(Result<usize, String>),
+    TogglePlaintextMode(bool),
+    FontScaleSelected(FontScale),
+    AccessibilitySaved(Result<(), String>),
+    ToggleListRelativeDates(bool),
+    ToggleDetailRelativeDates(bool),
+    DateDisplaySaved(Result<(), String>),
+    MarkSelectedRead,
+    MarkReadDelayElapsed(String),
+    MarkReadDone(Result<String, String>),
+    AccountsLoaded(Result<Vec<Account>, String>),
+    SwitchAccount(Account),
+    SortKeyChanged(SortKey),
+    ToggleSortDirection,
+    SortSaved(Result<(), String>),
+    ApplyAction(MailAction),
+    ActionDone(Result<(MailAction, String, String), String>),
+    Undo,
+    UndoDone(Result<Option<String>, String>),
+    UndoTick,
+    SearchQueryChanged(String),
+    SearchCache,
+    SearchDone(Result<Vec<SearchHit>, String>),
+    TogglePin,
+    PinSaved(Result<(String, bool), String>),
+    PinsLoaded(Result<HashSet<String>, String>),
+    BlockSender(String),
+    SenderBlocked(Result<String, String>),
+    UnblockSender(String),
+    SenderUnblocked(Result<String, String>),
+    BlockedLoaded(Result<Vec<String>, String>),
+    RulesLoaded(Result<Vec<MailRule>, String>),
+    RuleConditionSelected(RuleConditionKind),
+    RuleConditionValueChanged(String),
+    RuleActionSelected(RuleActionKind),
+    RuleActionValueChanged(String),
+    SaveRule,
+    EditRule(MailRule),
+    CancelRuleEdit,
+    DeleteRule(i64),
+    RulesChanged(Result<Vec<MailRule>, String>),
+    SelectRuleFolder(String),
+    ScopesLoaded(Result<Vec<String>, String>),
+    Snooze(i64),
+    Snoozed(Result<String, String>),
+    SnoozeTick,
+    SnoozesRestored(Result<Vec<MailMessage>, String>),
+    SetFollowUp(i64),
+    ClearFollowUp,
+    FollowUpSaved(Result<(String, Option<FollowUp>), String>),
+    FollowUpsLoaded(Result<Vec<FollowUp>, String>),
+    FollowUpMessagesLoaded(Result<Vec<MailMessage>, String>),
+    RunSelfTest(Provider),
+    SelfTestDone(Result<SelfTestReport, String>),
+    Unsubscribe,
+    ConfirmUnsubscribe,
+    CancelUnsubscribe,
+    UnsubscribeDone(Result<(), String>),
+    ToggleBulkSelect(String, bool),
+    ClearBulkSelection,
+    BulkAction(MailAction),
+    BulkMarkRead,
+    BulkMove(Folder),
+    BulkDone(Result<BulkOutcome, String>),
+    ToggleDateFilter,
+    DateAfterChanged(String),
+    DateBeforeChanged(String),
+    ApplyDateFilter,
+    ClearDateFilter,
+    RangeFetched(Result<LoginResult, String>),
+    ResetApp,
+    ConfirmResetApp,
+    CancelResetApp,
+    ResetAppDone(Result<(), String>),
+    CloseRequested(window::Id),
+    ShutdownFlushed(window::Id, Result<(), String>),
+    ImportMbox,
+    ImportMboxDone(Result<Option<usize>, String>),
+    ImportedLoaded(Result<Vec<MailMessage>, String>),
+    SelectFolderMessage(usize),
+    FolderFetched(String, Result<LoginResult, String>),
+    LastSyncLoaded(String, Result<Option<i64>, String>),
+    StatusServerStopped(Result<(), String>),
+}
+
+type Attachment = (String, Vec<u8>, String);
+
+#[derive(Debug, Clone)]
+enum SessionError {
+    InteractionRequired(Provider, String),
+    TokenExpired(Provider, String),
+    Other(String),
+}
+
+impl SessionError {
+    fn from_engine(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<EngineError>() {
+            Some(EngineError::InteractionRequired { provider, .. }) => {
+                SessionError::InteractionRequired(*provider, format!("{error:#}"))
+            }
+            Some(EngineError::TokenExpired { provider }) => {
+                SessionError::TokenExpired(*provider, format!("{error:#}"))
+            }
+            _ => SessionError::Other(format!("{error:#}")),
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -59,10 +406,16 @@ enum MailFolder {
     #[default]
     Inbox,
     Starred,
+    Important,
     Sent,
     Drafts,
     Spam,
     Trash,
+    Imported,
+    /// Messages flagged with [`Message::SetFollowUp`], earliest due first.
+    FollowUp,
+    /// Results of [`Message::SearchCache`]; not listed in the sidebar.
+    Search,
 }
 
 impl MailFolder {
@@ -70,21 +423,70 @@ impl MailFolder {
         match self {
             MailFolder::Inbox => "Inbox",
             MailFolder::Starred => "Starred",
+            MailFolder::Important => "Important",
             MailFolder::Sent => "Sent",
             MailFolder::Drafts => "Drafts",
             MailFolder::Spam => "Spam",
             MailFolder::Trash => "Trash",
+            MailFolder::Imported => "Imported",
+            MailFolder::FollowUp => "Opvolgen",
+            MailFolder::Search => "Zoekresultaten",
+        }
+    }
+
+    fn gmail_label_id(self) -> Option<&'static str> {
+        match self {
+            MailFolder::Inbox => Some("INBOX"),
+            MailFolder::Starred => Some("STARRED"),
+            MailFolder::Important => Some("IMPORTANT"),
+            MailFolder::Sent => Some("SENT"),
+            MailFolder::Drafts => Some("DRAFT"),
+            MailFolder::Spam => Some("SPAM"),
+            MailFolder::Trash => Some("TRASH"),
+            MailFolder::Imported | MailFolder::FollowUp | MailFolder::Search => None,
+        }
+    }
+
+    fn graph_folder_name(self) -> Option<&'static str> {
+        match self {
+            MailFolder::Inbox => Some("inbox"),
+            MailFolder::Sent => Some("sentitems"),
+            MailFolder::Drafts => Some("drafts"),
+            MailFolder::Spam => Some("junkemail"),
+            MailFolder::Trash => Some("deleteditems"),
+            MailFolder::Starred
+            | MailFolder::Important
+            | MailFolder::Imported
+            | MailFolder::FollowUp
+            | MailFolder::Search => None,
+        }
+    }
+
+    fn id(self, provider: Provider) -> Option<&'static str> {
+        match provider {
+            Provider::Google => self.gmail_label_id(),
+            Provider::Outlook => self.graph_folder_name(),
         }
     }
 
+    fn from_id(provider: Provider, id: &str) -> Option<Self> {
+        Self::all()
+            .iter()
+            .copied()
+            .find(|folder| folder.id(provider) == Some(id))
+    }
+
     fn all() -> &'static [MailFolder] {
-        const FOLDERS: [MailFolder; 6] = [
+        const FOLDERS: [MailFolder; 9] = [
             MailFolder::Inbox,
             MailFolder::Starred,
+            MailFolder::Important,
             MailFolder::Sent,
             MailFolder::Drafts,
             MailFolder::Spam,
             MailFolder::Trash,
+            MailFolder::Imported,
+            MailFolder::FollowUp,
         ];
         &FOLDERS
     }
@@ -101,11 +503,436 @@ struct MailApp {
     show_google_setup: bool,
     google_client_id: String,
     google_client_secret: String,
+    google_fix: Option<GoogleSetupFix>,
+    account_provider: Option<Provider>,
+    show_compose: bool,
+    compose_to: String,
+    compose_subject: String,
+    compose_body: text_editor::Content,
+    compose_attachments: Vec<Attachment>,
+    compose_draft_id: Option<String>,
+    compose_dirty: bool,
+    drafts: Vec<Draft>,
+    folders: Vec<Folder>,
+    inbox_counts: Option<FolderCounts>,
+    show_db_prompt: bool,
+    db_location: Option<DbLocation>,
+    reauth_provider: Option<Provider>,
+    restore_failed: bool,
+    default_provider: DefaultProviderChoice,
+    inbox_total: Option<u64>,
+    inbox_has_more: bool,
+    inbox_empty: bool,
+    /// Last sync per folder id of the active account; `""` is the inbox.
+    last_synced: HashMap<String, i64>,
+    remote_images_allowed: HashSet<String>,
+    raw_source: Option<(String, String)>,
+    thread: Option<ThreadView>,
+    /// Rows of the running inbox fetch, shown until its result arrives.
+    streamed: Vec<MailMessage>,
+    storage_usage: Option<Result<String, String>>,
+    authorize_url: Option<String>,
+    pending_login: Option<PendingLogin>,
+    /// Login waiting for the pasted redirect when the loopback callback
+    /// can't be reached.
+    manual_login: Option<ManualLogin>,
+    manual_code: String,
+    pending_fetch: Option<PendingFetch>,
+    labels: Vec<MailLabel>,
+    load_remote_images: bool,
+    auto_mark_read: bool,
+    prefer_html: bool,
+    mark_read_delay: Duration,
+    mark_read_delay_input: String,
+    /// Pending delayed mark-read; dropping it cancels the timer.
+    mark_read_timer: Option<task::Handle>,
+    preview_length: usize,
+    preview_length_input: String,
+    /// Scroll offset and height of the message list viewport; only the
+    /// inbox rows within it (plus a buffer) are built.
+    list_offset: f32,
+    list_height: f32,
+    list_date_display: DateDisplay,
+    detail_date_display: DateDisplay,
+    plaintext_mode: bool,
+    font_scale: f32,
+    offline_bodies: bool,
+    cache_limit: usize,
+    cache_limit_input: String,
+    offline_progress: Option<String>,
+    backup_progress: Option<String>,
+    trusted_image_senders: HashSet<String>,
+    /// Addresses whose messages are left out of every list.
+    blocked_senders: HashSet<String>,
+    /// Local filter rules, in the order the engine applies them.
+    rules: Vec<MailRule>,
+    rule_condition: RuleConditionKind,
+    rule_condition_value: String,
+    rule_action: RuleActionKind,
+    rule_action_value: String,
+    /// Id of the rule loaded into the form, `None` for a new one.
+    editing_rule: Option<i64>,
+    /// Rule pseudo-folder shown instead of the plain inbox.
+    rule_folder: Option<String>,
+    granted_scopes: Vec<String>,
+    search_query: String,
+    search_results: Vec<MailMessage>,
+    /// Account of each entry in `search_results`.
+    search_accounts: Vec<Account>,
+    /// Ids of locally pinned messages for the active provider and imports.
+    pinned: HashSet<String>,
+    followups: HashMap<String, FollowUp>,
+    accounts: Vec<Account>,
+    active_account: Option<Account>,
+    account_cache: HashMap<Account, Vec<MailMessage>>,
+    sort_key: SortKey,
+    sort_ascending: bool,
+    /// Most recent last; entries expire after [`UNDO_WINDOW_SECS`].
+    undo: Vec<PendingUndo>,
+    selftest_report: Option<SelfTestReport>,
+    confirm_unsubscribe: Option<String>,
+    show_quoted: bool,
+    bulk_selection: HashSet<String>,
+    imported: Vec<MailMessage>,
+    folder_messages: Vec<MailMessage>,
+    folder_selection: Option<usize>,
+    /// Messages whose details failed to load, with the error; each gets a
+    /// retry button instead of needing a full refresh.
+    load_errors: HashMap<String, String>,
+    /// Messages with a retry in flight; retries leave the global state alone.
+    retrying: HashSet<String>,
+    show_date_filter: bool,
+    date_after: String,
+    date_before: String,
+    date_range: DateRange,
+    /// Ask the provider for unread messages only.
+    unread_only: bool,
+    in_flight: Vec<(Weak<()>, task::Handle)>,
+    confirm_reset: bool,
+    shutting_down: bool,
+}
+
+#[derive(Debug, Clone)]
+enum BulkOutcome {
+    /// Ids that left the list, and the ids that failed with their error.
+    Removed(Vec<String>, Vec<(String, String)>),
+    MarkedRead(Vec<String>),
+}
+
+/// A conversation opened from the detail pane. Bodies are fetched the first
+/// time a message is expanded.
+#[derive(Debug, Clone)]
+struct ThreadView {
+    conversation_id: String,
+    messages: Vec<MailMessage>,
+    expanded: HashSet<String>,
+    loaded: HashSet<String>,
+    loading: HashSet<String>,
+}
+
+/// The login that is waiting for the OAuth callback, kept so it can be
+/// restarted when the user closed the browser tab.
+#[derive(Debug)]
+struct PendingLogin {
+    provider: Provider,
+    client_id: String,
+    client_secret: String,
+    kind: LoginKind,
+    handle: task::Handle,
+}
+
+/// A running inbox or folder fetch and the state to return to when the user
+/// cancels it.
+#[derive(Debug)]
+struct PendingFetch {
+    handle: task::Handle,
+    previous: UiState,
+    view: FetchView,
+}
+
+/// The list settings a fetch replaces, put back by [`Message::CancelFetch`].
+#[derive(Debug)]
+struct FetchView {
+    unread_only: bool,
+    date_range: DateRange,
+    selected_folder: MailFolder,
+    rule_folder: Option<String>,
+    selected_message: Option<usize>,
+    folder_messages: Vec<MailMessage>,
+    folder_selection: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingUndo {
+    provider: Provider,
+    kind: UndoKind,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+enum UndoKind {
+    /// Archived or trashed; `message` goes back at `index` in the inbox.
+    Action {
+        action: MailAction,
+        message: MailMessage,
+        index: usize,
+    },
+    Moved {
+        folder_id: String,
+        message: MailMessage,
+        index: usize,
+    },
+    MarkedRead(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum DefaultProviderChoice {
+    #[default]
+    LastUsed,
+    Google,
+    Outlook,
+}
+
+impl DefaultProviderChoice {
+    const ALL: [DefaultProviderChoice; 3] = [
+        DefaultProviderChoice::LastUsed,
+        DefaultProviderChoice::Google,
+        DefaultProviderChoice::Outlook,
+    ];
+
+    fn from_provider(provider: Option<Provider>) -> Self {
+        match provider {
+            None => DefaultProviderChoice::LastUsed,
+            Some(Provider::Google) => DefaultProviderChoice::Google,
+            Some(Provider::Outlook) => DefaultProviderChoice::Outlook,
+        }
+    }
+
+    fn provider(self) -> Option<Provider> {
+        match self {
+            DefaultProviderChoice::LastUsed => None,
+            DefaultProviderChoice::Google => Some(Provider::Google),
+            DefaultProviderChoice::Outlook => Some(Provider::Outlook),
+        }
+    }
+}
+
+impl std::fmt::Display for DefaultProviderChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.provider() {
+            None => f.write_str("Laatst gebruikt"),
+            Some(provider) => f.write_str(provider.label()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RuleConditionKind {
+    #[default]
+    FromContains,
+    SubjectContains,
+    HasAttachment,
+}
+
+impl RuleConditionKind {
+    const ALL: [RuleConditionKind; 3] = [
+        RuleConditionKind::FromContains,
+        RuleConditionKind::SubjectContains,
+        RuleConditionKind::HasAttachment,
+    ];
+
+    fn of(condition: &RuleCondition) -> Self {
+        match condition {
+            RuleCondition::FromContains(_) => RuleConditionKind::FromContains,
+            RuleCondition::SubjectContains(_) => RuleConditionKind::SubjectContains,
+            RuleCondition::HasAttachment => RuleConditionKind::HasAttachment,
+        }
+    }
+
+    fn needs_value(self) -> bool {
+        self != RuleConditionKind::HasAttachment
+    }
+
+    fn build(self, value: String) -> RuleCondition {
+        match self {
+            RuleConditionKind::FromContains => RuleCondition::FromContains(value),
+            RuleConditionKind::SubjectContains => RuleCondition::SubjectContains(value),
+            RuleConditionKind::HasAttachment => RuleCondition::HasAttachment,
+        }
+    }
+}
+
+impl std::fmt::Display for RuleConditionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RuleConditionKind::FromContains => "Afzender bevat",
+            RuleConditionKind::SubjectContains => "Onderwerp bevat",
+            RuleConditionKind::HasAttachment => "Heeft bijlage",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RuleActionKind {
+    #[default]
+    Label,
+    MarkRead,
+    MoveTo,
+    Hide,
+}
+
+impl RuleActionKind {
+    const ALL: [RuleActionKind; 4] = [
+        RuleActionKind::Label,
+        RuleActionKind::MarkRead,
+        RuleActionKind::MoveTo,
+        RuleActionKind::Hide,
+    ];
+
+    fn of(action: &RuleAction) -> Self {
+        match action {
+            RuleAction::Label(_) => RuleActionKind::Label,
+            RuleAction::MarkRead => RuleActionKind::MarkRead,
+            RuleAction::MoveTo(_) => RuleActionKind::MoveTo,
+            RuleAction::Hide => RuleActionKind::Hide,
+        }
+    }
+
+    fn needs_value(self) -> bool {
+        matches!(self, RuleActionKind::Label | RuleActionKind::MoveTo)
+    }
+
+    fn build(self, value: String) -> RuleAction {
+        match self {
+            RuleActionKind::Label => RuleAction::Label(value),
+            RuleActionKind::MarkRead => RuleAction::MarkRead,
+            RuleActionKind::MoveTo => RuleAction::MoveTo(value),
+            RuleActionKind::Hide => RuleAction::Hide,
+        }
+    }
+}
+
+impl std::fmt::Display for RuleActionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RuleActionKind::Label => "Label toevoegen",
+            RuleActionKind::MarkRead => "Als gelezen markeren",
+            RuleActionKind::MoveTo => "Verplaatsen naar map",
+            RuleActionKind::Hide => "Verbergen",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FontScale(f32);
+
+impl FontScale {
+    const ALL: [FontScale; 4] = [
+        FontScale(1.0),
+        FontScale(1.25),
+        FontScale(1.5),
+        FontScale(2.0),
+    ];
+}
+
+impl std::fmt::Display for FontScale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.0}%", self.0 * 100.0)
+    }
+}
+
+fn theme(state: &MailApp) -> iced::Theme {
+    if !state.plaintext_mode {
+        return iced::Theme::default();
+    }
+    iced::Theme::custom(
+        "Hoog contrast".to_owned(),
+        iced::theme::Palette {
+            background: iced::Color::BLACK,
+            text: iced::Color::WHITE,
+            primary: iced::Color::from_rgb8(0xff, 0xd4, 0x00),
+            success: iced::Color::from_rgb8(0x00, 0xe0, 0x5a),
+            danger: iced::Color::from_rgb8(0xff, 0x5c, 0x5c),
+        },
+    )
 }
 
 fn update(state: &mut MailApp, message: Message) -> Task<Message> {
+    if state.shutting_down && !matches!(message, Message::ShutdownFlushed(..)) {
+        return Task::none();
+    }
     match message {
+        Message::CloseRequested(id) => {
+            state.shutting_down = true;
+            for (_, handle) in state.in_flight.drain(..) {
+                handle.abort();
+            }
+            if !state.compose_dirty {
+                return window::close(id);
+            }
+            state.compose_dirty = false;
+            Task::perform(save_local_draft(state.compose_draft()), move |result| {
+                Message::ShutdownFlushed(id, result)
+            })
+        }
+        Message::ResetApp => {
+            state.confirm_reset = true;
+            Task::none()
+        }
+        Message::CancelResetApp => {
+            state.confirm_reset = false;
+            Task::none()
+        }
+        Message::ConfirmResetApp => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            state.confirm_reset = false;
+            for (_, handle) in state.in_flight.drain(..) {
+                handle.abort();
+            }
+            state.state = UiState::Working("App resetten...".to_owned());
+            state.perform(clear_all_data(), Message::ResetAppDone)
+        }
+        Message::ResetAppDone(Ok(())) => {
+            *state = MailApp {
+                google_client_id: DEFAULT_GOOGLE_CLIENT_ID.to_owned(),
+                db_location: state.db_location.take(),
+                status_note: Some("Alle lokale gegevens zijn gewist.".to_owned()),
+                ..MailApp::default()
+            };
+            Task::none()
+        }
+        Message::ResetAppDone(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ShutdownFlushed(id, result) => {
+            if let Err(error) = result {
+                tracing::warn!(%error, "local draft not saved on shutdown");
+            }
+            window::close(id)
+        }
         Message::SettingsLoaded(Ok(settings)) => {
+            state.default_provider =
+                DefaultProviderChoice::from_provider(settings.default_provider);
+            state.load_remote_images = settings.load_remote_images;
+            state.auto_mark_read = settings.auto_mark_read_on_open;
+            state.mark_read_delay = settings.mark_read_delay;
+            state.prefer_html = settings.prefer_html;
+            state.mark_read_delay_input = settings.mark_read_delay.as_secs().to_string();
+            state.preview_length = settings.preview_length;
+            state.preview_length_input = settings.preview_length.to_string();
+            state.list_date_display = settings.list_date_display;
+            state.detail_date_display = settings.detail_date_display;
+            state.sort_key = settings.message_sort;
+            state.sort_ascending = settings.sort_ascending;
+            state.plaintext_mode = settings.plaintext_mode;
+            state.font_scale = settings.font_scale;
+            state.offline_bodies = settings.offline_bodies;
+            state.cache_limit = settings.cache_message_limit;
+            state.cache_limit_input = settings.cache_message_limit.to_string();
+            state.trusted_image_senders = settings.trusted_image_senders.into_iter().collect();
             if let Some(google) = settings.google {
                 state.google_client_id = google.client_id;
                 state.google_client_secret = google.client_secret.unwrap_or_default();
@@ -119,25 +946,163 @@ fn update(state: &mut MailApp, message: Message) -> Task<Message> {
             state.state = UiState::Error(error);
             Task::none()
         }
+        Message::DefaultProviderSelected(choice) => {
+            state.default_provider = choice;
+            state.perform(
+                save_default_provider(choice.provider()),
+                Message::DefaultProviderSaved,
+            )
+        }
+        Message::DefaultProviderSaved(Ok(())) => {
+            state.status_note = Some("Standaard provider opgeslagen.".to_owned());
+            Task::none()
+        }
+        Message::DefaultProviderSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
         Message::ToggleGoogleSetup => {
             state.show_google_setup = !state.show_google_setup;
             Task::none()
         }
         Message::SelectFolder(folder) => {
+            let view = state.fetch_view();
             state.selected_folder = folder;
+            state.rule_folder = None;
             if folder == MailFolder::Inbox {
                 state.selected_message = state.first_message_index();
             } else {
                 state.selected_message = None;
             }
-            Task::none()
+            match (folder, state.account_provider) {
+                (MailFolder::Drafts, Some(provider)) => {
+                    state.state = UiState::Working("Concepten ophalen...".to_owned());
+                    state.perform(list_drafts(provider), Message::DraftsLoaded)
+                }
+                (MailFolder::Imported, _) => {
+                    state.folder_selection = None;
+                    state.perform(list_imported_messages(), Message::ImportedLoaded)
+                }
+                (MailFolder::FollowUp, Some(provider)) => {
+                    state.folder_messages.clear();
+                    state.folder_selection = None;
+                    state.state = UiState::Working("Opvolgen ophalen...".to_owned());
+                    state.perform(followup_messages(provider), Message::FollowUpMessagesLoaded)
+                }
+                (MailFolder::Inbox | MailFolder::Search, _) | (_, None) => Task::none(),
+                (folder, Some(provider)) => {
+                    state.folder_messages.clear();
+                    state.folder_selection = None;
+                    let Some(folder_id) = folder.id(provider) else {
+                        state.status_note = Some(format!(
+                            "{} is niet beschikbaar bij {}.",
+                            folder.label(),
+                            provider.label()
+                        ));
+                        return Task::none();
+                    };
+                    let previous = std::mem::replace(
+                        &mut state.state,
+                        UiState::Working(format!("{} ophalen...", folder.label())),
+                    );
+                    let (fetch, handle) = state.perform_with_handle(
+                        fetch_folder(provider, folder_id, state.unread_only),
+                        move |result| Message::FolderFetched(folder_id.to_owned(), result),
+                    );
+                    state.pending_fetch = Some(PendingFetch {
+                        handle,
+                        previous,
+                        view,
+                    });
+                    fetch
+                }
+            }
         }
         Message::SelectMessage(index) => {
-            if index < state.messages.len() {
-                state.selected_message = Some(index);
+            if index >= state.messages.len() {
+                return Task::none();
+            }
+            state.selected_message = Some(index);
+            state.confirm_unsubscribe = None;
+            state.show_quoted = false;
+            state.mark_read_timer = None;
+            if !state.auto_mark_read {
+                Task::none()
+            } else if state.mark_read_delay.is_zero() {
+                state.mark_selected_read()
+            } else {
+                let message_id = state.messages[index].id.clone();
+                let (timer, handle) =
+                    Task::perform(tokio::time::sleep(state.mark_read_delay), move |()| {
+                        Message::MarkReadDelayElapsed(message_id.clone())
+                    })
+                    .abortable();
+                state.mark_read_timer = Some(handle.abort_on_drop());
+                timer
+            }
+        }
+        Message::MarkReadDelayElapsed(message_id) => {
+            state.mark_read_timer = None;
+            if state
+                .selected_mail_message()
+                .is_some_and(|message| message.id == message_id)
+            {
+                state.mark_selected_read()
+            } else {
+                Task::none()
+            }
+        }
+        Message::SelectAdjacent(step) => {
+            if state.selected_folder != MailFolder::Inbox {
+                return Task::none();
+            }
+            let order = state.display_order();
+            let position = state
+                .selected_message
+                .and_then(|selected| order.iter().position(|&index| index == selected));
+            let target = match position {
+                Some(position) => position.saturating_add_signed(step).min(order.len() - 1),
+                None if order.is_empty() => return Task::none(),
+                None => 0,
+            };
+            let select = update(state, Message::SelectMessage(order[target]));
+            Task::batch([select, state.scroll_to_selection()])
+        }
+        Message::ListScrolled(viewport) => {
+            state.list_offset = viewport.absolute_offset().y;
+            state.list_height = viewport.bounds().height;
+            Task::none()
+        }
+        Message::ToggleUnreadOnly => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let view = state.fetch_view();
+            state.unread_only = !state.unread_only;
+            let fetch = match state.selected_folder {
+                MailFolder::Inbox => state.fetch_range(state.date_range),
+                MailFolder::Search
+                | MailFolder::Imported
+                | MailFolder::Drafts
+                | MailFolder::FollowUp => Task::none(),
+                folder => update(state, Message::SelectFolder(folder)),
+            };
+            if let Some(pending) = &mut state.pending_fetch {
+                pending.view = view;
+            }
+            fetch
+        }
+        Message::MarkSelectedRead => state.mark_selected_read(),
+        Message::MarkReadDone(Ok(message_id)) => {
+            if let Some(message) = state.messages.iter_mut().find(|m| m.id == message_id) {
+                message.labels.retain(|label| label != "UNREAD");
             }
             Task::none()
         }
+        Message::MarkReadDone(Err(error)) => {
+            state.status_note = Some(format!("Als gelezen markeren mislukt: {error}"));
+            Task::none()
+        }
         Message::GoogleClientIdChanged(value) => {
             state.google_client_id = value;
             Task::none()
@@ -148,7 +1113,7 @@ fn update(state: &mut MailApp, message: Message) -> Task<Message> {
         }
         Message::SaveGoogleSettings => {
             state.state = UiState::Working("Google-instellingen opslaan...".to_owned());
-            Task::perform(
+            state.perform(
                 save_settings(
                     Provider::Google,
                     state.google_client_id.clone(),
@@ -166,288 +1131,4661 @@ fn update(state: &mut MailApp, message: Message) -> Task<Message> {
             state.state = UiState::Error(error);
             Task::none()
         }
-        Message::LoginGoogle => {
+        Message::LoginGoogle { other_account } => {
             state.state = UiState::Working("Login met Google...".to_owned());
-            Task::perform(
-                login_and_fetch(
+            state.google_fix = None;
+            let (client_id, client_secret) = (
+                state.google_client_id.clone(),
+                state.google_client_secret.clone(),
+            );
+            let kind = if other_account {
+                LoginKind::OtherAccount
+            } else {
+                LoginKind::Resume
+            };
+            state.login(Provider::Google, client_id, client_secret, kind)
+        }
+        Message::CancelFetch => {
+            let Some(pending) = state.pending_fetch.take() else {
+                return Task::none();
+            };
+            // Aborting drops the engine future, which cancels the listing and
+            // the parallel detail requests; partial results are discarded.
+            pending.handle.abort();
+            state.streamed.clear();
+            state.state = pending.previous;
+            state.restore_fetch_view(pending.view);
+            state.status_note = Some("Ophalen geannuleerd.".to_owned());
+            Task::none()
+        }
+        Message::RetryLogin => {
+            let Some(pending) = state.pending_login.take() else {
+                return Task::none();
+            };
+            // Aborting drops the callback listener so the new attempt can
+            // bind the same port.
+            pending.handle.abort();
+            state.authorize_url = None;
+            state.status_note = Some("Login opnieuw gestart.".to_owned());
+            state.state = UiState::Working(format!("Login met {}...", pending.provider.label()));
+            state.login(
+                pending.provider,
+                pending.client_id,
+                pending.client_secret,
+                pending.kind,
+            )
+        }
+        Message::StartManualLogin => {
+            state.state = UiState::Working("Login met Google voorbereiden...".to_owned());
+            state.google_fix = None;
+            state.perform(
+                start_manual_login(
                     Provider::Google,
                     state.google_client_id.clone(),
                     state.google_client_secret.clone(),
                 ),
-                Message::LoginDone,
+                Message::ManualLoginReady,
             )
         }
+        Message::ManualLoginReady(Ok(login)) => {
+            if webbrowser::open(&login.authorize_url).is_err() {
+                state.authorize_url = Some(login.authorize_url.clone());
+            }
+            state.manual_code.clear();
+            state.manual_login = Some(login);
+            state.state = UiState::Working(
+                "Log in in de browser en plak daarna de adresregel hieronder.".to_owned(),
+            );
+            Task::none()
+        }
+        Message::ManualLoginReady(Err(error)) => {
+            state.google_fix = GoogleSetupFix::detect(&error);
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ManualCodeChanged(code) => {
+            state.manual_code = code;
+            Task::none()
+        }
+        Message::FinishManualLogin => {
+            let Some(login) = state.manual_login.clone() else {
+                return Task::none();
+            };
+            state.state = UiState::Working(format!("Login met {}...", login.provider.label()));
+            let pasted = std::mem::take(&mut state.manual_code);
+            state.perform(finish_manual_login(login, pasted), Message::LoginDone)
+        }
+        Message::CancelManualLogin => {
+            state.manual_login = None;
+            state.manual_code.clear();
+            state.authorize_url = None;
+            state.state = UiState::Idle;
+            Task::none()
+        }
+        Message::AuthorizeUrlReady(url) => {
+            state.authorize_url = Some(url);
+            Task::none()
+        }
+        Message::CopyErrorReport => match &state.state {
+            UiState::Error(error) => {
+                state.status_note = Some("Foutmelding gekopieerd.".to_owned());
+                iced::clipboard::write(error_report(error, state.account_provider))
+            }
+            _ => Task::none(),
+        },
+        Message::ReportIssue => {
+            if let UiState::Error(error) = &state.state {
+                let url = issue_url(&error_report(error, state.account_provider));
+                if let Err(error) = webbrowser::open(&url) {
+                    state.status_note = Some(format!("Kon de browser niet openen: {error}"));
+                }
+            }
+            Task::none()
+        }
+        Message::CopyAuthorizeUrl => match &state.authorize_url {
+            Some(url) => {
+                state.status_note = Some("Login-URL gekopieerd.".to_owned());
+                iced::clipboard::write(url.clone())
+            }
+            None => Task::none(),
+        },
+        Message::MessageStreamed(message) => {
+            if matches!(state.state, UiState::Working(_)) {
+                state.streamed.push(message);
+            }
+            Task::none()
+        }
         Message::LoginDone(Ok(result)) => {
-            state.state = UiState::Loaded;
+            state.authorize_url = None;
+            state.pending_login = None;
+            state.manual_login = None;
             state.status_note = Some("Inbox opgehaald.".to_owned());
-            state.account_label = Some(format!("{}: {}", result.provider.label(), result.account));
-            state.messages = result.messages;
-            state.selected_folder = MailFolder::Inbox;
-            state.selected_message = state.first_message_index();
+            state.date_range = DateRange::default();
+            state.apply_login_result(result)
+        }
+        Message::OpenGoogleFix(fix) => {
+            let url = fix.url(&state.google_client_id);
+            if let Err(error) = webbrowser::open(&url) {
+                state.status_note = Some(format!("Open handmatig: {url} ({error})"));
+            }
             Task::none()
         }
         Message::LoginDone(Err(error)) => {
+            state.authorize_url = None;
+            state.pending_login = None;
+            state.manual_login = None;
+            state.streamed.clear();
+            state.google_fix = GoogleSetupFix::detect(&error);
             state.state = UiState::Error(error);
             Task::none()
         }
         Message::RestoreSessionDone(Ok(Some(result))) => {
-            state.state = UiState::Loaded;
             state.status_note = Some("Sessie hersteld.".to_owned());
-            state.account_label = Some(format!("{}: {}", result.provider.label(), result.account));
-            state.messages = result.messages;
-            state.selected_folder = MailFolder::Inbox;
-            state.selected_message = state.first_message_index();
-            Task::none()
+            state.apply_login_result(result)
         }
         Message::RestoreSessionDone(Ok(None)) => Task::none(),
-        Message::RestoreSessionDone(Err(error)) => {
+        Message::RestoreSessionDone(Err(SessionError::InteractionRequired(provider, error))) => {
+            state.reauth_provider = Some(provider);
+            state.status_note = Some(error);
+            Task::none()
+        }
+        Message::RestoreSessionDone(Err(SessionError::TokenExpired(provider, error))) => {
+            state.reauth_provider = Some(provider);
+            state.status_note = Some(format!("{error}."));
+            Task::none()
+        }
+        Message::RestoreSessionDone(Err(SessionError::Other(error))) => {
+            state.restore_failed = true;
+            state.google_fix = GoogleSetupFix::detect(&error);
             state.status_note = Some(format!(
                 "Sessie kon niet automatisch worden hersteld: {error}"
             ));
             Task::none()
         }
-    }
-}
-
-fn view(state: &MailApp) -> Element<'_, Message> {
-    let is_working = matches!(state.state, UiState::Working(_));
-
-    let status_line = match &state.state {
-        UiState::Idle => "Klaar om in te loggen".to_owned(),
-        UiState::Working(text) => text.clone(),
-        UiState::Loaded => "Klaar".to_owned(),
-        UiState::Error(error) => format!("Fout: {error}"),
-    };
+        Message::RetryRestoreSession => {
+            state.restore_failed = false;
+            state.status_note = Some("Sessie opnieuw herstellen...".to_owned());
+            state.perform(restore_session(), Message::RestoreSessionDone)
+        }
+        Message::Reauthenticate(provider) => {
+            state.reauth_provider = None;
+            state.state = UiState::Working(format!("Login met {}...", provider.label()));
+            let (client_id, client_secret) = match provider {
+                Provider::Google => (
+                    state.google_client_id.clone(),
+                    state.google_client_secret.clone(),
+                ),
+                Provider::Outlook => (String::new(), String::new()),
+            };
+            state.login(provider, client_id, client_secret, LoginKind::Resume)
+        }
+        Message::Reauthorize(provider) => {
+            state.state =
+                UiState::Working(format!("Opnieuw autoriseren bij {}...", provider.label()));
+            let (client_id, client_secret) = match provider {
+                Provider::Google => (
+                    state.google_client_id.clone(),
+                    state.google_client_secret.clone(),
+                ),
+                Provider::Outlook => (String::new(), String::new()),
+            };
+            state.login(provider, client_id, client_secret, LoginKind::Reauthorize)
+        }
+        Message::ToggleCompose => {
+            state.show_compose = !state.show_compose;
+            Task::none()
+        }
+        Message::ComposeToChanged(value) => {
+            state.compose_to = value;
+            state.compose_dirty = true;
+            Task::none()
+        }
+        Message::ComposeSubjectChanged(value) => {
+            state.compose_subject = value;
+            state.compose_dirty = true;
+            Task::none()
+        }
+        Message::ComposeBodyEdited(action) => {
+            if action.is_edit() {
+                state.compose_dirty = true;
+            }
+            state.compose_body.perform(action);
+            Task::none()
+        }
+        Message::PickAttachments => state.perform(pick_attachments(), Message::AttachmentsPicked),
+        Message::AttachmentsPicked(attachments) => {
+            state.compose_attachments.extend(attachments);
+            Task::none()
+        }
+        Message::RemoveAttachment(index) => {
+            if index < state.compose_attachments.len() {
+                state.compose_attachments.remove(index);
+            }
+            Task::none()
+        }
+        Message::SendCompose => {
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Bericht verzenden...".to_owned());
+            state.perform(
+                send_message(provider, state.compose_draft()),
+                Message::SendDone,
+            )
+        }
+        Message::SendDone(Ok(status)) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some(status);
+            state.reset_compose();
+            Task::none()
+        }
+        Message::SendDone(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::SaveDraft => {
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Concept opslaan...".to_owned());
+            state.perform(
+                save_draft(provider, state.compose_draft()),
+                Message::DraftSaved,
+            )
+        }
+        Message::DraftSaved(Ok(draft_id)) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some("Concept opgeslagen.".to_owned());
+            state.compose_draft_id = Some(draft_id);
+            state.compose_dirty = false;
+            state.perform(
+                save_local_draft(state.compose_draft()),
+                Message::LocalDraftSaved,
+            )
+        }
+        Message::DraftSaved(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::DeleteDraft => {
+            let remote = state.account_provider.zip(state.compose_draft_id.clone());
+            state.state = UiState::Working("Concept verwijderen...".to_owned());
+            state.perform(delete_draft(remote), Message::DraftDeleted)
+        }
+        Message::DraftDeleted(Ok(status)) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some(status);
+            if let Some(draft_id) = state.compose_draft_id.as_deref() {
+                state
+                    .drafts
+                    .retain(|draft| draft.id.as_deref() != Some(draft_id));
+            }
+            state.reset_compose();
+            Task::none()
+        }
+        Message::DraftDeleted(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::DraftsLoaded(Ok(drafts)) => {
+            state.state = UiState::Loaded;
+            state.drafts = drafts;
+            Task::none()
+        }
+        Message::DraftsLoaded(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ToggleDateFilter => {
+            state.show_date_filter = !state.show_date_filter;
+            Task::none()
+        }
+        Message::DateAfterChanged(value) => {
+            state.date_after = value;
+            Task::none()
+        }
+        Message::DateBeforeChanged(value) => {
+            state.date_before = value;
+            Task::none()
+        }
+        Message::ApplyDateFilter => match DateRange::parse(&state.date_after, &state.date_before) {
+            Ok(range) => state.fetch_range(range),
+            Err(error) => {
+                state.state = UiState::Error(format!("{error:#}"));
+                Task::none()
+            }
+        },
+        Message::ClearDateFilter => {
+            state.date_after.clear();
+            state.date_before.clear();
+            if state.date_range.is_empty() {
+                return Task::none();
+            }
+            state.fetch_range(DateRange::default())
+        }
+        Message::RangeFetched(Ok(result)) => {
+            state.pending_fetch = None;
+            state.messages.clear();
+            state.status_note = Some(if state.date_range.is_empty() {
+                "Inbox opgehaald.".to_owned()
+            } else {
+                "Inbox opgehaald binnen het gekozen datumbereik.".to_owned()
+            });
+            state.apply_login_result(result)
+        }
+        Message::RangeFetched(Err(error)) => {
+            state.pending_fetch = None;
+            state.streamed.clear();
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ImportMbox => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            state.state = UiState::Working("mbox importeren...".to_owned());
+            state.perform(import_mbox(), Message::ImportMboxDone)
+        }
+        Message::ImportMboxDone(Ok(None)) => {
+            state.state = UiState::Idle;
+            Task::none()
+        }
+        Message::ImportMboxDone(Ok(Some(count))) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some(format!("{count} berichten geimporteerd."));
+            state.selected_folder = MailFolder::Imported;
+            state.folder_selection = None;
+            state.perform(list_imported_messages(), Message::ImportedLoaded)
+        }
+        Message::ImportMboxDone(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ImportedLoaded(Ok(messages)) => {
+            state.imported = messages;
+            Task::none()
+        }
+        Message::ImportedLoaded(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::SelectFolderMessage(index) => {
+            if index < state.folder_listing().len() {
+                state.folder_selection = Some(index);
+                state.show_quoted = false;
+            }
+            Task::none()
+        }
+        Message::ToggleQuoted => {
+            state.show_quoted = !state.show_quoted;
+            Task::none()
+        }
+        Message::FolderFetched(folder_id, Ok(result)) => {
+            state.pending_fetch = None;
+            state.state = UiState::Loaded;
+            if MailFolder::from_id(result.provider, &folder_id) == Some(state.selected_folder) {
+                state.note_fetch_warning(result.warning.as_deref());
+                state.track_load_errors(&result);
+                state.folder_messages = result.messages;
+            }
+            state.perform(
+                last_synced(result.provider, result.account, folder_id.clone()),
+                move |synced| Message::LastSyncLoaded(folder_id.clone(), synced),
+            )
+        }
+        Message::LastSyncLoaded(folder_id, Ok(Some(synced_at))) => {
+            state.last_synced.insert(folder_id, synced_at);
+            Task::none()
+        }
+        Message::LastSyncLoaded(_, Ok(None)) => Task::none(),
+        Message::StatusServerStopped(result) => {
+            if let Err(error) = result {
+                state.status_note = Some(format!("Statusserver gestopt: {error}"));
+            }
+            Task::none()
+        }
+        Message::LastSyncLoaded(_, Err(error)) => {
+            tracing::warn!(%error, "last sync time unavailable");
+            Task::none()
+        }
+        Message::FolderFetched(_, Err(error)) => {
+            state.pending_fetch = None;
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::OpenDraft(index) => {
+            if let Some(draft) = state.drafts.get(index).cloned() {
+                state.open_in_compose(draft);
+                state.show_compose = true;
+            }
+            Task::none()
+        }
+        Message::AutoSaveDraft => {
+            if !state.compose_dirty {
+                return Task::none();
+            }
+            state.compose_dirty = false;
+            state.perform(
+                save_local_draft(state.compose_draft()),
+                Message::LocalDraftSaved,
+            )
+        }
+        Message::LocalDraftSaved(Ok(())) => Task::none(),
+        Message::LocalDraftSaved(Err(error)) => {
+            state.status_note = Some(format!("Concept kon niet lokaal worden bewaard: {error}"));
+            Task::none()
+        }
+        Message::LocalDraftLoaded(Ok(Some(local))) => {
+            state.open_in_compose(local.draft);
+            let mut note =
+                "Niet-verzonden concept hersteld. Open 'Nieuw bericht' om verder te gaan."
+                    .to_owned();
+            if !local.lost_attachments.is_empty() {
+                note.push_str(&format!(
+                    " Bijlagen zijn niet bewaard, voeg ze opnieuw toe: {}.",
+                    local.lost_attachments.join(", ")
+                ));
+            }
+            state.status_note = Some(note);
+            Task::none()
+        }
+        Message::LocalDraftLoaded(Ok(None)) => Task::none(),
+        Message::LocalDraftLoaded(Err(error)) => {
+            state.status_note = Some(format!("Lokaal concept kon niet worden geladen: {error}"));
+            Task::none()
+        }
+        Message::FoldersLoaded(Ok(folders)) => {
+            state.folders = folders;
+            Task::none()
+        }
+        Message::FoldersLoaded(Err(error)) => {
+            state.status_note = Some(format!("Mappen konden niet worden geladen: {error}"));
+            Task::none()
+        }
+        Message::LabelsLoaded(Ok(labels)) => {
+            state.labels = labels;
+            Task::none()
+        }
+        Message::LabelsLoaded(Err(error)) => {
+            state.status_note = Some(format!("Labels konden niet worden geladen: {error}"));
+            Task::none()
+        }
+        Message::AddLabel(label) => state.modify_selected_labels(vec![label.id], Vec::new()),
+        Message::RemoveLabel(label_id) => state.modify_selected_labels(Vec::new(), vec![label_id]),
+        Message::LabelsModified(Ok((message_id, add, remove))) => {
+            state.state = UiState::Loaded;
+            if let Some(message) = state.messages.iter_mut().find(|m| m.id == message_id) {
+                message.labels.retain(|label| !remove.contains(label));
+                for label in add {
+                    if !message.labels.contains(&label) {
+                        message.labels.push(label);
+                    }
+                }
+            }
+            Task::none()
+        }
+        Message::LabelsModified(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::InboxCountsLoaded(Ok(counts)) => {
+            state.inbox_counts = Some(counts);
+            Task::none()
+        }
+        Message::InboxCountsLoaded(Err(error)) => {
+            tracing::warn!(%error, "inbox counts unavailable");
+            state.inbox_counts = None;
+            Task::none()
+        }
+        Message::MoveSelected(folder) => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let (Some(provider), Some(message_id)) = (
+                state.account_provider,
+                state.selected_mail_message().map(|m| m.id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working(format!("Verplaatsen naar {}...", folder.name));
+            state.perform(
+                move_message(provider, message_id, folder.id),
+                Message::MoveDone,
+            )
+        }
+        Message::MoveDone(Ok((old_id, new_id, folder_id))) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some("Bericht verplaatst.".to_owned());
+            let index = state.messages.iter().position(|m| m.id == old_id);
+            if let (Some(provider), Some(index), Some(mut message)) =
+                (state.account_provider, index, state.remove_message(&old_id))
+            {
+                message.id = new_id;
+                state.push_undo(
+                    provider,
+                    UndoKind::Moved {
+                        folder_id,
+                        message,
+                        index,
+                    },
+                );
+            }
+            state.scroll_to_selection()
+        }
+        Message::MoveDone(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::CopyMessageJson => {
+            let Some(message) = state.selected_mail_message() else {
+                return Task::none();
+            };
+            match message.to_pretty_json() {
+                Ok(json) => {
+                    state.status_note = Some("Bericht als JSON gekopieerd.".to_owned());
+                    iced::clipboard::write(json)
+                }
+                Err(error) => {
+                    state.status_note = Some(format!("JSON maken mislukt: {error:#}"));
+                    Task::none()
+                }
+            }
+        }
+        Message::LoadStorageUsage => {
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            state.storage_usage = None;
+            state.perform(storage_usage(provider), Message::StorageUsageLoaded)
+        }
+        Message::StorageUsageLoaded(result) => {
+            state.storage_usage = Some(result.map(|usage| {
+                let provider = state.account_provider.map(Provider::label).unwrap_or("");
+                match usage {
+                    Some(StorageUsage {
+                        used,
+                        total: Some(total),
+                    }) => format!(
+                        "Opslag: {} van {} gebruikt",
+                        format_size(used as usize),
+                        format_size(total as usize)
+                    ),
+                    Some(StorageUsage { used, total: None }) => {
+                        format!("Opslag: {} gebruikt", format_size(used as usize))
+                    }
+                    None => format!("Opslaggebruik is niet beschikbaar bij {provider}."),
+                }
+            }));
+            Task::none()
+        }
+        Message::CopyPermalink => {
+            let Some(link) = state.selected_permalink() else {
+                return Task::none();
+            };
+            state.status_note = Some("Link naar bericht gekopieerd.".to_owned());
+            iced::clipboard::write(link)
+        }
+        Message::OpenPermalink => {
+            let Some(link) = state.selected_permalink() else {
+                return Task::none();
+            };
+            state.status_note = Some(match webbrowser::open(&link) {
+                Ok(()) => "Bericht geopend in de webmail.".to_owned(),
+                Err(error) => format!("Webmail openen mislukt: {error}"),
+            });
+            Task::none()
+        }
+        Message::OpenLink(url) => {
+            if let Err(error) = webbrowser::open(&url) {
+                state.status_note = Some(format!("Link openen mislukt: {url} ({error})"));
+            }
+            Task::none()
+        }
+        Message::LoadFullMessage => {
+            let (Some(provider), Some(message_id)) = (
+                state.account_provider,
+                state.selected_mail_message().map(|m| m.id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Volledig bericht ophalen...".to_owned());
+            state.perform(fetch_message(provider, message_id.clone()), move |result| {
+                Message::FullMessageLoaded(message_id.clone(), result)
+            })
+        }
+        Message::RetryMessage(message_id) => {
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            if !state.retrying.insert(message_id.clone()) {
+                return Task::none();
+            }
+            state.perform(fetch_message(provider, message_id.clone()), move |result| {
+                Message::FullMessageLoaded(message_id.clone(), result)
+            })
+        }
+        Message::FullMessageLoaded(message_id, Ok(message)) => {
+            if !state.retrying.remove(&message_id) {
+                if matches!(state.state, UiState::Working(_)) {
+                    state.state = UiState::Loaded;
+                }
+                state.status_note = None;
+            }
+            state.load_errors.remove(&message_id);
+            for existing in state
+                .messages
+                .iter_mut()
+                .chain(state.folder_messages.iter_mut())
+                .filter(|m| m.id == message_id)
+            {
+                *existing = message.clone();
+            }
+            Task::none()
+        }
+        Message::FullMessageLoaded(message_id, Err(error)) => {
+            if !state.retrying.remove(&message_id) {
+                if matches!(state.state, UiState::Working(_)) {
+                    state.state = UiState::Loaded;
+                }
+                state.status_note = Some(format!("Bericht laden mislukt: {error}"));
+            }
+            state.load_errors.insert(message_id, error);
+            Task::none()
+        }
+        Message::ViewSource => {
+            let (Some(provider), Some(message_id)) = (
+                state.account_provider,
+                state.selected_mail_message().map(|m| m.id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Berichtbron ophalen...".to_owned());
+            state.perform(
+                fetch_raw_message(provider, message_id),
+                Message::SourceLoaded,
+            )
+        }
+        Message::SourceLoaded(Ok(source)) => {
+            state.state = UiState::Loaded;
+            state.raw_source = Some(source);
+            Task::none()
+        }
+        Message::SourceLoaded(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::CloseSource => {
+            state.raw_source = None;
+            Task::none()
+        }
+        Message::LoadThread => {
+            let (Some(provider), Some(conversation_id)) = (
+                state.account_provider,
+                state
+                    .selected_mail_message()
+                    .and_then(|m| m.conversation_id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Gesprek ophalen...".to_owned());
+            state.perform(
+                fetch_thread(provider, conversation_id),
+                Message::ThreadLoaded,
+            )
+        }
+        Message::ThreadLoaded(Ok((conversation_id, messages))) => {
+            state.state = UiState::Loaded;
+            state.thread = Some(ThreadView {
+                conversation_id,
+                messages,
+                expanded: HashSet::new(),
+                loaded: HashSet::new(),
+                loading: HashSet::new(),
+            });
+            let newest = state
+                .thread
+                .as_ref()
+                .and_then(|thread| thread.messages.last())
+                .map(|message| message.id.clone());
+            match newest {
+                Some(id) => update(state, Message::ToggleThreadMessage(id)),
+                None => Task::none(),
+            }
+        }
+        Message::ThreadLoaded(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ToggleThreadMessage(id) => {
+            let Some(thread) = state.thread.as_mut() else {
+                return Task::none();
+            };
+            if !thread.expanded.insert(id.clone()) {
+                thread.expanded.remove(&id);
+                return Task::none();
+            }
+            if thread.loaded.contains(&id) || !thread.loading.insert(id.clone()) {
+                return Task::none();
+            }
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            state.perform(fetch_message(provider, id.clone()), move |result| {
+                Message::ThreadMessageLoaded(id.clone(), result)
+            })
+        }
+        Message::ThreadMessageLoaded(id, result) => {
+            let Some(thread) = state.thread.as_mut() else {
+                return Task::none();
+            };
+            thread.loading.remove(&id);
+            match result {
+                Ok(message) => {
+                    thread.loaded.insert(id.clone());
+                    if let Some(existing) = thread.messages.iter_mut().find(|m| m.id == id) {
+                        *existing = message;
+                    }
+                }
+                Err(error) => {
+                    thread.expanded.remove(&id);
+                    state.status_note = Some(format!("Bericht laden mislukt: {error}"));
+                }
+            }
+            Task::none()
+        }
+        Message::CloseThread => {
+            state.thread = None;
+            Task::none()
+        }
+        Message::OpenHtmlView => {
+            if state.plaintext_mode {
+                return Task::none();
+            }
+            let Some(message) = state.selected_mail_message() else {
+                return Task::none();
+            };
+            let Some(html) = message.body_html.as_deref() else {
+                return Task::none();
+            };
+            let html = if state.remote_images_allowed_for(message) {
+                html.to_owned()
+            } else {
+                block_remote_images(html)
+            };
+            state.status_note = Some(match open_html_preview(&html) {
+                Ok(()) => "HTML-weergave geopend in de browser.".to_owned(),
+                Err(error) => format!("HTML-weergave openen mislukt: {error}"),
+            });
+            Task::none()
+        }
+        Message::AllowRemoteImages(message_id) => {
+            state.remote_images_allowed.insert(message_id);
+            update(state, Message::OpenHtmlView)
+        }
+        Message::TrustImageSender(from) => {
+            state.perform(trust_image_sender(from), Message::ImageSenderTrusted)
+        }
+        Message::ImageSenderTrusted(Ok(sender)) => {
+            state.status_note = Some(format!(
+                "Afbeeldingen van {sender} worden voortaan geladen."
+            ));
+            state.trusted_image_senders.insert(sender);
+            Task::none()
+        }
+        Message::ImageSenderTrusted(Err(error)) => {
+            state.status_note = Some(format!("Afzender vertrouwen mislukt: {error}"));
+            Task::none()
+        }
+        Message::ToggleLoadRemoteImages(enabled) => {
+            state.load_remote_images = enabled;
+            state.perform(
+                save_load_remote_images(enabled),
+                Message::LoadRemoteImagesSaved,
+            )
+        }
+        Message::LoadRemoteImagesSaved(Ok(())) => Task::none(),
+        Message::ToggleAutoMarkRead(enabled) => {
+            state.auto_mark_read = enabled;
+            state.perform(save_auto_mark_read(enabled), Message::AutoMarkReadSaved)
+        }
+        Message::AutoMarkReadSaved(Ok(())) => Task::none(),
+        Message::TogglePreferHtml(enabled) => {
+            state.prefer_html = enabled;
+            state.perform(save_prefer_html(enabled), Message::PreferHtmlSaved)
+        }
+        Message::PreferHtmlSaved(Ok(())) => Task::none(),
+        Message::PreferHtmlSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::MarkReadDelayChanged(value) => {
+            let parsed = value.trim().parse::<u64>().ok().map(Duration::from_secs);
+            state.mark_read_delay_input = value;
+            match parsed {
+                Some(delay) if delay != state.mark_read_delay => {
+                    state.mark_read_delay = delay;
+                    state.perform(save_mark_read_delay(delay), Message::AutoMarkReadSaved)
+                }
+                _ => Task::none(),
+            }
+        }
+        Message::PreviewLengthChanged(value) => {
+            let parsed = value.trim().parse::<usize>().ok();
+            state.preview_length_input = value;
+            match parsed {
+                Some(max_chars) if max_chars != state.preview_length => {
+                    state.preview_length = max_chars;
+                    state.perform(save_preview_length(max_chars), Message::PreviewLengthSaved)
+                }
+                _ => Task::none(),
+            }
+        }
+        Message::PreviewLengthSaved(Ok(())) => Task::none(),
+        Message::ToggleOfflineBodies(enabled) => {
+            state.offline_bodies = enabled;
+            state.perform(
+                save_offline_sync(state.offline_bodies, state.cache_limit),
+                Message::OfflineSyncSaved,
+            )
+        }
+        Message::CacheLimitChanged(value) => {
+            let parsed = value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|limit| *limit > 0);
+            state.cache_limit_input = value;
+            match parsed {
+                Some(limit) if limit != state.cache_limit => {
+                    state.cache_limit = limit;
+                    state.perform(
+                        save_offline_sync(state.offline_bodies, state.cache_limit),
+                        Message::OfflineSyncSaved,
+                    )
+                }
+                _ => Task::none(),
+            }
+        }
+        Message::OfflineSyncSaved(Ok(())) => Task::none(),
+        Message::OfflineSyncSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::DownloadForOffline => {
+            let Some(provider) = state.account_provider else {
+                return Task::none();
+            };
+            state.offline_progress = Some("Offline downloaden gestart...".to_owned());
+            let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+            let download = state.perform(
+                download_for_offline(provider, move |progress| {
+                    let _ = sender.unbounded_send(progress);
+                }),
+                Message::OfflineDownloadDone,
+            );
+            Task::batch([Task::run(receiver, Message::OfflineProgress), download])
+        }
+        Message::OfflineProgress(progress) => {
+            state.offline_progress = Some(match progress.estimated_total {
+                Some(total) => format!(
+                    "{} van ~{total} berichten gedownload...",
+                    progress.downloaded
+                ),
+                None => format!("{} berichten gedownload...", progress.downloaded),
+            });
+            Task::none()
+        }
+        Message::BackupAccount => {
+            let Some(account) = state.active_account.clone() else {
+                return Task::none();
+            };
+            state.backup_progress = Some("Backup gestart...".to_owned());
+            let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+            let backup = state.perform(
+                backup_account(account, move |progress| {
+                    let _ = sender.unbounded_send(progress);
+                }),
+                Message::BackupDone,
+            );
+            Task::batch([Task::run(receiver, Message::BackupProgress), backup])
+        }
+        Message::BackupProgress(progress) => {
+            state.backup_progress = Some(match progress.estimated_total {
+                Some(total) => {
+                    format!("{} van ~{total} berichten opgeslagen...", progress.exported)
+                }
+                None => format!("{} berichten opgeslagen...", progress.exported),
+            });
+            Task::none()
+        }
+        Message::BackupDone(Ok(None)) => {
+            state.backup_progress = None;
+            Task::none()
+        }
+        Message::BackupDone(Ok(Some(count))) => {
+            state.backup_progress = Some(format!("Backup klaar: {count} berichten."));
+            Task::none()
+        }
+        Message::BackupDone(Err(error)) => {
+            state.backup_progress = Some(format!(
+                "Backup mislukt: {error}. Opnieuw starten gaat verder waar hij stopte."
+            ));
+            Task::none()
+        }
+        Message::OfflineDownloadDone(Ok(count)) => {
+            state.offline_progress = Some(format!("{count} berichten offline beschikbaar."));
+            Task::none()
+        }
+        Message::OfflineDownloadDone(Err(error)) => {
+            state.offline_progress = Some(format!("Offline downloaden mislukt: {error}"));
+            Task::none()
+        }
+        Message::TogglePlaintextMode(enabled) => {
+            state.plaintext_mode = enabled;
+            state.perform(
+                save_accessibility(state.plaintext_mode, state.font_scale),
+                Message::AccessibilitySaved,
+            )
+        }
+        Message::FontScaleSelected(FontScale(scale)) => {
+            state.font_scale = scale;
+            state.perform(
+                save_accessibility(state.plaintext_mode, state.font_scale),
+                Message::AccessibilitySaved,
+            )
+        }
+        Message::AccessibilitySaved(Ok(())) => Task::none(),
+        Message::AccessibilitySaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ToggleListRelativeDates(enabled) => {
+            state.list_date_display = date_display(enabled);
+            state.perform(
+                save_date_display(state.list_date_display, state.detail_date_display),
+                Message::DateDisplaySaved,
+            )
+        }
+        Message::ToggleDetailRelativeDates(enabled) => {
+            state.detail_date_display = date_display(enabled);
+            state.perform(
+                save_date_display(state.list_date_display, state.detail_date_display),
+                Message::DateDisplaySaved,
+            )
+        }
+        Message::DateDisplaySaved(Ok(())) => Task::none(),
+        Message::DateDisplaySaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::PreviewLengthSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::AutoMarkReadSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::LoadRemoteImagesSaved(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::AccountsLoaded(Ok(accounts)) => {
+            let duplicates = duplicate_account_emails(&accounts);
+            if !duplicates.is_empty() {
+                state.status_note = Some(format!(
+                    "Let op: {} is via meer dan een provider gekoppeld.",
+                    duplicates.join(", ")
+                ));
+            }
+            state.accounts = accounts;
+            Task::none()
+        }
+        Message::AccountsLoaded(Err(error)) => {
+            state.status_note = Some(format!("Accounts konden niet worden geladen: {error}"));
+            Task::none()
+        }
+        Message::SwitchAccount(account) => {
+            if matches!(state.state, UiState::Working(_))
+                || state.active_account.as_ref() == Some(&account)
+            {
+                return Task::none();
+            }
+            state.stash_active_account();
+            state.messages = state
+                .account_cache
+                .get(&account)
+                .cloned()
+                .unwrap_or_default();
+            state.selected_folder = MailFolder::Inbox;
+            state.selected_message = state.first_message_index();
+            state.account_label = Some(account.to_string());
+            state.account_provider = Some(account.provider);
+            state.active_account = Some(account.clone());
+            state.state = UiState::Working(format!("{account} verversen..."));
+            state.perform(switch_account(account), Message::LoginDone)
+        }
+        Message::ApplyAction(action) => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let (Some(provider), Some(message_id)) = (
+                state.account_provider,
+                state.selected_mail_message().map(|m| m.id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Bericht bijwerken...".to_owned());
+            state.perform(
+                apply_action(provider, action, message_id),
+                Message::ActionDone,
+            )
+        }
+        Message::ActionDone(Ok((action, old_id, new_id))) => {
+            state.state = UiState::Loaded;
+            let index = state.messages.iter().position(|m| m.id == old_id);
+            if let (Some(provider), Some(index), Some(mut message)) =
+                (state.account_provider, index, state.remove_message(&old_id))
+            {
+                message.id = new_id;
+                state.push_undo(
+                    provider,
+                    UndoKind::Action {
+                        action,
+                        message,
+                        index,
+                    },
+                );
+            }
+            state.status_note = Some(format!("Bericht {}.", action.label()));
+            state.scroll_to_selection()
+        }
+        Message::ActionDone(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::Undo => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let Some(undo) = state.undo.last() else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Ongedaan maken...".to_owned());
+            state.perform(
+                undo_last(undo.provider, undo.kind.clone()),
+                Message::UndoDone,
+            )
+        }
+        Message::UndoDone(Ok(restored_id)) => {
+            state.state = UiState::Loaded;
+            match state.undo.pop().map(|undo| undo.kind) {
+                Some(
+                    UndoKind::Action { message, index, .. }
+                    | UndoKind::Moved { message, index, .. },
+                ) => {
+                    let index = index.min(state.messages.len());
+                    let mut message = message;
+                    if let Some(id) = restored_id {
+                        message.id = id;
+                    }
+                    state.messages.insert(index, message);
+                    state.selected_message = Some(index);
+                }
+                Some(UndoKind::MarkedRead(ids)) => {
+                    for message in state.messages.iter_mut().filter(|m| ids.contains(&m.id)) {
+                        if !message.labels.iter().any(|label| label == "UNREAD") {
+                            message.labels.push("UNREAD".to_owned());
+                        }
+                    }
+                }
+                None => {}
+            }
+            state.status_note = Some("Actie ongedaan gemaakt.".to_owned());
+            state.scroll_to_selection()
+        }
+        Message::UndoDone(Err(error)) => {
+            state.undo.pop();
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::UndoTick => {
+            let now = Instant::now();
+            state.undo.retain(|undo| now < undo.expires_at);
+            Task::none()
+        }
+        Message::SearchQueryChanged(query) => {
+            state.search_query = query;
+            Task::none()
+        }
+        Message::SearchCache => {
+            if state.search_query.trim().is_empty() {
+                return Task::none();
+            }
+            state.perform(
+                search_cache(state.search_query.clone()),
+                Message::SearchDone,
+            )
+        }
+        Message::SearchDone(Ok(hits)) => {
+            state.status_note = Some(format!("{} resultaten in de lokale cache.", hits.len()));
+            (state.search_accounts, state.search_results) = hits
+                .into_iter()
+                .map(|hit| (hit.account, hit.message))
+                .unzip();
+            state.selected_folder = MailFolder::Search;
+            state.folder_selection = None;
+            Task::none()
+        }
+        Message::SearchDone(Err(error)) => {
+            state.status_note = Some(format!("Zoeken mislukt: {error}"));
+            Task::none()
+        }
+        Message::TogglePin => {
+            let (provider, message) = match state.selected_folder {
+                MailFolder::Inbox => (state.account_provider, state.selected_mail_message()),
+                MailFolder::Imported => (None, state.selected_folder_message()),
+                MailFolder::Search => (
+                    state
+                        .folder_selection
+                        .and_then(|index| state.search_accounts.get(index))
+                        .map(|account| account.provider),
+                    state.selected_folder_message(),
+                ),
+                _ => (state.account_provider, state.selected_folder_message()),
+            };
+            let Some(message_id) = message.map(|m| m.id.clone()) else {
+                return Task::none();
+            };
+            let pinned = !state.pinned.contains(&message_id);
+            state.perform(
+                set_local_pin(provider, message_id, pinned),
+                Message::PinSaved,
+            )
+        }
+        Message::PinSaved(Ok((message_id, pinned))) => {
+            if pinned {
+                state.pinned.insert(message_id);
+            } else {
+                state.pinned.remove(&message_id);
+            }
+            state.scroll_to_selection()
+        }
+        Message::PinSaved(Err(error)) => {
+            state.status_note = Some(format!("Vastzetten mislukt: {error}"));
+            Task::none()
+        }
+        Message::PinsLoaded(Ok(pinned)) => {
+            state.pinned = pinned;
+            Task::none()
+        }
+        Message::PinsLoaded(Err(error)) => {
+            state.status_note = Some(format!("Vastgezette berichten laden mislukt: {error}"));
+            Task::none()
+        }
+        Message::BlockSender(from) => state.perform(block_sender(from), Message::SenderBlocked),
+        Message::SenderBlocked(Ok(sender)) => {
+            state.status_note = Some(format!("Berichten van {sender} worden verborgen."));
+            state.blocked_senders.insert(sender);
+            if state
+                .selected_mail_message()
+                .is_some_and(|message| state.is_blocked(message))
+            {
+                state.selected_message = state.first_message_index();
+            }
+            if state
+                .selected_folder_message()
+                .is_some_and(|message| state.is_blocked(message))
+            {
+                state.folder_selection = None;
+            }
+            Task::none()
+        }
+        Message::SenderBlocked(Err(error)) => {
+            state.status_note = Some(format!("Afzender blokkeren mislukt: {error}"));
+            Task::none()
+        }
+        Message::UnblockSender(sender) => {
+            state.perform(unblock_sender(sender), Message::SenderUnblocked)
+        }
+        Message::SenderUnblocked(Ok(sender)) => {
+            state.status_note = Some(format!("{sender} is niet langer geblokkeerd."));
+            state.blocked_senders.remove(&sender);
+            Task::none()
+        }
+        Message::SenderUnblocked(Err(error)) => {
+            state.status_note = Some(format!("Deblokkeren mislukt: {error}"));
+            Task::none()
+        }
+        Message::ScopesLoaded(Ok(scopes)) => {
+            state.granted_scopes = scopes;
+            Task::none()
+        }
+        Message::ScopesLoaded(Err(error)) => {
+            tracing::warn!(error, "granted scopes could not be loaded");
+            Task::none()
+        }
+        Message::BlockedLoaded(Ok(senders)) => {
+            state.blocked_senders = senders.into_iter().collect();
+            Task::none()
+        }
+        Message::BlockedLoaded(Err(error)) => {
+            state.status_note = Some(format!("Geblokkeerde afzenders laden mislukt: {error}"));
+            Task::none()
+        }
+        Message::RulesLoaded(Ok(rules)) => {
+            state.rules = rules;
+            Task::none()
+        }
+        Message::RulesLoaded(Err(error)) => {
+            state.status_note = Some(format!("Regels laden mislukt: {error}"));
+            Task::none()
+        }
+        Message::RuleConditionSelected(kind) => {
+            state.rule_condition = kind;
+            Task::none()
+        }
+        Message::RuleConditionValueChanged(value) => {
+            state.rule_condition_value = value;
+            Task::none()
+        }
+        Message::RuleActionSelected(kind) => {
+            state.rule_action = kind;
+            Task::none()
+        }
+        Message::RuleActionValueChanged(value) => {
+            state.rule_action_value = value;
+            Task::none()
+        }
+        Message::SaveRule => {
+            let condition_value = state.rule_condition_value.trim().to_owned();
+            let action_value = state.rule_action_value.trim().to_owned();
+            if (state.rule_condition.needs_value() && condition_value.is_empty())
+                || (state.rule_action.needs_value() && action_value.is_empty())
+            {
+                state.status_note = Some("Vul de voorwaarde en actie van de regel in.".to_owned());
+                return Task::none();
+            }
+            let rule = MailRule {
+                id: state.editing_rule,
+                condition: state.rule_condition.build(condition_value),
+                action: state.rule_action.build(action_value),
+            };
+            state.perform(save_rule(rule), Message::RulesChanged)
+        }
+        Message::EditRule(rule) => {
+            state.editing_rule = rule.id;
+            state.rule_condition = RuleConditionKind::of(&rule.condition);
+            state.rule_condition_value = match rule.condition {
+                RuleCondition::FromContains(value) | RuleCondition::SubjectContains(value) => value,
+                RuleCondition::HasAttachment => String::new(),
+            };
+            state.rule_action = RuleActionKind::of(&rule.action);
+            state.rule_action_value = match rule.action {
+                RuleAction::Label(value) | RuleAction::MoveTo(value) => value,
+                RuleAction::MarkRead | RuleAction::Hide => String::new(),
+            };
+            Task::none()
+        }
+        Message::CancelRuleEdit => {
+            state.clear_rule_form();
+            Task::none()
+        }
+        Message::DeleteRule(id) => state.perform(delete_rule(id), Message::RulesChanged),
+        Message::RulesChanged(Ok(rules)) => {
+            state.rules = rules;
+            state.clear_rule_form();
+            state.status_note =
+                Some("Regels opgeslagen; de inbox wordt opnieuw opgehaald.".to_owned());
+            state.fetch_range(state.date_range)
+        }
+        Message::RulesChanged(Err(error)) => {
+            state.status_note = Some(format!("Regel opslaan mislukt: {error}"));
+            Task::none()
+        }
+        Message::SelectRuleFolder(folder) => {
+            state.selected_folder = MailFolder::Inbox;
+            state.rule_folder = Some(folder);
+            state.selected_message = state.first_message_index();
+            Task::none()
+        }
+        Message::Snooze(duration) => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let (Some(provider), Some(message_id)) = (
+                state.account_provider,
+                state.selected_mail_message().map(|m| m.id.clone()),
+            ) else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Snoozen...".to_owned());
+            state.perform(
+                snooze_message(provider, message_id, unix_now() + duration),
+                Message::Snoozed,
+            )
+        }
+        Message::Snoozed(Ok(message_id)) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some("Bericht gesnoozed.".to_owned());
+            state.remove_message(&message_id);
+            state.scroll_to_selection()
+        }
+        Message::Snoozed(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::SnoozeTick => match state.account_provider {
+            Some(provider) => {
+                state.perform(restore_due_snoozes(provider), Message::SnoozesRestored)
+            }
+            None => Task::none(),
+        },
+        Message::SnoozesRestored(Ok(restored)) => {
+            if !restored.is_empty() {
+                state.status_note = Some(format!(
+                    "{} gesnoozed bericht(en) terug in de inbox.",
+                    restored.len()
+                ));
+                state.selected_message = state
+                    .selected_message
+                    .map(|selected| selected + restored.len());
+                state.messages.splice(0..0, restored);
+            }
+            Task::none()
+        }
+        Message::SnoozesRestored(Err(error)) => {
+            state.status_note = Some(format!("Gesnoozede berichten terugzetten mislukt: {error}"));
+            Task::none()
+        }
+        Message::SetFollowUp(offset) => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let Some((provider, message_id)) = state.followup_target() else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Opvolging instellen...".to_owned());
+            state.perform(
+                set_followup(provider, message_id, unix_now() + offset),
+                Message::FollowUpSaved,
+            )
+        }
+        Message::ClearFollowUp => {
+            if matches!(state.state, UiState::Working(_)) {
+                return Task::none();
+            }
+            let Some((provider, message_id)) = state.followup_target() else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Opvolging wissen...".to_owned());
+            state.perform(clear_followup(provider, message_id), Message::FollowUpSaved)
+        }
+        Message::FollowUpSaved(Ok((message_id, Some(followup)))) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some(format!("Opvolgen vóór {}.", followup.due_day()));
+            state.followups.insert(message_id, followup);
+            Task::none()
+        }
+        Message::FollowUpSaved(Ok((message_id, None))) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some("Opvolging gewist.".to_owned());
+            state.followups.remove(&message_id);
+            if state.selected_folder == MailFolder::FollowUp {
+                state
+                    .folder_messages
+                    .retain(|message| message.id != message_id);
+                state.folder_selection = None;
+            }
+            Task::none()
+        }
+        Message::FollowUpSaved(Err(error)) => {
+            state.write_failed(error);
+            Task::none()
+        }
+        Message::FollowUpsLoaded(Ok(followups)) => {
+            state.followups = followups
+                .into_iter()
+                .map(|followup| (followup.message_id.clone(), followup))
+                .collect();
+            Task::none()
+        }
+        Message::FollowUpsLoaded(Err(error)) => {
+            tracing::warn!(%error, "follow-ups unavailable");
+            Task::none()
+        }
+        Message::FollowUpMessagesLoaded(Ok(messages)) => {
+            state.state = UiState::Loaded;
+            if state.selected_folder == MailFolder::FollowUp {
+                state.folder_messages = messages;
+            }
+            Task::none()
+        }
+        Message::FollowUpMessagesLoaded(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::RunSelfTest(provider) => {
+            state.selftest_report = None;
+            state.state =
+                UiState::Working(format!("Verbinding met {} testen...", provider.label()));
+            state.perform(run_selftest(provider), Message::SelfTestDone)
+        }
+        Message::SelfTestDone(Ok(report)) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some(if report.passed() {
+                format!("Verbinding met {} werkt.", report.provider.label())
+            } else {
+                format!(
+                    "Verbindingstest met {} gaf fouten.",
+                    report.provider.label()
+                )
+            });
+            state.selftest_report = Some(report);
+            Task::none()
+        }
+        Message::SelfTestDone(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::Unsubscribe => {
+            let Some(unsubscribe) = state
+                .selected_mail_message()
+                .and_then(|message| message.unsubscribe.clone())
+            else {
+                return Task::none();
+            };
+            if unsubscribe.one_click
+                && let Some(url) = unsubscribe.url
+            {
+                state.confirm_unsubscribe = Some(url);
+                return Task::none();
+            }
+            let Some(target) = unsubscribe.url.or(unsubscribe.mailto) else {
+                return Task::none();
+            };
+            state.status_note = Some(match webbrowser::open(&target) {
+                Ok(()) => "Afmeldpagina geopend.".to_owned(),
+                Err(error) => format!("Open handmatig: {target} ({error})"),
+            });
+            Task::none()
+        }
+        Message::ConfirmUnsubscribe => {
+            let Some(url) = state.confirm_unsubscribe.take() else {
+                return Task::none();
+            };
+            state.state = UiState::Working("Afmelden...".to_owned());
+            state.perform(unsubscribe_one_click(url), Message::UnsubscribeDone)
+        }
+        Message::CancelUnsubscribe => {
+            state.confirm_unsubscribe = None;
+            Task::none()
+        }
+        Message::UnsubscribeDone(Ok(())) => {
+            state.state = UiState::Loaded;
+            state.status_note = Some("Afgemeld.".to_owned());
+            Task::none()
+        }
+        Message::UnsubscribeDone(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::ToggleBulkSelect(message_id, selected) => {
+            if selected {
+                state.bulk_selection.insert(message_id);
+            } else {
+                state.bulk_selection.remove(&message_id);
+            }
+            Task::none()
+        }
+        Message::ClearBulkSelection => {
+            state.bulk_selection.clear();
+            Task::none()
+        }
+        Message::BulkAction(action) => {
+            let Some((provider, ids)) = state.bulk_target() else {
+                return Task::none();
+            };
+            state.state = UiState::Working(format!("{} berichten bijwerken...", ids.len()));
+            state.perform(apply_action_bulk(provider, action, ids), Message::BulkDone)
+        }
+        Message::BulkMarkRead => {
+            let Some((provider, ids)) = state.bulk_target() else {
+                return Task::none();
+            };
+            state.state =
+                UiState::Working(format!("{} berichten als gelezen markeren...", ids.len()));
+            state.perform(mark_read(provider, ids), Message::BulkDone)
+        }
+        Message::BulkMove(folder) => {
+            let Some((provider, ids)) = state.bulk_target() else {
+                return Task::none();
+            };
+            state.state = UiState::Working(format!(
+                "{} berichten verplaatsen naar {}...",
+                ids.len(),
+                folder.name
+            ));
+            state.perform(move_messages(provider, ids, folder.id), Message::BulkDone)
+        }
+        Message::BulkDone(Ok(outcome)) => {
+            state.state = UiState::Loaded;
+            state.bulk_selection.clear();
+            match outcome {
+                BulkOutcome::Removed(ids, failed) => {
+                    for id in &ids {
+                        state.remove_message(id);
+                    }
+                    state.status_note = Some(match failed.first() {
+                        None => format!("{} berichten bijgewerkt.", ids.len()),
+                        Some((_, error)) => format!(
+                            "{} berichten bijgewerkt, {} mislukt: {error}",
+                            ids.len(),
+                            failed.len()
+                        ),
+                    });
+                    // Failed messages stay selected so the action can be retried.
+                    state
+                        .bulk_selection
+                        .extend(failed.into_iter().map(|(id, _)| id));
+                }
+                BulkOutcome::MarkedRead(ids) => {
+                    for message in state.messages.iter_mut().filter(|m| ids.contains(&m.id)) {
+                        message.labels.retain(|label| label != "UNREAD");
+                    }
+                    state.status_note =
+                        Some(format!("{} berichten als gelezen gemarkeerd.", ids.len()));
+                    if let Some(provider) = state.account_provider {
+                        state.push_undo(provider, UndoKind::MarkedRead(ids));
+                    }
+                }
+            }
+            Task::none()
+        }
+        Message::BulkDone(Err(error)) => {
+            state.state = UiState::Error(error);
+            Task::none()
+        }
+        Message::SortKeyChanged(key) => {
+            state.sort_key = key;
+            state.sort_ascending = key.default_ascending();
+            state.resort()
+        }
+        Message::ToggleSortDirection => {
+            state.sort_ascending = !state.sort_ascending;
+            state.resort()
+        }
+        Message::SortSaved(Ok(())) => Task::none(),
+        Message::SortSaved(Err(error)) => {
+            state.status_note = Some(format!("Sortering opslaan mislukt: {error}"));
+            Task::none()
+        }
+        Message::ChooseDbLocation(portable) => {
+            let first_run = std::mem::take(&mut state.show_db_prompt);
+            match set_portable_mode(portable) {
+                Ok(location) => {
+                    state.status_note = Some(format!(
+                        "Gegevens worden bewaard in {}.",
+                        location.path.display()
+                    ));
+                    state.db_location = Some(location);
+                }
+                Err(error) => state.state = UiState::Error(format!("{error:#}")),
+            }
+            if first_run {
+                state.startup()
+            } else {
+                Task::none()
+            }
+        }
+    }
+}
+
+fn subscription(state: &MailApp) -> Subscription<Message> {
+    let close_requests = window::close_requests().map(Message::CloseRequested);
+    if state.shutting_down {
+        return close_requests;
+    }
+    let mut subscriptions = vec![
+        close_requests,
+        iced::keyboard::on_key_press(handle_key_press),
+    ];
+    if state.compose_dirty {
+        subscriptions.push(
+            iced::time::every(Duration::from_secs(DRAFT_AUTOSAVE_SECS))
+                .map(|_| Message::AutoSaveDraft),
+        );
+    }
+    if !state.undo.is_empty() {
+        subscriptions.push(iced::time::every(Duration::from_secs(1)).map(|_| Message::UndoTick));
+    }
+    if state.account_provider.is_some() {
+        subscriptions.push(
+            iced::time::every(Duration::from_secs(SNOOZE_CHECK_SECS)).map(|_| Message::SnoozeTick),
+        );
+    }
+    Subscription::batch(subscriptions)
+}
+
+fn handle_key_press(key: Key, modifiers: Modifiers) -> Option<Message> {
+    match key.as_ref() {
+        Key::Character("z") if modifiers.command() => Some(Message::Undo),
+        Key::Character("e") if modifiers.is_empty() => {
+            Some(Message::ApplyAction(MailAction::Archive))
+        }
+        Key::Character("#") => Some(Message::ApplyAction(MailAction::Trash)),
+        Key::Named(key::Named::Delete) => Some(Message::ApplyAction(MailAction::Trash)),
+        Key::Named(key::Named::ArrowDown) | Key::Character("j") if modifiers.is_empty() => {
+            Some(Message::SelectAdjacent(1))
+        }
+        Key::Named(key::Named::ArrowUp) | Key::Character("k") if modifiers.is_empty() => {
+            Some(Message::SelectAdjacent(-1))
+        }
+        _ => None,
+    }
+}
+
+fn view(state: &MailApp) -> Element<'_, Message> {
+    let is_working = matches!(state.state, UiState::Working(_));
+
+    let status_line = match &state.state {
+        UiState::Idle => "Klaar om in te loggen".to_owned(),
+        UiState::Working(text) => text.clone(),
+        UiState::Loaded => "Klaar".to_owned(),
+        UiState::Error(error) => format!("Fout: {error}"),
+    };
+
+    let mut google_save_btn = button("Opslaan");
+    let mut google_login_btn = button("Login met Google").style(iced::widget::button::primary);
+    let mut google_other_account_btn = button("Ander Google-account");
+    let mut google_manual_btn = button("Login met code plakken");
+    let mut google_toggle_btn = if state.show_google_setup {
+        button("Google instellingen verbergen")
+    } else {
+        button("Google instellingen")
+    };
+
+    let mut compose_toggle_btn = if state.show_compose {
+        button("Opstellen sluiten")
+    } else {
+        button("Nieuw bericht")
+    };
+
+    if !is_working {
+        if state.account_provider.is_some() {
+            compose_toggle_btn = compose_toggle_btn.on_press(Message::ToggleCompose);
+        }
+        google_toggle_btn = google_toggle_btn.on_press(Message::ToggleGoogleSetup);
+        google_login_btn = google_login_btn.on_press(Message::LoginGoogle {
+            other_account: false,
+        });
+        google_other_account_btn = google_other_account_btn.on_press(Message::LoginGoogle {
+            other_account: true,
+        });
+        google_manual_btn = google_manual_btn.on_press(Message::StartManualLogin);
+        if !state.google_client_id.trim().is_empty() {
+            google_save_btn = google_save_btn.on_press(Message::SaveGoogleSettings);
+        }
+    }
+
+    let mut actions = row![
+        google_login_btn,
+        google_other_account_btn,
+        google_manual_btn,
+        google_toggle_btn,
+        compose_toggle_btn
+    ]
+    .spacing(10);
+
+    if !state.accounts.is_empty() {
+        actions = actions.push(
+            pick_list(
+                state.accounts.as_slice(),
+                state.active_account.clone(),
+                Message::SwitchAccount,
+            )
+            .placeholder("Kies account"),
+        );
+    }
+
+    let mut status = row![text(status_line)].spacing(10).align_y(iced::Center);
+    if state.pending_fetch.is_some() {
+        status = status.push(
+            button(text("Annuleren").size(13))
+                .style(iced::widget::button::secondary)
+                .on_press(Message::CancelFetch),
+        );
+    }
+    let mut header = column![text("mail"), status, actions].spacing(8);
+
+    if let UiState::Error(_) = &state.state {
+        header = header.push(
+            row![
+                button(text("Kopieer foutmelding").size(13))
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::CopyErrorReport),
+                button(text("Probleem melden").size(13))
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::ReportIssue),
+            ]
+            .spacing(10),
+        );
+    }
+    if let Some(fix) = state.google_fix.filter(|_| !is_working) {
+        header = header.push(
+            row![
+                button(fix.label()).on_press(Message::OpenGoogleFix(fix)),
+                button("Opnieuw inloggen").on_press(Message::LoginGoogle {
+                    other_account: false,
+                }),
+            ]
+            .spacing(10),
+        );
+    }
+    if let Some(provider) = state.reauth_provider {
+        let mut reauth_btn = button(text(format!("Opnieuw inloggen bij {}", provider.label())))
+            .style(iced::widget::button::primary);
+        if !is_working {
+            reauth_btn = reauth_btn.on_press(Message::Reauthenticate(provider));
+        }
+        header = header.push(reauth_btn);
+    }
+    if state.restore_failed {
+        let mut retry_btn = button("Opnieuw proberen");
+        if !is_working {
+            retry_btn = retry_btn.on_press(Message::RetryRestoreSession);
+        }
+        header = header.push(retry_btn);
+    }
+    if let Some(note) = &state.status_note {
+        let mut note_row = row![text(note)].spacing(10);
+        if !state.undo.is_empty() {
+            let label = match state.undo.len() {
+                1 => "Ongedaan maken".to_owned(),
+                count => format!("Ongedaan maken ({count})"),
+            };
+            let mut undo_btn = button(text(label)).style(iced::widget::button::secondary);
+            if !is_working {
+                undo_btn = undo_btn.on_press(Message::Undo);
+            }
+            note_row = note_row.push(undo_btn);
+        }
+        header = header.push(note_row);
+    }
+    if let Some(pending) = &state.pending_login {
+        header = header.push(
+            row![
+                text(format!(
+                    "Wachten op de login bij {} in de browser.",
+                    pending.provider.label()
+                ))
+                .size(13),
+                button(text("Tabblad gesloten? Opnieuw proberen").size(13))
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::RetryLogin),
+            ]
+            .spacing(10)
+            .align_y(iced::Center),
+        );
+    }
+    if state.manual_login.is_some() {
+        let mut login_btn = button("Inloggen").style(iced::widget::button::primary);
+        if !state.manual_code.trim().is_empty() {
+            login_btn = login_btn.on_press(Message::FinishManualLogin);
+        }
+        header = header.push(
+            container(
+                column![
+                    text(
+                        "Na het inloggen laadt de browser een pagina die niet bereikbaar is. \
+                         Kopieer de volledige adresregel (of alleen de code) en plak die hier:"
+                    )
+                    .size(13),
+                    row![
+                        text_input(
+                            "http://127.0.0.1:8080/callback?code=...",
+                            &state.manual_code
+                        )
+                        .on_input(Message::ManualCodeChanged)
+                        .on_submit(Message::FinishManualLogin),
+                        login_btn,
+                        button("Annuleren")
+                            .style(iced::widget::button::secondary)
+                            .on_press(Message::CancelManualLogin),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                ]
+                .spacing(6),
+            )
+            .padding(8)
+            .width(iced::Fill)
+            .style(iced::widget::container::bordered_box),
+        );
+    }
+    if let Some(url) = &state.authorize_url {
+        header = header.push(
+            container(
+                column![
+                    text("Open deze URL in een browser om in te loggen:"),
+                    text(url).size(12),
+                    button("Kopieer URL")
+                        .style(iced::widget::button::secondary)
+                        .on_press(Message::CopyAuthorizeUrl),
+                ]
+                .spacing(6),
+            )
+            .padding(8)
+            .width(iced::Fill)
+            .style(iced::widget::container::bordered_box),
+        );
+    }
+    if let Some(account) = &state.account_label {
+        let (color, detail) = state.connection_status();
+        let account_line = if state.plaintext_mode {
+            format!("Ingelogd als {account} ({detail})")
+        } else {
+            format!("Ingelogd als {account}")
+        };
+        let dot = container(text(""))
+            .width(10)
+            .height(10)
+            .style(move |_| container::Style {
+                background: Some(color.into()),
+                border: iced::Border {
+                    radius: 5.0.into(),
+                    ..iced::Border::default()
+                },
+                ..container::Style::default()
+            });
+        header = header.push(
+            container(
+                row![
+                    tooltip(
+                        dot,
+                        container(text(detail).size(13))
+                            .padding(6)
+                            .style(iced::widget::container::bordered_box),
+                        tooltip::Position::Bottom,
+                    ),
+                    text(account_line),
+                ]
+                .spacing(8)
+                .align_y(iced::Alignment::Center),
+            )
+            .padding(8)
+            .style(iced::widget::container::rounded_box),
+        );
+    }
+
+    let mut content = column![header].spacing(12).padding(12);
+
+    if state.show_db_prompt {
+        let location = state
+            .db_location
+            .as_ref()
+            .map(|location| location.path.display().to_string())
+            .unwrap_or_default();
+        content = content.push(
+            container(
+                column![
+                    text("Eerste keer opstarten: waar wil je je instellingen en mail bewaren?"),
+                    text(format!("Standaard: {location}")).size(13),
+                    row![
+                        button("In mijn gebruikersmap").on_press(Message::ChooseDbLocation(false)),
+                        button("Naast het programma (portable)")
+                            .on_press(Message::ChooseDbLocation(true)),
+                    ]
+                    .spacing(10),
+                ]
+                .spacing(8),
+            )
+            .padding(10)
+            .style(iced::widget::container::rounded_box),
+        );
+    }
+
+    if state.show_google_setup {
+        let mut selftest_btn = button("Verbinding testen").style(iced::widget::button::secondary);
+        if !is_working {
+            selftest_btn = selftest_btn.on_press(Message::RunSelfTest(Provider::Google));
+        }
+
+        let mut selftest_results = column![].spacing(4);
+        if let Some(report) = &state.selftest_report {
+            for step in &report.steps {
+                let line = match &step.outcome {
+                    SelfTestOutcome::Passed => format!("[ok] {}", step.name),
+                    SelfTestOutcome::Failed(error) => format!("[fout] {}: {error}", step.name),
+                    SelfTestOutcome::Skipped => format!("[--] {} (overgeslagen)", step.name),
+                };
+                selftest_results = selftest_results.push(text(line).size(13));
+            }
+        }
+
+        let mut storage = column![].spacing(6);
+        if let Some(location) = &state.db_location {
+            storage = storage.push(text(format!("Database: {}", location.path.display())).size(13));
+            match location.kind {
+                DbLocationKind::EnvOverride => {}
+                DbLocationKind::Portable if portable_mode_forced() => {
+                    storage =
+                        storage.push(text("Portable modus staat aan via MAIL_PORTABLE.").size(13));
+                }
+                DbLocationKind::Portable => {
+                    storage = storage.push(
+                        button("Portable modus uitzetten")
+                            .on_press(Message::ChooseDbLocation(false)),
+                    );
+                }
+                DbLocationKind::UserData => {
+                    storage = storage.push(
+                        button("Portable modus aanzetten")
+                            .on_press(Message::ChooseDbLocation(true)),
+                    );
+                }
+            }
+        }
+        if !state.blocked_senders.is_empty() {
+            let mut blocked: Vec<&String> = state.blocked_senders.iter().collect();
+            blocked.sort();
+            storage = storage.push(text("Geblokkeerde afzenders").size(13));
+            for sender in blocked {
+                storage = storage.push(
+                    row![
+                        text(sender.as_str()).size(13),
+                        button(text("Deblokkeren").size(13))
+                            .style(iced::widget::button::secondary)
+                            .on_press(Message::UnblockSender(sender.clone())),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                );
+            }
+        }
+        storage = storage.push(rules_section(state, is_working));
+        storage = storage.push(
+            checkbox(
+                "Volledige berichten opslaan voor offline zoeken",
+                state.offline_bodies,
+            )
+            .on_toggle(Message::ToggleOfflineBodies),
+        );
+        storage = storage.push(
+            row![
+                text("Max. berichten in cache:"),
+                text_input("5000", &state.cache_limit_input)
+                    .on_input(Message::CacheLimitChanged)
+                    .width(100),
+            ]
+            .spacing(10)
+            .align_y(iced::Center),
+        );
+        if let Some(provider) = state.account_provider {
+            let downloading = state
+                .offline_progress
+                .as_deref()
+                .is_some_and(|progress| progress.ends_with("..."));
+            let mut download_btn =
+                button("Alles downloaden voor offline").style(iced::widget::button::secondary);
+            if !is_working && !downloading {
+                download_btn = download_btn.on_press(Message::DownloadForOffline);
+            }
+            storage = storage.push(
+                row![
+                    download_btn,
+                    text(state.offline_progress.clone().unwrap_or_default()).size(13)
+                ]
+                .spacing(10)
+                .align_y(iced::Center),
+            );
+            if let Some(account) = &state.active_account {
+                let running = state
+                    .backup_progress
+                    .as_deref()
+                    .is_some_and(|progress| progress.ends_with("..."));
+                let mut backup_btn = button(text(format!("Backup van {}", account.email)))
+                    .style(iced::widget::button::secondary);
+                if !running {
+                    backup_btn = backup_btn.on_press(Message::BackupAccount);
+                }
+                storage = storage.push(
+                    row![
+                        backup_btn,
+                        text(state.backup_progress.clone().unwrap_or_default()).size(13)
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                );
+            }
+            let mut reauthorize_btn = button(text(format!(
+                "Opnieuw autoriseren bij {}",
+                provider.label()
+            )))
+            .style(iced::widget::button::secondary);
+            if !is_working {
+                reauthorize_btn = reauthorize_btn.on_press(Message::Reauthorize(provider));
+            }
+            storage = storage.push(reauthorize_btn);
+            if state.granted_scopes.is_empty() {
+                storage = storage.push(
+                    text("Toegekende rechten: onbekend (nog niet door de provider gemeld)")
+                        .size(13),
+                );
+            } else {
+                storage = storage.push(
+                    text(format!(
+                        "Toegekende rechten: {}",
+                        state.granted_scopes.join(", ")
+                    ))
+                    .size(13),
+                );
+                let missing = missing_write_scopes(provider, &state.granted_scopes);
+                if !missing.is_empty() {
+                    storage = storage.push(
+                        text(format!(
+                            "Ontbreekt voor verzenden/verplaatsen: {}. Autoriseer opnieuw.",
+                            missing.join(", ")
+                        ))
+                        .size(13)
+                        .style(iced::widget::text::danger),
+                    );
+                }
+            }
+            let mut usage_btn =
+                button("Opslaggebruik opvragen").style(iced::widget::button::secondary);
+            if !is_working {
+                usage_btn = usage_btn.on_press(Message::LoadStorageUsage);
+            }
+            let usage = match &state.storage_usage {
+                Some(Ok(line)) => line.clone(),
+                Some(Err(error)) => format!("Opslaggebruik opvragen mislukt: {error}"),
+                None => String::new(),
+            };
+            storage = storage.push(
+                row![usage_btn, text(usage).size(13)]
+                    .spacing(10)
+                    .align_y(iced::Center),
+            );
+        }
+        if state.confirm_reset {
+            let mut confirm_btn = button("Alles wissen").style(iced::widget::button::danger);
+            if !is_working {
+                confirm_btn = confirm_btn.on_press(Message::ConfirmResetApp);
+            }
+            storage = storage.push(
+                row![
+                    text("Alle accounts, tokens, instellingen en lokale mail wissen?"),
+                    confirm_btn,
+                    button("Annuleren")
+                        .style(iced::widget::button::secondary)
+                        .on_press(Message::CancelResetApp),
+                ]
+                .spacing(10),
+            );
+        } else {
+            storage = storage.push(
+                button("App resetten")
+                    .style(iced::widget::button::danger)
+                    .on_press(Message::ResetApp),
+            );
+        }
+
+        content = content.push(
+            container(
+                column![
+                    text("Google OAuth instellingen"),
+                    text_input("Google Client ID", &state.google_client_id)
+                        .on_input(Message::GoogleClientIdChanged),
+                    text_input("Google Client Secret", &state.google_client_secret)
+                        .on_input(Message::GoogleClientSecretChanged),
+                    row![google_save_btn, selftest_btn].spacing(10),
+                    selftest_results,
+                    row![
+                        text("Standaard provider bij opstarten"),
+                        pick_list(
+                            DefaultProviderChoice::ALL,
+                            Some(state.default_provider),
+                            Message::DefaultProviderSelected,
+                        ),
+                    ]
+                    .spacing(10),
+                    checkbox(
+                        "Externe afbeeldingen altijd laden",
+                        state.load_remote_images
+                    )
+                    .on_toggle(Message::ToggleLoadRemoteImages),
+                    row![
+                        checkbox(
+                            "Bericht als gelezen markeren bij openen",
+                            state.auto_mark_read
+                        )
+                        .on_toggle(Message::ToggleAutoMarkRead),
+                        text("na"),
+                        text_input("0", &state.mark_read_delay_input)
+                            .on_input(Message::MarkReadDelayChanged)
+                            .width(60),
+                        text("seconden"),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                    checkbox(
+                        "HTML-versie als berichttekst gebruiken (Gmail)",
+                        state.prefer_html
+                    )
+                    .on_toggle(Message::TogglePreferHtml),
+                    checkbox(
+                        "Relatieve datums in de berichtenlijst",
+                        state.list_date_display == DateDisplay::Relative
+                    )
+                    .on_toggle(Message::ToggleListRelativeDates),
+                    checkbox(
+                        "Relatieve datums in het leesvenster",
+                        state.detail_date_display == DateDisplay::Relative
+                    )
+                    .on_toggle(Message::ToggleDetailRelativeDates),
+                    checkbox(
+                        "Toegankelijkheidsmodus: alleen platte tekst, hoog contrast",
+                        state.plaintext_mode
+                    )
+                    .on_toggle(Message::TogglePlaintextMode),
+                    row![
+                        text("Tekstgrootte"),
+                        pick_list(
+                            FontScale::ALL,
+                            Some(FontScale(state.font_scale)),
+                            Message::FontScaleSelected,
+                        ),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                    row![
+                        text("Voorbeeldlengte (tekens, 0 = uit):"),
+                        text_input("100", &state.preview_length_input)
+                            .on_input(Message::PreviewLengthChanged)
+                            .width(80),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Center),
+                    storage,
+                ]
+                .spacing(8),
+            )
+            .padding(10)
+            .style(iced::widget::container::rounded_box),
+        );
+    }
+
+    if state.show_compose {
+        content = content.push(compose_pane(state, is_working));
+    }
+
+    let folder_pane = folder_pane(state, is_working);
+    let list_pane = message_list_pane(state, is_working);
+    let detail_pane = message_detail_pane(state, is_working);
+
+    content = content.push(
+        row![folder_pane, list_pane, detail_pane]
+            .spacing(10)
+            .height(iced::Fill),
+    );
+
+    container(content)
+        .width(iced::Fill)
+        .height(iced::Fill)
+        .into()
+}
+
+fn rules_section(state: &MailApp, is_working: bool) -> Element<'_, Message> {
+    let mut section =
+        column![text("Regels (van boven naar beneden toegepast)").size(13)].spacing(6);
+    for rule in &state.rules {
+        let mut edit_btn = button(text("Bewerken").size(13)).style(iced::widget::button::secondary);
+        let mut delete_btn =
+            button(text("Verwijderen").size(13)).style(iced::widget::button::secondary);
+        if let Some(id) = rule.id.filter(|_| !is_working) {
+            edit_btn = edit_btn.on_press(Message::EditRule(rule.clone()));
+            delete_btn = delete_btn.on_press(Message::DeleteRule(id));
+        }
+        section = section.push(
+            row![
+                text(format!("Als {}: {}", rule.condition, rule.action)).size(13),
+                edit_btn,
+                delete_btn,
+            ]
+            .spacing(10)
+            .align_y(iced::Center),
+        );
+    }
+
+    let mut form = row![pick_list(
+        RuleConditionKind::ALL,
+        Some(state.rule_condition),
+        Message::RuleConditionSelected,
+    )]
+    .spacing(10)
+    .align_y(iced::Center);
+    if state.rule_condition.needs_value() {
+        form = form.push(
+            text_input("tekst", &state.rule_condition_value)
+                .on_input(Message::RuleConditionValueChanged)
+                .width(160),
+        );
+    }
+    form = form.push(pick_list(
+        RuleActionKind::ALL,
+        Some(state.rule_action),
+        Message::RuleActionSelected,
+    ));
+    if state.rule_action.needs_value() {
+        form = form.push(
+            text_input("naam", &state.rule_action_value)
+                .on_input(Message::RuleActionValueChanged)
+                .width(160),
+        );
+    }
+    let mut save_btn = button(text(if state.editing_rule.is_some() {
+        "Regel opslaan"
+    } else {
+        "Regel toevoegen"
+    }));
+    if !is_working {
+        save_btn = save_btn.on_press(Message::SaveRule);
+    }
+    form = form.push(save_btn);
+    if state.editing_rule.is_some() {
+        form = form.push(
+            button("Annuleren")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::CancelRuleEdit),
+        );
+    }
+    section.push(form).into()
+}
+
+fn compose_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
+    let mut attachments = column![].spacing(4);
+    for (index, (filename, data, _)) in state.compose_attachments.iter().enumerate() {
+        let mut remove_btn = button("Verwijderen").style(iced::widget::button::secondary);
+        if !is_working {
+            remove_btn = remove_btn.on_press(Message::RemoveAttachment(index));
+        }
+        attachments = attachments.push(
+            row![
+                text(format!("{filename} ({})", format_size(data.len()))),
+                remove_btn
+            ]
+            .spacing(10),
+        );
+    }
+
+    let mut attach_btn = button("Bijlage toevoegen");
+    let mut send_btn = button("Verzenden").style(iced::widget::button::primary);
+    let mut save_draft_btn = button("Concept opslaan");
+    let mut delete_draft_btn = button("Concept verwijderen").style(iced::widget::button::danger);
+    if !is_working {
+        attach_btn = attach_btn.on_press(Message::PickAttachments);
+        save_draft_btn = save_draft_btn.on_press(Message::SaveDraft);
+        delete_draft_btn = delete_draft_btn.on_press(Message::DeleteDraft);
+        if !state.compose_to.trim().is_empty() {
+            send_btn = send_btn.on_press(Message::SendCompose);
+        }
+    }
+
+    container(
+        column![
+            text("Nieuw bericht"),
+            text_input("Aan (gescheiden door komma's)", &state.compose_to)
+                .on_input(Message::ComposeToChanged),
+            text_input("Onderwerp", &state.compose_subject)
+                .on_input(Message::ComposeSubjectChanged),
+            text_editor(&state.compose_body)
+                .on_action(Message::ComposeBodyEdited)
+                .height(160),
+            attachments,
+            row![attach_btn, save_draft_btn, delete_draft_btn, send_btn].spacing(10),
+        ]
+        .spacing(8),
+    )
+    .padding(10)
+    .style(iced::widget::container::rounded_box)
+    .into()
+}
+
+fn is_unread(message: &MailMessage) -> bool {
+    message.labels.iter().any(|label| label == "UNREAD")
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.0} KB", (bytes as f64 / 1024.0).ceil())
+    }
+}
+
+fn folder_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
+    let mut content = column![text("Mailboxen")].spacing(6);
+
+    for folder in MailFolder::all() {
+        let is_selected = *folder == state.selected_folder && state.rule_folder.is_none();
+        let label = match (folder, state.inbox_counts) {
+            (MailFolder::Inbox, Some(counts)) if counts.unread > 0 => {
+                format!("{} ({}/{})", folder.label(), counts.unread, counts.total)
+            }
+            (MailFolder::Inbox, Some(counts)) => format!("{} ({})", folder.label(), counts.total),
+            _ => folder.label().to_owned(),
+        };
+        let mut item = button(text(label));
+        item = if is_selected {
+            item.style(iced::widget::button::primary)
+        } else {
+            item.style(iced::widget::button::secondary)
+        };
+        if !is_working {
+            item = item.on_press(Message::SelectFolder(*folder));
+        }
+        content = content.push(item);
+        if *folder == MailFolder::Inbox {
+            for rule_folder in state.rule_folders() {
+                let mut item = button(text(rule_folder).size(14));
+                item = if state.rule_folder.as_deref() == Some(rule_folder) {
+                    item.style(iced::widget::button::primary)
+                } else {
+                    item.style(iced::widget::button::secondary)
+                };
+                if !is_working {
+                    item = item.on_press(Message::SelectRuleFolder(rule_folder.to_owned()));
+                }
+                content = content.push(container(item).padding(iced::Padding::ZERO.left(16)));
+            }
+        }
+    }
+
+    container(scrollable(content))
+        .padding(10)
+        .style(iced::widget::container::rounded_box)
+        .width(iced::Length::FillPortion(1))
+        .height(iced::Fill)
+        .into()
+}
+
+fn message_list_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
+    let mut title = format!(
+        "{} berichten",
+        state
+            .rule_folder
+            .as_deref()
+            .unwrap_or(state.selected_folder.label())
+    );
+    if state.rule_folder.is_some() {
+        title.push_str(&format!(" ({})", state.display_order().len()));
+    } else if state.selected_folder == MailFolder::Inbox && !state.messages.is_empty() {
+        match state.inbox_total {
+            Some(total) if state.inbox_has_more => {
+                title.push_str(&format!(" ({} van ~{total})", state.messages.len()));
+            }
+            _ => title.push_str(&format!(" ({})", state.messages.len())),
+        }
+    }
+    let mut header = row![text(title).width(iced::Fill)].spacing(10);
+    if state.selected_folder == MailFolder::Inbox && state.account_provider.is_some() {
+        let filter_label = if state.date_range.is_empty() {
+            "Zoeken op datum"
+        } else {
+            "Datumfilter actief"
+        };
+        header = header.push(
+            button(text(filter_label).size(13))
+                .style(iced::widget::button::secondary)
+                .on_press(Message::ToggleDateFilter),
+        );
+    }
+    if state.account_provider.is_some()
+        && !matches!(
+            state.selected_folder,
+            MailFolder::Search | MailFolder::Imported | MailFolder::Drafts | MailFolder::FollowUp
+        )
+    {
+        let unread_label = if state.unread_only {
+            "Alle berichten"
+        } else {
+            "Alleen ongelezen"
+        };
+        let mut unread_btn =
+            button(text(unread_label).size(13)).style(iced::widget::button::secondary);
+        if !is_working {
+            unread_btn = unread_btn.on_press(Message::ToggleUnreadOnly);
+        }
+        header = header.push(unread_btn);
+    }
+    if state.selected_folder == MailFolder::Inbox && !state.messages.is_empty() {
+        header = header.push(
+            row![
+                text("Sorteer:").size(13),
+                pick_list(SortKey::ALL, Some(state.sort_key), Message::SortKeyChanged)
+                    .text_size(13),
+                button(text(state.sort_key.direction_label(state.sort_ascending)).size(13))
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::ToggleSortDirection),
+            ]
+            .spacing(6)
+            .align_y(iced::Center),
+        );
+    }
+    let search = text_input("Zoek in alle accounts (offline)...", &state.search_query)
+        .on_input(Message::SearchQueryChanged)
+        .on_submit(Message::SearchCache);
+    let mut content = column![search, header].spacing(6);
+    let mut list = column![].spacing(6);
+    let hidden = state.hidden_count();
+    if hidden > 0 {
+        content = content.push(
+            text(format!(
+                "{hidden} berichten van geblokkeerde afzenders verborgen"
+            ))
+            .size(12),
+        );
+    }
+    let sync_key = match (state.selected_folder, state.account_provider) {
+        (MailFolder::Inbox, Some(_)) => Some(""),
+        (folder, Some(provider)) => folder.id(provider),
+        (_, None) => None,
+    };
+    if let Some(&synced_at) = sync_key.and_then(|key| state.last_synced.get(key)) {
+        let age = unix_now() - synced_at;
+        let mut sync_line = text(format!("Gesynchroniseerd {}", format_sync_age(age))).size(12);
+        if age > STALE_SYNC_SECS {
+            sync_line = sync_line.style(iced::widget::text::danger);
+        }
+        content = content.push(sync_line);
+    }
+
+    if state.selected_folder == MailFolder::Inbox && state.show_date_filter {
+        let mut apply_btn = button(text("Ophalen").size(13));
+        let mut clear_btn = button(text("Wissen").size(13)).style(iced::widget::button::secondary);
+        if !is_working {
+            apply_btn = apply_btn.on_press(Message::ApplyDateFilter);
+            clear_btn = clear_btn.on_press(Message::ClearDateFilter);
+        }
+        content = content.push(
+            column![
+                row![
+                    text_input("Vanaf (JJJJ-MM-DD)", &state.date_after)
+                        .on_input(Message::DateAfterChanged)
+                        .on_submit(Message::ApplyDateFilter),
+                    text_input("Tot en met (JJJJ-MM-DD)", &state.date_before)
+                        .on_input(Message::DateBeforeChanged)
+                        .on_submit(Message::ApplyDateFilter),
+                ]
+                .spacing(6),
+                row![apply_btn, clear_btn].spacing(6),
+                text("Datums gelden als hele dagen in UTC.").size(12),
+            ]
+            .spacing(6),
+        );
+    }
+
+    if state.selected_folder == MailFolder::Inbox && state.bulk_selection.len() > 1 {
+        let count = state.bulk_selection.len();
+        let mut archive_btn = button(text(format!("Archiveren ({count})")).size(13));
+        let mut trash_btn = button(text("Prullenbak").size(13)).style(iced::widget::button::danger);
+        let mut read_btn = button(text("Markeer gelezen").size(13));
+        let mut clear_btn =
+            button(text("Selectie wissen").size(13)).style(iced::widget::button::secondary);
+        if !is_working {
+            archive_btn = archive_btn.on_press(Message::BulkAction(MailAction::Archive));
+            trash_btn = trash_btn.on_press(Message::BulkAction(MailAction::Trash));
+            read_btn = read_btn.on_press(Message::BulkMarkRead);
+            clear_btn = clear_btn.on_press(Message::ClearBulkSelection);
+        }
+        content = content.push(
+            column![
+                row![archive_btn, trash_btn, read_btn, clear_btn].spacing(6),
+                pick_list(state.folders.as_slice(), None::<Folder>, Message::BulkMove)
+                    .placeholder("Selectie verplaatsen naar..."),
+            ]
+            .spacing(6),
+        );
+    }
+
+    if state.selected_folder == MailFolder::Drafts {
+        if state.drafts.is_empty() {
+            list = list.push(text("Geen concepten gevonden."));
+        }
+        for (index, draft) in state.drafts.iter().enumerate() {
+            let subject = if draft.message.subject.trim().is_empty() {
+                "(geen onderwerp)"
+            } else {
+                draft.message.subject.as_str()
+            };
+            let mut row_btn = button(
+                column![
+                    text(subject),
+                    text(format!("Aan: {}", draft.message.to.join(", "))).size(13),
+                ]
+                .spacing(3),
+            )
+            .style(iced::widget::button::secondary);
+            if !is_working {
+                row_btn = row_btn.on_press(Message::OpenDraft(index));
+            }
+            list = list.push(row_btn.width(iced::Fill));
+        }
+    } else if state.selected_folder == MailFolder::Imported {
+        let mut import_btn = button("mbox importeren...").style(iced::widget::button::secondary);
+        if !is_working {
+            import_btn = import_btn.on_press(Message::ImportMbox);
+        }
+        content = content.push(import_btn);
+        if state.imported.is_empty() {
+            list = list.push(text("Nog geen berichten geimporteerd."));
+        }
+        list = push_folder_rows(list, state, is_working);
+    } else if state.selected_folder != MailFolder::Inbox {
+        if state.folder_messages.is_empty() && !is_working {
+            list = list.push(text("Geen berichten in deze map."));
+        }
+        list = push_folder_rows(list, state, is_working);
+    } else if is_working && !state.streamed.is_empty() {
+        content =
+            content.push(text(format!("{} berichten geladen...", state.streamed.len())).size(13));
+        for item in &state.streamed {
+            list = list.push(
+                button(message_row(item, state))
+                    .style(iced::widget::button::secondary)
+                    .width(iced::Fill),
+            );
+        }
+    } else if state.messages.is_empty() {
+        list = list.push(text(if !state.inbox_empty {
+            "Nog geen berichten geladen."
+        } else if state.unread_only {
+            "Geen ongelezen berichten."
+        } else if state.date_range.is_empty() {
+            "Je inbox is leeg."
+        } else {
+            "Geen berichten binnen het gekozen datumbereik."
+        }));
+    } else {
+        // Rows have a fixed height, so spacers stand in for the rows outside
+        // the viewport and the scrollbar keeps its full range.
+        let order = state.display_order();
+        let row_height = state.list_row_height();
+        let visible = state.visible_rows(order.len());
+        list = list.spacing(0).push(iced::widget::Space::with_height(
+            visible.start as f32 * row_height,
+        ));
+        for position in visible.clone() {
+            let index = order[position];
+            let item = &state.messages[index];
+            let continues_conversation = !state.custom_sort()
+                && position > 0
+                && state.messages[order[position - 1]].conversation_key()
+                    == item.conversation_key();
+            let is_selected = Some(index) == state.selected_message;
+            let mut row_btn = button(message_row(item, state));
+            row_btn = if is_selected {
+                row_btn.style(iced::widget::button::primary)
+            } else {
+                row_btn.style(iced::widget::button::secondary)
+            };
+            if !is_working {
+                row_btn = row_btn.on_press(Message::SelectMessage(index));
+            }
+            let message_id = item.id.clone();
+            let bulk_label = if state.plaintext_mode {
+                "Selecteren"
+            } else {
+                ""
+            };
+            let bulk_toggle = checkbox(bulk_label, state.bulk_selection.contains(&item.id))
+                .on_toggle(move |checked| Message::ToggleBulkSelect(message_id.clone(), checked));
+            let indent = if continues_conversation { 24 } else { 0 };
+            list = list.push(
+                container(
+                    row![bulk_toggle, row_btn.width(iced::Fill)]
+                        .push_maybe(retry_button(state, item, is_working))
+                        .spacing(6)
+                        .align_y(iced::Center)
+                        .padding(iced::Padding::ZERO.left(indent)),
+                )
+                .height(row_height)
+                .align_y(iced::Center)
+                .clip(true),
+            );
+        }
+        list = list.push(iced::widget::Space::with_height(
+            (order.len() - visible.end) as f32 * row_height,
+        ));
+    }
+
+    content = content.push(
+        scrollable(list)
+            .id(message_list_id())
+            .on_scroll(Message::ListScrolled)
+            .height(iced::Fill),
+    );
+    container(content)
+        .padding(10)
+        .style(iced::widget::container::rounded_box)
+        .width(iced::Length::FillPortion(2))
+        .height(iced::Fill)
+        .into()
+}
+
+fn label_chips<'a>(state: &'a MailApp, message: &MailMessage) -> Element<'a, Message> {
+    let mut chips = row![].spacing(6).align_y(iced::Center);
+    for label in state
+        .labels
+        .iter()
+        .filter(|label| message.labels.contains(&label.id))
+    {
+        let background = label
+            .color
+            .as_deref()
+            .and_then(iced::Color::parse)
+            .unwrap_or(iced::Color::from_rgb(0.55, 0.58, 0.62));
+        let text_color = if background.r * 0.299 + background.g * 0.587 + background.b * 0.114 > 0.6
+        {
+            iced::Color::BLACK
+        } else {
+            iced::Color::WHITE
+        };
+        chips = chips.push(
+            container(
+                row![
+                    text(&label.name).size(12),
+                    button(
+                        text(if state.plaintext_mode {
+                            "Verwijderen"
+                        } else {
+                            "x"
+                        })
+                        .size(12)
+                    )
+                    .padding([0, 4])
+                    .style(iced::widget::button::text)
+                    .on_press(Message::RemoveLabel(label.id.clone())),
+                ]
+                .spacing(2)
+                .align_y(iced::Center),
+            )
+            .padding([2, 8])
+            .style(move |_| container::Style {
+                background: Some(background.into()),
+                text_color: Some(text_color),
+                border: iced::Border {
+                    radius: 10.0.into(),
+                    ..iced::Border::default()
+                },
+                ..container::Style::default()
+            }),
+        );
+    }
+
+    for name in message.local_labels() {
+        chips = chips.push(
+            container(text(name.to_owned()).size(12))
+                .padding([2, 8])
+                .style(iced::widget::container::bordered_box),
+        );
+    }
+
+    let available: Vec<MailLabel> = state
+        .labels
+        .iter()
+        .filter(|label| !message.labels.contains(&label.id))
+        .cloned()
+        .collect();
+    if !available.is_empty() {
+        chips = chips.push(
+            pick_list(available, None::<MailLabel>, Message::AddLabel)
+                .placeholder("Label toevoegen...")
+                .text_size(12),
+        );
+    }
+    chips.into()
+}
+
+fn message_list_id() -> scrollable::Id {
+    scrollable::Id::new("message-list")
+}
+
+fn message_row<'a>(item: &'a MailMessage, state: &MailApp) -> Element<'a, Message> {
+    let date = state.list_date_display.format(&item.date, unix_now());
+    let mut subject = if item.importance == Importance::High {
+        format!("! {}", item.subject)
+    } else {
+        item.subject.clone()
+    };
+    if state.pinned.contains(&item.id) {
+        subject = format!("[vast] {subject}");
+    }
+    let mut details = format!("{} | {date}", item.from);
+    if let Some(followup) = state.followups.get(&item.id) {
+        details.push_str(&format!(" | opvolgen {}", followup.due_day()));
+    }
+    let mut row_content = column![
+        text(subject).wrapping(Wrapping::None),
+        text(details).size(13).wrapping(Wrapping::None),
+    ]
+    .spacing(3);
+    if state.preview_length > 0 {
+        row_content = row_content.push(
+            text(item.preview(state.preview_length))
+                .size(12)
+                .wrapping(Wrapping::None),
+        );
+    }
+    row_content.into()
+}
+
+fn retry_button<'a>(
+    state: &MailApp,
+    message: &MailMessage,
+    is_working: bool,
+) -> Option<Element<'a, Message>> {
+    state.load_errors.get(&message.id)?;
+    let mut retry =
+        button(text(retry_label(state, message)).size(13)).style(iced::widget::button::secondary);
+    if !is_working && !state.retrying.contains(&message.id) {
+        retry = retry.on_press(Message::RetryMessage(message.id.clone()));
+    }
+    Some(retry.into())
+}
+
+fn retry_label(state: &MailApp, message: &MailMessage) -> &'static str {
+    if state.retrying.contains(&message.id) {
+        "Bezig..."
+    } else {
+        "Opnieuw proberen"
+    }
+}
+
+/// The load error of `message` with a retry button; empty without an error.
+fn load_error_notice<'a>(
+    state: &'a MailApp,
+    message: &MailMessage,
+    is_working: bool,
+) -> Element<'a, Message> {
+    let Some(error) = state.load_errors.get(&message.id) else {
+        return column![].into();
+    };
+    let mut retry = button(retry_label(state, message)).style(iced::widget::button::secondary);
+    if !is_working && !state.retrying.contains(&message.id) {
+        retry = retry.on_press(Message::RetryMessage(message.id.clone()));
+    }
+    container(
+        row![
+            text(format!("Bericht kon niet volledig geladen worden: {error}"))
+                .style(iced::widget::text::danger)
+                .width(iced::Fill),
+            retry,
+        ]
+        .spacing(10)
+        .align_y(iced::Center),
+    )
+    .padding(8)
+    .width(iced::Fill)
+    .style(iced::widget::container::bordered_box)
+    .into()
+}
+
+fn thread_panel<'a>(state: &MailApp, thread: &'a ThreadView) -> Element<'a, Message> {
+    let mut messages = column![].spacing(6);
+    for message in &thread.messages {
+        let expanded = thread.expanded.contains(&message.id);
+        let marker = if expanded { "v" } else { ">" };
+        let mut entry = column![
+            button(text(format!(
+                "{marker} {} | {}",
+                message.from,
+                state.detail_date_display.format(&message.date, unix_now())
+            )))
+            .style(iced::widget::button::text)
+            .on_press(Message::ToggleThreadMessage(message.id.clone()))
+        ]
+        .spacing(4);
+        if expanded {
+            entry = entry.push(if thread.loading.contains(&message.id) {
+                text("Laden...").size(13).into()
+            } else {
+                linkified_body(&message.body, 13)
+            });
+        } else {
+            entry = entry.push(text(message.preview(120)).size(12));
+        }
+        messages = messages.push(entry);
+    }
+    container(
+        column![
+            row![
+                text(format!("Gesprek ({} berichten)", thread.messages.len())).size(18),
+                button("Gesprek sluiten")
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::CloseThread),
+            ]
+            .spacing(10)
+            .align_y(iced::Center),
+            messages,
+        ]
+        .spacing(8),
+    )
+    .padding(8)
+    .width(iced::Fill)
+    .style(iced::widget::container::bordered_box)
+    .into()
+}
+
+fn pin_button<'a>(state: &MailApp, message: &MailMessage) -> Element<'a, Message> {
+    let label = if state.pinned.contains(&message.id) {
+        "Losmaken"
+    } else {
+        "Vastzetten"
+    };
+    button(label)
+        .style(iced::widget::button::secondary)
+        .on_press(Message::TogglePin)
+        .into()
+}
+
+fn date_display(relative: bool) -> DateDisplay {
+    if relative {
+        DateDisplay::Relative
+    } else {
+        DateDisplay::Absolute
+    }
+}
+
+fn format_sync_age(age_secs: i64) -> String {
+    match age_secs {
+        ..60 => "zojuist".to_owned(),
+        60..3600 => format!("{} min geleden", age_secs / 60),
+        3600..86_400 => format!("{} uur geleden", age_secs / 3600),
+        _ => format!("{} dagen geleden", age_secs / 86_400),
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn push_folder_rows<'a>(
+    mut content: iced::widget::Column<'a, Message>,
+    state: &'a MailApp,
+    is_working: bool,
+) -> iced::widget::Column<'a, Message> {
+    for index in state.folder_display_order() {
+        let item = &state.folder_listing()[index];
+        let mut row_btn = button(message_row(item, state));
+        row_btn = if Some(index) == state.folder_selection {
+            row_btn.style(iced::widget::button::primary)
+        } else {
+            row_btn.style(iced::widget::button::secondary)
+        };
+        if !is_working {
+            row_btn = row_btn.on_press(Message::SelectFolderMessage(index));
+        }
+        content = content.push(
+            row![row_btn.width(iced::Fill)]
+                .push_maybe(retry_button(state, item, is_working))
+                .spacing(6)
+                .align_y(iced::Center),
+        );
+    }
+    content
+}
+
+/// The current follow-up date with a button to clear it, or the presets to
+/// set one.
+fn followup_row<'a>(state: &MailApp, message: &MailMessage) -> Element<'a, Message> {
+    let mut content = row![].spacing(10).align_y(iced::Center);
+    if let Some(followup) = state.followups.get(&message.id) {
+        let label = if followup.is_overdue(unix_now()) {
+            format!("Opvolgen vóór {} (verlopen)", followup.due_day())
+        } else {
+            format!("Opvolgen vóór {}", followup.due_day())
+        };
+        content = content.push(text(label)).push(
+            button("Opvolging wissen")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::ClearFollowUp),
+        );
+    } else {
+        content = content.push(text("Opvolgen:"));
+        for (label, offset) in FOLLOW_UP_PRESETS {
+            content = content.push(
+                button(label)
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::SetFollowUp(offset)),
+            );
+        }
+    }
+    content.into()
+}
+
+/// A plain-text body whose URLs open in the browser when clicked.
+fn linkified_body(body: &str, size: u16) -> Element<'_, Message> {
+    const LINK: iced::Color = iced::Color::from_rgb(0.2, 0.45, 0.9);
+    let spans: Vec<_> = linkify(body)
+        .into_iter()
+        .map(|segment| match segment {
+            TextSegment::Text(plain) => span(plain),
+            TextSegment::Link(url) => span(url)
+                .color(LINK)
+                .underline(true)
+                .link(Message::OpenLink(url.to_owned())),
+        })
+        .collect();
+    rich_text(spans).size(size).into()
+}
+
+/// The body with a quoted earlier message collapsed behind a toggle, unless
+/// the body is nothing but quote.
+fn quoted_body<'a>(state: &MailApp, body: &'a str) -> Element<'a, Message> {
+    let Some(start) = quoted_reply_start(body).filter(|&start| !body[..start].trim().is_empty())
+    else {
+        return linkified_body(body, 16);
+    };
+    let (shown, toggle) = if state.show_quoted {
+        (body, "Geciteerde tekst verbergen")
+    } else {
+        (body[..start].trim_end(), "Geciteerde tekst tonen")
+    };
+    column![
+        linkified_body(shown, 16),
+        button(text(toggle).size(13))
+            .style(iced::widget::button::secondary)
+            .on_press(Message::ToggleQuoted),
+    ]
+    .spacing(8)
+    .into()
+}
+
+/// The from address with a verified/unverified badge when the message
+/// carries SPF/DKIM/DMARC results.
+fn sender_line(message: &MailMessage) -> Element<'_, Message> {
+    let line = row![text(format!("Van: {}", message.from))]
+        .spacing(10)
+        .align_y(iced::Center);
+    let Some((auth, trust)) = message
+        .authentication
+        .as_ref()
+        .and_then(|auth| Some((auth, auth.sender_trust()?)))
+    else {
+        return line.into();
+    };
+    let badge = container(text(trust.label()).size(12).style(match trust {
+        SenderTrust::Verified => iced::widget::text::success,
+        SenderTrust::Unverified => iced::widget::text::danger,
+    }))
+    .padding([2, 8])
+    .style(iced::widget::container::bordered_box);
+    line.push(tooltip(
+        badge,
+        container(text(auth.summary()).size(13))
+            .padding(6)
+            .style(iced::widget::container::bordered_box),
+        tooltip::Position::Bottom,
+    ))
+    .into()
+}
+
+fn message_detail_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
+    let content = if let Some(message) = state.selected_mail_message() {
+        let move_list = pick_list(
+            state.folders.as_slice(),
+            None::<Folder>,
+            Message::MoveSelected,
+        )
+        .placeholder("Verplaatsen naar...");
+
+        let mut actions = row![
+            move_list,
+            pin_button(state, message),
+            button("Kopieer als JSON")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::CopyMessageJson),
+            button("Afzender blokkeren")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::BlockSender(message.from.clone())),
+            button("Bron bekijken")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::ViewSource),
+            button("Kopieer link")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::CopyPermalink),
+            button("Open in webmail")
+                .style(iced::widget::button::secondary)
+                .on_press(Message::OpenPermalink),
+        ]
+        .spacing(10);
+
+        if let Some(html) = message.body_html.as_deref() {
+            if !state.plaintext_mode {
+                actions = actions.push(
+                    button("HTML-weergave openen")
+                        .style(iced::widget::button::secondary)
+                        .on_press(Message::OpenHtmlView),
+                );
+                if !state.remote_images_allowed_for(message) && has_remote_images(html) {
+                    actions = actions.push(
+                        button("Afbeeldingen laden")
+                            .style(iced::widget::button::secondary)
+                            .on_press(Message::AllowRemoteImages(message.id.clone())),
+                    );
+                    actions = actions.push(
+                        button("Altijd laden van deze afzender")
+                            .style(iced::widget::button::secondary)
+                            .on_press(Message::TrustImageSender(message.from.clone())),
+                    );
+                }
+            }
+        } else {
+            actions = actions.push(
+                button("Volledig bericht laden")
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::LoadFullMessage),
+            );
+        }
+
+        if !state.auto_mark_read && is_unread(message) {
+            actions = actions.push(
+                button("Markeer als gelezen")
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::MarkSelectedRead),
+            );
+        }
+
+        if message.conversation_id.is_some() {
+            actions = actions.push(
+                button("Gesprek laden")
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::LoadThread),
+            );
+        }
+
+        if message.unsubscribe.is_some() {
+            actions = actions.push(
+                button("Afmelden")
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::Unsubscribe),
+            );
+        }
+
+        let mut snooze = row![text("Snoozen:")].spacing(10).align_y(iced::Center);
+        for (label, duration) in SNOOZE_PRESETS {
+            snooze = snooze.push(
+                button(label)
+                    .style(iced::widget::button::secondary)
+                    .on_press(Message::Snooze(duration)),
+            );
+        }
+
+        let mut confirm = column![];
+        if let Some(url) = &state.confirm_unsubscribe {
+            confirm = confirm.push(
+                row![
+                    text(format!("Afmeldverzoek versturen naar {url}?")),
+                    button("Afmelden bevestigen")
+                        .style(iced::widget::button::danger)
+                        .on_press(Message::ConfirmUnsubscribe),
+                    button("Annuleren")
+                        .style(iced::widget::button::secondary)
+                        .on_press(Message::CancelUnsubscribe),
+                ]
+                .spacing(10),
+            );
+        }
+
+        let mut warning = column![];
+        if message.is_suspicious {
+            let mut reasons = Vec::new();
+            if message.labels.iter().any(|label| label == "SPAM") {
+                reasons.push("gemarkeerd als spam".to_owned());
+            }
+            if let Some(score) = message.spam_score {
+                reasons.push(format!("spamscore {score}"));
+            }
+            if let Some(auth) = &message.authentication {
+                for (name, verdict) in [
+                    ("SPF", auth.spf),
+                    ("DKIM", auth.dkim),
+                    ("DMARC", auth.dmarc),
+                ] {
+                    if verdict == Some(AuthVerdict::Fail) {
+                        reasons.push(format!("{name} mislukt"));
+                    }
+                }
+            }
+            warning = warning.push(
+                container(text(format!(
+                    "Let op: dit bericht is mogelijk spam of phishing ({}).",
+                    reasons.join(", ")
+                )))
+                .padding(8)
+                .width(iced::Fill)
+                .style(iced::widget::container::bordered_box),
+            );
+        }
+
+        let mut source = column![];
+        if let Some((_, raw)) = state
+            .raw_source
+            .as_ref()
+            .filter(|(id, _)| *id == message.id)
+        {
+            source = source.push(
+                container(
+                    column![
+                        row![
+                            text("Berichtbron").size(18),
+                            button("Bron sluiten")
+                                .style(iced::widget::button::secondary)
+                                .on_press(Message::CloseSource),
+                        ]
+                        .spacing(10)
+                        .align_y(iced::Center),
+                        scrollable(text(raw).size(12)).height(300),
+                    ]
+                    .spacing(8),
+                )
+                .padding(8)
+                .width(iced::Fill)
+                .style(iced::widget::container::bordered_box),
+            );
+        }
+
+        let mut conversation = column![];
+        if let Some(thread) = state
+            .thread
+            .as_ref()
+            .filter(|thread| message.conversation_id.as_ref() == Some(&thread.conversation_id))
+        {
+            conversation = conversation.push(thread_panel(state, thread));
+        }
+
+        column![
+            load_error_notice(state, message, is_working),
+            warning,
+            actions,
+            snooze,
+            followup_row(state, message),
+            confirm,
+            source,
+            conversation,
+            label_chips(state, message),
+            text(&message.subject).size(24),
+            sender_line(message),
+            text(format!(
+                "Datum: {}",
+                state.detail_date_display.format(&message.date, unix_now())
+            )),
+            text(format!("Prioriteit: {}", message.importance.label())),
+            text(format!(
+                "Grootte: {}",
+                message
+                    .size_estimate
+                    .map(|size| format_size(size as usize))
+                    .unwrap_or_else(|| "onbekend".to_owned())
+            )),
+            text(""),
+            quoted_body(state, &message.body),
+        ]
+        .spacing(8)
+    } else if let Some(message) = state.selected_folder_message() {
+        let mut sender = column![sender_line(message)].spacing(8);
+        if let Some(account) = state
+            .folder_selection
+            .filter(|_| state.selected_folder == MailFolder::Search)
+            .and_then(|index| state.search_accounts.get(index))
+        {
+            sender = sender.push(text(format!(
+                "Account: {} ({})",
+                account.email,
+                account.provider.label()
+            )));
+        }
+        column![
+            load_error_notice(state, message, is_working),
+            row![pin_button(state, message)].spacing(20).push_maybe(
+                state
+                    .followup_target()
+                    .map(|_| followup_row(state, message))
+            ),
+            text(&message.subject).size(24),
+            sender,
+            text(format!(
+                "Datum: {}",
+                state.detail_date_display.format(&message.date, unix_now())
+            )),
+            text(""),
+            quoted_body(state, &message.body),
+        ]
+        .spacing(8)
+    } else if state.selected_folder == MailFolder::Imported {
+        column![text("Selecteer een geimporteerd bericht om te lezen.")]
+    } else if state.selected_folder != MailFolder::Inbox
+        && state.selected_folder != MailFolder::Drafts
+    {
+        column![text("Selecteer een bericht om te lezen.")]
+    } else if state.selected_folder == MailFolder::Inbox {
+        column![text("Selecteer een email om te lezen.")]
+    } else if state.selected_folder == MailFolder::Drafts {
+        column![text("Selecteer een concept om verder te schrijven.")]
+    } else {
+        column![text("Selecteer Inbox om berichten te lezen.")]
+    };
+
+    container(scrollable(content))
+        .padding(12)
+        .style(iced::widget::container::rounded_box)
+        .width(iced::Length::FillPortion(4))
+        .height(iced::Fill)
+        .into()
+}
+
+impl MailApp {
+    /// Inbox indices in the order the list pane shows them.
+    fn is_blocked(&self, message: &MailMessage) -> bool {
+        !self.blocked_senders.is_empty()
+            && self
+                .blocked_senders
+                .contains(&sender_address(&message.from))
+    }
+
+    fn hidden_count(&self) -> usize {
+        let listing = match self.selected_folder {
+            MailFolder::Inbox => self.messages.as_slice(),
+            _ => self.folder_listing(),
+        };
+        listing
+            .iter()
+            .filter(|message| self.is_blocked(message))
+            .count()
+    }
+
+    fn display_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.messages.len())
+            .filter(|&index| {
+                let message = &self.messages[index];
+                !self.is_blocked(message) && message.rule_folder() == self.rule_folder.as_deref()
+            })
+            .collect();
+        if self.custom_sort() {
+            order.sort_by(|&a, &b| {
+                let ordering = self.sort_key.compare(&self.messages[a], &self.messages[b]);
+                if self.sort_ascending {
+                    ordering
+                } else {
+                    ordering.reverse()
+                }
+            });
+        }
+        order.sort_by_key(|&index| !self.pinned.contains(&self.messages[index].id));
+        order
+    }
+
+    /// Anything but newest first, which keeps the provider order with
+    /// conversations grouped.
+    fn custom_sort(&self) -> bool {
+        self.sort_key != SortKey::Date || self.sort_ascending
+    }
+
+    /// Persists the sort choice. The selection is an index into `messages`,
+    /// which sorting leaves alone, so only the scroll position follows.
+    fn resort(&mut self) -> Task<Message> {
+        Task::batch([
+            self.perform(
+                save_message_sort(self.sort_key, self.sort_ascending),
+                Message::SortSaved,
+            ),
+            self.scroll_to_selection(),
+        ])
+    }
+
+    fn folder_display_order(&self) -> Vec<usize> {
+        let listing = self.folder_listing();
+        let mut order: Vec<usize> = (0..listing.len())
+            .filter(|&index| !self.is_blocked(&listing[index]))
+            .collect();
+        order.sort_by_key(|&index| !self.pinned.contains(&listing[index].id));
+        order
+    }
+
+    /// Scrolls the message list so the selected row is in view, centring it
+    /// when it was outside the viewport.
+    fn scroll_to_selection(&self) -> Task<Message> {
+        let order = self.display_order();
+        let Some(position) = self
+            .selected_message
+            .and_then(|selected| order.iter().position(|&index| index == selected))
+        else {
+            return Task::none();
+        };
+        let row_height = self.list_row_height();
+        let top = position as f32 * row_height;
+        if top >= self.list_offset && top + row_height <= self.list_offset + self.list_height {
+            return Task::none();
+        }
+        let y = (top - (self.list_height - row_height) / 2.0).max(0.0);
+        scrollable::scroll_to(message_list_id(), scrollable::AbsoluteOffset { x: 0.0, y })
+    }
+
+    /// Fixed height of an inbox row, gap included; rows never wrap.
+    fn list_row_height(&self) -> f32 {
+        if self.preview_length > 0 { 82.0 } else { 62.0 }
+    }
+
+    /// Positions in `display_order` to build: the rows in the viewport plus
+    /// `LIST_BUFFER_ROWS` on either side.
+    fn visible_rows(&self, len: usize) -> Range<usize> {
+        let row_height = self.list_row_height();
+        let first = (self.list_offset / row_height) as usize;
+        let shown = (self.list_height / row_height).ceil() as usize + 1;
+        let start = first.saturating_sub(LIST_BUFFER_ROWS).min(len);
+        let end = (first + shown + LIST_BUFFER_ROWS).min(len);
+        start..end
+    }
+
+    /// Rule pseudo-folders that hold at least one inbox message.
+    fn rule_folders(&self) -> BTreeSet<&str> {
+        self.messages
+            .iter()
+            .filter_map(MailMessage::rule_folder)
+            .collect()
+    }
+
+    fn clear_rule_form(&mut self) {
+        self.editing_rule = None;
+        self.rule_condition = RuleConditionKind::default();
+        self.rule_condition_value.clear();
+        self.rule_action = RuleActionKind::default();
+        self.rule_action_value.clear();
+    }
+
+    fn first_message_index(&self) -> Option<usize> {
+        self.display_order().first().copied()
+    }
+
+    /// Pushes an undoable action, dropping the oldest beyond
+    /// [`UNDO_STACK_LIMIT`].
+    fn push_undo(&mut self, provider: Provider, kind: UndoKind) {
+        if self.undo.len() == UNDO_STACK_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(PendingUndo {
+            provider,
+            kind,
+            expires_at: Instant::now() + Duration::from_secs(UNDO_WINDOW_SECS),
+        });
+    }
+
+    fn stash_active_account(&mut self) {
+        self.undo.clear();
+        if let Some(active) = self.active_account.take() {
+            self.account_cache.insert(active, self.messages.clone());
+        }
+    }
+
+    fn apply_login_result(&mut self, result: LoginResult) -> Task<Message> {
+        self.state = UiState::Loaded;
+        self.google_fix = None;
+        self.note_fetch_warning(result.warning.as_deref());
+        self.track_load_errors(&result);
+        self.streamed.clear();
+        self.reauth_provider = None;
+        self.restore_failed = false;
+        let account = Account {
+            provider: result.provider,
+            email: result.account.clone(),
+        };
+        if self.active_account.as_ref() != Some(&account) {
+            self.stash_active_account();
+            self.last_synced.clear();
+            self.unread_only = false;
+        }
+        self.active_account = Some(account);
+        let account_label = format!("{}: {}", result.provider.label(), result.account);
+        self.messages = if self.account_label.as_deref() == Some(account_label.as_str()) {
+            Engine::merge_messages(std::mem::take(&mut self.messages), result.messages)
+        } else {
+            result.messages
+        };
+        let messages = &self.messages;
+        self.bulk_selection
+            .retain(|id| messages.iter().any(|message| &message.id == id));
+        self.account_label = Some(account_label);
+        self.account_provider = Some(result.provider);
+        self.inbox_total = result.estimated_total;
+        self.inbox_has_more = result.next_cursor.is_some();
+        self.inbox_empty = result.mailbox_empty;
+        self.selected_folder = MailFolder::Inbox;
+        self.selected_message = self.first_message_index();
+        Task::batch(vec![
+            self.scroll_to_selection(),
+            self.perform(list_folders(result.provider), Message::FoldersLoaded),
+            self.perform(list_labels(result.provider), Message::LabelsLoaded),
+            self.perform(load_pins(Some(result.provider)), Message::PinsLoaded),
+            self.perform(load_followups(result.provider), Message::FollowUpsLoaded),
+            self.perform(inbox_counts(result.provider), Message::InboxCountsLoaded),
+            self.perform(list_accounts(), Message::AccountsLoaded),
+            self.perform(granted_scopes(result.provider), Message::ScopesLoaded),
+            self.perform(
+                last_synced(result.provider, result.account, String::new()),
+                |synced| Message::LastSyncLoaded(String::new(), synced),
+            ),
+        ])
+    }
+
+    /// Appends the skipped-messages warning of a fetch to the status note.
+    fn note_fetch_warning(&mut self, warning: Option<&str>) {
+        let Some(warning) = warning else {
+            return;
+        };
+        self.status_note = Some(match self.status_note.take() {
+            Some(note) => format!("{note} {warning}."),
+            None => format!("{warning}."),
+        });
+    }
+
+    /// Forgets errors of messages this fetch loaded and remembers new ones.
+    fn track_load_errors(&mut self, result: &LoginResult) {
+        for message in &result.messages {
+            self.load_errors.remove(&message.id);
+        }
+        self.load_errors.extend(result.load_errors.clone());
+    }
+
+    /// Shows a failed write action, pointing at re-authorization when the
+    /// session lacks the scopes such actions need.
+    fn write_failed(&mut self, error: String) {
+        let missing = self
+            .account_provider
+            .map(|provider| missing_write_scopes(provider, &self.granted_scopes))
+            .unwrap_or_default();
+        self.state = UiState::Error(if missing.is_empty() {
+            error
+        } else {
+            format!(
+                "{error}\nOntbrekende rechten: {}. Autoriseer opnieuw via de instellingen.",
+                missing.join(", ")
+            )
+        });
+    }
+
+    fn remove_message(&mut self, message_id: &str) -> Option<MailMessage> {
+        let index = self.messages.iter().position(|m| m.id == message_id)?;
+        let removed = self.messages.remove(index);
+        self.selected_message = self.selected_message.and_then(|selected| {
+            if selected < index {
+                Some(selected)
+            } else if selected > index {
+                Some(selected - 1)
+            } else {
+                let last = self.messages.len().checked_sub(1)?;
+                Some(index.min(last))
+            }
+        });
+        Some(removed)
+    }
+
+    fn compose_draft(&self) -> Draft {
+        Draft {
+            id: self.compose_draft_id.clone(),
+            message: OutgoingMessage {
+                to: split_recipients(&self.compose_to),
+                subject: self.compose_subject.clone(),
+                body: self.compose_body.text(),
+                attachments: self.compose_attachments.clone(),
+            },
+        }
+    }
+
+    fn open_in_compose(&mut self, draft: Draft) {
+        self.compose_draft_id = draft.id;
+        self.compose_to = draft.message.to.join(", ");
+        self.compose_subject = draft.message.subject;
+        self.compose_body = text_editor::Content::with_text(&draft.message.body);
+        self.compose_attachments = draft.message.attachments;
+        self.compose_dirty = false;
+    }
+
+    fn reset_compose(&mut self) {
+        self.show_compose = false;
+        self.open_in_compose(Draft::default());
+    }
+
+    fn remote_images_allowed_for(&self, message: &MailMessage) -> bool {
+        self.load_remote_images
+            || self.remote_images_allowed.contains(&message.id)
+            || self
+                .trusted_image_senders
+                .contains(&sender_address(&message.from))
+    }
+
+    fn bulk_target(&self) -> Option<(Provider, Vec<String>)> {
+        if matches!(self.state, UiState::Working(_)) || self.bulk_selection.is_empty() {
+            return None;
+        }
+        let provider = self.account_provider?;
+        let ids = self
+            .messages
+            .iter()
+            .filter(|message| self.bulk_selection.contains(&message.id))
+            .map(|message| message.id.clone())
+            .collect();
+        Some((provider, ids))
+    }
+
+    fn selected_mail_message(&self) -> Option<&MailMessage> {
+        if self.selected_folder != MailFolder::Inbox {
+            return None;
+        }
+        self.selected_message
+            .and_then(|index| self.messages.get(index))
+    }
+
+    /// Loads settings, session and local data and starts the status server.
+    fn startup(&mut self) -> Task<Message> {
+        let mut tasks = vec![
+            self.perform(load_saved_settings(), Message::SettingsLoaded),
+            self.perform(restore_session(), Message::RestoreSessionDone),
+            self.perform(load_local_draft(), Message::LocalDraftLoaded),
+            self.perform(list_accounts(), Message::AccountsLoaded),
+            self.perform(load_pins(None), Message::PinsLoaded),
+            self.perform(list_blocked(), Message::BlockedLoaded),
+            self.perform(list_rules(), Message::RulesLoaded),
+        ];
+        if let Some(port) = status_port() {
+            tasks.push(self.perform(serve_status(port), Message::StatusServerStopped));
+        }
+        Task::batch(tasks)
+    }
+
+    fn fetch_view(&self) -> FetchView {
+        FetchView {
+            unread_only: self.unread_only,
+            date_range: self.date_range,
+            selected_folder: self.selected_folder,
+            rule_folder: self.rule_folder.clone(),
+            selected_message: self.selected_message,
+            folder_messages: self.folder_messages.clone(),
+            folder_selection: self.folder_selection,
+        }
+    }
+
+    fn restore_fetch_view(&mut self, view: FetchView) {
+        self.unread_only = view.unread_only;
+        self.date_range = view.date_range;
+        self.selected_folder = view.selected_folder;
+        self.rule_folder = view.rule_folder;
+        self.selected_message = view.selected_message;
+        self.folder_messages = view.folder_messages;
+        self.folder_selection = view.folder_selection;
+    }
+
+    fn fetch_range(&mut self, range: DateRange) -> Task<Message> {
+        let Some(provider) = self.account_provider else {
+            return Task::none();
+        };
+        if matches!(self.state, UiState::Working(_)) {
+            return Task::none();
+        }
+        let view = self.fetch_view();
+        self.date_range = range;
+        let previous = std::mem::replace(
+            &mut self.state,
+            UiState::Working("Inbox ophalen...".to_owned()),
+        );
+        self.streamed.clear();
+        let (sender, receiver) = iced::futures::channel::mpsc::unbounded();
+        let (fetch, handle) = self.perform_with_handle(
+            fetch_inbox_in_range(
+                provider,
+                range.with_unread_only(self.unread_only),
+                move |message| {
+                    let _ = sender.unbounded_send(message.clone());
+                },
+            ),
+            Message::RangeFetched,
+        );
+        self.pending_fetch = Some(PendingFetch {
+            handle,
+            previous,
+            view,
+        });
+        Task::batch([Task::run(receiver, Message::MessageStreamed), fetch])
+    }
+
+    fn connection_status(&self) -> (iced::Color, String) {
+        const CONNECTED: iced::Color = iced::Color::from_rgb(0.2, 0.7, 0.3);
+        const BUSY: iced::Color = iced::Color::from_rgb(0.95, 0.65, 0.1);
+        const PROBLEM: iced::Color = iced::Color::from_rgb(0.85, 0.2, 0.2);
+
+        if let Some(provider) = self.reauth_provider {
+            return (
+                PROBLEM,
+                format!(
+                    "Sessie bij {} verlopen, opnieuw inloggen nodig",
+                    provider.label()
+                ),
+            );
+        }
+        if self.restore_failed {
+            return (PROBLEM, "Sessie herstellen mislukt".to_owned());
+        }
+        match &self.state {
+            UiState::Working(detail) => (BUSY, detail.clone()),
+            UiState::Error(error) => (PROBLEM, format!("Fout: {error}")),
+            UiState::Idle | UiState::Loaded => (CONNECTED, "Verbonden, token geldig".to_owned()),
+        }
+    }
+
+    fn mark_selected_read(&mut self) -> Task<Message> {
+        let (Some(provider), Some(message)) = (self.account_provider, self.selected_mail_message())
+        else {
+            return Task::none();
+        };
+        if !is_unread(message) {
+            return Task::none();
+        }
+        let message_id = message.id.clone();
+        self.perform(
+            mark_message_read(provider, message_id),
+            Message::MarkReadDone,
+        )
+    }
+
+    /// Starts a login; if the browser can't be opened the authorize URL
+    /// arrives as [`Message::AuthorizeUrlReady`] while the login keeps waiting
+    /// for the callback.
+    fn login(
+        &mut self,
+        provider: Provider,
+        client_id: String,
+        client_secret: String,
+        kind: LoginKind,
+    ) -> Task<Message> {
+        let (url_sender, url_receiver) = iced::futures::channel::mpsc::unbounded();
+        let (message_sender, message_receiver) = iced::futures::channel::mpsc::unbounded();
+        self.streamed.clear();
+        let (login, handle) = self.perform_with_handle(
+            login_and_fetch(
+                provider,
+                client_id.clone(),
+                client_secret.clone(),
+                kind,
+                move |url| {
+                    let _ = url_sender.unbounded_send(url.to_owned());
+                },
+                move |message| {
+                    let _ = message_sender.unbounded_send(message.clone());
+                },
+            ),
+            Message::LoginDone,
+        );
+        self.pending_login = Some(PendingLogin {
+            provider,
+            client_id,
+            client_secret,
+            kind,
+            handle,
+        });
+        Task::batch([
+            Task::run(url_receiver, Message::AuthorizeUrlReady),
+            Task::run(message_receiver, Message::MessageStreamed),
+            login,
+        ])
+    }
+
+    /// Runs `future` as an abortable task so a close request can cancel it.
+    fn perform<T: Send + 'static>(
+        &mut self,
+        future: impl Future<Output = T> + Send + 'static,
+        to_message: impl Fn(T) -> Message + Send + 'static,
+    ) -> Task<Message> {
+        self.perform_with_handle(future, to_message).0
+    }
+
+    /// [`MailApp::perform`] that also hands out the abort handle.
+    fn perform_with_handle<T: Send + 'static>(
+        &mut self,
+        future: impl Future<Output = T> + Send + 'static,
+        to_message: impl Fn(T) -> Message + Send + 'static,
+    ) -> (Task<Message>, task::Handle) {
+        let alive = Arc::new(());
+        self.in_flight
+            .retain(|(running, _)| running.strong_count() > 0);
+        let running = Arc::downgrade(&alive);
+        let (task, handle) = Task::perform(
+            async move {
+                let _alive = alive;
+                future.await
+            },
+            to_message,
+        )
+        .abortable();
+        self.in_flight.push((running, handle.clone()));
+        (task, handle)
+    }
+
+    fn modify_selected_labels(&mut self, add: Vec<String>, remove: Vec<String>) -> Task<Message> {
+        if matches!(self.state, UiState::Working(_)) {
+            return Task::none();
+        }
+        let (Some(provider), Some(message_id)) = (
+            self.account_provider,
+            self.selected_mail_message().map(|m| m.id.clone()),
+        ) else {
+            return Task::none();
+        };
+        self.state = UiState::Working("Labels bijwerken...".to_owned());
+        self.perform(
+            modify_labels(provider, message_id, add, remove),
+            Message::LabelsModified,
+        )
+    }
+
+    fn selected_permalink(&self) -> Option<String> {
+        let account = self.active_account.as_ref()?;
+        let message = self.selected_mail_message()?;
+        Some(account.message_permalink(&message.id))
+    }
+
+    fn folder_listing(&self) -> &[MailMessage] {
+        match self.selected_folder {
+            MailFolder::Imported => &self.imported,
+            MailFolder::Search => &self.search_results,
+            MailFolder::Inbox | MailFolder::Drafts => &[],
+            _ => &self.folder_messages,
+        }
+    }
+
+    /// The open message a follow-up applies to; imported and search results
+    /// have no single account to flag them in.
+    fn followup_target(&self) -> Option<(Provider, String)> {
+        let message = match self.selected_folder {
+            MailFolder::Inbox => self.selected_mail_message(),
+            MailFolder::Search | MailFolder::Imported | MailFolder::Drafts => None,
+            _ => self.selected_folder_message(),
+        }?;
+        Some((self.account_provider?, message.id.clone()))
+    }
+
+    fn selected_folder_message(&self) -> Option<&MailMessage> {
+        self.folder_selection
+            .and_then(|index| self.folder_listing().get(index))
+    }
+}
+
+async fn load_saved_settings() -> Result<SavedOAuthSettings, String> {
+    let engine = Engine::new("mail");
+    engine
+        .load_oauth_settings()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn save_settings(
+    provider: Provider,
+    client_id: String,
+    client_secret: String,
+) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    let credentials = ProviderCredentials {
+        client_id,
+        client_secret: normalize_secret(client_secret),
+        tenant: None,
+    };
+
+    let warning = engine
+        .save_provider_credentials(provider, credentials)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    let saved = format!("{}-instellingen opgeslagen.", provider.label());
+    Ok(match warning {
+        Some(warning) => format!("{saved} Let op: {warning}"),
+        None => saved,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LoginKind {
+    Resume,
+    OtherAccount,
+    /// Skip the stored refresh token and ask for consent again.
+    Reauthorize,
+}
+
+async fn login_and_fetch(
+    provider: Provider,
+    client_id: String,
+    client_secret: String,
+    kind: LoginKind,
+    on_authorize_url: impl Fn(&str) + Send + Sync + 'static,
+    on_message: impl Fn(&MailMessage) + Send + Sync + 'static,
+) -> Result<LoginResult, String> {
+    let engine = Engine::builder()
+        .app_name("mail")
+        .on_authorize_url(on_authorize_url)
+        .on_fetched_message(on_message)
+        .build();
+    save_entered_credentials(&engine, provider, client_id, client_secret).await?;
+
+    let result = match kind {
+        LoginKind::Resume => engine.login_and_fetch(provider).await,
+        LoginKind::OtherAccount => engine.login_with_other_account(provider).await,
+        LoginKind::Reauthorize => engine.reauthorize(provider).await,
+    };
+    result.map_err(|error| format!("{error:#}"))
+}
+
+async fn start_manual_login(
+    provider: Provider,
+    client_id: String,
+    client_secret: String,
+) -> Result<ManualLogin, String> {
+    let engine = Engine::new("mail");
+    save_entered_credentials(&engine, provider, client_id, client_secret).await?;
+    engine
+        .start_manual_login(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn finish_manual_login(login: ManualLogin, pasted: String) -> Result<LoginResult, String> {
+    Engine::new("mail")
+        .finish_manual_login(&login, &pasted)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+/// Stores the client id from the settings fields before a login, so the
+/// engine uses what the user typed even if they never pressed save.
+async fn save_entered_credentials(
+    engine: &Engine,
+    provider: Provider,
+    client_id: String,
+    client_secret: String,
+) -> Result<(), String> {
+    let client_id = client_id.trim().to_owned();
+    if client_id.is_empty() {
+        return Ok(());
+    }
+    engine
+        .save_provider_credentials(
+            provider,
+            ProviderCredentials {
+                client_id,
+                client_secret: normalize_secret(client_secret),
+                tenant: None,
+            },
+        )
+        .await
+        .map(|_| ())
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn send_message(provider: Provider, draft: Draft) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .send_message(provider, draft.message)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    if let Some(draft_id) = draft.id {
+        engine
+            .delete_draft(provider, &draft_id)
+            .await
+            .map_err(|error| format!("Verzonden, maar concept niet verwijderd: {error:#}"))?;
+    }
+    engine
+        .clear_local_draft()
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok("Bericht verzonden.".to_owned())
+}
+
+async fn save_draft(provider: Provider, draft: Draft) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .save_draft(provider, draft)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn list_drafts(provider: Provider) -> Result<Vec<Draft>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_drafts(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn delete_draft(remote: Option<(Provider, String)>) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    if let Some((provider, draft_id)) = remote {
+        engine
+            .delete_draft(provider, &draft_id)
+            .await
+            .map_err(|error| format!("{error:#}"))?;
+    }
+    engine
+        .clear_local_draft()
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok("Concept verwijderd.".to_owned())
+}
+
+async fn list_folders(provider: Provider) -> Result<Vec<Folder>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_folders(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn apply_action_bulk(
+    provider: Provider,
+    action: MailAction,
+    message_ids: Vec<String>,
+) -> Result<BulkOutcome, String> {
+    let engine = Engine::new("mail");
+    let results = engine
+        .apply_action_bulk(provider, action, &message_ids)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok(removed_outcome(message_ids, results))
+}
+
+async fn move_messages(
+    provider: Provider,
+    message_ids: Vec<String>,
+    target_folder_id: String,
+) -> Result<BulkOutcome, String> {
+    let engine = Engine::new("mail");
+    let results = engine
+        .move_messages(
+            provider,
+            &message_ids,
+            provider.inbox_folder_id(),
+            &target_folder_id,
+        )
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok(removed_outcome(message_ids, results))
+}
+
+/// Pairs per-message results with their ids, keeping partial success.
+fn removed_outcome<E: std::fmt::Display>(
+    message_ids: Vec<String>,
+    results: Vec<Result<String, E>>,
+) -> BulkOutcome {
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+    for (id, result) in message_ids.into_iter().zip(results) {
+        match result {
+            Ok(_) => removed.push(id),
+            Err(error) => failed.push((id, format!("{error:#}"))),
+        }
+    }
+    BulkOutcome::Removed(removed, failed)
+}
+
+async fn mark_read(provider: Provider, message_ids: Vec<String>) -> Result<BulkOutcome, String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_read_state(provider, &message_ids, true)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok(BulkOutcome::MarkedRead(message_ids))
+}
+
+async fn unsubscribe_one_click(url: String) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .unsubscribe_one_click(&url)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn run_selftest(provider: Provider) -> Result<SelfTestReport, String> {
+    let engine = Engine::new("mail");
+    engine
+        .selftest(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn apply_action(
+    provider: Provider,
+    action: MailAction,
+    message_id: String,
+) -> Result<(MailAction, String, String), String> {
+    let engine = Engine::new("mail");
+    let new_id = engine
+        .apply_action(provider, action, &message_id)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok((action, message_id, new_id))
+}
+
+/// Reverses one undo stack entry; returns the restored message's id when it
+/// changed provider-side.
+async fn undo_last(provider: Provider, kind: UndoKind) -> Result<Option<String>, String> {
+    let engine = Engine::new("mail");
+    let restored = match kind {
+        UndoKind::Action {
+            action, message, ..
+        } => engine
+            .undo_action(provider, action, &message.id)
+            .await
+            .map(Some),
+        UndoKind::Moved {
+            folder_id, message, ..
+        } => engine
+            .undo_move(provider, &message.id, &folder_id)
+            .await
+            .map(Some),
+        UndoKind::MarkedRead(ids) => engine
+            .set_read_state(provider, &ids, false)
+            .await
+            .map(|()| None),
+    };
+    restored.map_err(|error| format!("{error:#}"))
+}
+
+async fn fetch_message(provider: Provider, message_id: String) -> Result<MailMessage, String> {
+    let engine = Engine::new("mail");
+    engine
+        .fetch_message(provider, &message_id)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn fetch_thread(
+    provider: Provider,
+    conversation_id: String,
+) -> Result<(String, Vec<MailMessage>), String> {
+    let engine = Engine::new("mail");
+    let messages = engine
+        .fetch_thread(provider, &conversation_id)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    Ok((conversation_id, messages))
+}
+
+async fn list_labels(provider: Provider) -> Result<Vec<MailLabel>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_labels(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn modify_labels(
+    provider: Provider,
+    message_id: String,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<(String, Vec<String>, Vec<String>), String> {
+    let engine = Engine::new("mail");
+    engine
+        .modify_labels(provider, &message_id, &add, &remove)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok((message_id, add, remove))
+}
+
+async fn storage_usage(provider: Provider) -> Result<Option<StorageUsage>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .storage_usage(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn fetch_raw_message(
+    provider: Provider,
+    message_id: String,
+) -> Result<(String, String), String> {
+    let engine = Engine::new("mail");
+    let raw = engine
+        .fetch_raw_message(provider, &message_id)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+
+    Ok((message_id, String::from_utf8_lossy(&raw).into_owned()))
+}
+
+fn open_html_preview(html: &str) -> Result<(), String> {
+    let document = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"></head><body>{html}</body></html>"
+    );
+    let path = write_preview_file(document.as_bytes())?;
+    webbrowser::open(&path.to_string_lossy()).map_err(|error| error.to_string())
+}
+
+/// Writes a preview to a new file only the user can read, in the user cache
+/// directory when there is one; a fixed name in a shared temp dir could be
+/// read or planted by other users.
+fn write_preview_file(contents: &[u8]) -> Result<std::path::PathBuf, String> {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let dir = directories::ProjectDirs::from("", "", "mail")
+        .map(|dirs| dirs.cache_dir().join("previews"))
+        .unwrap_or_else(std::env::temp_dir);
+    std::fs::create_dir_all(&dir).map_err(|error| format!("{}: {error}", dir.display()))?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let path = dir.join(format!(
+        "mail-preview-{}-{nanos}-{count}.html",
+        std::process::id()
+    ));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|error| format!("{}: {error}", path.display()))?;
+    Ok(path)
+}
+
+/// Returns the old id, the id after the move and the target folder.
+async fn move_message(
+    provider: Provider,
+    message_id: String,
+    target_folder_id: String,
+) -> Result<(String, String, String), String> {
+    let engine = Engine::new("mail");
+    let moved = engine
+        .move_messages(
+            provider,
+            std::slice::from_ref(&message_id),
+            provider.inbox_folder_id(),
+            &target_folder_id,
+        )
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    let new_id = match moved.into_iter().next() {
+        Some(moved) => moved.map_err(|error| format!("{error:#}"))?,
+        None => message_id.clone(),
+    };
+
+    Ok((message_id, new_id, target_folder_id))
+}
+
+async fn save_local_draft(draft: Draft) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .save_local_draft(&draft)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn load_local_draft() -> Result<Option<LocalDraft>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .load_local_draft()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn import_mbox() -> Result<Option<usize>, String> {
+    let Some(file) = rfd::AsyncFileDialog::new()
+        .set_title("mbox-bestand kiezen")
+        .add_filter("mbox", &["mbox", "mbx"])
+        .add_filter("Alle bestanden", &["*"])
+        .pick_file()
+        .await
+    else {
+        return Ok(None);
+    };
+
+    let engine = Engine::new("mail");
+    engine
+        .import_mbox(file.path())
+        .await
+        .map(Some)
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn list_imported_messages() -> Result<Vec<MailMessage>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_imported_messages()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn pick_attachments() -> Vec<Attachment> {
+    let Some(files) = rfd::AsyncFileDialog::new()
+        .set_title("Bijlagen kiezen")
+        .pick_files()
+        .await
+    else {
+        return Vec::new();
+    };
+
+    let mut attachments = Vec::with_capacity(files.len());
+    for file in files {
+        let filename = file.file_name();
+        let data = file.read().await;
+        let mime = attachment_mime_type(&filename).to_owned();
+        attachments.push((filename, data, mime));
+    }
+
+    attachments
+}
+
+async fn restore_session() -> Result<Option<LoginResult>, SessionError> {
+    let engine = Engine::new("mail");
+    let settings = engine
+        .load_oauth_settings()
+        .await
+        .map_err(SessionError::from_engine)?;
+    engine
+        .try_restore_session(settings.startup_provider())
+        .await
+        .map_err(SessionError::from_engine)
+}
+
+async fn list_accounts() -> Result<Vec<Account>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_accounts()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn switch_account(account: Account) -> Result<LoginResult, String> {
+    let engine = Engine::new("mail");
+    engine
+        .switch_account(&account)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn clear_all_data() -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .clear_all_data()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn fetch_folder(
+    provider: Provider,
+    folder_id: &str,
+    unread_only: bool,
+) -> Result<LoginResult, String> {
+    let engine = Engine::new("mail");
+    engine
+        .fetch_folder(provider, folder_id, unread_only)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn inbox_counts(provider: Provider) -> Result<FolderCounts, String> {
+    let engine = Engine::new("mail");
+    engine
+        .folder_counts(provider, provider.inbox_folder_id())
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn last_synced(
+    provider: Provider,
+    account: String,
+    folder_id: String,
+) -> Result<Option<i64>, String> {
+    let engine = Engine::new("mail");
+    let folder = Some(folder_id.as_str()).filter(|id| !id.is_empty());
+    engine
+        .last_synced(provider, &account, folder)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn fetch_inbox_in_range(
+    provider: Provider,
+    range: DateRange,
+    on_message: impl Fn(&MailMessage) + Send + Sync + 'static,
+) -> Result<LoginResult, String> {
+    let engine = Engine::builder()
+        .app_name("mail")
+        .on_fetched_message(on_message)
+        .build();
+    engine
+        .fetch_inbox_in_range(provider, &range)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn save_message_sort(key: SortKey, ascending: bool) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_message_sort(key, ascending)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn save_date_display(list: DateDisplay, detail: DateDisplay) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_date_display(list, detail)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn save_preview_length(max_chars: usize) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_preview_length(max_chars)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn save_offline_sync(offline_bodies: bool, cache_limit: usize) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_offline_sync(offline_bodies, cache_limit)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn download_for_offline(
+    provider: Provider,
+    on_progress: impl Fn(OfflineProgress) + Send + 'static,
+) -> Result<usize, String> {
+    let engine = Engine::new("mail");
+    engine
+        .download_for_offline(provider, on_progress)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    let mut google_save_btn = button("Opslaan");
-    let mut google_login_btn = button("Login met Google").style(iced::widget::button::primary);
-    let mut google_toggle_btn = if state.show_google_setup {
-        button("Google instellingen verbergen")
-    } else {
-        button("Google instellingen")
+/// Resumes an unfinished backup of `account` in place; otherwise asks where
+/// to save the mbox file. `None` when the dialog was cancelled.
+async fn backup_account(
+    account: Account,
+    on_progress: impl Fn(ExportProgress) + Send + 'static,
+) -> Result<Option<usize>, String> {
+    let engine = Engine::new("mail");
+    let pending = engine
+        .pending_mbox_export(&account)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    let path = match pending {
+        Some(path) => path,
+        None => {
+            let Some(file) = rfd::AsyncFileDialog::new()
+                .set_title("Backup opslaan als mbox")
+                .set_file_name(format!("{}.mbox", account.email))
+                .add_filter("mbox", &["mbox"])
+                .save_file()
+                .await
+            else {
+                return Ok(None);
+            };
+            file.path().to_owned()
+        }
     };
+    engine
+        .export_account_mbox(&account, &path, on_progress)
+        .await
+        .map(Some)
+        .map_err(|error| format!("{error:#}"))
+}
 
-    if !is_working {
-        google_toggle_btn = google_toggle_btn.on_press(Message::ToggleGoogleSetup);
-        google_login_btn = google_login_btn.on_press(Message::LoginGoogle);
-        if !state.google_client_id.trim().is_empty() {
-            google_save_btn = google_save_btn.on_press(Message::SaveGoogleSettings);
-        }
-    }
+async fn search_cache(query: String) -> Result<Vec<SearchHit>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .search_cache(&query)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    let mut header = column![
-        text("mail"),
-        text(status_line),
-        row![google_login_btn, google_toggle_btn].spacing(10),
-    ]
-    .spacing(8);
+async fn set_local_pin(
+    provider: Option<Provider>,
+    message_id: String,
+    pinned: bool,
+) -> Result<(String, bool), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_local_pin(provider, &message_id, pinned)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    Ok((message_id, pinned))
+}
 
-    if let Some(note) = &state.status_note {
-        header = header.push(text(note));
-    }
-    if let Some(account) = &state.account_label {
-        header = header.push(
-            container(text(format!("Ingelogd als {account}")))
-                .padding(8)
-                .style(iced::widget::container::rounded_box),
-        );
+/// Pins for `provider` plus those of imported messages, which are always listed.
+async fn load_pins(provider: Option<Provider>) -> Result<HashSet<String>, String> {
+    let engine = Engine::new("mail");
+    let mut pinned = engine
+        .load_local_flags(None)
+        .await
+        .map_err(|error| format!("{error:#}"))?
+        .pinned;
+    if provider.is_some() {
+        let flags = engine
+            .load_local_flags(provider)
+            .await
+            .map_err(|error| format!("{error:#}"))?;
+        pinned.extend(flags.pinned);
     }
+    Ok(pinned)
+}
 
-    let mut content = column![header].spacing(12).padding(12);
+async fn load_followups(provider: Provider) -> Result<Vec<FollowUp>, String> {
+    Engine::new("mail")
+        .followups(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    if state.show_google_setup {
-        content = content.push(
-            container(
-                column![
-                    text("Google OAuth instellingen"),
-                    text_input("Google Client ID", &state.google_client_id)
-                        .on_input(Message::GoogleClientIdChanged),
-                    text_input("Google Client Secret", &state.google_client_secret)
-                        .on_input(Message::GoogleClientSecretChanged),
-                    google_save_btn,
-                ]
-                .spacing(8),
-            )
-            .padding(10)
-            .style(iced::widget::container::rounded_box),
-        );
-    }
+async fn followup_messages(provider: Provider) -> Result<Vec<MailMessage>, String> {
+    Engine::new("mail")
+        .followup_messages(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    let folder_pane = folder_pane(state, is_working);
-    let list_pane = message_list_pane(state, is_working);
-    let detail_pane = message_detail_pane(state);
+async fn set_followup(
+    provider: Provider,
+    message_id: String,
+    due: i64,
+) -> Result<(String, Option<FollowUp>), String> {
+    Engine::new("mail")
+        .set_followup(provider, &message_id, due)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    let followup = FollowUp {
+        message_id: message_id.clone(),
+        due_at: due,
+    };
+    Ok((message_id, Some(followup)))
+}
 
-    content = content.push(
-        row![folder_pane, list_pane, detail_pane]
-            .spacing(10)
-            .height(iced::Fill),
-    );
+async fn clear_followup(
+    provider: Provider,
+    message_id: String,
+) -> Result<(String, Option<FollowUp>), String> {
+    Engine::new("mail")
+        .clear_followup(provider, &message_id)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    Ok((message_id, None))
+}
 
-    container(content).width(iced::Fill).height(iced::Fill).into()
+async fn snooze_message(
+    provider: Provider,
+    message_id: String,
+    until: i64,
+) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .snooze(provider, &message_id, until)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    Ok(message_id)
 }
 
-fn folder_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
-    let mut content = column![text("Mailboxen")].spacing(6);
+async fn restore_due_snoozes(provider: Provider) -> Result<Vec<MailMessage>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .restore_due_snoozes(provider, unix_now())
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    for folder in MailFolder::all() {
-        let is_selected = *folder == state.selected_folder;
-        let mut item = button(folder.label());
-        item = if is_selected {
-            item.style(iced::widget::button::primary)
-        } else {
-            item.style(iced::widget::button::secondary)
-        };
-        if !is_working {
-            item = item.on_press(Message::SelectFolder(*folder));
-        }
-        content = content.push(item);
-    }
+async fn save_accessibility(plaintext_mode: bool, font_scale: f32) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_accessibility(plaintext_mode, font_scale)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    container(scrollable(content))
-        .padding(10)
-        .style(iced::widget::container::rounded_box)
-        .width(iced::Length::FillPortion(1))
-        .height(iced::Fill)
-        .into()
+async fn save_auto_mark_read(enabled: bool) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_auto_mark_read_on_open(enabled)
+        .await
+        .map_err(|error| format!("{error:#}"))
 }
 
-fn message_list_pane(state: &MailApp, is_working: bool) -> Element<'_, Message> {
-    let mut content = column![text(format!("{} berichten", state.selected_folder.label()))].spacing(6);
+async fn block_sender(from: String) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .block_sender(&from)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    if state.selected_folder != MailFolder::Inbox {
-        content = content.push(text("Deze map is nog niet gekoppeld."));
-    } else if state.messages.is_empty() {
-        content = content.push(text("Nog geen berichten geladen."));
-    } else {
-        for (index, item) in state.messages.iter().enumerate() {
-            let is_selected = Some(index) == state.selected_message;
-            let mut row_btn = button(
-                column![
-                    text(&item.subject),
-                    text(format!("{} | {}", item.from, item.date)).size(13),
-                ]
-                .spacing(3),
-            );
-            row_btn = if is_selected {
-                row_btn.style(iced::widget::button::primary)
-            } else {
-                row_btn.style(iced::widget::button::secondary)
-            };
-            if !is_working {
-                row_btn = row_btn.on_press(Message::SelectMessage(index));
-            }
-            content = content.push(row_btn.width(iced::Fill));
-        }
-    }
+async fn unblock_sender(sender: String) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .unblock_sender(&sender)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    Ok(sender)
+}
 
-    container(scrollable(content))
-        .padding(10)
-        .style(iced::widget::container::rounded_box)
-        .width(iced::Length::FillPortion(2))
-        .height(iced::Fill)
-        .into()
+async fn serve_status(port: u16) -> Result<(), String> {
+    let engine = Engine::new("mail");
+    engine
+        .serve_status(port)
+        .await
+        .map_err(|error| format!("{error:#}"))
 }
 
-fn message_detail_pane(state: &MailApp) -> Element<'_, Message> {
-    let content = if let Some(message) = state.selected_mail_message() {
-        column![
-            text(&message.subject).size(24),
-            text(format!("Van: {}", message.from)),
-            text(format!("Datum: {}", message.date)),
-            text(""),
-            text(&message.body),
-        ]
-        .spacing(8)
-    } else if state.selected_folder == MailFolder::Inbox {
-        column![text("Selecteer een email om te lezen.")]
-    } else {
-        column![text("Selecteer Inbox om berichten te lezen.")]
-    };
+async fn granted_scopes(provider: Provider) -> Result<Vec<String>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .granted_scopes(provider)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
-    container(scrollable(content))
-        .padding(12)
-        .style(iced::widget::container::rounded_box)
-        .width(iced::Length::FillPortion(4))
-        .height(iced::Fill)
-        .into()
+async fn list_rules() -> Result<Vec<MailRule>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .list_rules()
+        .await
+        .map_err(|error| format!("{error:#}"))
 }
 
-impl MailApp {
-    fn first_message_index(&self) -> Option<usize> {
-        if self.messages.is_empty() {
-            None
-        } else {
-            Some(0)
-        }
-    }
+async fn save_rule(rule: MailRule) -> Result<Vec<MailRule>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .save_rule(&rule)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    list_rules().await
+}
 
-    fn selected_mail_message(&self) -> Option<&MailMessage> {
-        if self.selected_folder != MailFolder::Inbox {
-            return None;
-        }
-        self.selected_message
-            .and_then(|index| self.messages.get(index))
-    }
+async fn delete_rule(id: i64) -> Result<Vec<MailRule>, String> {
+    let engine = Engine::new("mail");
+    engine
+        .delete_rule(id)
+        .await
+        .map_err(|error| format!("{error:#}"))?;
+    list_rules().await
 }
 
-async fn load_saved_settings() -> Result<SavedOAuthSettings, String> {
+async fn list_blocked() -> Result<Vec<String>, String> {
     let engine = Engine::new("mail");
     engine
-        .load_oauth_settings()
+        .list_blocked()
         .await
         .map_err(|error| format!("{error:#}"))
 }
 
-async fn save_settings(
-    provider: Provider,
-    client_id: String,
-    client_secret: String,
-) -> Result<String, String> {
+async fn save_prefer_html(enabled: bool) -> Result<(), String> {
     let engine = Engine::new("mail");
-    let credentials = ProviderCredentials {
-        client_id,
-        client_secret: normalize_secret(client_secret),
-    };
+    engine
+        .set_prefer_html(enabled)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
+async fn save_mark_read_delay(delay: Duration) -> Result<(), String> {
+    let engine = Engine::new("mail");
     engine
-        .save_provider_credentials(provider, credentials)
+        .set_mark_read_delay(delay)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+async fn mark_message_read(provider: Provider, message_id: String) -> Result<String, String> {
+    let engine = Engine::new("mail");
+    engine
+        .set_read_state(provider, std::slice::from_ref(&message_id), true)
         .await
         .map_err(|error| format!("{error:#}"))?;
 
-    Ok(format!("{}-instellingen opgeslagen.", provider.label()))
+    Ok(message_id)
 }
 
-async fn login_and_fetch(
-    provider: Provider,
-    client_id: String,
-    client_secret: String,
-) -> Result<LoginResult, String> {
+async fn save_load_remote_images(enabled: bool) -> Result<(), String> {
     let engine = Engine::new("mail");
-    let client_id = client_id.trim().to_owned();
-
-    if !client_id.is_empty() {
-        engine
-            .save_provider_credentials(
-                provider,
-                ProviderCredentials {
-                    client_id,
-                    client_secret: normalize_secret(client_secret),
-                },
-            )
-            .await
-            .map_err(|error| format!("{error:#}"))?;
-    }
+    engine
+        .set_load_remote_images(enabled)
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
 
+async fn trust_image_sender(from: String) -> Result<String, String> {
+    let engine = Engine::new("mail");
     engine
-        .login_and_fetch(provider)
+        .trust_image_sender(&from)
         .await
         .map_err(|error| format!("{error:#}"))
 }
 
-async fn restore_google_session() -> Result<Option<LoginResult>, String> {
+async fn save_default_provider(provider: Option<Provider>) -> Result<(), String> {
     let engine = Engine::new("mail");
     engine
-        .try_restore_session(Provider::Google)
+        .set_default_provider(provider)
         .await
         .map_err(|error| format!("{error:#}"))
 }